@@ -0,0 +1,942 @@
+//! Configuration stored in flash
+//!
+//! Each field of [`Config`] is stored under its own [`Key`] in a
+//! [key-value store][crate::kv_store] on the `config` partition, together
+//! with the schema version.
+//! Defaults and valid pins depend on the [`Board`].
+//! Missing or invalid values fall back to the defaults, so a blank partition
+//! yields the default configuration, and values stored with an unknown schema
+//! version are ignored.
+//! Stored I²C pins that are no longer valid on the board are moved to free
+//! pins instead.
+//!
+//! Durations are stored as seconds, pins as GPIO numbers, the I²C frequency
+//! in kilohertz, the battery divider ratio and the display refresh deltas in
+//! thousandths, all as little endian integers, the time zone as a POSIX
+//! TZ string, the external RTC model as a single byte, zero for none, the
+//! World Time API time zone as an IANA time zone name, and whether to stay
+//! always on as a single byte, one for true.
+
+use core::fmt::Debug;
+use core::fmt::Error as FmtError;
+use core::fmt::Write;
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use log::debug;
+use log::warn;
+
+use embassy_time::Duration;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use heapless::String;
+
+use crate::external_rtc::Model as ExternalRtcModel;
+use crate::kv_store::Error as StoreError;
+use crate::kv_store::Store;
+use crate::kv_store::MAXIMAL_VALUE_SIZE;
+use crate::tz::TimeZone;
+
+/// Version of the schema, to be incremented when keys change meaning
+pub const SCHEMA_VERSION: u8 = 1;
+
+/// Default period between sensor samples
+const SAMPLING_PERIOD: Duration = Duration::from_secs(60);
+
+/// Default duration of deep sleep
+const DEEP_SLEEP_DURATION: Duration = Duration::from_secs(300);
+
+/// Range of valid I²C frequencies in kilohertz
+const I2C_FREQUENCIES_KHZ: Range<u32> = 1..401;
+
+/// Default battery divider ratio in thousandths, no battery
+const BATTERY_DIVIDER_THOUSANDTHS: u32 = 0;
+
+/// Range of valid battery divider ratios in thousandths, besides zero
+const BATTERY_DIVIDERS_THOUSANDTHS: Range<u32> = 1_000..20_001;
+
+/// Default temperature change to refresh the display in thousandths of degree
+/// Celsius
+const TEMPERATURE_DELTA_THOUSANDTHS: u32 = 200;
+
+/// Default humidity change to refresh the display in thousandths of percent
+const HUMIDITY_DELTA_THOUSANDTHS: u32 = 1_000;
+
+/// Default pressure change to refresh the display in thousandths of
+/// hectopascal
+const PRESSURE_DELTA_THOUSANDTHS: u32 = 500;
+
+/// Range of valid display refresh deltas in thousandths
+const DELTAS_THOUSANDTHS: Range<u32> = 0..100_001;
+
+/// Name of the external RTC model when there is none
+const NO_EXTERNAL_RTC: &str = "none";
+
+/// Default time zone queried on World Time API
+///
+/// Only the UTC time is used, but an unknown time zone is an error.
+const WORLD_TIME_ZONE: &str = "Europe/Copenhagen";
+
+/// Defaults and constraints that differ between boards
+///
+/// Boards are unit types, deriving the traits derived by [`Config`].
+pub trait Board: Clone + Debug + PartialEq + Eq {
+    /// Default duration to stay awake before deep sleep
+    const AWAKE_PERIOD: Duration;
+
+    /// Default local time zone as a POSIX TZ string
+    const TIME_ZONE: &'static str;
+
+    /// Default GPIO of I²C data
+    const SDA_PIN: u8;
+
+    /// Default GPIO of I²C clock
+    const SCL_PIN: u8;
+
+    /// Default I²C frequency in kilohertz
+    const I2C_FREQUENCY_KHZ: u32;
+
+    /// GPIOs that can be assigned to the I²C bus
+    const I2C_PINS: &'static [u8];
+}
+
+/// A key in the configuration store
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Key {
+    /// Schema version
+    SchemaVersion = 0,
+
+    /// Period between sensor samples
+    SamplingPeriod = 1,
+
+    /// Duration of deep sleep
+    DeepSleepDuration = 2,
+
+    /// Duration to stay awake before deep sleep
+    AwakePeriod = 3,
+
+    /// Local time zone
+    TimeZone = 4,
+
+    /// GPIO of I²C data
+    SdaPin = 5,
+
+    /// GPIO of I²C clock
+    SclPin = 6,
+
+    /// I²C frequency
+    I2cFrequency = 7,
+
+    /// Battery divider ratio
+    BatteryDivider = 8,
+
+    /// Temperature change to refresh the display
+    TemperatureDelta = 9,
+
+    /// Humidity change to refresh the display
+    HumidityDelta = 10,
+
+    /// Pressure change to refresh the display
+    PressureDelta = 11,
+
+    /// Model of the external real-time clock
+    ExternalRtc = 12,
+
+    /// Time zone queried on World Time API
+    WorldTimeZone = 13,
+
+    /// Whether to stay awake instead of entering deep sleep
+    AlwaysOn = 14,
+}
+
+impl Key {
+    /// Keys of all configuration fields
+    pub const FIELDS: [Self; 14] = [
+        Self::SamplingPeriod,
+        Self::DeepSleepDuration,
+        Self::AwakePeriod,
+        Self::TimeZone,
+        Self::SdaPin,
+        Self::SclPin,
+        Self::I2cFrequency,
+        Self::BatteryDivider,
+        Self::TemperatureDelta,
+        Self::HumidityDelta,
+        Self::PressureDelta,
+        Self::ExternalRtc,
+        Self::WorldTimeZone,
+        Self::AlwaysOn,
+    ];
+
+    /// Return the name of the key
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SchemaVersion => "schema_version",
+            Self::SamplingPeriod => "sampling_period",
+            Self::DeepSleepDuration => "deep_sleep_duration",
+            Self::AwakePeriod => "awake_period",
+            Self::TimeZone => "time_zone",
+            Self::SdaPin => "sda_pin",
+            Self::SclPin => "scl_pin",
+            Self::I2cFrequency => "i2c_frequency",
+            Self::BatteryDivider => "battery_divider",
+            Self::TemperatureDelta => "temperature_delta",
+            Self::HumidityDelta => "humidity_delta",
+            Self::PressureDelta => "pressure_delta",
+            Self::ExternalRtc => "external_rtc",
+            Self::WorldTimeZone => "world_time_zone",
+            Self::AlwaysOn => "always_on",
+        }
+    }
+
+    /// Return the unit of the value, if any
+    pub fn unit(self) -> Option<&'static str> {
+        match self {
+            Self::SamplingPeriod | Self::DeepSleepDuration | Self::AwakePeriod => Some("s"),
+            Self::I2cFrequency => Some("kHz"),
+            Self::TemperatureDelta => Some("°C"),
+            Self::HumidityDelta => Some("%"),
+            Self::PressureDelta => Some("hPa"),
+            Self::SchemaVersion
+            | Self::TimeZone
+            | Self::SdaPin
+            | Self::SclPin
+            | Self::BatteryDivider
+            | Self::ExternalRtc
+            | Self::WorldTimeZone
+            | Self::AlwaysOn => None,
+        }
+    }
+
+    /// Find a configuration field by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::FIELDS.into_iter().find(|key| key.name() == name)
+    }
+}
+
+/// Firmware configuration of a board
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config<B> {
+    /// Period between sensor samples
+    pub sampling_period: Duration,
+
+    /// Duration of deep sleep
+    pub deep_sleep_duration: Duration,
+
+    /// Duration to stay awake before deep sleep
+    pub awake_period: Duration,
+
+    /// Local time zone as a POSIX TZ string
+    pub time_zone: String<MAXIMAL_VALUE_SIZE>,
+
+    /// GPIO of I²C data
+    pub sda_pin: u8,
+
+    /// GPIO of I²C clock
+    pub scl_pin: u8,
+
+    /// I²C frequency in kilohertz
+    pub i2c_frequency_khz: u32,
+
+    /// Ratio of the battery voltage to the voltage at the battery pin in
+    /// thousandths, or zero without battery
+    pub battery_divider_thousandths: u32,
+
+    /// Temperature change to refresh the display in thousandths of degree
+    /// Celsius
+    pub temperature_delta_thousandths: u32,
+
+    /// Humidity change to refresh the display in thousandths of percent
+    pub humidity_delta_thousandths: u32,
+
+    /// Pressure change to refresh the display in thousandths of hectopascal
+    pub pressure_delta_thousandths: u32,
+
+    /// Model of the external real-time clock on the I²C bus, if any
+    pub external_rtc: Option<ExternalRtcModel>,
+
+    /// Time zone queried on World Time API as an IANA time zone name
+    pub world_time_zone: String<MAXIMAL_VALUE_SIZE>,
+
+    /// Whether to stay awake and sample under light sleep instead of entering
+    /// deep sleep, only supported by the Crussant firmware
+    pub always_on: bool,
+
+    /// Board of the defaults and valid pins
+    board: PhantomData<B>,
+}
+
+impl<B: Board> Default for Config<B> {
+    fn default() -> Self {
+        let mut time_zone = String::new();
+        // The default time zone is shorter than a value
+        let _ = time_zone.push_str(B::TIME_ZONE);
+        let mut world_time_zone = String::new();
+        let _ = world_time_zone.push_str(WORLD_TIME_ZONE);
+
+        Self {
+            sampling_period: SAMPLING_PERIOD,
+            deep_sleep_duration: DEEP_SLEEP_DURATION,
+            awake_period: B::AWAKE_PERIOD,
+            time_zone,
+            sda_pin: B::SDA_PIN,
+            scl_pin: B::SCL_PIN,
+            i2c_frequency_khz: B::I2C_FREQUENCY_KHZ,
+            battery_divider_thousandths: BATTERY_DIVIDER_THOUSANDTHS,
+            temperature_delta_thousandths: TEMPERATURE_DELTA_THOUSANDTHS,
+            humidity_delta_thousandths: HUMIDITY_DELTA_THOUSANDTHS,
+            pressure_delta_thousandths: PRESSURE_DELTA_THOUSANDTHS,
+            external_rtc: None,
+            world_time_zone,
+            always_on: false,
+            board: PhantomData,
+        }
+    }
+}
+
+impl<B: Board> Config<B> {
+    /// Load the configuration from a store
+    ///
+    /// Missing and invalid values are replaced by defaults.
+    /// Stored I²C pins that cannot be assigned on the board, for example after
+    /// a newer firmware reserved them, are moved to free pins and saved.
+    pub fn load<F: NorFlash>(store: &mut Store<F>) -> Result<Self, Error> {
+        let mut config = Self::default();
+        let mut buffer = [0_u8; MAXIMAL_VALUE_SIZE];
+
+        match store.fetch(Key::SchemaVersion as u16, &mut buffer)? {
+            None => {
+                debug!("No stored configuration, use defaults");
+                return Ok(config);
+            }
+            Some([SCHEMA_VERSION]) => {}
+            Some(version) => {
+                warn!("Ignore configuration with unknown schema version {version:?}");
+                return Ok(config);
+            }
+        }
+
+        let mut move_sda_pin = false;
+        let mut move_scl_pin = false;
+        for key in Key::FIELDS {
+            if let Some(value) = store.fetch(key as u16, &mut buffer)? {
+                match config.decode(key, value) {
+                    Ok(()) => {}
+                    Err(Error::InvalidValue(Key::SdaPin)) => {
+                        warn!("Stored sda_pin {value:?} cannot be assigned to I²C on this board");
+                        move_sda_pin = true;
+                    }
+                    Err(Error::InvalidValue(Key::SclPin)) => {
+                        warn!("Stored scl_pin {value:?} cannot be assigned to I²C on this board");
+                        move_scl_pin = true;
+                    }
+                    Err(error) => warn!("Ignore stored value of {key:?}: {error:?}"),
+                }
+            }
+        }
+
+        if move_sda_pin {
+            config.sda_pin = Self::move_pin(store, Key::SdaPin, B::SDA_PIN, config.scl_pin);
+        }
+        if move_scl_pin {
+            config.scl_pin = Self::move_pin(store, Key::SclPin, B::SCL_PIN, config.sda_pin);
+        }
+
+        if config.sda_pin == config.scl_pin {
+            warn!("Ignore stored I²C pins, both are GPIO{}", config.sda_pin);
+            config.sda_pin = B::SDA_PIN;
+            config.scl_pin = B::SCL_PIN;
+        }
+
+        Ok(config)
+    }
+
+    /// Move a stored pin that cannot be assigned to I²C and save the new pin
+    ///
+    /// The pin moves to its default, or to the first free I²C pin if the
+    /// default is taken by the other pin.
+    fn move_pin<F: NorFlash>(store: &mut Store<F>, key: Key, default: u8, other: u8) -> u8 {
+        let pin = if default == other {
+            B::I2C_PINS
+                .iter()
+                .copied()
+                .find(|&pin| pin != other)
+                .unwrap_or(default)
+        } else {
+            default
+        };
+        warn!("Move {} to GPIO{pin}", key.name());
+        if let Err(error) = store.store(key as u16, &[pin]) {
+            warn!("Cannot save moved {}: {error:?}", key.name());
+        }
+        pin
+    }
+
+    /// Save the configuration to a store
+    ///
+    /// Only changed values are written.
+    pub fn save<F: NorFlash>(&self, store: &mut Store<F>) -> Result<(), Error> {
+        store.store(Key::SchemaVersion as u16, &[SCHEMA_VERSION])?;
+        let mut buffer = [0_u8; MAXIMAL_VALUE_SIZE];
+        for key in Key::FIELDS {
+            let length = self.encode(key, &mut buffer);
+            store.store(key as u16, buffer.get(..length).unwrap_or_default())?;
+        }
+        Ok(())
+    }
+
+    /// Write the value of a field as text
+    pub fn write_value(&self, key: Key, output: &mut impl Write) -> Result<(), FmtError> {
+        match key {
+            Key::SchemaVersion => write!(output, "{SCHEMA_VERSION}"),
+            Key::SamplingPeriod => write!(output, "{}", self.sampling_period.as_secs()),
+            Key::DeepSleepDuration => write!(output, "{}", self.deep_sleep_duration.as_secs()),
+            Key::AwakePeriod => write!(output, "{}", self.awake_period.as_secs()),
+            Key::TimeZone => output.write_str(&self.time_zone),
+            Key::SdaPin => write!(output, "{}", self.sda_pin),
+            Key::SclPin => write!(output, "{}", self.scl_pin),
+            Key::I2cFrequency => write!(output, "{}", self.i2c_frequency_khz),
+            Key::BatteryDivider => write_thousandths(output, self.battery_divider_thousandths),
+            Key::TemperatureDelta => write_thousandths(output, self.temperature_delta_thousandths),
+            Key::HumidityDelta => write_thousandths(output, self.humidity_delta_thousandths),
+            Key::PressureDelta => write_thousandths(output, self.pressure_delta_thousandths),
+            Key::ExternalRtc => output.write_str(
+                self.external_rtc
+                    .map_or(NO_EXTERNAL_RTC, ExternalRtcModel::name),
+            ),
+            Key::WorldTimeZone => output.write_str(&self.world_time_zone),
+            Key::AlwaysOn => write!(output, "{}", self.always_on),
+        }
+    }
+
+    /// Set the value of a field from text
+    ///
+    /// Durations are in seconds, the I²C frequency in kilohertz, and the
+    /// battery divider ratio and the display refresh deltas decimal numbers
+    /// with up to three decimals, the external RTC model a model name or
+    /// `none`, the World Time API time zone an IANA time zone name such as
+    /// `Europe/Copenhagen`, and whether to stay always on `true` or `false`.
+    /// The configuration is left unchanged if the value is invalid.
+    pub fn set(&mut self, key: Key, text: &str) -> Result<(), Error> {
+        let invalid = Error::InvalidValue(key);
+        let integer = || text.parse::<u32>().map_err(|_| invalid);
+        let buffer: [u8; 4];
+        let value: &[u8] = match key {
+            Key::SchemaVersion => return Err(invalid),
+            Key::SamplingPeriod | Key::DeepSleepDuration | Key::AwakePeriod | Key::I2cFrequency => {
+                buffer = integer()?.to_le_bytes();
+                &buffer
+            }
+            Key::SdaPin | Key::SclPin => {
+                let pin = u8::try_from(integer()?).map_err(|_| invalid)?;
+                buffer = [pin, 0, 0, 0];
+                buffer.get(..1).unwrap_or_default()
+            }
+            Key::BatteryDivider
+            | Key::TemperatureDelta
+            | Key::HumidityDelta
+            | Key::PressureDelta => {
+                buffer = parse_thousandths(text).ok_or(invalid)?.to_le_bytes();
+                &buffer
+            }
+            Key::TimeZone | Key::WorldTimeZone => text.as_bytes(),
+            Key::ExternalRtc => {
+                let model = if text == NO_EXTERNAL_RTC {
+                    None
+                } else {
+                    Some(ExternalRtcModel::from_name(text).ok_or(invalid)?)
+                };
+                buffer = [encode_external_rtc(model), 0, 0, 0];
+                buffer.get(..1).unwrap_or_default()
+            }
+            Key::AlwaysOn => {
+                let always_on = text.parse::<bool>().map_err(|_| invalid)?;
+                buffer = [u8::from(always_on), 0, 0, 0];
+                buffer.get(..1).unwrap_or_default()
+            }
+        };
+
+        let mut config = self.clone();
+        config.decode(key, value)?;
+        if config.sda_pin == config.scl_pin {
+            return Err(invalid);
+        }
+        *self = config;
+        Ok(())
+    }
+
+    /// Encode the value of a field
+    ///
+    /// Return the number of bytes written.
+    fn encode(&self, key: Key, output: &mut [u8; MAXIMAL_VALUE_SIZE]) -> usize {
+        let bytes: &[u8] = match key {
+            Key::SchemaVersion => &[SCHEMA_VERSION],
+            Key::SamplingPeriod => &seconds(self.sampling_period).to_le_bytes(),
+            Key::DeepSleepDuration => &seconds(self.deep_sleep_duration).to_le_bytes(),
+            Key::AwakePeriod => &seconds(self.awake_period).to_le_bytes(),
+            Key::TimeZone => self.time_zone.as_bytes(),
+            Key::SdaPin => &[self.sda_pin],
+            Key::SclPin => &[self.scl_pin],
+            Key::I2cFrequency => &self.i2c_frequency_khz.to_le_bytes(),
+            Key::BatteryDivider => &self.battery_divider_thousandths.to_le_bytes(),
+            Key::TemperatureDelta => &self.temperature_delta_thousandths.to_le_bytes(),
+            Key::HumidityDelta => &self.humidity_delta_thousandths.to_le_bytes(),
+            Key::PressureDelta => &self.pressure_delta_thousandths.to_le_bytes(),
+            Key::ExternalRtc => &[encode_external_rtc(self.external_rtc)],
+            Key::WorldTimeZone => self.world_time_zone.as_bytes(),
+            Key::AlwaysOn => &[u8::from(self.always_on)],
+        };
+        let length = bytes.len().min(output.len());
+        output
+            .get_mut(..length)
+            .unwrap_or_default()
+            .copy_from_slice(bytes.get(..length).unwrap_or_default());
+        length
+    }
+
+    /// Decode and validate the value of a field
+    fn decode(&mut self, key: Key, value: &[u8]) -> Result<(), Error> {
+        let invalid = Error::InvalidValue(key);
+        match key {
+            Key::SchemaVersion => {}
+            Key::SamplingPeriod => self.sampling_period = decode_duration(value, invalid)?,
+            Key::DeepSleepDuration => {
+                self.deep_sleep_duration = decode_duration(value, invalid)?;
+            }
+            Key::AwakePeriod => self.awake_period = decode_duration(value, invalid)?,
+            Key::TimeZone => {
+                let text = core::str::from_utf8(value).map_err(|_| invalid)?;
+                TimeZone::parse(text).map_err(|_| invalid)?;
+                self.time_zone = String::try_from(text).map_err(|()| invalid)?;
+            }
+            Key::SdaPin => self.sda_pin = decode_pin(value, B::I2C_PINS, invalid)?,
+            Key::SclPin => self.scl_pin = decode_pin(value, B::I2C_PINS, invalid)?,
+            Key::I2cFrequency => {
+                let frequency = u32::from_le_bytes(value.try_into().map_err(|_| invalid)?);
+                if !I2C_FREQUENCIES_KHZ.contains(&frequency) {
+                    return Err(invalid);
+                }
+                self.i2c_frequency_khz = frequency;
+            }
+            Key::BatteryDivider => {
+                let divider = u32::from_le_bytes(value.try_into().map_err(|_| invalid)?);
+                if divider != 0 && !BATTERY_DIVIDERS_THOUSANDTHS.contains(&divider) {
+                    return Err(invalid);
+                }
+                self.battery_divider_thousandths = divider;
+            }
+            Key::TemperatureDelta => {
+                self.temperature_delta_thousandths = decode_delta(value, invalid)?;
+            }
+            Key::HumidityDelta => self.humidity_delta_thousandths = decode_delta(value, invalid)?,
+            Key::PressureDelta => self.pressure_delta_thousandths = decode_delta(value, invalid)?,
+            Key::ExternalRtc => {
+                self.external_rtc = match value {
+                    [0] => None,
+                    [1] => Some(ExternalRtcModel::Ds3231),
+                    [2] => Some(ExternalRtcModel::Pcf8563),
+                    _ => return Err(invalid),
+                };
+            }
+            Key::WorldTimeZone => {
+                let text = core::str::from_utf8(value).map_err(|_| invalid)?;
+                if !is_time_zone_name(text) {
+                    return Err(invalid);
+                }
+                self.world_time_zone = String::try_from(text).map_err(|()| invalid)?;
+            }
+            Key::AlwaysOn => {
+                self.always_on = match value {
+                    [0] => false,
+                    [1] => true,
+                    _ => return Err(invalid),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Convert a duration to whole seconds, saturating
+fn seconds(duration: Duration) -> u32 {
+    u32::try_from(duration.as_secs()).unwrap_or(u32::MAX)
+}
+
+/// Parse a decimal number with up to three decimals as thousandths
+fn parse_thousandths(text: &str) -> Option<u32> {
+    let (integer, decimals) = text.split_once('.').unwrap_or((text, ""));
+    if integer.is_empty() || decimals.len() > 3 {
+        return None;
+    }
+    if !integer
+        .bytes()
+        .chain(decimals.bytes())
+        .all(|byte| byte.is_ascii_digit())
+    {
+        return None;
+    }
+    let integer = integer.parse::<u32>().ok()?;
+    let mut thousandths = 0;
+    for position in 0..3 {
+        let digit = decimals
+            .as_bytes()
+            .get(position)
+            .map_or(0, |byte| byte - b'0');
+        thousandths = thousandths * 10 + u32::from(digit);
+    }
+    integer.checked_mul(1000)?.checked_add(thousandths)
+}
+
+/// Write thousandths as a decimal number with three decimals
+fn write_thousandths(output: &mut impl Write, thousandths: u32) -> Result<(), FmtError> {
+    write!(output, "{}.{:03}", thousandths / 1000, thousandths % 1000)
+}
+
+/// Decode a display refresh delta in thousandths
+fn decode_delta(value: &[u8], invalid: Error) -> Result<u32, Error> {
+    let delta = u32::from_le_bytes(value.try_into().map_err(|_| invalid)?);
+    if !DELTAS_THOUSANDTHS.contains(&delta) {
+        return Err(invalid);
+    }
+    Ok(delta)
+}
+
+/// Decode a non-zero duration in seconds
+fn decode_duration(value: &[u8], invalid: Error) -> Result<Duration, Error> {
+    let seconds = u32::from_le_bytes(value.try_into().map_err(|_| invalid)?);
+    if seconds == 0 {
+        return Err(invalid);
+    }
+    Ok(Duration::from_secs(u64::from(seconds)))
+}
+
+/// Encode an external RTC model as a byte
+fn encode_external_rtc(model: Option<ExternalRtcModel>) -> u8 {
+    match model {
+        None => 0,
+        Some(ExternalRtcModel::Ds3231) => 1,
+        Some(ExternalRtcModel::Pcf8563) => 2,
+    }
+}
+
+/// Check that a text looks like an IANA time zone name
+///
+/// Only the characters found in IANA time zone names are accepted, which are
+/// also safe in a URL path, and names cannot escape the time zone endpoint.
+fn is_time_zone_name(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'))
+        && text.split('/').all(|part| !part.is_empty())
+}
+
+/// Decode a GPIO that can be assigned to the I²C bus
+fn decode_pin(value: &[u8], pins: &[u8], invalid: Error) -> Result<u8, Error> {
+    match value {
+        [pin] if pins.contains(pin) => Ok(*pin),
+        _ => Err(invalid),
+    }
+}
+
+/// An error within configuration operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A stored value is invalid
+    InvalidValue(#[allow(unused)] Key),
+
+    /// Error from the key-value store
+    Store(#[allow(unused)] StoreError),
+}
+
+impl From<StoreError> for Error {
+    fn from(error: StoreError) -> Self {
+        Self::Store(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::kv_store::tests::Flash;
+    use crate::kv_store::tests::RANGE;
+
+    /// Board of the tests, where GPIO0 cannot be assigned to I²C
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct TestBoard;
+
+    impl Board for TestBoard {
+        const AWAKE_PERIOD: Duration = Duration::from_secs(300);
+        const TIME_ZONE: &'static str = "CET-1CEST,M3.5.0,M10.5.0/3";
+        const SDA_PIN: u8 = 1;
+        const SCL_PIN: u8 = 2;
+        const I2C_FREQUENCY_KHZ: u32 = 25;
+        const I2C_PINS: &'static [u8] = &[1, 2, 4, 5];
+    }
+
+    type TestConfig = Config<TestBoard>;
+
+    /// Store raw values with the current schema version
+    fn store_values(flash: &mut Flash, values: &[(Key, &[u8])]) {
+        let mut store = Store::mount(flash, RANGE).unwrap();
+        store
+            .store(Key::SchemaVersion as u16, &[SCHEMA_VERSION])
+            .unwrap();
+        for &(key, value) in values {
+            store.store(key as u16, value).unwrap();
+        }
+    }
+
+    /// Load the configuration and the stored I²C pins
+    fn load(flash: &mut Flash) -> (TestConfig, std::vec::Vec<u8>) {
+        let mut store = Store::mount(flash, RANGE).unwrap();
+        let config = TestConfig::load(&mut store).unwrap();
+        let mut stored_pins = std::vec::Vec::new();
+        for key in [Key::SdaPin, Key::SclPin] {
+            let mut buffer = [0_u8; MAXIMAL_VALUE_SIZE];
+            if let Some(value) = store.fetch(key as u16, &mut buffer).unwrap() {
+                stored_pins.extend_from_slice(value);
+            }
+        }
+        (config, stored_pins)
+    }
+
+    #[test]
+    fn blank_store_yields_defaults() {
+        let mut flash = Flash::new(RANGE);
+        let (config, stored_pins) = load(&mut flash);
+        assert_eq!(config, TestConfig::default());
+        assert!(stored_pins.is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut flash = Flash::new(RANGE);
+        let mut config = TestConfig::default();
+        config.set(Key::SamplingPeriod, "30").unwrap();
+        config.set(Key::SdaPin, "4").unwrap();
+        config.set(Key::SclPin, "5").unwrap();
+        config.set(Key::BatteryDivider, "2.5").unwrap();
+        config
+            .save(&mut Store::mount(&mut flash, RANGE).unwrap())
+            .unwrap();
+
+        let (loaded, stored_pins) = load(&mut flash);
+        assert_eq!(loaded, config);
+        assert_eq!(stored_pins, [4, 5]);
+    }
+
+    #[test]
+    fn ignore_unknown_schema_version() {
+        let mut flash = Flash::new(RANGE);
+        store_values(&mut flash, &[(Key::SamplingPeriod, &30_u32.to_le_bytes())]);
+        Store::mount(&mut flash, RANGE)
+            .unwrap()
+            .store(Key::SchemaVersion as u16, &[SCHEMA_VERSION + 1])
+            .unwrap();
+
+        let (config, _) = load(&mut flash);
+        assert_eq!(config, TestConfig::default());
+    }
+
+    #[test]
+    fn ignore_invalid_values() {
+        let mut flash = Flash::new(RANGE);
+        store_values(
+            &mut flash,
+            &[
+                (Key::SamplingPeriod, &0_u32.to_le_bytes()),
+                (Key::DeepSleepDuration, &600_u32.to_le_bytes()),
+                (Key::I2cFrequency, &1_000_u32.to_le_bytes()),
+            ],
+        );
+
+        let (config, _) = load(&mut flash);
+        assert_eq!(config.sampling_period, SAMPLING_PERIOD);
+        assert_eq!(config.deep_sleep_duration, Duration::from_secs(600));
+        assert_eq!(config.i2c_frequency_khz, TestBoard::I2C_FREQUENCY_KHZ);
+    }
+
+    #[test]
+    fn move_unassignable_pin_to_default() {
+        let mut flash = Flash::new(RANGE);
+        store_values(
+            &mut flash,
+            &[
+                (Key::SamplingPeriod, &30_u32.to_le_bytes()),
+                (Key::SdaPin, &[0]),
+                (Key::SclPin, &[4]),
+            ],
+        );
+
+        let (config, stored_pins) = load(&mut flash);
+        assert_eq!(config.sampling_period, Duration::from_secs(30));
+        assert_eq!((config.sda_pin, config.scl_pin), (1, 4));
+        assert_eq!(stored_pins, [1, 4]);
+    }
+
+    #[test]
+    fn move_unassignable_pin_to_free_pin() {
+        let mut flash = Flash::new(RANGE);
+        store_values(&mut flash, &[(Key::SdaPin, &[4]), (Key::SclPin, &[0])]);
+        let (config, stored_pins) = load(&mut flash);
+        assert_eq!((config.sda_pin, config.scl_pin), (4, 2));
+        assert_eq!(stored_pins, [4, 2]);
+
+        // The default of the moved pin is taken by the other pin
+        let mut flash = Flash::new(RANGE);
+        store_values(&mut flash, &[(Key::SdaPin, &[0]), (Key::SclPin, &[1])]);
+        let (config, stored_pins) = load(&mut flash);
+        assert_eq!((config.sda_pin, config.scl_pin), (2, 1));
+        assert_eq!(stored_pins, [2, 1]);
+    }
+
+    #[test]
+    fn move_both_unassignable_pins() {
+        let mut flash = Flash::new(RANGE);
+        store_values(&mut flash, &[(Key::SdaPin, &[0]), (Key::SclPin, &[3])]);
+        let (config, stored_pins) = load(&mut flash);
+        assert_eq!((config.sda_pin, config.scl_pin), (1, 2));
+        assert_eq!(stored_pins, [1, 2]);
+
+        // Moved pins load unchanged afterwards
+        let (reloaded, _) = load(&mut flash);
+        assert_eq!(reloaded, config);
+    }
+
+    #[test]
+    fn reset_identical_pins() {
+        let mut flash = Flash::new(RANGE);
+        store_values(&mut flash, &[(Key::SdaPin, &[4]), (Key::SclPin, &[4])]);
+        let (config, _) = load(&mut flash);
+        assert_eq!((config.sda_pin, config.scl_pin), (1, 2));
+    }
+
+    #[test]
+    fn external_rtc_model() {
+        let mut config = TestConfig::default();
+        let mut text = String::<16>::new();
+        config.write_value(Key::ExternalRtc, &mut text).unwrap();
+        assert_eq!(text, "none");
+
+        config.set(Key::ExternalRtc, "pcf8563").unwrap();
+        assert_eq!(config.external_rtc, Some(ExternalRtcModel::Pcf8563));
+        text.clear();
+        config.write_value(Key::ExternalRtc, &mut text).unwrap();
+        assert_eq!(text, "pcf8563");
+
+        assert_eq!(
+            config.set(Key::ExternalRtc, "ds1307"),
+            Err(Error::InvalidValue(Key::ExternalRtc))
+        );
+        assert_eq!(config.external_rtc, Some(ExternalRtcModel::Pcf8563));
+
+        config.set(Key::ExternalRtc, "none").unwrap();
+        assert_eq!(config.external_rtc, None);
+    }
+
+    #[test]
+    fn external_rtc_model_is_stored() {
+        let mut flash = Flash::new(RANGE);
+        let mut config = TestConfig::default();
+        config.set(Key::ExternalRtc, "ds3231").unwrap();
+        config
+            .save(&mut Store::mount(&mut flash, RANGE).unwrap())
+            .unwrap();
+        let (loaded, _) = load(&mut flash);
+        assert_eq!(loaded.external_rtc, Some(ExternalRtcModel::Ds3231));
+
+        let mut flash = Flash::new(RANGE);
+        store_values(&mut flash, &[(Key::ExternalRtc, &[3])]);
+        let (loaded, _) = load(&mut flash);
+        assert_eq!(loaded.external_rtc, None);
+    }
+
+    #[test]
+    fn world_time_zone() {
+        let mut config = TestConfig::default();
+        assert_eq!(config.world_time_zone, "Europe/Copenhagen");
+
+        config
+            .set(Key::WorldTimeZone, "America/Argentina/Buenos_Aires")
+            .unwrap();
+        let mut text = String::<32>::new();
+        config.write_value(Key::WorldTimeZone, &mut text).unwrap();
+        assert_eq!(text, "America/Argentina/Buenos_Aires");
+
+        config.set(Key::WorldTimeZone, "Etc/GMT+5").unwrap();
+        for invalid in [
+            "",
+            "Europe/Paris?x=1",
+            "../Europe",
+            "/Europe",
+            "Europe/",
+            "Europe//Paris",
+        ] {
+            assert_eq!(
+                config.set(Key::WorldTimeZone, invalid),
+                Err(Error::InvalidValue(Key::WorldTimeZone)),
+                "{invalid:?}"
+            );
+        }
+        assert_eq!(config.world_time_zone, "Etc/GMT+5");
+    }
+
+    #[test]
+    fn world_time_zone_is_stored() {
+        let mut flash = Flash::new(RANGE);
+        let mut config = TestConfig::default();
+        config.set(Key::WorldTimeZone, "Pacific/Auckland").unwrap();
+        config
+            .save(&mut Store::mount(&mut flash, RANGE).unwrap())
+            .unwrap();
+        let (loaded, _) = load(&mut flash);
+        assert_eq!(loaded.world_time_zone, "Pacific/Auckland");
+
+        let mut flash = Flash::new(RANGE);
+        store_values(&mut flash, &[(Key::WorldTimeZone, b"Europe/../etc")]);
+        let (loaded, _) = load(&mut flash);
+        assert_eq!(loaded.world_time_zone, "Europe/Copenhagen");
+    }
+
+    #[test]
+    fn always_on() {
+        let mut config = TestConfig::default();
+        assert!(!config.always_on);
+
+        config.set(Key::AlwaysOn, "true").unwrap();
+        assert!(config.always_on);
+        let mut text = String::<8>::new();
+        config.write_value(Key::AlwaysOn, &mut text).unwrap();
+        assert_eq!(text, "true");
+
+        for invalid in ["", "1", "yes", "True"] {
+            assert_eq!(
+                config.set(Key::AlwaysOn, invalid),
+                Err(Error::InvalidValue(Key::AlwaysOn)),
+                "{invalid:?}"
+            );
+        }
+        assert!(config.always_on);
+    }
+
+    #[test]
+    fn always_on_is_stored() {
+        let mut flash = Flash::new(RANGE);
+        let mut config = TestConfig::default();
+        config.set(Key::AlwaysOn, "true").unwrap();
+        config
+            .save(&mut Store::mount(&mut flash, RANGE).unwrap())
+            .unwrap();
+        let (loaded, _) = load(&mut flash);
+        assert!(loaded.always_on);
+
+        let mut flash = Flash::new(RANGE);
+        store_values(&mut flash, &[(Key::AlwaysOn, &[2])]);
+        let (loaded, _) = load(&mut flash);
+        assert!(!loaded.always_on);
+    }
+}
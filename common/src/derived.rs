@@ -0,0 +1,235 @@
+//! Quantities derived from temperature, humidity and pressure
+//!
+//! Values are plain numbers in fixed units, degrees Celsius, percent of
+//! relative humidity, hectopascal, grams per cubic meter and meters, so that
+//! this module does not depend on the version of `uom` used by the sensor
+//! drivers of each firmware.
+
+use libm::expf;
+use libm::logf;
+use libm::powf;
+use libm::sqrtf;
+
+/// Magnus coefficient `b` for water vapour over water (Sonntag 1990)
+const MAGNUS_B: f32 = 17.62;
+
+/// Magnus coefficient `c` in °C for water vapour over water (Sonntag 1990)
+const MAGNUS_C: f32 = 243.12;
+
+/// Standard sea level pressure in hPa
+const STANDARD_PRESSURE_HPA: f32 = 1013.25;
+
+/// Lowest relative humidity in percent used in logarithms, to avoid `ln(0)`
+const MINIMUM_HUMIDITY_PERCENT: f32 = 0.01;
+
+/// Thermal comfort of indoor air
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comfort {
+    /// Temperature below 18 C
+    Cold,
+    /// Temperature above 26 C
+    Hot,
+    /// Relative humidity below 30 %
+    Dry,
+    /// Relative humidity above 60 %
+    Humid,
+    /// Temperature and humidity are within the comfort zone
+    Comfortable,
+}
+
+impl Comfort {
+    /// Classify a temperature in °C and relative humidity in % pair
+    ///
+    /// Temperature takes precedence over humidity, so a cold and dry room is
+    /// classified as [`Comfort::Cold`].
+    pub fn classify(temperature_celsius: f32, humidity_percent: f32) -> Self {
+        if temperature_celsius < 18.0 {
+            Self::Cold
+        } else if temperature_celsius > 26.0 {
+            Self::Hot
+        } else if humidity_percent < 30.0 {
+            Self::Dry
+        } else if humidity_percent > 60.0 {
+            Self::Humid
+        } else {
+            Self::Comfortable
+        }
+    }
+
+    /// Short human readable label
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Cold => "Cold",
+            Self::Hot => "Hot",
+            Self::Dry => "Dry",
+            Self::Humid => "Humid",
+            Self::Comfortable => "Comfortable",
+        }
+    }
+}
+
+/// Compute the dew point in °C using the Magnus formula
+///
+/// Accurate to within 0.35 C for temperatures between -45 C and 60 C.
+pub fn dew_point(temperature_celsius: f32, humidity_percent: f32) -> f32 {
+    let humidity = humidity_percent.max(MINIMUM_HUMIDITY_PERCENT);
+
+    let gamma =
+        logf(humidity / 100.0) + MAGNUS_B * temperature_celsius / (MAGNUS_C + temperature_celsius);
+    MAGNUS_C * gamma / (MAGNUS_B - gamma)
+}
+
+/// Compute the absolute humidity in g/m³, i.e. the mass of water vapour in a
+/// volume of air
+pub fn absolute_humidity(temperature_celsius: f32, humidity_percent: f32) -> f32 {
+    // Saturation vapour pressure in hPa, scaled by relative humidity and
+    // divided by the specific gas constant of water vapour
+    let saturation =
+        6.112 * expf(MAGNUS_B * temperature_celsius / (MAGNUS_C + temperature_celsius));
+    saturation * humidity_percent * 2.1674 / (273.15 + temperature_celsius)
+}
+
+/// Compute the heat index in °C using the NOAA Rothfusz regression
+///
+/// Below roughly 27 C the simpler Steadman formula is used instead, as the
+/// regression is not valid there.
+pub fn heat_index(temperature_celsius: f32, humidity_percent: f32) -> f32 {
+    let t = celsius_to_fahrenheit(temperature_celsius);
+    let rh = humidity_percent;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < 80.0 {
+        return fahrenheit_to_celsius(simple);
+    }
+
+    let mut index = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+        - 0.224_755_4 * t * rh
+        - 0.006_837_83 * t * t
+        - 0.054_817_17 * rh * rh
+        + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh
+        - 0.000_001_99 * t * t * rh * rh;
+
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        index -= ((13.0 - rh) / 4.0) * sqrtf((17.0 - (t - 95.0).abs()) / 17.0);
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        index += ((rh - 85.0) / 10.0) * ((87.0 - t) / 5.0);
+    }
+
+    fahrenheit_to_celsius(index)
+}
+
+/// Compute the humidex in °C from temperature and dew point in °C
+pub fn humidex(temperature_celsius: f32, dew_point_celsius: f32) -> f32 {
+    let vapour_pressure =
+        6.11 * expf(5417.753 * (1.0 / 273.16 - 1.0 / (273.15 + dew_point_celsius)));
+    temperature_celsius + 0.5555 * (vapour_pressure - 10.0)
+}
+
+/// Compute the pressure altitude in m in the International Standard
+/// Atmosphere from a pressure in hPa
+pub fn pressure_altitude(pressure_hectopascal: f32) -> f32 {
+    44_330.8 * (1.0 - powf(pressure_hectopascal / STANDARD_PRESSURE_HPA, 0.190_284))
+}
+
+/// Convert a temperature from °C to °F
+fn celsius_to_fahrenheit(celsius: f32) -> f32 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+/// Convert a temperature from °F to °C
+fn fahrenheit_to_celsius(fahrenheit: f32) -> f32 {
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assert that a value is within a tolerance of an expected value
+    fn assert_close(value: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{value} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn dew_point_matches_reference_table() {
+        // Temperature in C, relative humidity in %, dew point in C
+        let table = [
+            (10.0, 100.0, 10.0),
+            (20.0, 50.0, 9.3),
+            (25.0, 60.0, 16.7),
+            (30.0, 80.0, 26.2),
+            (0.0, 50.0, -9.2),
+        ];
+        for (temperature, humidity, expected) in table {
+            assert_close(dew_point(temperature, humidity), expected, 0.2);
+        }
+    }
+
+    #[test]
+    fn dew_point_of_dry_air_is_finite() {
+        assert!(dew_point(20.0, 0.0).is_finite());
+    }
+
+    #[test]
+    fn heat_index_matches_noaa_table() {
+        // Temperature in F, relative humidity in %, heat index in F
+        let table = [
+            (80.0, 40.0, 80.0),
+            (90.0, 50.0, 95.0),
+            (96.0, 65.0, 121.0),
+            (100.0, 40.0, 109.0),
+            (104.0, 55.0, 137.0),
+        ];
+        for (temperature, humidity, expected) in table {
+            let index = heat_index(fahrenheit_to_celsius(temperature), humidity);
+            assert_close(celsius_to_fahrenheit(index), expected, 1.0);
+        }
+    }
+
+    #[test]
+    fn heat_index_of_mild_air_is_close_to_temperature() {
+        assert_close(heat_index(20.0, 50.0), 20.0, 1.0);
+    }
+
+    #[test]
+    fn absolute_humidity_matches_reference_table() {
+        // Temperature in C, relative humidity in %, absolute humidity in g/m³
+        let table = [
+            (0.0, 100.0, 4.85),
+            (10.0, 100.0, 9.40),
+            (20.0, 50.0, 8.65),
+            (25.0, 100.0, 23.0),
+            (30.0, 80.0, 24.3),
+        ];
+        for (temperature, humidity, expected) in table {
+            assert_close(absolute_humidity(temperature, humidity), expected, 0.1);
+        }
+    }
+
+    #[test]
+    fn humidex_matches_environment_canada_example() {
+        assert_close(humidex(30.0, 15.0), 34.0, 0.5);
+    }
+
+    #[test]
+    fn pressure_altitude_matches_standard_atmosphere() {
+        // Pressure in hPa, altitude in m
+        let table = [(1013.25, 0.0), (898.76, 1000.0), (795.01, 2000.0)];
+        for (pressure, expected) in table {
+            assert_close(pressure_altitude(pressure), expected, 2.0);
+        }
+    }
+
+    #[test]
+    fn comfort_prefers_temperature_over_humidity() {
+        assert_eq!(Comfort::classify(15.0, 20.0), Comfort::Cold);
+        assert_eq!(Comfort::classify(28.0, 70.0), Comfort::Hot);
+        assert_eq!(Comfort::classify(22.0, 20.0), Comfort::Dry);
+        assert_eq!(Comfort::classify(22.0, 70.0), Comfort::Humid);
+        assert_eq!(Comfort::classify(22.0, 45.0), Comfort::Comfortable);
+    }
+}
@@ -0,0 +1,641 @@
+//! Drivers for battery-backed external real-time clocks
+//!
+//! An external RTC keeps time across power loss, when both RTC Fast memory
+//! and the network might be unavailable.
+//! Time is stored in UTC, and the century is assumed to be 2000.
+//!
+//! Supported chips are the DS3231 and the PCF8563, both on I²C.
+//! Temperatures are plain numbers in degrees Celsius, so that this module
+//! does not depend on the version of `uom` used by each firmware.
+
+use embedded_hal_async::i2c::Error as _;
+use embedded_hal_async::i2c::ErrorKind as I2cErrorKind;
+use embedded_hal_async::i2c::I2c;
+
+use time::error::ComponentRange as TimeComponentRangeError;
+use time::Date;
+use time::Month;
+use time::OffsetDateTime;
+use time::PrimitiveDateTime;
+use time::Time;
+use time::UtcOffset;
+
+/// I²C address of DS3231
+const DS3231_ADDRESS: u8 = 0x68;
+
+/// DS3231 register of seconds, first of the time registers
+const DS3231_TIME_REGISTER: u8 = 0x00;
+
+/// DS3231 status register
+const DS3231_STATUS_REGISTER: u8 = 0x0f;
+
+/// DS3231 register of the temperature integer part
+const DS3231_TEMPERATURE_REGISTER: u8 = 0x11;
+
+/// DS3231 oscillator stop flag, set when time was lost
+const DS3231_OSCILLATOR_STOP_FLAG: u8 = 0b1000_0000;
+
+/// I²C address of PCF8563
+const PCF8563_ADDRESS: u8 = 0x51;
+
+/// PCF8563 register of seconds, first of the time registers
+const PCF8563_TIME_REGISTER: u8 = 0x02;
+
+/// PCF8563 voltage low flag in the seconds register, set when time was lost
+const PCF8563_VOLTAGE_LOW_FLAG: u8 = 0b1000_0000;
+
+/// First year representable by the external RTCs
+const BASE_YEAR: i32 = 2000;
+
+/// A real-time clock
+///
+/// The futures are not `Send`, as the firmwares run on a single thread.
+#[allow(async_fn_in_trait)]
+pub trait Rtc {
+    /// Read the current time in UTC
+    async fn read_time(&mut self) -> Result<OffsetDateTime, Error>;
+
+    /// Set the current time
+    async fn write_time(&mut self, now: OffsetDateTime) -> Result<(), Error>;
+
+    /// Read the chip temperature in °C, if the chip has a temperature sensor
+    async fn read_temperature(&mut self) -> Result<Option<f32>, Error>;
+}
+
+/// A supported external RTC model
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    /// Maxim DS3231, with temperature compensated oscillator
+    Ds3231,
+
+    /// NXP PCF8563
+    Pcf8563,
+}
+
+impl Model {
+    /// All supported models
+    pub const ALL: [Self; 2] = [Self::Ds3231, Self::Pcf8563];
+
+    /// Return the name of the model, as used in the configuration
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ds3231 => "ds3231",
+            Self::Pcf8563 => "pcf8563",
+        }
+    }
+
+    /// Find a model by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|model| model.name() == name)
+    }
+}
+
+/// An external RTC of any supported model
+#[derive(Debug)]
+pub enum ExternalRtc<I2C> {
+    /// A DS3231
+    Ds3231(Ds3231<I2C>),
+
+    /// A PCF8563
+    Pcf8563(Pcf8563<I2C>),
+}
+
+impl<I2C> ExternalRtc<I2C> {
+    /// Create a driver for an external RTC model
+    pub fn new(model: Model, i2c: I2C) -> Self {
+        match model {
+            Model::Ds3231 => Self::Ds3231(Ds3231 { i2c }),
+            Model::Pcf8563 => Self::Pcf8563(Pcf8563 { i2c }),
+        }
+    }
+}
+
+impl<I2C> Rtc for ExternalRtc<I2C>
+where
+    I2C: I2c,
+{
+    async fn read_time(&mut self) -> Result<OffsetDateTime, Error> {
+        match self {
+            Self::Ds3231(rtc) => rtc.read_time().await,
+            Self::Pcf8563(rtc) => rtc.read_time().await,
+        }
+    }
+
+    async fn write_time(&mut self, now: OffsetDateTime) -> Result<(), Error> {
+        match self {
+            Self::Ds3231(rtc) => rtc.write_time(now).await,
+            Self::Pcf8563(rtc) => rtc.write_time(now).await,
+        }
+    }
+
+    async fn read_temperature(&mut self) -> Result<Option<f32>, Error> {
+        match self {
+            Self::Ds3231(rtc) => rtc.read_temperature().await,
+            Self::Pcf8563(rtc) => rtc.read_temperature().await,
+        }
+    }
+}
+
+/// A DS3231 driver
+#[derive(Debug)]
+pub struct Ds3231<I2C> {
+    /// I²C device
+    i2c: I2C,
+}
+
+impl<I2C> Rtc for Ds3231<I2C>
+where
+    I2C: I2c,
+{
+    async fn read_time(&mut self) -> Result<OffsetDateTime, Error> {
+        let mut status = [0_u8; 1];
+        self.i2c
+            .write_read(DS3231_ADDRESS, &[DS3231_STATUS_REGISTER], &mut status)
+            .await
+            .map_err(|error| Error::I2c(error.kind()))?;
+        let [status] = status;
+        if status & DS3231_OSCILLATOR_STOP_FLAG != 0 {
+            return Err(Error::TimeLost);
+        }
+
+        let mut registers = [0_u8; 7];
+        self.i2c
+            .write_read(DS3231_ADDRESS, &[DS3231_TIME_REGISTER], &mut registers)
+            .await
+            .map_err(|error| Error::I2c(error.kind()))?;
+
+        let [seconds, minutes, hours, _weekday, day, month, year] = registers;
+        decode_time(
+            seconds,
+            minutes,
+            decode_hours(hours),
+            day,
+            month & 0b0001_1111,
+            year,
+        )
+    }
+
+    async fn write_time(&mut self, now: OffsetDateTime) -> Result<(), Error> {
+        let [seconds, minutes, hours, weekday, day, month, year] = encode_time(now)?;
+        self.i2c
+            .write(
+                DS3231_ADDRESS,
+                &[
+                    DS3231_TIME_REGISTER,
+                    seconds,
+                    minutes,
+                    hours,
+                    weekday + 1,
+                    day,
+                    month,
+                    year,
+                ],
+            )
+            .await
+            .map_err(|error| Error::I2c(error.kind()))?;
+
+        // Clear the oscillator stop flag, the time is valid again
+        self.i2c
+            .write(DS3231_ADDRESS, &[DS3231_STATUS_REGISTER, 0])
+            .await
+            .map_err(|error| Error::I2c(error.kind()))?;
+
+        Ok(())
+    }
+
+    async fn read_temperature(&mut self) -> Result<Option<f32>, Error> {
+        let mut registers = [0_u8; 2];
+        self.i2c
+            .write_read(
+                DS3231_ADDRESS,
+                &[DS3231_TEMPERATURE_REGISTER],
+                &mut registers,
+            )
+            .await
+            .map_err(|error| Error::I2c(error.kind()))?;
+
+        // Temperature is a 10-bit two's complement value in quarters of a
+        // degree, left-aligned in two registers
+        let raw = i16::from_be_bytes(registers) >> 6;
+
+        Ok(Some(f32::from(raw) * 0.25))
+    }
+}
+
+/// A PCF8563 driver
+#[derive(Debug)]
+pub struct Pcf8563<I2C> {
+    /// I²C device
+    i2c: I2C,
+}
+
+impl<I2C> Rtc for Pcf8563<I2C>
+where
+    I2C: I2c,
+{
+    async fn read_time(&mut self) -> Result<OffsetDateTime, Error> {
+        let mut registers = [0_u8; 7];
+        self.i2c
+            .write_read(PCF8563_ADDRESS, &[PCF8563_TIME_REGISTER], &mut registers)
+            .await
+            .map_err(|error| Error::I2c(error.kind()))?;
+
+        let [seconds, minutes, hours, day, _weekday, month, year] = registers;
+        if seconds & PCF8563_VOLTAGE_LOW_FLAG != 0 {
+            return Err(Error::TimeLost);
+        }
+
+        decode_time(
+            seconds & 0b0111_1111,
+            minutes & 0b0111_1111,
+            from_bcd(hours & 0b0011_1111),
+            day & 0b0011_1111,
+            month & 0b0001_1111,
+            year,
+        )
+    }
+
+    async fn write_time(&mut self, now: OffsetDateTime) -> Result<(), Error> {
+        // Writing the seconds register also clears the voltage low flag
+        let [seconds, minutes, hours, weekday, day, month, year] = encode_time(now)?;
+        self.i2c
+            .write(
+                PCF8563_ADDRESS,
+                &[
+                    PCF8563_TIME_REGISTER,
+                    seconds,
+                    minutes,
+                    hours,
+                    day,
+                    weekday,
+                    month,
+                    year,
+                ],
+            )
+            .await
+            .map_err(|error| Error::I2c(error.kind()))?;
+
+        Ok(())
+    }
+
+    async fn read_temperature(&mut self) -> Result<Option<f32>, Error> {
+        Ok(None)
+    }
+}
+
+/// Decode the DS3231 hours register, in either 12 or 24 hours mode
+fn decode_hours(hours: u8) -> u8 {
+    if hours & 0b0100_0000 == 0 {
+        from_bcd(hours & 0b0011_1111)
+    } else {
+        let is_afternoon = hours & 0b0010_0000 != 0;
+        let hours = from_bcd(hours & 0b0001_1111) % 12;
+        if is_afternoon {
+            hours + 12
+        } else {
+            hours
+        }
+    }
+}
+
+/// Decode time registers in BCD, except for the already decoded hours
+fn decode_time(
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+) -> Result<OffsetDateTime, Error> {
+    let month = Month::try_from(from_bcd(month))?;
+    let year = BASE_YEAR + i32::from(from_bcd(year));
+    let date = Date::from_calendar_date(year, month, from_bcd(day))?;
+    let time = Time::from_hms(hours, from_bcd(minutes), from_bcd(seconds))?;
+    Ok(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+/// Encode a time to registers in BCD
+///
+/// Registers are seconds, minutes, hours, weekday (0 for Sunday), day, month
+/// and year.
+fn encode_time(now: OffsetDateTime) -> Result<[u8; 7], Error> {
+    let now = now.to_offset(UtcOffset::UTC);
+    let year = u8::try_from(now.year() - BASE_YEAR)
+        .ok()
+        .filter(|year| *year < 100)
+        .ok_or(Error::YearOutOfRange)?;
+
+    Ok([
+        to_bcd(now.second()),
+        to_bcd(now.minute()),
+        to_bcd(now.hour()),
+        now.weekday().number_days_from_sunday(),
+        to_bcd(now.day()),
+        to_bcd(u8::from(now.month())),
+        to_bcd(year),
+    ])
+}
+
+/// Convert a binary-coded decimal to binary
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Convert a binary value below 100 to binary-coded decimal
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// An error within external RTC operations
+#[derive(Debug)]
+pub enum Error {
+    /// Error communicating with the chip
+    I2c(#[allow(unused)] I2cErrorKind),
+
+    /// The chip lost time, e.g. because its battery ran out
+    TimeLost,
+
+    /// The year cannot be stored in the chip
+    YearOutOfRange,
+
+    /// A time component is out of range
+    TimeComponentRange(#[allow(unused)] TimeComponentRangeError),
+}
+
+impl From<TimeComponentRangeError> for Error {
+    fn from(error: TimeComponentRangeError) -> Self {
+        Self::TimeComponentRange(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+
+    use embedded_hal_async::i2c::ErrorType;
+    use embedded_hal_async::i2c::Operation;
+    use embedded_hal_async::i2c::SevenBitAddress;
+
+    use time::macros::datetime;
+
+    /// A chip on a fake I²C bus, with auto-incrementing registers
+    struct FakeChip {
+        /// I²C address of the chip
+        address: u8,
+
+        /// Registers
+        registers: [u8; 32],
+    }
+
+    impl FakeChip {
+        /// Create a chip with registers starting at an index
+        fn new(address: u8, start: u8, values: &[u8]) -> Self {
+            let mut registers = [0; 32];
+            let start = usize::from(start);
+            registers[start..start + values.len()].copy_from_slice(values);
+            Self { address, registers }
+        }
+    }
+
+    impl ErrorType for FakeChip {
+        type Error = Infallible;
+    }
+
+    impl I2c<SevenBitAddress> for FakeChip {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            assert_eq!(address, self.address, "Wrong I²C address");
+            let mut pointer = 0;
+            for operation in operations {
+                match operation {
+                    Operation::Write([register, values @ ..]) => {
+                        pointer = usize::from(*register);
+                        for value in values.iter() {
+                            self.registers[pointer] = *value;
+                            pointer += 1;
+                        }
+                    }
+                    Operation::Write([]) => {}
+                    Operation::Read(buffer) => {
+                        for value in buffer.iter_mut() {
+                            *value = self.registers[pointer];
+                            pointer += 1;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Time in the registers of the tests
+    const TIME: OffsetDateTime = datetime!(2024-08-14 12:34:56 UTC);
+
+    #[test]
+    fn bcd_round_trips_below_100() {
+        for value in 0..100 {
+            assert_eq!(from_bcd(to_bcd(value)), value);
+        }
+        assert_eq!(to_bcd(59), 0x59);
+        assert_eq!(from_bcd(0x31), 31);
+    }
+
+    #[test]
+    fn hours_are_decoded_in_both_modes() {
+        // 24 hours mode
+        assert_eq!(decode_hours(0x00), 0);
+        assert_eq!(decode_hours(0x12), 12);
+        assert_eq!(decode_hours(0x23), 23);
+
+        // 12 hours mode, with bit 5 set in the afternoon
+        assert_eq!(decode_hours(0b0100_0000 | 0x12), 0);
+        assert_eq!(decode_hours(0b0100_0000 | 0x01), 1);
+        assert_eq!(decode_hours(0b0110_0000 | 0x12), 12);
+        assert_eq!(decode_hours(0b0110_0000 | 0x11), 23);
+    }
+
+    #[test]
+    fn invalid_registers_are_rejected() {
+        assert!(matches!(
+            decode_time(0x00, 0x00, 0, 0x01, 0x13, 0x24),
+            Err(Error::TimeComponentRange(_))
+        ));
+        assert!(matches!(
+            decode_time(0x00, 0x00, 0, 0x30, 0x02, 0x23),
+            Err(Error::TimeComponentRange(_))
+        ));
+        assert!(matches!(
+            decode_time(0x60, 0x00, 0, 0x01, 0x01, 0x24),
+            Err(Error::TimeComponentRange(_))
+        ));
+    }
+
+    #[test]
+    fn years_outside_the_century_cannot_be_encoded() {
+        assert!(matches!(
+            encode_time(datetime!(1999-12-31 23:59:59 UTC)),
+            Err(Error::YearOutOfRange)
+        ));
+        assert!(matches!(
+            encode_time(datetime!(2100-01-01 00:00:00 UTC)),
+            Err(Error::YearOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn time_is_encoded_in_utc() {
+        let local = datetime!(2024-08-14 14:34:56 +02:00);
+        assert_eq!(
+            encode_time(local).unwrap(),
+            [0x56, 0x34, 0x12, 3, 0x14, 0x08, 0x24]
+        );
+    }
+
+    #[test]
+    fn ds3231_time_is_decoded() {
+        // Century bit set in the month register
+        let chip = FakeChip::new(
+            DS3231_ADDRESS,
+            DS3231_TIME_REGISTER,
+            &[0x56, 0x34, 0x12, 0x04, 0x14, 0x88, 0x24],
+        );
+        let mut rtc = ExternalRtc::new(Model::Ds3231, chip);
+        assert_eq!(block_on(rtc.read_time()).unwrap(), TIME);
+    }
+
+    #[test]
+    fn ds3231_time_is_decoded_in_12_hours_mode() {
+        let chip = FakeChip::new(
+            DS3231_ADDRESS,
+            DS3231_TIME_REGISTER,
+            &[0x56, 0x34, 0b0110_0010, 0x04, 0x14, 0x08, 0x24],
+        );
+        let mut rtc = ExternalRtc::new(Model::Ds3231, chip);
+        assert_eq!(
+            block_on(rtc.read_time()).unwrap(),
+            datetime!(2024-08-14 14:34:56 UTC)
+        );
+    }
+
+    #[test]
+    fn ds3231_lost_time_is_reported() {
+        let mut chip = FakeChip::new(
+            DS3231_ADDRESS,
+            DS3231_TIME_REGISTER,
+            &[0x56, 0x34, 0x12, 0x04, 0x14, 0x08, 0x24],
+        );
+        chip.registers[usize::from(DS3231_STATUS_REGISTER)] = DS3231_OSCILLATOR_STOP_FLAG;
+        let mut rtc = ExternalRtc::new(Model::Ds3231, chip);
+        assert!(matches!(block_on(rtc.read_time()), Err(Error::TimeLost)));
+    }
+
+    #[test]
+    fn ds3231_write_clears_lost_time() {
+        let mut chip = FakeChip::new(DS3231_ADDRESS, 0, &[]);
+        chip.registers[usize::from(DS3231_STATUS_REGISTER)] = DS3231_OSCILLATOR_STOP_FLAG;
+        let mut rtc = ExternalRtc::new(Model::Ds3231, chip);
+
+        block_on(rtc.write_time(TIME)).unwrap();
+        assert_eq!(block_on(rtc.read_time()).unwrap(), TIME);
+
+        let ExternalRtc::Ds3231(Ds3231 { i2c }) = rtc else {
+            panic!("Wrong driver");
+        };
+        // Weekday is 1 to 7 starting on Sunday
+        assert_eq!(
+            i2c.registers[..7],
+            [0x56, 0x34, 0x12, 0x04, 0x14, 0x08, 0x24]
+        );
+        assert_eq!(i2c.registers[usize::from(DS3231_STATUS_REGISTER)], 0);
+    }
+
+    #[test]
+    fn ds3231_temperature_is_decoded() {
+        let table = [
+            ([0x19, 0x40], 25.25),
+            ([0x00, 0xc0], 0.75),
+            ([0xe7, 0x00], -25.0),
+            ([0xff, 0x40], -0.75),
+        ];
+        for (registers, expected) in table {
+            let chip = FakeChip::new(DS3231_ADDRESS, DS3231_TEMPERATURE_REGISTER, &registers);
+            let mut rtc = ExternalRtc::new(Model::Ds3231, chip);
+            assert_eq!(
+                block_on(rtc.read_temperature()).unwrap(),
+                Some(expected),
+                "{registers:02x?}"
+            );
+        }
+    }
+
+    #[test]
+    fn pcf8563_time_is_decoded() {
+        // Unused bits set in minutes, hours and day, century bit set in month
+        let chip = FakeChip::new(
+            PCF8563_ADDRESS,
+            PCF8563_TIME_REGISTER,
+            &[0x56, 0xb4, 0xd2, 0xd4, 0x03, 0x88, 0x24],
+        );
+        let mut rtc = ExternalRtc::new(Model::Pcf8563, chip);
+        assert_eq!(block_on(rtc.read_time()).unwrap(), TIME);
+    }
+
+    #[test]
+    fn pcf8563_lost_time_is_reported() {
+        let chip = FakeChip::new(
+            PCF8563_ADDRESS,
+            PCF8563_TIME_REGISTER,
+            &[
+                PCF8563_VOLTAGE_LOW_FLAG | 0x56,
+                0x34,
+                0x12,
+                0x14,
+                0x03,
+                0x08,
+                0x24,
+            ],
+        );
+        let mut rtc = ExternalRtc::new(Model::Pcf8563, chip);
+        assert!(matches!(block_on(rtc.read_time()), Err(Error::TimeLost)));
+    }
+
+    #[test]
+    fn pcf8563_write_round_trips() {
+        let chip = FakeChip::new(PCF8563_ADDRESS, 0, &[]);
+        let mut rtc = ExternalRtc::new(Model::Pcf8563, chip);
+
+        block_on(rtc.write_time(TIME)).unwrap();
+        assert_eq!(block_on(rtc.read_time()).unwrap(), TIME);
+
+        let ExternalRtc::Pcf8563(Pcf8563 { i2c }) = rtc else {
+            panic!("Wrong driver");
+        };
+        // Day comes before weekday, which is 0 to 6 starting on Sunday
+        assert_eq!(
+            i2c.registers[2..9],
+            [0x56, 0x34, 0x12, 0x14, 0x03, 0x08, 0x24]
+        );
+    }
+
+    #[test]
+    fn pcf8563_has_no_temperature() {
+        let chip = FakeChip::new(PCF8563_ADDRESS, 0, &[]);
+        let mut rtc = ExternalRtc::new(Model::Pcf8563, chip);
+        assert_eq!(block_on(rtc.read_temperature()).unwrap(), None);
+    }
+
+    #[test]
+    fn model_names_round_trip() {
+        for model in Model::ALL {
+            assert_eq!(Model::from_name(model.name()), Some(model));
+        }
+        assert_eq!(Model::from_name("DS3231"), None);
+    }
+}
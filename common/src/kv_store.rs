@@ -0,0 +1,790 @@
+//! Wear-levelled key-value store in flash
+//!
+//! The store spans a range of flash pages used as a ring.
+//! Items are appended to the active page, and the latest item of a key holds
+//! its value, so that changing a value never erases flash.
+//! When the active page is full the next page is opened, the live items of
+//! the page after it are copied to the new page, and that page is erased.
+//! Therefore there is always an erased page after the active one, and all
+//! pages are erased in turn.
+//!
+//! A page starts with a header made of a magic number, a sequence number
+//! incremented on every page opening, and a CRC of both.
+//! An item is a header made of a key, the value length, the value length with
+//! inverted bits and a CRC of key, length and value, followed by the value
+//! padded to a word.
+//! An empty value marks a removed key.
+//! Integers are little endian.
+//!
+//! A write interrupted by power loss leaves an item or a page header with a
+//! wrong CRC.
+//! Items with a wrong CRC are skipped, using the length if it matches its
+//! inverted copy, or a single word otherwise, since nothing past the first
+//! word was written in that case.
+//! Pages with a wrong header are erased before being opened, and an
+//! interrupted page rotation is completed when mounting the store.
+
+use core::ops::Range;
+
+use log::debug;
+use log::info;
+use log::warn;
+
+use crc::Crc;
+use crc::CRC_32_ISO_HDLC;
+
+use embedded_storage::nor_flash::NorFlash;
+use embedded_storage::nor_flash::NorFlashError as _;
+use embedded_storage::nor_flash::NorFlashErrorKind;
+
+use heapless::Vec;
+
+/// Size of a page, which must be a multiple of the flash erase size
+pub const PAGE_SIZE: u32 = 4096;
+
+/// Maximal number of pages in a store
+pub const MAXIMAL_PAGES: usize = 16;
+
+/// Maximal size of a value
+pub const MAXIMAL_VALUE_SIZE: usize = 64;
+
+/// Maximal number of distinct keys in a page
+pub const MAXIMAL_KEYS: usize = 32;
+
+/// Size of a flash word, to which all writes are aligned
+const WORD_SIZE: u32 = 4;
+
+/// Size of a page header
+const PAGE_HEADER_SIZE: u32 = 12;
+
+/// Size of an item header
+const ITEM_HEADER_SIZE: u32 = 8;
+
+/// Magic number marking a page, `KVST` in ASCII
+const MAGIC: u32 = 0x4b56_5354;
+
+/// Value of an erased flash word
+const ERASED: [u8; 4] = [0xff; 4];
+
+/// Checksum algorithm
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// State of a page
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PageState {
+    /// The page header is erased
+    Erased,
+
+    /// The page is open with a sequence number
+    Used(u32),
+
+    /// The page header is corrupted
+    Corrupted,
+}
+
+/// Location of a valid item
+#[derive(Clone, Copy, Debug)]
+struct Item {
+    /// Key
+    key: u16,
+
+    /// Page index
+    page: u32,
+
+    /// Offset of the item header within the page
+    offset: u32,
+}
+
+/// A key-value store in flash
+pub struct Store<F> {
+    /// Flash
+    flash: F,
+
+    /// Offset of the first page
+    start: u32,
+
+    /// Number of pages
+    page_count: u32,
+
+    /// Index of the active page
+    active: u32,
+
+    /// Sequence number of the active page
+    sequence: u32,
+
+    /// Offset of the next item within the active page
+    position: u32,
+}
+
+impl<F> Store<F>
+where
+    F: NorFlash,
+{
+    /// Mount a store on a range of flash
+    ///
+    /// The range must be aligned to [`PAGE_SIZE`] and contain at least two
+    /// pages.
+    /// An empty range is formatted.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn mount(flash: F, range: Range<u32>) -> Result<Self, Error> {
+        let length = range.end.saturating_sub(range.start);
+        let page_count = length / PAGE_SIZE;
+        if !range.start.is_multiple_of(PAGE_SIZE)
+            || !length.is_multiple_of(PAGE_SIZE)
+            || !PAGE_SIZE.is_multiple_of(F::ERASE_SIZE as u32)
+            || !WORD_SIZE.is_multiple_of(F::WRITE_SIZE as u32)
+            || !(2..=MAXIMAL_PAGES as u32).contains(&page_count)
+        {
+            return Err(Error::InvalidRange);
+        }
+
+        let mut store = Self {
+            flash,
+            start: range.start,
+            page_count,
+            active: 0,
+            sequence: 0,
+            position: PAGE_SIZE,
+        };
+
+        let newest = store
+            .pages()?
+            .into_iter()
+            .max_by_key(|(sequence, _)| *sequence);
+        let Some((sequence, active)) = newest else {
+            info!("Format key-value store");
+            store.open_page(0, 0)?;
+            return Ok(store);
+        };
+
+        store.active = active;
+        store.sequence = sequence;
+        store.position = store.scan(active, |_, _| {})?;
+
+        let next = store.next_page(active);
+        if store.page_state(next)? != PageState::Erased {
+            warn!("Complete interrupted page rotation");
+            store.collect(next)?;
+        }
+
+        debug!(
+            "Mounted key-value store on page {active} (sequence {sequence}), {} bytes free",
+            PAGE_SIZE - store.position
+        );
+        Ok(store)
+    }
+
+    /// Fetch the value of a key
+    ///
+    /// Return `None` if the key is not stored.
+    pub fn fetch<'b>(&mut self, key: u16, buffer: &'b mut [u8]) -> Result<Option<&'b [u8]>, Error> {
+        let mut found = None;
+        for (_, page) in self.pages()? {
+            self.scan(page, |item, value| {
+                if item.key == key {
+                    found = Some((value.len(), *item));
+                }
+            })?;
+        }
+
+        match found {
+            None | Some((0, _)) => Ok(None),
+            Some((length, item)) => {
+                let value = buffer.get_mut(..length).ok_or(Error::ValueTooLarge)?;
+                self.read_value(item, value)?;
+                Ok(Some(value))
+            }
+        }
+    }
+
+    /// Store the value of a key
+    ///
+    /// Nothing is written if the key already has this value.
+    pub fn store(&mut self, key: u16, value: &[u8]) -> Result<(), Error> {
+        if value.is_empty() || value.len() > MAXIMAL_VALUE_SIZE {
+            return Err(Error::ValueTooLarge);
+        }
+        let mut current = [0_u8; MAXIMAL_VALUE_SIZE];
+        if self.fetch(key, &mut current)? == Some(value) {
+            return Ok(());
+        }
+        self.append(key, value)
+    }
+
+    /// Remove a key
+    pub fn remove(&mut self, key: u16) -> Result<(), Error> {
+        let mut current = [0_u8; MAXIMAL_VALUE_SIZE];
+        if self.fetch(key, &mut current)?.is_none() {
+            return Ok(());
+        }
+        self.append(key, &[])
+    }
+
+    /// Remove all keys and erase all pages
+    pub fn clear(&mut self) -> Result<(), Error> {
+        info!("Clear key-value store");
+        for page in 0..self.page_count {
+            if page != self.active && self.page_state(page)? != PageState::Erased {
+                self.erase_page(page)?;
+            }
+        }
+        self.open_page(self.active, self.sequence.wrapping_add(1))
+    }
+
+    /// Append an item, rotating pages if the active one is full
+    fn append(&mut self, key: u16, value: &[u8]) -> Result<(), Error> {
+        if self.position + item_size(value.len()) > PAGE_SIZE {
+            self.rotate()?;
+            if self.position + item_size(value.len()) > PAGE_SIZE {
+                return Err(Error::Full);
+            }
+        }
+        self.write_item(key, value)
+    }
+
+    /// Open the next page and collect the page after it
+    fn rotate(&mut self) -> Result<(), Error> {
+        let next = self.next_page(self.active);
+        debug!("Rotate key-value store to page {next}");
+        self.open_page(next, self.sequence.wrapping_add(1))?;
+        self.collect(self.next_page(next))
+    }
+
+    /// Copy live items of a page to the active page, and erase it
+    ///
+    /// The page must be the oldest one, so removed keys are dropped.
+    fn collect(&mut self, page: u32) -> Result<(), Error> {
+        if let PageState::Used(sequence) = self.page_state(page)? {
+            let mut latest = Vec::<(Item, usize), MAXIMAL_KEYS>::new();
+            let mut overflow = false;
+            self.scan(page, |item, value| {
+                let entry = (*item, value.len());
+                if let Some(existing) = latest.iter_mut().find(|(other, _)| other.key == item.key) {
+                    *existing = entry;
+                } else {
+                    overflow |= latest.push(entry).is_err();
+                }
+            })?;
+            if overflow {
+                return Err(Error::Full);
+            }
+
+            for (item, length) in latest {
+                if length == 0 || self.is_superseded(item.key, sequence)? {
+                    continue;
+                }
+                if self.position + item_size(length) > PAGE_SIZE {
+                    return Err(Error::Full);
+                }
+                let mut value = [0_u8; MAXIMAL_VALUE_SIZE];
+                let value = value.get_mut(..length).ok_or(Error::ValueTooLarge)?;
+                self.read_value(item, value)?;
+                self.write_item(item.key, value)?;
+            }
+        }
+        self.erase_page(page)
+    }
+
+    /// Check whether a key has an item in a page newer than a sequence number
+    fn is_superseded(&mut self, key: u16, sequence: u32) -> Result<bool, Error> {
+        let mut found = false;
+        for (other, page) in self.pages()? {
+            if other > sequence {
+                self.scan(page, |item, _| found |= item.key == key)?;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Return sequence numbers and indices of used pages, oldest first
+    fn pages(&mut self) -> Result<Vec<(u32, u32), MAXIMAL_PAGES>, Error> {
+        let mut pages = Vec::<(u32, u32), MAXIMAL_PAGES>::new();
+        for page in 0..self.page_count {
+            if let PageState::Used(sequence) = self.page_state(page)? {
+                // There are at most MAXIMAL_PAGES pages
+                let _ = pages.push((sequence, page));
+            }
+        }
+        pages.sort_unstable();
+        Ok(pages)
+    }
+
+    /// Visit the valid items of a page in order
+    ///
+    /// Return the offset after the last written item.
+    fn scan(&mut self, page: u32, mut visit: impl FnMut(&Item, &[u8])) -> Result<u32, Error> {
+        let mut offset = PAGE_HEADER_SIZE;
+        let mut buffer = [0_u8; MAXIMAL_VALUE_SIZE];
+        while offset + ITEM_HEADER_SIZE <= PAGE_SIZE {
+            let mut header = [0_u8; ITEM_HEADER_SIZE as usize];
+            self.read(page, offset, &mut header)?;
+            let [key_low, key_high, length, inverted_length, checksum @ ..] = header;
+
+            if header.get(..4) == Some(&ERASED) {
+                break;
+            }
+            if length != !inverted_length {
+                // Power was lost while writing the first word of the item
+                offset += WORD_SIZE;
+                continue;
+            }
+            let size = item_size(usize::from(length));
+            if usize::from(length) > MAXIMAL_VALUE_SIZE || offset + size > PAGE_SIZE {
+                warn!("Invalid item at page {page} offset {offset}");
+                return Ok(PAGE_SIZE);
+            }
+
+            let padded = buffer
+                .get_mut(..size as usize - ITEM_HEADER_SIZE as usize)
+                .unwrap_or_default();
+            self.read(page, offset + ITEM_HEADER_SIZE, padded)?;
+            let value = padded.get(..usize::from(length)).unwrap_or_default();
+
+            let key = u16::from_le_bytes([key_low, key_high]);
+            if checksum == item_checksum(key, value).to_le_bytes() {
+                visit(&Item { key, page, offset }, value);
+            } else {
+                warn!("Skip corrupted item at page {page} offset {offset}");
+            }
+            offset += size;
+        }
+        Ok(offset)
+    }
+
+    /// Read the value of a valid item
+    fn read_value(&mut self, item: Item, value: &mut [u8]) -> Result<(), Error> {
+        let mut buffer = [0_u8; MAXIMAL_VALUE_SIZE];
+        let padded = buffer
+            .get_mut(..value.len().next_multiple_of(WORD_SIZE as usize))
+            .ok_or(Error::ValueTooLarge)?;
+        self.read(item.page, item.offset + ITEM_HEADER_SIZE, padded)?;
+        value.copy_from_slice(padded.get(..value.len()).unwrap_or_default());
+        Ok(())
+    }
+
+    /// Write an item at the current position of the active page
+    #[allow(clippy::cast_possible_truncation)]
+    fn write_item(&mut self, key: u16, value: &[u8]) -> Result<(), Error> {
+        let mut buffer = [0xff_u8; ITEM_HEADER_SIZE as usize + MAXIMAL_VALUE_SIZE];
+        let size = item_size(value.len());
+        // Values are at most MAXIMAL_VALUE_SIZE bytes, checked by callers
+        let length = value.len() as u8;
+
+        let [key_low, key_high] = key.to_le_bytes();
+        let [checksum_0, checksum_1, checksum_2, checksum_3] =
+            item_checksum(key, value).to_le_bytes();
+        let header = [
+            key_low, key_high, length, !length, checksum_0, checksum_1, checksum_2, checksum_3,
+        ];
+        let (head, rest) = buffer.split_at_mut(ITEM_HEADER_SIZE as usize);
+        head.copy_from_slice(&header);
+        rest.get_mut(..value.len())
+            .ok_or(Error::ValueTooLarge)?
+            .copy_from_slice(value);
+
+        let item = buffer.get(..size as usize).unwrap_or_default();
+        let result = self.write(self.active, self.position, item);
+        // Skip the item even if the write failed midway
+        self.position += size;
+        result
+    }
+
+    /// Erase a page if needed and write its header
+    fn open_page(&mut self, page: u32, sequence: u32) -> Result<(), Error> {
+        if !self.is_erased(page)? {
+            self.erase_page(page)?;
+        }
+
+        let [magic_0, magic_1, magic_2, magic_3] = MAGIC.to_le_bytes();
+        let [sequence_0, sequence_1, sequence_2, sequence_3] = sequence.to_le_bytes();
+        let [checksum_0, checksum_1, checksum_2, checksum_3] =
+            page_checksum(sequence).to_le_bytes();
+        let header = [
+            magic_0, magic_1, magic_2, magic_3, sequence_0, sequence_1, sequence_2, sequence_3,
+            checksum_0, checksum_1, checksum_2, checksum_3,
+        ];
+        self.write(page, 0, &header)?;
+
+        // Only switch once the header is written, so that a failed write
+        // never leaves the previous page to be erased as the next one
+        self.active = page;
+        self.sequence = sequence;
+        self.position = PAGE_HEADER_SIZE;
+        Ok(())
+    }
+
+    /// Read the state of a page from its header
+    fn page_state(&mut self, page: u32) -> Result<PageState, Error> {
+        let mut header = [0_u8; PAGE_HEADER_SIZE as usize];
+        self.read(page, 0, &mut header)?;
+        let [magic_0, magic_1, magic_2, magic_3, sequence_0, sequence_1, sequence_2, sequence_3, checksum_0, checksum_1, checksum_2, checksum_3] =
+            header;
+        let magic = u32::from_le_bytes([magic_0, magic_1, magic_2, magic_3]);
+        let sequence = u32::from_le_bytes([sequence_0, sequence_1, sequence_2, sequence_3]);
+        let checksum = u32::from_le_bytes([checksum_0, checksum_1, checksum_2, checksum_3]);
+
+        let state = if header.iter().all(|byte| *byte == 0xff) {
+            PageState::Erased
+        } else if magic == MAGIC && checksum == page_checksum(sequence) {
+            PageState::Used(sequence)
+        } else {
+            PageState::Corrupted
+        };
+        Ok(state)
+    }
+
+    /// Check whether a whole page is erased
+    fn is_erased(&mut self, page: u32) -> Result<bool, Error> {
+        let mut buffer = [0_u8; 256];
+        for offset in (0..PAGE_SIZE).step_by(buffer.len()) {
+            self.read(page, offset, &mut buffer)?;
+            if buffer.iter().any(|byte| *byte != 0xff) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Return the page following another in the ring
+    fn next_page(&self, page: u32) -> u32 {
+        (page + 1) % self.page_count
+    }
+
+    /// Read bytes from a page
+    fn read(&mut self, page: u32, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.flash
+            .read(self.start + page * PAGE_SIZE + offset, bytes)
+            .map_err(|error| Error::Flash(error.kind()))
+    }
+
+    /// Write bytes to a page
+    fn write(&mut self, page: u32, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        self.flash
+            .write(self.start + page * PAGE_SIZE + offset, bytes)
+            .map_err(|error| Error::Flash(error.kind()))
+    }
+
+    /// Erase a page
+    fn erase_page(&mut self, page: u32) -> Result<(), Error> {
+        let start = self.start + page * PAGE_SIZE;
+        self.flash
+            .erase(start, start + PAGE_SIZE)
+            .map_err(|error| Error::Flash(error.kind()))
+    }
+}
+
+/// Compute the size of an item with a value length, including padding
+#[allow(clippy::cast_possible_truncation)]
+fn item_size(length: usize) -> u32 {
+    // Values are at most MAXIMAL_VALUE_SIZE bytes
+    ITEM_HEADER_SIZE + length.next_multiple_of(WORD_SIZE as usize) as u32
+}
+
+/// Compute the checksum of an item
+#[allow(clippy::cast_possible_truncation)]
+fn item_checksum(key: u16, value: &[u8]) -> u32 {
+    let mut digest = CRC.digest();
+    digest.update(&key.to_le_bytes());
+    digest.update(&[value.len() as u8]);
+    digest.update(value);
+    digest.finalize()
+}
+
+/// Compute the checksum of a page header
+fn page_checksum(sequence: u32) -> u32 {
+    let mut digest = CRC.digest();
+    digest.update(&MAGIC.to_le_bytes());
+    digest.update(&sequence.to_le_bytes());
+    digest.finalize()
+}
+
+/// An error within the key-value store
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The flash range is not aligned to pages or has too few pages
+    InvalidRange,
+
+    /// A value is empty or larger than [`MAXIMAL_VALUE_SIZE`]
+    ValueTooLarge,
+
+    /// Live items do not fit in a page, or a page has more than
+    /// [`MAXIMAL_KEYS`] keys
+    Full,
+
+    /// Error from flash
+    Flash(#[allow(unused)] NorFlashErrorKind),
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use embedded_storage::nor_flash::ErrorType;
+    use embedded_storage::nor_flash::NorFlashError;
+    use embedded_storage::nor_flash::ReadNorFlash;
+
+    /// Flash range of a store with two pages
+    pub(crate) const RANGE: Range<u32> = 0..2 * PAGE_SIZE;
+
+    /// Error of a flash operation after power loss
+    #[derive(Debug)]
+    pub(crate) struct PowerLost;
+
+    impl NorFlashError for PowerLost {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::Other
+        }
+    }
+
+    /// Flash in memory that loses power after a number of operations
+    ///
+    /// Writes and erases proceed one word at a time, so that power can be cut
+    /// in the middle of either.
+    #[derive(Clone)]
+    pub(crate) struct Flash {
+        /// Contents
+        bytes: std::vec::Vec<u8>,
+
+        /// Number of words that can be written or erased before power is
+        /// lost, or `None` for no limit
+        budget: Option<usize>,
+    }
+
+    impl Flash {
+        /// Create an erased flash covering a range
+        pub(crate) fn new(range: Range<u32>) -> Self {
+            Self {
+                bytes: std::vec![0xff; range.end as usize],
+                budget: None,
+            }
+        }
+
+        /// Spend the budget of a word operation
+        fn spend(&mut self) -> Result<(), PowerLost> {
+            match &mut self.budget {
+                None => Ok(()),
+                Some(0) => Err(PowerLost),
+                Some(budget) => {
+                    *budget -= 1;
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    impl ErrorType for Flash {
+        type Error = PowerLost;
+    }
+
+    impl ReadNorFlash for Flash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), PowerLost> {
+            let start = offset as usize;
+            bytes.copy_from_slice(&self.bytes[start..start + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for Flash {
+        const WRITE_SIZE: usize = 4;
+
+        const ERASE_SIZE: usize = 4096;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), PowerLost> {
+            for start in (from as usize..to as usize).step_by(4) {
+                self.spend()?;
+                self.bytes[start..start + 4].fill(0xff);
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), PowerLost> {
+            for (index, word) in bytes.chunks(4).enumerate() {
+                self.spend()?;
+                let start = offset as usize + index * 4;
+                for (target, byte) in self.bytes[start..start + word.len()].iter_mut().zip(word) {
+                    // Programming only clears bits
+                    *target &= byte;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Fetch a value as a vector
+    fn fetch(store: &mut Store<&mut Flash>, key: u16) -> Option<std::vec::Vec<u8>> {
+        let mut buffer = [0_u8; MAXIMAL_VALUE_SIZE];
+        store.fetch(key, &mut buffer).unwrap().map(<[u8]>::to_vec)
+    }
+
+    #[test]
+    fn store_fetch_and_remove() {
+        let mut flash = Flash::new(RANGE);
+        let mut store = Store::mount(&mut flash, RANGE).unwrap();
+        assert_eq!(fetch(&mut store, 1), None);
+
+        store.store(1, b"first").unwrap();
+        store.store(2, b"other").unwrap();
+        store.store(1, b"second").unwrap();
+        assert_eq!(fetch(&mut store, 1).as_deref(), Some(&b"second"[..]));
+
+        store.remove(2).unwrap();
+        assert_eq!(fetch(&mut store, 2), None);
+
+        let mut store = Store::mount(&mut flash, RANGE).unwrap();
+        assert_eq!(fetch(&mut store, 1).as_deref(), Some(&b"second"[..]));
+        assert_eq!(fetch(&mut store, 2), None);
+    }
+
+    #[test]
+    fn unchanged_value_is_not_written() {
+        let mut flash = Flash::new(RANGE);
+        let mut store = Store::mount(&mut flash, RANGE).unwrap();
+        store.store(1, b"value").unwrap();
+        let position = store.position;
+        store.store(1, b"value").unwrap();
+        assert_eq!(store.position, position);
+    }
+
+    #[test]
+    fn reject_invalid_ranges_and_values() {
+        let mut flash = Flash::new(RANGE);
+        assert!(matches!(
+            Store::mount(&mut flash, 0..PAGE_SIZE),
+            Err(Error::InvalidRange)
+        ));
+        assert!(matches!(
+            Store::mount(&mut flash, 1..PAGE_SIZE + 1),
+            Err(Error::InvalidRange)
+        ));
+
+        let mut store = Store::mount(&mut flash, RANGE).unwrap();
+        assert_eq!(store.store(1, &[]), Err(Error::ValueTooLarge));
+        assert_eq!(
+            store.store(1, &[0; MAXIMAL_VALUE_SIZE + 1]),
+            Err(Error::ValueTooLarge)
+        );
+    }
+
+    #[test]
+    fn rotate_pages_keeping_values() {
+        let mut flash = Flash::new(RANGE);
+        let mut store = Store::mount(&mut flash, RANGE).unwrap();
+        store.store(1, b"kept").unwrap();
+        for value in 0..2000_u32 {
+            store.store(2, &value.to_le_bytes()).unwrap();
+        }
+        assert!(store.sequence > 1);
+
+        let mut store = Store::mount(&mut flash, RANGE).unwrap();
+        assert_eq!(fetch(&mut store, 1).as_deref(), Some(&b"kept"[..]));
+        assert_eq!(
+            fetch(&mut store, 2).as_deref(),
+            Some(&1999_u32.to_le_bytes()[..])
+        );
+    }
+
+    #[test]
+    fn power_cut_while_writing_keeps_previous_value() {
+        let mut flash = Flash::new(RANGE);
+        Store::mount(&mut flash, RANGE)
+            .unwrap()
+            .store(1, b"previous")
+            .unwrap();
+
+        let next = b"a longer value that spans several words";
+        let words = item_size(next.len()) as usize / 4;
+        for budget in 0..=words {
+            let mut flash = flash.clone();
+            let mut store = Store::mount(&mut flash, RANGE).unwrap();
+            store.flash.budget = Some(budget);
+            let result = store.store(1, next);
+            flash.budget = None;
+
+            let expected: &[u8] = if result.is_ok() { next } else { b"previous" };
+            assert_eq!(result.is_ok(), budget == words, "budget {budget}");
+            let mut store = Store::mount(&mut flash, RANGE).unwrap();
+            assert_eq!(
+                fetch(&mut store, 1).as_deref(),
+                Some(expected),
+                "budget {budget}"
+            );
+
+            // The torn item is skipped by later writes
+            store.store(2, b"after").unwrap();
+            let mut store = Store::mount(&mut flash, RANGE).unwrap();
+            assert_eq!(
+                fetch(&mut store, 1).as_deref(),
+                Some(expected),
+                "budget {budget}"
+            );
+            assert_eq!(fetch(&mut store, 2).as_deref(), Some(&b"after"[..]));
+        }
+    }
+
+    #[test]
+    fn power_cut_while_compacting_keeps_previous_values() {
+        let mut flash = Flash::new(RANGE);
+        let mut store = Store::mount(&mut flash, RANGE).unwrap();
+        store.store(1, b"kept").unwrap();
+        let mut value = 0_u32;
+        // Fill the active page, so that the next write rotates pages
+        while store.position + item_size(4) <= PAGE_SIZE {
+            value += 1;
+            store.store(2, &value.to_le_bytes()).unwrap();
+        }
+        let previous = value.to_le_bytes();
+        let next = (value + 1).to_le_bytes();
+
+        // Count the words written and erased by the rotation
+        let mut unlimited = flash.clone();
+        unlimited.budget = Some(usize::MAX);
+        Store::mount(&mut unlimited, RANGE)
+            .unwrap()
+            .store(2, &next)
+            .unwrap();
+        let words = usize::MAX - unlimited.budget.unwrap();
+        assert!(words > PAGE_SIZE as usize / 4);
+
+        for budget in 0..=words {
+            let mut flash = flash.clone();
+            let mut store = Store::mount(&mut flash, RANGE).unwrap();
+            store.flash.budget = Some(budget);
+            let result = store.store(2, &next);
+            flash.budget = None;
+
+            let expected = if result.is_ok() { next } else { previous };
+            assert_eq!(result.is_ok(), budget == words, "budget {budget}");
+            let mut store = Store::mount(&mut flash, RANGE).unwrap();
+            assert_eq!(
+                fetch(&mut store, 1).as_deref(),
+                Some(&b"kept"[..]),
+                "budget {budget}"
+            );
+            assert_eq!(
+                fetch(&mut store, 2).as_deref(),
+                Some(&expected[..]),
+                "budget {budget}"
+            );
+
+            // The store is still usable
+            store.store(2, &next).unwrap();
+            let mut store = Store::mount(&mut flash, RANGE).unwrap();
+            assert_eq!(
+                fetch(&mut store, 1).as_deref(),
+                Some(&b"kept"[..]),
+                "budget {budget}"
+            );
+            assert_eq!(
+                fetch(&mut store, 2).as_deref(),
+                Some(&next[..]),
+                "budget {budget}"
+            );
+        }
+    }
+}
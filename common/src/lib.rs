@@ -0,0 +1,14 @@
+//! Modules shared by the firmwares of the station
+//!
+//! These modules do not depend on the hardware, so they are built and tested
+//! on the host as well.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod config;
+pub mod derived;
+pub mod external_rtc;
+pub mod kv_store;
+pub mod retained;
+pub mod timers;
+pub mod tz;
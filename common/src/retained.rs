@@ -0,0 +1,268 @@
+//! Validation of state retained in RTC Fast memory between deep sleep cycles
+//!
+//! RTC Fast memory survives deep sleep, but after a power loss, a brownout, a
+//! panic, a watchdog or a software reset it may contain garbage or a stale
+//! state, and after a firmware update it may hold a state of a different
+//! layout.
+//! A state is therefore stored behind a [`Header`] with a magic number, layout
+//! version, size and CRC, and it is only trusted after waking up from deep
+//! sleep with a valid header.
+//!
+//! The CRC is computed over the values of the fields, fed through
+//! [`Checksum`], rather than over the raw bytes of the state, whose padding is
+//! not preserved by field assignments.
+
+use crc::Crc;
+use crc::Digest;
+use crc::CRC_32_ISO_HDLC;
+
+/// Checksum algorithm
+static CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Header protecting retained state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Header {
+    /// Magic number
+    magic: u32,
+
+    /// Layout version
+    version: u16,
+
+    /// Size of the state in bytes, truncated to 16 bits
+    size: u16,
+
+    /// CRC of the state
+    checksum: u32,
+}
+
+impl Header {
+    /// An invalid header
+    pub const EMPTY: Self = Self {
+        magic: 0,
+        version: 0,
+        size: 0,
+        checksum: 0,
+    };
+
+    /// Create a header describing the layout of a state, without checksum
+    ///
+    /// The version must be incremented whenever the layout changes in a way
+    /// that is not caught by the size check.
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn layout(magic: u32, version: u16, size: usize) -> Self {
+        Self {
+            magic,
+            version,
+            size: size as u16,
+            checksum: 0,
+        }
+    }
+
+    /// Create a header of the same layout protecting a state with a checksum
+    pub const fn seal(self, checksum: u32) -> Self {
+        Self { checksum, ..self }
+    }
+
+    /// Verify that a header protects a state of a layout
+    ///
+    /// Retained state is only valid after waking up from deep sleep, any other
+    /// reset reason means that RTC Fast memory might have been lost or left
+    /// stale.
+    /// The checksum of the state is only computed once everything else is
+    /// valid, since until then the state might not hold valid values.
+    pub fn verify(
+        &self,
+        layout: &Self,
+        woke_from_deep_sleep: bool,
+        checksum: impl FnOnce() -> u32,
+    ) -> Result<(), Invalid> {
+        if !woke_from_deep_sleep {
+            return Err(Invalid::WakeReason);
+        }
+        if self.magic != layout.magic {
+            return Err(Invalid::Magic);
+        }
+        if self.version != layout.version {
+            return Err(Invalid::Version);
+        }
+        if self.size != layout.size {
+            return Err(Invalid::Size);
+        }
+        if self.checksum != checksum() {
+            return Err(Invalid::Checksum);
+        }
+        Ok(())
+    }
+}
+
+/// Reason for rejecting retained state
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Invalid {
+    /// The device did not wake up from deep sleep
+    WakeReason,
+
+    /// The magic number is wrong
+    Magic,
+
+    /// The layout version is different
+    Version,
+
+    /// The size is different
+    Size,
+
+    /// The checksum does not match
+    Checksum,
+}
+
+/// A value fed to the checksum of the retained state
+pub trait Checksum {
+    /// Feed the value to a digest, without padding
+    fn feed(&self, digest: &mut Digest<'_, u32>);
+
+    /// Compute the checksum of the value
+    fn checksum(&self) -> u32 {
+        let mut digest = CRC.digest();
+        self.feed(&mut digest);
+        digest.finalize()
+    }
+}
+
+/// Implement [`Checksum`] for primitive types as little endian bytes
+macro_rules! impl_checksum_for_primitives {
+    ($($type:ty),*) => {
+        $(
+            impl Checksum for $type {
+                fn feed(&self, digest: &mut Digest<'_, u32>) {
+                    digest.update(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_checksum_for_primitives!(u8, u16, u32, u64, i16, i32, i64, f32);
+
+impl Checksum for bool {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        u8::from(*self).feed(digest);
+    }
+}
+
+impl<T: Checksum, const N: usize> Checksum for [T; N] {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        for item in self {
+            item.feed(digest);
+        }
+    }
+}
+
+impl<T: Checksum> Checksum for Option<T> {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.is_some().feed(digest);
+        if let Some(value) = self {
+            value.feed(digest);
+        }
+    }
+}
+
+impl<A: Checksum, B: Checksum> Checksum for (A, B) {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.0.feed(digest);
+        self.1.feed(digest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Layout of the test state
+    const LAYOUT: Header = Header::layout(0x5445_5354, 3, 24);
+
+    /// Checksum of the test state
+    const CHECKSUM: u32 = 0x1234_5678;
+
+    #[test]
+    fn verify_accepts_a_sealed_header_after_deep_sleep() {
+        let header = LAYOUT.seal(CHECKSUM);
+        assert_eq!(header.verify(&LAYOUT, true, || CHECKSUM), Ok(()));
+    }
+
+    #[test]
+    fn verify_rejects_any_other_wake_reason() {
+        let header = LAYOUT.seal(CHECKSUM);
+        let checksum = || panic!("Checksum computed before checking the wake reason");
+        assert_eq!(
+            header.verify(&LAYOUT, false, checksum),
+            Err(Invalid::WakeReason)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_a_wrong_magic_number() {
+        let header = Header::layout(0x5445_5355, 3, 24).seal(CHECKSUM);
+        let checksum = || panic!("Checksum computed for a wrong magic number");
+        assert_eq!(header.verify(&LAYOUT, true, checksum), Err(Invalid::Magic));
+        assert_eq!(
+            Header::EMPTY.verify(&LAYOUT, true, || 0),
+            Err(Invalid::Magic)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_a_different_layout_version() {
+        let header = Header::layout(0x5445_5354, 2, 24).seal(CHECKSUM);
+        let checksum = || panic!("Checksum computed for a different version");
+        assert_eq!(
+            header.verify(&LAYOUT, true, checksum),
+            Err(Invalid::Version)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_a_different_size() {
+        let header = Header::layout(0x5445_5354, 3, 32).seal(CHECKSUM);
+        let checksum = || panic!("Checksum computed for a different size");
+        assert_eq!(header.verify(&LAYOUT, true, checksum), Err(Invalid::Size));
+    }
+
+    #[test]
+    fn verify_rejects_a_checksum_mismatch() {
+        let header = LAYOUT.seal(CHECKSUM);
+        assert_eq!(
+            header.verify(&LAYOUT, true, || CHECKSUM ^ 1),
+            Err(Invalid::Checksum)
+        );
+    }
+
+    #[test]
+    fn size_is_truncated_to_16_bits() {
+        assert_eq!(Header::layout(0, 0, 0x1_0018), Header::layout(0, 0, 24));
+    }
+
+    #[test]
+    fn checksum_matches_crc_of_little_endian_bytes() {
+        assert_eq!(0x1234_u16.checksum(), CRC.checksum(&[0x34, 0x12]));
+        assert_eq!(true.checksum(), CRC.checksum(&[1]));
+        assert_eq!((1_u8, 2_u8).checksum(), CRC.checksum(&[1, 2]));
+        assert_eq!([3_u8, 4_u8].checksum(), CRC.checksum(&[3, 4]));
+    }
+
+    #[test]
+    fn checksum_distinguishes_none_from_some_zero() {
+        assert_ne!(None::<u32>.checksum(), Some(0_u32).checksum());
+        assert_ne!(Some(0_u32).checksum(), Some(1_u32).checksum());
+    }
+
+    #[test]
+    fn checksum_changes_with_any_element() {
+        let original = [1_i16, 2, 3];
+        for index in 0..original.len() {
+            let mut changed = original;
+            changed[index] += 1;
+            assert_ne!(changed.checksum(), original.checksum(), "element {index}");
+        }
+        assert_ne!((1_u8, 2_u8).checksum(), (2_u8, 1_u8).checksum());
+    }
+}
@@ -0,0 +1,344 @@
+//! Pending wakeups of a timer queue
+//!
+//! The Crussant firmware replaces the generic timer queue of `embassy-time`
+//! with one that keeps these, so that power management can see the pending
+//! deadlines before entering light sleep.
+//!
+//! Light sleep stops the embassy time driver, and a [`Kind::Timer`] checks
+//! its deadline against the driver, so it would be late by the whole sleep.
+//! A [`Kind::Sleep`] checks its deadline against the time including light
+//! sleep instead, so after waking up its deadline is moved earlier by the
+//! time lost with [`Timers::skip()`].
+
+use core::task::Waker;
+
+use embassy_time::Duration;
+use embassy_time::Instant;
+
+use heapless::Vec;
+
+/// Number of wakeups that can be pending at the same time
+///
+/// When the queue is full a wakeup is triggered early to make room, and its
+/// task schedules it again when polled.
+const QUEUE_SIZE: usize = 16;
+
+/// Shortest light sleep worth entering
+///
+/// Entering and leaving light sleep takes about a millisecond, and shorter
+/// waits are better spent with the CPU clock gated.
+pub const MINIMAL_LIGHT_SLEEP: Duration = Duration::from_millis(20);
+
+/// Kind of a pending wakeup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// An embassy timer, which stops in light sleep
+    Timer,
+
+    /// A sleep, which continues in light sleep
+    Sleep,
+}
+
+/// How to wait when no task is ready
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wakeup {
+    /// Enter light sleep for at most a duration
+    LightSleep(Duration),
+
+    /// Wait for an interrupt with the CPU clock gated
+    Interrupt,
+}
+
+/// A pending wakeup
+struct Timer {
+    /// Instant at which to wake the task
+    deadline: Instant,
+
+    /// Waker of the task
+    waker: Waker,
+
+    /// Kind of wakeup
+    kind: Kind,
+}
+
+/// Pending wakeups, in no particular order
+pub struct Timers {
+    /// Pending wakeups
+    timers: Vec<Timer, QUEUE_SIZE>,
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timers {
+    /// Create an empty queue
+    pub const fn new() -> Self {
+        Self { timers: Vec::new() }
+    }
+
+    /// Return the earliest deadline of a kind
+    pub fn next_deadline(&self, kind: Kind) -> Option<Instant> {
+        self.timers
+            .iter()
+            .filter(|timer| timer.kind == kind)
+            .map(|timer| timer.deadline)
+            .min()
+    }
+
+    /// Choose how to wait when no task is ready
+    ///
+    /// Light sleep lasts until the earliest deadline of a sleep, and is only
+    /// entered when the chip need not stay `awake`, no timer is pending and
+    /// the sleep lasts at least [`MINIMAL_LIGHT_SLEEP`].
+    pub fn next_wakeup(&self, now: Instant, awake: bool) -> Wakeup {
+        if awake || self.next_deadline(Kind::Timer).is_some() {
+            return Wakeup::Interrupt;
+        }
+        match self
+            .next_deadline(Kind::Sleep)
+            .and_then(|deadline| deadline.checked_duration_since(now))
+        {
+            Some(remaining) if remaining >= MINIMAL_LIGHT_SLEEP => Wakeup::LightSleep(remaining),
+            _ => Wakeup::Interrupt,
+        }
+    }
+
+    /// Schedule a wakeup, keeping the earliest deadline of a task and kind
+    ///
+    /// When the queue is full, the last wakeup is triggered to make room.
+    pub fn schedule(&mut self, deadline: Instant, waker: &Waker, kind: Kind) {
+        if let Some(timer) = self
+            .timers
+            .iter_mut()
+            .find(|timer| timer.kind == kind && timer.waker.will_wake(waker))
+        {
+            timer.deadline = timer.deadline.min(deadline);
+        } else {
+            let mut timer = Timer {
+                deadline,
+                waker: waker.clone(),
+                kind,
+            };
+            while let Err(rejected) = self.timers.push(timer) {
+                timer = rejected;
+                if let Some(evicted) = self.timers.pop() {
+                    evicted.waker.wake();
+                }
+            }
+        }
+    }
+
+    /// Move the deadlines of sleeps earlier by a duration
+    pub fn skip(&mut self, duration: Duration) {
+        for timer in &mut self.timers {
+            if timer.kind == Kind::Sleep {
+                timer.deadline = timer.deadline.checked_sub(duration).unwrap_or(Instant::MIN);
+            }
+        }
+    }
+
+    /// Wake the tasks whose deadline is due
+    ///
+    /// Return the earliest deadline still pending, for which to program the
+    /// alarm.
+    pub fn wake_due(&mut self, now: Instant) -> Option<Instant> {
+        let mut index = 0;
+        while let Some(deadline) = self.timers.get(index).map(|timer| timer.deadline) {
+            if deadline <= now {
+                self.timers.swap_remove(index).waker.wake();
+            } else {
+                index += 1;
+            }
+        }
+        self.timers.iter().map(|timer| timer.deadline).min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    use super::*;
+
+    /// A task counting its wakeups
+    #[derive(Default)]
+    struct Task {
+        /// Number of wakeups
+        wakeups: AtomicUsize,
+    }
+
+    impl Wake for Task {
+        fn wake(self: Arc<Self>) {
+            self.wakeups.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Create a task and its waker
+    fn task() -> (Arc<Task>, Waker) {
+        let task = Arc::new(Task::default());
+        let waker = Waker::from(Arc::clone(&task));
+        (task, waker)
+    }
+
+    /// Return the number of wakeups of a task
+    fn wakeups(task: &Task) -> usize {
+        task.wakeups.load(Ordering::SeqCst)
+    }
+
+    /// Return an instant in milliseconds
+    fn at(millis: u64) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    #[test]
+    fn empty_queue() {
+        let mut timers = Timers::new();
+        assert_eq!(timers.next_deadline(Kind::Timer), None);
+        assert_eq!(timers.next_deadline(Kind::Sleep), None);
+        assert_eq!(timers.wake_due(at(1_000)), None);
+        assert_eq!(timers.next_wakeup(at(0), false), Wakeup::Interrupt);
+    }
+
+    #[test]
+    fn earliest_deadline_per_kind() {
+        let mut timers = Timers::new();
+        let (_, first) = task();
+        let (_, second) = task();
+        timers.schedule(at(300), &first, Kind::Sleep);
+        timers.schedule(at(100), &second, Kind::Sleep);
+        timers.schedule(at(500), &first, Kind::Timer);
+        timers.schedule(at(200), &second, Kind::Timer);
+
+        assert_eq!(timers.next_deadline(Kind::Sleep), Some(at(100)));
+        assert_eq!(timers.next_deadline(Kind::Timer), Some(at(200)));
+    }
+
+    #[test]
+    fn keep_earliest_deadline_of_a_task() {
+        let mut timers = Timers::new();
+        let (task, waker) = task();
+        timers.schedule(at(300), &waker, Kind::Sleep);
+        timers.schedule(at(500), &waker, Kind::Sleep);
+        assert_eq!(timers.next_deadline(Kind::Sleep), Some(at(300)));
+        timers.schedule(at(200), &waker, Kind::Sleep);
+        assert_eq!(timers.next_deadline(Kind::Sleep), Some(at(200)));
+
+        // A single wakeup is pending
+        assert_eq!(timers.wake_due(at(1_000)), None);
+        assert_eq!(wakeups(&task), 1);
+    }
+
+    #[test]
+    fn wake_due_in_order() {
+        let mut timers = Timers::new();
+        let tasks: std::vec::Vec<_> = (0..3).map(|_| task()).collect();
+        for (deadline, (_, waker)) in [300, 100, 200].into_iter().zip(&tasks) {
+            timers.schedule(at(deadline), waker, Kind::Timer);
+        }
+
+        assert_eq!(timers.wake_due(at(50)), Some(at(100)));
+        assert_eq!(timers.wake_due(at(100)), Some(at(200)));
+        assert_eq!(
+            tasks
+                .iter()
+                .map(|(task, _)| wakeups(task))
+                .collect::<std::vec::Vec<_>>(),
+            [0, 1, 0]
+        );
+        assert_eq!(timers.wake_due(at(250)), Some(at(300)));
+        assert_eq!(timers.wake_due(at(300)), None);
+        assert!(tasks.iter().all(|(task, _)| wakeups(task) == 1));
+
+        // Due wakeups are removed
+        assert_eq!(timers.wake_due(at(1_000)), None);
+        assert!(tasks.iter().all(|(task, _)| wakeups(task) == 1));
+    }
+
+    #[test]
+    fn evict_a_wakeup_when_full() {
+        let mut timers = Timers::new();
+        let tasks: std::vec::Vec<_> = (0..=QUEUE_SIZE).map(|_| task()).collect();
+        for (index, (_, waker)) in tasks.iter().enumerate() {
+            timers.schedule(at(100 + index as u64), waker, Kind::Sleep);
+        }
+
+        // The last queued wakeup made room for the new one
+        let woken: std::vec::Vec<_> = tasks.iter().map(|(task, _)| wakeups(task)).collect();
+        assert_eq!(woken.iter().sum::<usize>(), 1);
+        assert_eq!(woken[QUEUE_SIZE - 1], 1);
+        assert_eq!(timers.next_deadline(Kind::Sleep), Some(at(100)));
+
+        timers.wake_due(at(1_000));
+        assert!(tasks.iter().all(|(task, _)| wakeups(task) == 1));
+    }
+
+    #[test]
+    fn skip_only_moves_sleeps() {
+        let mut timers = Timers::new();
+        let (_, waker) = task();
+        timers.schedule(at(5_000), &waker, Kind::Sleep);
+        timers.schedule(at(3_000), &waker, Kind::Timer);
+
+        timers.skip(Duration::from_millis(4_000));
+        assert_eq!(timers.next_deadline(Kind::Sleep), Some(at(1_000)));
+        assert_eq!(timers.next_deadline(Kind::Timer), Some(at(3_000)));
+        assert_eq!(timers.wake_due(at(1_000)), Some(at(3_000)));
+
+        // Sleeps skipped past the start of the driver are due at once
+        timers.schedule(at(200), &waker, Kind::Sleep);
+        timers.skip(Duration::from_millis(1_000));
+        assert_eq!(timers.next_deadline(Kind::Sleep), Some(Instant::MIN));
+    }
+
+    #[test]
+    fn light_sleep_until_next_sleep() {
+        let mut timers = Timers::new();
+        let (_, first) = task();
+        let (_, second) = task();
+        timers.schedule(at(60_000), &first, Kind::Sleep);
+        timers.schedule(at(30_000), &second, Kind::Sleep);
+
+        assert_eq!(
+            timers.next_wakeup(at(10_000), false),
+            Wakeup::LightSleep(Duration::from_millis(20_000))
+        );
+        assert_eq!(
+            timers.next_wakeup(at(29_980), false),
+            Wakeup::LightSleep(MINIMAL_LIGHT_SLEEP)
+        );
+    }
+
+    #[test]
+    fn stay_awake_for_locks_timers_and_short_sleeps() {
+        let mut timers = Timers::new();
+        let (_, waker) = task();
+        timers.schedule(at(30_000), &waker, Kind::Sleep);
+
+        // An awake lock is held
+        assert_eq!(timers.next_wakeup(at(0), true), Wakeup::Interrupt);
+
+        // The sleep is too short, or overdue
+        assert_eq!(timers.next_wakeup(at(29_990), false), Wakeup::Interrupt);
+        assert_eq!(timers.next_wakeup(at(40_000), false), Wakeup::Interrupt);
+
+        // A timer is pending, even a later one
+        timers.schedule(at(90_000), &waker, Kind::Timer);
+        assert_eq!(timers.next_wakeup(at(0), false), Wakeup::Interrupt);
+
+        // Light sleep is entered again once the timer is due
+        timers.wake_due(at(90_000));
+        timers.schedule(at(120_000), &waker, Kind::Sleep);
+        assert_eq!(
+            timers.next_wakeup(at(90_000), false),
+            Wakeup::LightSleep(Duration::from_millis(30_000))
+        );
+    }
+}
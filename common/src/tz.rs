@@ -0,0 +1,538 @@
+//! Time zones described by POSIX TZ strings
+//!
+//! A POSIX TZ string such as `NZST-12NZDT,M9.5.0,M4.1.0/3` describes the
+//! standard offset, the daylight saving offset and the rules for switching
+//! between them, which is enough to compute the UTC offset at any instant
+//! without a time zone database.
+//!
+//! Note that POSIX offsets are positive *west* of Greenwich, so `NZST-12`
+//! means UTC+12.
+//!
+//! See [the POSIX specification](https://pubs.opengroup.org/onlinepubs/9699919799/basedefs/V1_chap08.html).
+
+use time::error::ComponentRange as TimeComponentRangeError;
+use time::util::days_in_year_month;
+use time::util::is_leap_year;
+use time::Date;
+use time::Month;
+use time::OffsetDateTime;
+use time::UtcOffset;
+
+/// Seconds in an hour
+const SECONDS_PER_HOUR: i32 = 3600;
+
+/// Default time of day of transitions, 02:00:00
+const DEFAULT_TRANSITION_TIME: i32 = 2 * SECONDS_PER_HOUR;
+
+/// A time zone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeZone {
+    /// Standard offset in seconds east of UTC
+    standard_offset: i32,
+
+    /// Daylight saving time, if observed
+    daylight: Option<Daylight>,
+}
+
+/// Daylight saving time rules
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Daylight {
+    /// Daylight saving offset in seconds east of UTC
+    offset: i32,
+
+    /// Transition from standard to daylight saving time, in standard time
+    start: Transition,
+
+    /// Transition from daylight saving to standard time, in daylight time
+    end: Transition,
+}
+
+/// A yearly transition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Transition {
+    /// Day of the transition
+    day: TransitionDay,
+
+    /// Local time of day in seconds, possibly negative or beyond 24 hours
+    time: i32,
+}
+
+/// Day of a yearly transition
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TransitionDay {
+    /// Julian day `Jn` from 1 to 365, never counting February 29
+    Julian(u16),
+
+    /// Zero-based day `n` from 0 to 365, counting February 29
+    ZeroBased(u16),
+
+    /// Day `d` of week `w` of month `m`, written `Mm.w.d`
+    ///
+    /// Week 5 means the last week of the month, and day 0 means Sunday.
+    MonthWeekDay {
+        /// Month from 1 to 12
+        month: u8,
+
+        /// Week from 1 to 5
+        week: u8,
+
+        /// Day of week from 0 (Sunday) to 6
+        weekday: u8,
+    },
+}
+
+impl TimeZone {
+    /// Coordinated Universal Time
+    pub const UTC: Self = Self::fixed(0);
+
+    /// Create a time zone with a fixed offset in seconds east of UTC
+    pub const fn fixed(offset_in_seconds: i32) -> Self {
+        Self {
+            standard_offset: offset_in_seconds,
+            daylight: None,
+        }
+    }
+
+    /// Parse a POSIX TZ string
+    ///
+    /// If daylight saving time is observed but no rules are given, the
+    /// current United States rules `M3.2.0,M11.1.0` are assumed.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut parser = Parser { rest: text };
+
+        parser.name()?;
+        let standard_offset = -parser.offset()?;
+
+        if parser.rest.is_empty() {
+            return Ok(Self::fixed(standard_offset));
+        }
+
+        parser.name()?;
+        let daylight_offset = if parser.rest.is_empty() || parser.rest.starts_with(',') {
+            standard_offset + SECONDS_PER_HOUR
+        } else {
+            -parser.offset()?
+        };
+
+        let (start, end) = if parser.rest.is_empty() {
+            (
+                Transition {
+                    day: TransitionDay::MonthWeekDay {
+                        month: 3,
+                        week: 2,
+                        weekday: 0,
+                    },
+                    time: DEFAULT_TRANSITION_TIME,
+                },
+                Transition {
+                    day: TransitionDay::MonthWeekDay {
+                        month: 11,
+                        week: 1,
+                        weekday: 0,
+                    },
+                    time: DEFAULT_TRANSITION_TIME,
+                },
+            )
+        } else {
+            parser.expect(',')?;
+            let start = parser.transition()?;
+            parser.expect(',')?;
+            let end = parser.transition()?;
+            (start, end)
+        };
+
+        if !parser.rest.is_empty() {
+            return Err(Error::TrailingCharacters);
+        }
+
+        Ok(Self {
+            standard_offset,
+            daylight: Some(Daylight {
+                offset: daylight_offset,
+                start,
+                end,
+            }),
+        })
+    }
+
+    /// Return the UTC offset in seconds at an instant in Unix epoch
+    pub fn offset_in_seconds_at(&self, epoch: i64) -> Result<i32, Error> {
+        let Some(daylight) = self.daylight else {
+            return Ok(self.standard_offset);
+        };
+
+        // Transitions are computed for the year of the instant in standard
+        // time, which is the year the rules refer to
+        let local = OffsetDateTime::from_unix_timestamp(epoch + i64::from(self.standard_offset))?;
+        let year = local.year();
+
+        let start = daylight.start.epoch_in(year)? - i64::from(self.standard_offset);
+        let end = daylight.end.epoch_in(year)? - i64::from(daylight.offset);
+
+        let is_daylight = if start <= end {
+            // Northern hemisphere, daylight saving time within the year
+            start <= epoch && epoch < end
+        } else {
+            // Southern hemisphere, daylight saving time across new year
+            epoch < end || start <= epoch
+        };
+
+        Ok(if is_daylight {
+            daylight.offset
+        } else {
+            self.standard_offset
+        })
+    }
+
+    /// Return the UTC offset at an instant
+    pub fn offset_at(&self, instant: OffsetDateTime) -> Result<UtcOffset, Error> {
+        let offset = self.offset_in_seconds_at(instant.unix_timestamp())?;
+        Ok(UtcOffset::from_whole_seconds(offset)?)
+    }
+
+    /// Convert an instant to local time
+    pub fn to_local(&self, instant: OffsetDateTime) -> Result<OffsetDateTime, Error> {
+        let offset = self.offset_at(instant)?;
+        instant
+            .checked_to_offset(offset)
+            .ok_or(Error::InvalidInOffset)
+    }
+}
+
+impl Transition {
+    /// Return the local time of the transition in a year as a Unix epoch
+    fn epoch_in(&self, year: i32) -> Result<i64, Error> {
+        let date = self.day.date_in(year)?;
+        let midnight = date.midnight().assume_utc().unix_timestamp();
+        Ok(midnight + i64::from(self.time))
+    }
+}
+
+impl TransitionDay {
+    /// Return the date of the transition in a year
+    fn date_in(&self, year: i32) -> Result<Date, Error> {
+        match *self {
+            Self::Julian(day) => {
+                let leap_day = u16::from(is_leap_year(year) && day >= 60);
+                Ok(Date::from_ordinal_date(year, day + leap_day)?)
+            }
+            Self::ZeroBased(day) => Ok(Date::from_ordinal_date(year, day + 1)?),
+            Self::MonthWeekDay {
+                month,
+                week,
+                weekday,
+            } => {
+                let month = Month::try_from(month)?;
+                let first = Date::from_calendar_date(year, month, 1)?;
+                let first_weekday = first.weekday().number_days_from_sunday();
+
+                let first_match = 1 + (weekday + 7 - first_weekday) % 7;
+                let mut day = first_match + (week - 1) * 7;
+                let last_day = days_in_year_month(year, month);
+                while day > last_day {
+                    // Week 5 means the last occurrence in the month
+                    day -= 7;
+                }
+
+                Ok(Date::from_calendar_date(year, month, day)?)
+            }
+        }
+    }
+}
+
+/// A parser for POSIX TZ strings
+struct Parser<'a> {
+    /// Text left to parse
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    /// Parse a zone name, either alphabetic or quoted in angle brackets
+    fn name(&mut self) -> Result<(), Error> {
+        let (name, rest) = if let Some(quoted) = self.rest.strip_prefix('<') {
+            quoted.split_once('>').ok_or(Error::InvalidName)?
+        } else {
+            let end = self
+                .rest
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(self.rest.len());
+            self.rest.split_at(end)
+        };
+        self.rest = rest;
+
+        if name.len() < 3 {
+            return Err(Error::InvalidName);
+        }
+        Ok(())
+    }
+
+    /// Parse an offset `[+-]hh[:mm[:ss]]` in seconds west of UTC
+    fn offset(&mut self) -> Result<i32, Error> {
+        let sign = self.sign();
+        let offset = self.time(24)?;
+        Ok(sign * offset)
+    }
+
+    /// Parse a transition `date[/time]`
+    fn transition(&mut self) -> Result<Transition, Error> {
+        let day = if let Some(rest) = self.rest.strip_prefix('M') {
+            self.rest = rest;
+            let month = self.number(1, 12)?;
+            self.expect('.')?;
+            let week = self.number(1, 5)?;
+            self.expect('.')?;
+            let weekday = self.number(0, 6)?;
+
+            #[allow(clippy::cast_possible_truncation)]
+            TransitionDay::MonthWeekDay {
+                month: month as u8,
+                week: week as u8,
+                weekday: weekday as u8,
+            }
+        } else if let Some(rest) = self.rest.strip_prefix('J') {
+            self.rest = rest;
+            #[allow(clippy::cast_possible_truncation)]
+            TransitionDay::Julian(self.number(1, 365)? as u16)
+        } else {
+            #[allow(clippy::cast_possible_truncation)]
+            TransitionDay::ZeroBased(self.number(0, 365)? as u16)
+        };
+
+        let time = if let Some(rest) = self.rest.strip_prefix('/') {
+            self.rest = rest;
+            let sign = self.sign();
+            sign * self.time(167)?
+        } else {
+            DEFAULT_TRANSITION_TIME
+        };
+
+        Ok(Transition { day, time })
+    }
+
+    /// Parse a time `hh[:mm[:ss]]` in seconds
+    fn time(&mut self, maximum_hours: i32) -> Result<i32, Error> {
+        let hours = self.number(0, maximum_hours)?;
+        let mut seconds = hours * SECONDS_PER_HOUR;
+
+        if let Some(rest) = self.rest.strip_prefix(':') {
+            self.rest = rest;
+            seconds += self.number(0, 59)? * 60;
+
+            if let Some(rest) = self.rest.strip_prefix(':') {
+                self.rest = rest;
+                seconds += self.number(0, 59)?;
+            }
+        }
+
+        Ok(seconds)
+    }
+
+    /// Parse an optional sign
+    fn sign(&mut self) -> i32 {
+        if let Some(rest) = self.rest.strip_prefix('-') {
+            self.rest = rest;
+            -1
+        } else {
+            self.rest = self.rest.strip_prefix('+').unwrap_or(self.rest);
+            1
+        }
+    }
+
+    /// Parse a decimal number within a range
+    fn number(&mut self, minimum: i32, maximum: i32) -> Result<i32, Error> {
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let (digits, rest) = self.rest.split_at(end);
+
+        let number: i32 = digits.parse().map_err(|_| Error::InvalidNumber)?;
+        if number < minimum || number > maximum {
+            return Err(Error::InvalidNumber);
+        }
+
+        self.rest = rest;
+        Ok(number)
+    }
+
+    /// Consume an expected character
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        self.rest = self
+            .rest
+            .strip_prefix(expected)
+            .ok_or(Error::UnexpectedCharacter)?;
+        Ok(())
+    }
+}
+
+/// An error within time zone operations
+#[derive(Debug)]
+pub enum Error {
+    /// A zone name is shorter than three characters or not terminated
+    InvalidName,
+
+    /// A number is missing or out of range
+    InvalidNumber,
+
+    /// A separator is missing
+    UnexpectedCharacter,
+
+    /// The string continues after the rules
+    TrailingCharacters,
+
+    /// A time component is out of range
+    TimeComponentRange(#[allow(unused)] TimeComponentRangeError),
+
+    /// The time is invalid in the computed offset
+    InvalidInOffset,
+}
+
+impl From<TimeComponentRangeError> for Error {
+    fn from(error: TimeComponentRangeError) -> Self {
+        Self::TimeComponentRange(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::mem::discriminant;
+
+    use time::macros::date;
+    use time::macros::datetime;
+
+    /// Return the offset in hours at an instant
+    #[allow(clippy::cast_precision_loss)]
+    fn hours_at(time_zone: &TimeZone, instant: OffsetDateTime) -> f32 {
+        time_zone
+            .offset_in_seconds_at(instant.unix_timestamp())
+            .unwrap() as f32
+            / 3600.0
+    }
+
+    #[test]
+    fn parse_fixed_offsets() {
+        assert_eq!(TimeZone::parse("UTC0").unwrap(), TimeZone::UTC);
+        assert_eq!(TimeZone::parse("JST-9").unwrap(), TimeZone::fixed(9 * 3600));
+        assert_eq!(
+            TimeZone::parse("HST10").unwrap(),
+            TimeZone::fixed(-10 * 3600)
+        );
+        assert_eq!(
+            TimeZone::parse("<+0530>-5:30").unwrap(),
+            TimeZone::fixed(5 * 3600 + 30 * 60)
+        );
+    }
+
+    #[test]
+    fn parse_default_rules() {
+        assert_eq!(
+            TimeZone::parse("EST5EDT").unwrap(),
+            TimeZone::parse("EST5EDT4,M3.2.0/2,M11.1.0/2").unwrap()
+        );
+    }
+
+    #[test]
+    fn northern_hemisphere_transitions() {
+        let new_york = TimeZone::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        let cases = [
+            // Start at 02:00 EST on the second Sunday of March
+            (datetime!(2024-03-10 06:59:59 UTC), -5.0),
+            (datetime!(2024-03-10 07:00:00 UTC), -4.0),
+            // End at 02:00 EDT on the first Sunday of November
+            (datetime!(2024-11-03 05:59:59 UTC), -4.0),
+            (datetime!(2024-11-03 06:00:00 UTC), -5.0),
+            (datetime!(2024-01-01 00:00:00 UTC), -5.0),
+            (datetime!(2024-07-01 00:00:00 UTC), -4.0),
+        ];
+        for (instant, expected) in cases {
+            assert_eq!(hours_at(&new_york, instant), expected, "at {instant}");
+        }
+
+        let berlin = TimeZone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        let cases = [
+            // Start at 02:00 CET on the last Sunday of March
+            (datetime!(2024-03-31 00:59:59 UTC), 1.0),
+            (datetime!(2024-03-31 01:00:00 UTC), 2.0),
+            // End at 03:00 CEST on the last Sunday of October
+            (datetime!(2024-10-27 00:59:59 UTC), 2.0),
+            (datetime!(2024-10-27 01:00:00 UTC), 1.0),
+        ];
+        for (instant, expected) in cases {
+            assert_eq!(hours_at(&berlin, instant), expected, "at {instant}");
+        }
+    }
+
+    #[test]
+    fn southern_hemisphere_transitions() {
+        let auckland = TimeZone::parse("NZST-12NZDT,M9.5.0,M4.1.0/3").unwrap();
+        let cases = [
+            // End at 03:00 NZDT on the first Sunday of April
+            (datetime!(2024-04-06 13:59:59 UTC), 13.0),
+            (datetime!(2024-04-06 14:00:00 UTC), 12.0),
+            // Start at 02:00 NZST on the last Sunday of September
+            (datetime!(2024-09-28 13:59:59 UTC), 12.0),
+            (datetime!(2024-09-28 14:00:00 UTC), 13.0),
+            // Daylight saving time across new year
+            (datetime!(2024-12-31 11:30:00 UTC), 13.0),
+            (datetime!(2024-12-31 12:30:00 UTC), 13.0),
+            (datetime!(2024-07-01 00:00:00 UTC), 12.0),
+        ];
+        for (instant, expected) in cases {
+            assert_eq!(hours_at(&auckland, instant), expected, "at {instant}");
+        }
+    }
+
+    #[test]
+    fn to_local_applies_offset() {
+        let auckland = TimeZone::parse("NZST-12NZDT,M9.5.0,M4.1.0/3").unwrap();
+        let local = auckland
+            .to_local(datetime!(2024-12-31 12:30:00 UTC))
+            .unwrap();
+        assert_eq!(local, datetime!(2025-01-01 01:30:00 +13));
+    }
+
+    #[test]
+    fn transition_days() {
+        let julian = TransitionDay::Julian(60);
+        assert_eq!(julian.date_in(2023).unwrap(), date!(2023 - 03 - 01));
+        assert_eq!(julian.date_in(2024).unwrap(), date!(2024 - 03 - 01));
+
+        let zero_based = TransitionDay::ZeroBased(59);
+        assert_eq!(zero_based.date_in(2023).unwrap(), date!(2023 - 03 - 01));
+        assert_eq!(zero_based.date_in(2024).unwrap(), date!(2024 - 02 - 29));
+
+        let last_sunday = TransitionDay::MonthWeekDay {
+            month: 2,
+            week: 5,
+            weekday: 0,
+        };
+        assert_eq!(last_sunday.date_in(2024).unwrap(), date!(2024 - 02 - 25));
+        assert_eq!(last_sunday.date_in(2026).unwrap(), date!(2026 - 02 - 22));
+    }
+
+    #[test]
+    fn reject_invalid_strings() {
+        let cases = [
+            ("", Error::InvalidName),
+            ("AB1", Error::InvalidName),
+            ("<UTC+3", Error::InvalidName),
+            ("UTC", Error::InvalidNumber),
+            ("UTC25", Error::InvalidNumber),
+            ("EST5EDT,M13.1.0,M11.1.0", Error::InvalidNumber),
+            ("EST5EDT,M3.2.7,M11.1.0", Error::InvalidNumber),
+            ("EST5EDT,M3.2.0", Error::UnexpectedCharacter),
+            ("EST5EDT,M3-2-0,M11.1.0", Error::UnexpectedCharacter),
+            ("EST5EDT,M3.2.0,M11.1.0x", Error::TrailingCharacters),
+        ];
+        for (text, expected) in cases {
+            let error = TimeZone::parse(text).unwrap_err();
+            assert_eq!(
+                discriminant(&error),
+                discriminant(&expected),
+                "{text:?} failed with {error:?}"
+            );
+        }
+    }
+}
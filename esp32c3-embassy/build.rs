@@ -0,0 +1,18 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Get the current time as seconds since the Unix epoch
+    let current_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // Pass the current time as an environment variable to the Rust code
+    println!("cargo:rustc-env=BUILD_TIME={}", current_time);
+}
@@ -0,0 +1,304 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Battery voltage measurement and low battery policy
+//!
+//! The battery is connected to [`PIN`] through a resistor divider, whose
+//! ratio is configured as
+//! [`battery_divider`][crussant_common::config::Config::battery_divider_thousandths].
+//! The voltage is measured once per wakeup, before WiFi is turned on, and
+//! converted to a state of charge along a typical discharge curve of a
+//! single cell LiPo battery.
+//!
+//! When the voltage drops below [`Policy::critical_millivolts`], the station
+//! stops sampling and connecting to WiFi, and only wakes up rarely until the
+//! battery is replaced or recharged above [`Policy::recovery_millivolts`].
+
+use embassy_time::Duration;
+use embassy_time::Timer;
+
+use esp_hal::analog::adc::Adc;
+use esp_hal::analog::adc::AdcCalCurve;
+use esp_hal::analog::adc::AdcConfig;
+use esp_hal::analog::adc::Attenuation;
+use esp_hal::gpio::GpioPin;
+use esp_hal::peripherals::ADC1;
+
+use uom::si::electric_potential::millivolt;
+use uom::si::f32::ElectricPotential;
+use uom::si::f32::Ratio;
+use uom::si::ratio::percent;
+
+/// GPIO of the battery voltage, on ADC1
+pub const PIN: u8 = 0;
+
+/// Number of ADC samples averaged in a measurement
+const SAMPLES: u32 = 16;
+
+/// Number of times a conversion is polled before giving up
+const ATTEMPTS: u32 = 100;
+
+/// Delay between polls of a conversion
+const POLL_DELAY: Duration = Duration::from_micros(50);
+
+/// Discharge curve of a single cell LiPo battery at rest
+///
+/// Points are voltage in millivolts and state of charge in percent, from
+/// full to empty.
+const DISCHARGE_CURVE: [(u16, u8); 21] = [
+    (4200, 100),
+    (4150, 95),
+    (4110, 90),
+    (4080, 85),
+    (4020, 80),
+    (3980, 75),
+    (3950, 70),
+    (3910, 65),
+    (3870, 60),
+    (3850, 55),
+    (3840, 50),
+    (3820, 45),
+    (3800, 40),
+    (3790, 35),
+    (3770, 30),
+    (3750, 25),
+    (3730, 20),
+    (3710, 15),
+    (3690, 10),
+    (3610, 5),
+    (3270, 0),
+];
+
+/// A battery measurement
+#[derive(Clone, Copy, Debug)]
+pub struct Battery {
+    /// Voltage
+    pub voltage: ElectricPotential,
+
+    /// State of charge
+    pub charge: Ratio,
+}
+
+impl Battery {
+    /// Create a measurement from a voltage
+    pub fn from_voltage(voltage: ElectricPotential) -> Self {
+        Self {
+            voltage,
+            charge: charge(voltage),
+        }
+    }
+}
+
+/// Return the state of charge of a battery at a voltage
+///
+/// The state of charge is interpolated linearly between the points of the
+/// discharge curve, and saturates outside of it.
+pub fn charge(voltage: ElectricPotential) -> Ratio {
+    let millivolts = voltage.get::<millivolt>();
+
+    let mut upper: Option<(u16, u8)> = None;
+    for point in DISCHARGE_CURVE {
+        let (lower_millivolts, lower_percent) = point;
+        let lower_millivolts = f32::from(lower_millivolts);
+        let lower_percent = f32::from(lower_percent);
+        if millivolts >= lower_millivolts {
+            let Some((upper_millivolts, upper_percent)) = upper else {
+                // Above the curve
+                return Ratio::new::<percent>(lower_percent);
+            };
+            let fraction =
+                (millivolts - lower_millivolts) / (f32::from(upper_millivolts) - lower_millivolts);
+            let charge = lower_percent + fraction * (f32::from(upper_percent) - lower_percent);
+            return Ratio::new::<percent>(charge);
+        }
+        upper = Some(point);
+    }
+
+    // Below the curve, or not a number
+    Ratio::new::<percent>(0.0)
+}
+
+/// Policy for a low battery
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    /// Voltage in millivolts below which the battery is critical
+    pub critical_millivolts: u16,
+
+    /// Voltage in millivolts above which a critical battery is usable again
+    ///
+    /// This is higher than [`Self::critical_millivolts`], since the voltage
+    /// of a battery recovers while the station sleeps.
+    pub recovery_millivolts: u16,
+
+    /// Duration of deep sleep while the battery is critical
+    pub critical_sleep_duration: Duration,
+}
+
+impl Policy {
+    /// Decide whether the battery is critical
+    ///
+    /// A battery that was critical at the previous wakeup stays critical
+    /// until it recovers, so that the station does not keep switching between
+    /// the two modes.
+    pub fn is_critical(&self, voltage: ElectricPotential, was_critical: bool) -> bool {
+        let millivolts = voltage.get::<millivolt>();
+        if was_critical {
+            millivolts < f32::from(self.recovery_millivolts)
+        } else {
+            millivolts < f32::from(self.critical_millivolts)
+        }
+    }
+}
+
+/// Measure the battery voltage
+///
+/// The divider ratio is the battery voltage divided by the voltage at the
+/// pin, in thousandths.
+pub async fn measure(
+    adc: ADC1,
+    pin: GpioPin<{ PIN }>,
+    divider_thousandths: u32,
+) -> Result<Battery, Error> {
+    let mut config = AdcConfig::new();
+    let mut pin =
+        config.enable_pin_with_cal::<_, AdcCalCurve<ADC1>>(pin, Attenuation::Attenuation11dB);
+    let mut adc = Adc::new(adc, config);
+
+    let mut total: u32 = 0;
+    for _ in 0..SAMPLES {
+        let mut attempts = 0;
+        let millivolts = loop {
+            // Calibrated readings are in millivolts
+            if let Ok(millivolts) = adc.read_oneshot(&mut pin) {
+                break millivolts;
+            }
+            attempts += 1;
+            if attempts == ATTEMPTS {
+                return Err(Error::Timeout);
+            }
+            Timer::after(POLL_DELAY).await;
+        };
+        total += u32::from(millivolts);
+    }
+
+    // Both factors are small enough to be exact in `f32`
+    #[allow(clippy::cast_precision_loss)]
+    let millivolts = (total as f32 / SAMPLES as f32) * (divider_thousandths as f32 / 1000.0);
+    Ok(Battery::from_voltage(ElectricPotential::new::<millivolt>(
+        millivolts,
+    )))
+}
+
+/// An error within battery measurement
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The ADC did not complete a conversion
+    Timeout,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Policy of the tests
+    const POLICY: Policy = Policy {
+        critical_millivolts: 3400,
+        recovery_millivolts: 3600,
+        critical_sleep_duration: Duration::from_secs(3600),
+    };
+
+    /// Return the state of charge in percent at a voltage in millivolts
+    fn charge_percent(millivolts: f32) -> f32 {
+        charge(ElectricPotential::new::<millivolt>(millivolts)).get::<percent>()
+    }
+
+    /// Check whether a voltage in millivolts is critical
+    fn is_critical(millivolts: f32, was_critical: bool) -> bool {
+        POLICY.is_critical(
+            ElectricPotential::new::<millivolt>(millivolts),
+            was_critical,
+        )
+    }
+
+    /// Check that two percentages are equal up to rounding errors
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn charge_at_curve_points() {
+        for (millivolts, expected) in DISCHARGE_CURVE {
+            assert_close(charge_percent(f32::from(millivolts)), f32::from(expected));
+        }
+    }
+
+    #[test]
+    fn charge_between_curve_points() {
+        // Halfway between 3840mV at 50% and 3850mV at 55%
+        assert_close(charge_percent(3845.0), 52.5);
+        // A quarter of the way from 3270mV at 0% to 3610mV at 5%
+        assert_close(charge_percent(3355.0), 1.25);
+        // Just below full
+        assert_close(charge_percent(4190.0), 99.0);
+    }
+
+    #[test]
+    fn charge_is_monotonic() {
+        let mut previous = 0.0;
+        for millivolts in 3000_u16..4400 {
+            let charge = charge_percent(f32::from(millivolts));
+            assert!(charge >= previous, "{millivolts}mV");
+            previous = charge;
+        }
+    }
+
+    #[test]
+    fn charge_saturates() {
+        assert_close(charge_percent(4201.0), 100.0);
+        assert_close(charge_percent(5000.0), 100.0);
+        assert_close(charge_percent(f32::INFINITY), 100.0);
+        assert_close(charge_percent(3269.0), 0.0);
+        assert_close(charge_percent(0.0), 0.0);
+        assert_close(charge_percent(-100.0), 0.0);
+        assert_close(charge_percent(f32::NEG_INFINITY), 0.0);
+    }
+
+    #[test]
+    fn charge_of_nan_is_zero() {
+        assert_close(charge_percent(f32::NAN), 0.0);
+    }
+
+    #[test]
+    fn critical_below_threshold() {
+        assert!(!is_critical(3700.0, false));
+        assert!(!is_critical(3400.0, false));
+        assert!(is_critical(3399.0, false));
+        assert!(is_critical(0.0, false));
+    }
+
+    #[test]
+    fn critical_until_recovery() {
+        // Between the two thresholds the previous state is kept
+        assert!(!is_critical(3500.0, false));
+        assert!(is_critical(3500.0, true));
+        assert!(is_critical(3599.0, true));
+        assert!(!is_critical(3600.0, true));
+    }
+
+    #[test]
+    fn critical_state_over_wakeups() {
+        let voltages = [3800.0, 3450.0, 3390.0, 3450.0, 3550.0, 3650.0, 3450.0];
+        let expected = [false, false, true, true, true, false, false];
+        let mut critical = false;
+        for (millivolts, expected) in voltages.into_iter().zip(expected) {
+            critical = is_critical(millivolts, critical);
+            assert_eq!(critical, expected, "{millivolts}mV");
+        }
+    }
+}
@@ -0,0 +1,793 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Captive portal for entering WiFi credentials
+//!
+//! The portal runs on an open access point and consists of three small
+//! servers:
+//!
+//! * a DHCP server leasing addresses from a small pool and announcing the
+//!   portal URL (RFC 8910),
+//! * a DNS server resolving every name to the portal, so that operating
+//!   systems detect it and open it automatically,
+//! * an HTTP server answering every `GET` with a form, and accepting the form
+//!   with `POST /`.
+//!
+//! Submitted networks are signalled on [`SUBMITTED`].
+
+use core::fmt::Error as FmtError;
+use core::fmt::Write;
+use core::str::from_utf8;
+
+use log::debug;
+use log::info;
+use log::warn;
+
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::PacketMetadata;
+use embassy_net::udp::UdpSocket;
+use embassy_net::IpEndpoint;
+use embassy_net::Ipv4Address;
+use embassy_net::Stack;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use embassy_time::Duration;
+
+use embedded_io_async::Read as _;
+use embedded_io_async::Write as _;
+
+use esp_wifi::wifi::WifiApDevice;
+use esp_wifi::wifi::WifiDevice;
+
+use heapless::String;
+use heapless::Vec;
+
+use crate::credentials::Network;
+use crate::http_server::write_response_head;
+use crate::http_server::Status;
+use crate::http_server::HTTP_PORT;
+
+/// SSID of the access point
+pub const ACCESS_POINT_SSID: &str = "Crussant-Setup";
+
+/// Address of the portal on the access point network
+pub const ADDRESS: [u8; 4] = [192, 168, 4, 1];
+
+/// Prefix length of the access point network
+pub const PREFIX_LENGTH: u8 = 24;
+
+/// Signal carrying networks submitted through the portal
+pub static SUBMITTED: Signal<CriticalSectionRawMutex, Network> = Signal::new();
+
+/// UDP port of DHCP servers
+const DHCP_SERVER_PORT: u16 = 67;
+
+/// UDP port of DHCP clients
+const DHCP_CLIENT_PORT: u16 = 68;
+
+/// UDP port of DNS servers
+const DNS_PORT: u16 = 53;
+
+/// Number of addresses leased, starting from the one after [`ADDRESS`]
+const POOL_SIZE: usize = 8;
+
+/// Duration of a DHCP lease in seconds
+const LEASE_SECONDS: u32 = 60 * 60;
+
+/// TTL of DNS answers in seconds
+const DNS_TTL_SECONDS: u32 = 60;
+
+/// Size of a UDP packet buffer
+const PACKET_SIZE: usize = 576;
+
+/// Size of the request buffer
+const REQUEST_SIZE: usize = 1024;
+
+/// Size of the response body buffer
+const BODY_SIZE: usize = 2048;
+
+/// Size of the response head buffer
+const HEAD_SIZE: usize = 256;
+
+/// Time to wait for a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Offset of the options in a DHCP packet
+const DHCP_OPTIONS_OFFSET: usize = 240;
+
+/// Magic cookie preceding DHCP options
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// DHCP message types
+mod message_type {
+    /// DHCPDISCOVER
+    pub const DISCOVER: u8 = 1;
+
+    /// DHCPOFFER
+    pub const OFFER: u8 = 2;
+
+    /// DHCPREQUEST
+    pub const REQUEST: u8 = 3;
+
+    /// DHCPACK
+    pub const ACK: u8 = 5;
+}
+
+/// URL of the portal announced over DHCP
+const PORTAL_URL: &str = "http://192.168.4.1/";
+
+/// Addresses leased to clients
+#[derive(Debug, Default)]
+pub struct Leases {
+    /// Hardware addresses of clients, by position in the pool
+    clients: Vec<[u8; 6], POOL_SIZE>,
+
+    /// Position in the pool to reuse when it is full
+    next: usize,
+}
+
+impl Leases {
+    /// Create an empty pool
+    pub const fn new() -> Self {
+        Self {
+            clients: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Find the address leased to a client, leasing a new one if needed
+    ///
+    /// When the pool is full, the oldest lease is reused.
+    pub fn address_for(&mut self, client: [u8; 6]) -> [u8; 4] {
+        let position = if let Some(position) = self.clients.iter().position(|c| *c == client) {
+            position
+        } else if self.clients.push(client).is_ok() {
+            self.clients.len() - 1
+        } else {
+            let position = self.next;
+            if let Some(slot) = self.clients.get_mut(position) {
+                *slot = client;
+            }
+            self.next = (self.next + 1) % POOL_SIZE;
+            position
+        };
+        let [a, b, c, d] = ADDRESS;
+        // Position is lower than POOL_SIZE, which fits in a byte
+        #[allow(clippy::cast_possible_truncation)]
+        [a, b, c, d + 1 + position as u8]
+    }
+}
+
+/// Build a reply to a DHCP request
+///
+/// DHCPDISCOVER is answered with DHCPOFFER and DHCPREQUEST with DHCPACK.
+/// Requests for addresses other than the offered one are acknowledged anyway
+/// with the offered address, which clients accept after renewing.
+/// Return the length of the reply, or `None` if the packet is not answered.
+pub fn dhcp_reply(request: &[u8], leases: &mut Leases, output: &mut [u8]) -> Option<usize> {
+    let header = request.get(..DHCP_OPTIONS_OFFSET)?;
+    let is_request = header.first() == Some(&1);
+    let is_ethernet = header.get(1..3) == Some(&[1, 6]);
+    let has_cookie = header.get(236..240) == Some(&DHCP_MAGIC_COOKIE);
+    if !is_request || !is_ethernet || !has_cookie {
+        return None;
+    }
+
+    let reply_type = match dhcp_message_type(request.get(DHCP_OPTIONS_OFFSET..)?)? {
+        message_type::DISCOVER => message_type::OFFER,
+        message_type::REQUEST => message_type::ACK,
+        _ => return None,
+    };
+
+    let client: [u8; 6] = header.get(28..34)?.try_into().ok()?;
+    let address = leases.address_for(client);
+    debug!("DHCP reply {reply_type} to {client:02x?} with {address:?}");
+
+    let mut reply = Vec::<u8, PACKET_SIZE>::new();
+    let mut push = |bytes: &[u8]| reply.extend_from_slice(bytes).ok();
+
+    // op, htype, hlen, hops
+    push(&[2, 1, 6, 0])?;
+    // xid, secs, flags
+    push(header.get(4..12)?)?;
+    // ciaddr
+    push(&[0; 4])?;
+    // yiaddr
+    push(&address)?;
+    // siaddr
+    push(&ADDRESS)?;
+    // giaddr, chaddr
+    push(header.get(24..44)?)?;
+    // sname, file
+    push(&[0; 64 + 128])?;
+    push(&DHCP_MAGIC_COOKIE)?;
+
+    push(&[53, 1, reply_type])?;
+    push(&[54, 4])?;
+    push(&ADDRESS)?;
+    push(&[51, 4])?;
+    push(&LEASE_SECONDS.to_be_bytes())?;
+    push(&[1, 4])?;
+    push(&subnet_mask())?;
+    push(&[3, 4])?;
+    push(&ADDRESS)?;
+    push(&[6, 4])?;
+    push(&ADDRESS)?;
+    // Captive portal URL, RFC 8910
+    push(&[114, u8::try_from(PORTAL_URL.len()).ok()?])?;
+    push(PORTAL_URL.as_bytes())?;
+    push(&[255])?;
+
+    let output = output.get_mut(..reply.len())?;
+    output.copy_from_slice(&reply);
+    Some(reply.len())
+}
+
+/// Find the message type among DHCP options
+fn dhcp_message_type(mut options: &[u8]) -> Option<u8> {
+    loop {
+        match options {
+            [255, ..] | [] => return None,
+            [0, rest @ ..] => options = rest,
+            [53, 1, message_type, ..] => return Some(*message_type),
+            [_code, length, rest @ ..] => options = rest.get(usize::from(*length)..)?,
+            [_] => return None,
+        }
+    }
+}
+
+/// Compute the subnet mask of the access point network
+fn subnet_mask() -> [u8; 4] {
+    (u32::MAX << (32 - u32::from(PREFIX_LENGTH))).to_be_bytes()
+}
+
+/// Build a reply to a DNS query
+///
+/// Queries of type A are answered with [`ADDRESS`], other queries with no
+/// answer.
+/// Return the length of the reply, or `None` if the packet is not answered.
+pub fn dns_reply(query: &[u8], output: &mut [u8]) -> Option<usize> {
+    let header = query.get(..12)?;
+    let is_query = header.get(2).is_some_and(|flags| flags & 0x80 == 0);
+    let question_count = u16::from_be_bytes(header.get(4..6)?.try_into().ok()?);
+    if !is_query || question_count != 1 {
+        return None;
+    }
+
+    // Skip the name of the question
+    let mut position = 12;
+    loop {
+        let length = usize::from(*query.get(position)?);
+        position += 1;
+        if length == 0 {
+            break;
+        }
+        if length & 0xc0 != 0 {
+            return None;
+        }
+        position += length;
+    }
+    let question_type = query.get(position..position + 2)?;
+    let question_end = position + 4;
+    let question = query.get(12..question_end)?;
+    let is_address_query = question_type == [0, 1];
+
+    let mut reply = Vec::<u8, PACKET_SIZE>::new();
+    let mut push = |bytes: &[u8]| reply.extend_from_slice(bytes).ok();
+
+    // id
+    push(header.get(..2)?)?;
+    // QR, opcode and RD from the query, AA
+    push(&[0x84 | (header.get(2)? & 0x79), 0x80])?;
+    push(&[0, 1, 0, u8::from(is_address_query), 0, 0, 0, 0])?;
+    push(question)?;
+    if is_address_query {
+        // Pointer to the name in the question, type A, class IN
+        push(&[0xc0, 12, 0, 1, 0, 1])?;
+        push(&DNS_TTL_SECONDS.to_be_bytes())?;
+        push(&[0, 4])?;
+        push(&ADDRESS)?;
+    }
+
+    let output = output.get_mut(..reply.len())?;
+    output.copy_from_slice(&reply);
+    Some(reply.len())
+}
+
+/// A request to the portal
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Show the form
+    Form,
+
+    /// Submit a network
+    Submit(Network),
+
+    /// Submit an invalid form
+    Invalid,
+
+    /// Any other request
+    Unsupported,
+}
+
+/// Parse a request to the portal
+///
+/// Return `None` if the request is not complete yet.
+pub fn parse_request(buffer: &[u8]) -> Result<Option<Request>, Error> {
+    let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let head = from_utf8(buffer.get(..end).unwrap_or_default()).map_err(|_| Error::Malformed)?;
+    let body = buffer.get(end + 4..).unwrap_or_default();
+
+    let mut lines = head.lines();
+    let request_line = lines.next().ok_or(Error::Malformed)?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(Error::Malformed);
+    };
+
+    match method {
+        "GET" => Ok(Some(Request::Form)),
+        "POST" if target == "/" => {
+            let content_length = lines
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+                .map(|(_, value)| value.trim().parse::<usize>())
+                .transpose()
+                .map_err(|_| Error::Malformed)?
+                .unwrap_or(0);
+            let Some(body) = body.get(..content_length) else {
+                return Ok(None);
+            };
+            let body = from_utf8(body).map_err(|_| Error::Malformed)?;
+            Ok(Some(parse_form(body)))
+        }
+        _ => Ok(Some(Request::Unsupported)),
+    }
+}
+
+/// Parse a submitted form
+fn parse_form(body: &str) -> Request {
+    let mut ssid = String::<32>::new();
+    let mut password = String::<64>::new();
+    for pair in body.split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let result = match key {
+            "ssid" => decode_form_value(value, &mut ssid),
+            "password" => decode_form_value(value, &mut password),
+            _ => Ok(()),
+        };
+        if result.is_err() {
+            return Request::Invalid;
+        }
+    }
+    Network::new(&ssid, &password).map_or(Request::Invalid, Request::Submit)
+}
+
+/// Decode a value of a URL-encoded form
+pub fn decode_form_value<const N: usize>(value: &str, output: &mut String<N>) -> Result<(), Error> {
+    let mut bytes = Vec::<u8, N>::new();
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let high = input.next().and_then(hex_digit).ok_or(Error::Malformed)?;
+                let low = input.next().and_then(hex_digit).ok_or(Error::Malformed)?;
+                (high << 4) | low
+            }
+            byte => byte,
+        };
+        bytes.push(decoded).map_err(|_| Error::TooLarge)?;
+    }
+    let text = from_utf8(&bytes).map_err(|_| Error::Malformed)?;
+    output.clear();
+    output.push_str(text).map_err(|()| Error::TooLarge)
+}
+
+/// Parse a hexadecimal digit
+fn hex_digit(byte: u8) -> Option<u8> {
+    char::from(byte)
+        .to_digit(16)
+        .and_then(|digit| u8::try_from(digit).ok())
+}
+
+/// Write the form page, with an optional message
+pub fn write_form_page(output: &mut impl Write, message: Option<&str>) -> Result<(), FmtError> {
+    output.write_str(concat!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">",
+        "<meta name=\"viewport\" content=\"width=device-width\">",
+        "<title>Crussant setup</title></head><body>",
+        "<h1>Crussant setup</h1>",
+    ))?;
+    if let Some(message) = message {
+        write!(output, "<p><strong>{message}</strong></p>")?;
+    }
+    output.write_str(concat!(
+        "<form method=\"post\" action=\"/\">",
+        "<p><label>Network <input name=\"ssid\" maxlength=\"32\" required></label></p>",
+        "<p><label>Password <input name=\"password\" type=\"password\" maxlength=\"64\"></label></p>",
+        "<p><button type=\"submit\">Save and restart</button></p>",
+        "</form></body></html>",
+    ))
+}
+
+/// Write the page shown after a network was submitted
+pub fn write_saved_page(output: &mut impl Write, network: &Network) -> Result<(), FmtError> {
+    output.write_str(concat!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">",
+        "<title>Crussant setup</title></head><body>",
+        "<h1>Crussant setup</h1>",
+        "<p>Network <code>",
+    ))?;
+    write_escaped_html(output, &network.ssid)?;
+    output.write_str("</code> was saved, the station is restarting.</p></body></html>")
+}
+
+/// Write text escaping HTML special characters
+fn write_escaped_html(output: &mut impl Write, text: &str) -> Result<(), FmtError> {
+    for c in text.chars() {
+        match c {
+            '<' => output.write_str("&lt;")?,
+            '>' => output.write_str("&gt;")?,
+            '&' => output.write_str("&amp;")?,
+            '"' => output.write_str("&quot;")?,
+            c => output.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+/// Task for the DHCP server
+#[embassy_executor::task]
+pub async fn dhcp_task(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    let mut rx_metadata = [PacketMetadata::EMPTY; 2];
+    let mut tx_metadata = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0_u8; PACKET_SIZE];
+    let mut tx_buffer = [0_u8; PACKET_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_metadata,
+        &mut rx_buffer,
+        &mut tx_metadata,
+        &mut tx_buffer,
+    );
+    if let Err(error) = socket.bind(DHCP_SERVER_PORT) {
+        warn!("Cannot bind DHCP server: {error:?}");
+        return;
+    }
+
+    let mut leases = Leases::new();
+    let mut request = [0_u8; PACKET_SIZE];
+    let mut reply = [0_u8; PACKET_SIZE];
+    loop {
+        let Ok((length, _)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+        let request = request.get(..length).unwrap_or_default();
+        if let Some(length) = dhcp_reply(request, &mut leases, &mut reply) {
+            let broadcast = IpEndpoint::new(Ipv4Address::BROADCAST.into(), DHCP_CLIENT_PORT);
+            if let Err(error) = socket
+                .send_to(reply.get(..length).unwrap_or_default(), broadcast)
+                .await
+            {
+                warn!("Cannot send DHCP reply: {error:?}");
+            }
+        }
+    }
+}
+
+/// Task for the DNS server
+#[embassy_executor::task]
+pub async fn dns_task(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    let mut rx_metadata = [PacketMetadata::EMPTY; 4];
+    let mut tx_metadata = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0_u8; PACKET_SIZE];
+    let mut tx_buffer = [0_u8; PACKET_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_metadata,
+        &mut rx_buffer,
+        &mut tx_metadata,
+        &mut tx_buffer,
+    );
+    if let Err(error) = socket.bind(DNS_PORT) {
+        warn!("Cannot bind DNS server: {error:?}");
+        return;
+    }
+
+    let mut query = [0_u8; PACKET_SIZE];
+    let mut reply = [0_u8; PACKET_SIZE];
+    loop {
+        let Ok((length, endpoint)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        let query = query.get(..length).unwrap_or_default();
+        if let Some(length) = dns_reply(query, &mut reply) {
+            if let Err(error) = socket
+                .send_to(reply.get(..length).unwrap_or_default(), endpoint)
+                .await
+            {
+                warn!("Cannot send DNS reply: {error:?}");
+            }
+        }
+    }
+}
+
+/// Task for the HTTP server
+#[embassy_executor::task]
+pub async fn http_task(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    let mut rx_buffer = [0_u8; REQUEST_SIZE];
+    let mut tx_buffer = [0_u8; BODY_SIZE];
+
+    info!("Serve portal on http://{ADDRESS:?}:{HTTP_PORT}");
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(REQUEST_TIMEOUT));
+
+        if let Err(error) = socket.accept(HTTP_PORT).await {
+            warn!("Cannot accept connection: {error:?}");
+            continue;
+        }
+
+        match serve(&mut socket).await {
+            Ok(Some(network)) => {
+                info!("Received network {} from portal", network.ssid);
+                SUBMITTED.signal(network);
+            }
+            Ok(None) => {}
+            Err(error) => warn!("Cannot serve request: {error:?}"),
+        }
+
+        socket.close();
+        if let Err(error) = socket.flush().await {
+            debug!("Cannot flush socket: {error:?}");
+        }
+    }
+}
+
+/// Serve a request on a connection, returning the submitted network if any
+async fn serve(socket: &mut TcpSocket<'_>) -> Result<Option<Network>, Error> {
+    let mut buffer = [0_u8; REQUEST_SIZE];
+    let mut length = 0;
+    let request = loop {
+        let free = buffer.get_mut(length..).unwrap_or_default();
+        if free.is_empty() {
+            return Err(Error::TooLarge);
+        }
+        let read = socket.read(free).await.map_err(|_| Error::Connection)?;
+        if read == 0 {
+            return Err(Error::Connection);
+        }
+        length += read;
+        if let Some(request) = parse_request(buffer.get(..length).unwrap_or_default())? {
+            break request;
+        }
+    };
+
+    let mut body = String::<BODY_SIZE>::new();
+    let (status, network) = match request {
+        Request::Form => (Status::Ok, None),
+        Request::Submit(network) => (Status::Ok, Some(network)),
+        Request::Invalid => (Status::BadRequest, None),
+        Request::Unsupported => (Status::MethodNotAllowed, None),
+    };
+    match (&network, status) {
+        (Some(network), _) => write_saved_page(&mut body, network),
+        (None, Status::BadRequest) => {
+            write_form_page(&mut body, Some("Invalid network name or password"))
+        }
+        (None, _) => write_form_page(&mut body, None),
+    }
+    .map_err(|_| Error::TooLarge)?;
+
+    let mut head = String::<HEAD_SIZE>::new();
+    write_response_head(&mut head, status, "text/html; charset=utf-8", body.len())
+        .map_err(|_| Error::TooLarge)?;
+    socket
+        .write_all(head.as_bytes())
+        .await
+        .map_err(|_| Error::Connection)?;
+    socket
+        .write_all(body.as_bytes())
+        .await
+        .map_err(|_| Error::Connection)?;
+
+    Ok(network)
+}
+
+/// An error within the captive portal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The request is malformed
+    Malformed,
+
+    /// The request or the response does not fit in its buffer
+    TooLarge,
+
+    /// The connection failed or was closed
+    Connection,
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    /// Decode a form value into a buffer of a capacity
+    fn decoded<const N: usize>(value: &str) -> Result<String<N>, Error> {
+        let mut output = String::new();
+        decode_form_value(value, &mut output).map(|()| output)
+    }
+
+    /// Parse a complete request
+    fn parse(request: &[u8]) -> Request {
+        parse_request(request).unwrap().unwrap()
+    }
+
+    /// Build a form submission with a body
+    fn submission(body: &str) -> std::vec::Vec<u8> {
+        std::format!(
+            "POST / HTTP/1.1\r\nHost: 192.168.4.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .into_bytes()
+    }
+
+    /// Create a network
+    fn network(ssid: &str, password: &str) -> Network {
+        Network::new(ssid, password).unwrap()
+    }
+
+    #[test]
+    fn decode_plain_and_escaped_values() {
+        assert_eq!(decoded::<32>("Home").unwrap(), "Home");
+        assert_eq!(decoded::<32>("").unwrap(), "");
+        assert_eq!(decoded::<32>("My+Home").unwrap(), "My Home");
+        assert_eq!(decoded::<32>("a%2Bb%26c%3Dd").unwrap(), "a+b&c=d");
+        assert_eq!(decoded::<32>("%2b%2B").unwrap(), "++");
+        assert_eq!(decoded::<32>("100%25").unwrap(), "100%");
+        assert_eq!(decoded::<32>("%C3%A9t%C3%A9").unwrap(), "été");
+        assert_eq!(decoded::<32>("caf\u{e9}").unwrap(), "café");
+    }
+
+    #[test]
+    fn decode_rejects_invalid_escapes() {
+        for value in ["%", "%2", "abc%4", "%G1", "%1G", "% 1", "%%41", "%\u{e9}1"] {
+            assert_eq!(decoded::<32>(value), Err(Error::Malformed), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn decode_rejects_invalid_utf8() {
+        assert_eq!(decoded::<32>("%C3"), Err(Error::Malformed));
+        assert_eq!(decoded::<32>("%FF%FE"), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn decode_limits_decoded_length() {
+        assert_eq!(decoded::<4>("%41%42%43%44").unwrap(), "ABCD");
+        assert_eq!(decoded::<4>("%41%42%43%44%45"), Err(Error::TooLarge));
+        assert_eq!(decoded::<4>("%C3%A9%C3%A9").unwrap(), "éé");
+        assert_eq!(decoded::<4>("%C3%A9%C3%A9a"), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn decode_leaves_output_unchanged_on_error() {
+        let mut output = String::<8>::try_from("kept").unwrap();
+        assert_eq!(decode_form_value("%zz", &mut output), Err(Error::Malformed));
+        assert_eq!(output, "kept");
+    }
+
+    #[test]
+    fn parse_form_submissions() {
+        assert_eq!(
+            parse(&submission("ssid=My+Home&password=s%C3%A9cret%21")),
+            Request::Submit(network("My Home", "sécret!"))
+        );
+        assert_eq!(
+            parse(&submission("password=&ssid=Open&submit=")),
+            Request::Submit(network("Open", ""))
+        );
+        assert_eq!(
+            parse(&submission("ssid=Home")),
+            Request::Submit(network("Home", ""))
+        );
+    }
+
+    #[test]
+    fn parse_invalid_form_submissions() {
+        let ssid = "s".repeat(32);
+        let password = "p".repeat(64);
+        assert_eq!(
+            parse(&submission(&std::format!(
+                "ssid={ssid}&password={password}"
+            ))),
+            Request::Submit(network(&ssid, &password))
+        );
+
+        let bodies = [
+            std::format!("ssid={ssid}s&password=secret"),
+            std::format!("ssid=Home&password={password}p"),
+            std::format!("ssid={}", "%C3%A9".repeat(17)),
+            "ssid=&password=secret".into(),
+            "password=secret".into(),
+            "ssid=Home%2&password=secret".into(),
+            "ssid=%FF".into(),
+        ];
+        for body in bodies {
+            assert_eq!(parse(&submission(&body)), Request::Invalid, "{body}");
+        }
+    }
+
+    #[test]
+    fn parse_incomplete_submissions() {
+        let request = submission("ssid=Home&password=secret");
+        for length in 0..request.len() {
+            assert_eq!(
+                parse_request(request.get(..length).unwrap()),
+                Ok(None),
+                "request truncated to {length} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_content_length_header() {
+        assert_eq!(
+            parse(b"POST / HTTP/1.1\r\ncontent-length:  9 \r\n\r\nssid=Home"),
+            Request::Submit(network("Home", ""))
+        );
+        // Bytes after the announced body are ignored
+        assert_eq!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nssid=Home&password=x"),
+            Request::Submit(network("Home", ""))
+        );
+        assert_eq!(parse(b"POST / HTTP/1.1\r\n\r\nssid=Home"), Request::Invalid);
+        assert_eq!(
+            parse_request(b"POST / HTTP/1.1\r\nContent-Length: nine\r\n\r\nssid=Home"),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn parse_other_requests() {
+        assert_eq!(parse(b"GET / HTTP/1.1\r\n\r\n"), Request::Form);
+        assert_eq!(
+            parse(b"GET /generate_204 HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+            Request::Form
+        );
+        assert_eq!(
+            parse(b"POST /login HTTP/1.1\r\nContent-Length: 0\r\n\r\n"),
+            Request::Unsupported
+        );
+        assert_eq!(parse(b"PUT / HTTP/1.1\r\n\r\n"), Request::Unsupported);
+        assert_eq!(parse_request(b"GET\r\n\r\n"), Err(Error::Malformed));
+        assert_eq!(
+            parse_request(b"GET /\xff HTTP/1.1\r\n\r\n"),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn saved_page_escapes_the_ssid() {
+        let mut page = String::<BODY_SIZE>::new();
+        write_saved_page(&mut page, &network("<b>\"Tom & Jerry\"</b>", "")).unwrap();
+        assert!(page.contains("<code>&lt;b&gt;&quot;Tom &amp; Jerry&quot;&lt;/b&gt;</code>"));
+    }
+
+    #[test]
+    fn pages_fit_in_body() {
+        let mut page = String::<BODY_SIZE>::new();
+        write_form_page(&mut page, Some("Invalid network name or password")).unwrap();
+        page.clear();
+        write_saved_page(&mut page, &network(&"&".repeat(32), "")).unwrap();
+    }
+}
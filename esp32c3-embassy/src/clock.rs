@@ -9,94 +9,261 @@
 use embassy_time::Duration;
 use embassy_time::Instant;
 
-use esp_hal::macros::ram;
+use log::info;
+use log::warn;
 
 use time::error::ComponentRange as TimeComponentRange;
 use time::OffsetDateTime;
-use time::UtcOffset;
 
-use crate::http::Client as HttpClient;
-use crate::worldtimeapi::Error as WorldTimeApiError;
-use crate::worldtimeapi::WorldTimeApiClient as _;
+use crate::drift::Drift;
+use crate::external_rtc::Error as ExternalRtcError;
+use crate::external_rtc::Rtc;
+use crate::retained::SavedClock;
+use crate::synchronization::Source;
+use crate::synchronization::Status as SynchronizationStatus;
+use crate::time_source::from_epoch_micros;
+use crate::time_source::to_epoch_micros;
+use crate::time_source::TimeSource;
+use crate::tz::Error as TimeZoneError;
+use crate::tz::TimeZone;
 
-/// Stored boot time between deep sleep cycles
-///
-/// This is a statically allocated variable and it is placed in the RTC Fast
-/// memory, which survives deep sleep.
-#[ram(rtc_fast)]
-static mut BOOT_TIME: (u64, i32) = (0, 0);
+/// Accuracy of a time entered manually
+const MANUAL_ACCURACY: Duration = Duration::from_secs(1);
 
 /// A clock
 #[derive(Clone, Debug)]
 pub struct Clock {
-    /// The boot time in Unix epoch
+    /// The boot time in microseconds since Unix epoch
     boot_time: u64,
 
-    /// The time offset
-    offset: UtcOffset,
+    /// The time zone
+    time_zone: TimeZone,
+
+    /// The estimated drift
+    drift: Drift,
+
+    /// The source of the last synchronization
+    source: Source,
+
+    /// The accuracy of the source at the last synchronization
+    accuracy: Duration,
+
+    /// Whether the last synchronization attempt failed
+    uncertain: bool,
 }
 
 impl Clock {
-    /// Create a new clock
-    pub fn new(current_time: u64, offset: UtcOffset) -> Self {
-        let from_boot = Instant::now().as_secs();
+    /// Create a new clock synchronized now
+    ///
+    /// The current time is in microseconds since Unix epoch.
+    pub fn new(current_time: u64, time_zone: TimeZone, source: Source, accuracy: Duration) -> Self {
+        Self::with_drift(
+            current_time,
+            time_zone,
+            Drift::new(current_time),
+            source,
+            accuracy,
+            false,
+        )
+    }
+
+    /// Create a new clock with a drift estimate
+    fn with_drift(
+        current_time: u64,
+        time_zone: TimeZone,
+        drift: Drift,
+        source: Source,
+        accuracy: Duration,
+        uncertain: bool,
+    ) -> Self {
+        let from_boot = Instant::now().as_micros();
         let boot_time = current_time - from_boot;
 
-        Self { boot_time, offset }
+        Self {
+            boot_time,
+            time_zone,
+            drift,
+            source,
+            accuracy,
+            uncertain,
+        }
     }
 
     /// Return the current time
     pub fn now(&self) -> Result<OffsetDateTime, Error> {
-        let epoch = self.now_as_epoch();
-        #[allow(clippy::cast_possible_wrap)]
-        let utc = OffsetDateTime::from_unix_timestamp(epoch as i64)?;
-        let local = utc
-            .checked_to_offset(self.offset)
-            .ok_or(Error::InvalidInOffset)?;
+        let utc = from_epoch_micros(self.now_as_epoch_micros())?;
+        let local = self.time_zone.to_local(utc)?;
         Ok(local)
     }
 
-    /// Create a new clock by synchronizing with a server
-    pub async fn from_server(
-        http_client: &mut HttpClient,
-        // stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
-    ) -> Result<Self, Error> {
-        let now = http_client.fetch_current_time().await?;
+    /// Create a new clock from the first source that reports the time
+    ///
+    /// Sources are tried in order.
+    /// A clock set from the build time is marked as uncertain.
+    pub async fn from_sources<S>(sources: &mut [S], time_zone: TimeZone) -> Result<Self, Error>
+    where
+        S: TimeSource,
+    {
+        for source in sources {
+            let kind = source.source();
+            match source.fetch().await {
+                Ok(time) => {
+                    info!(
+                        "Time from {:?}: {} ± {} ms",
+                        kind,
+                        time.now,
+                        time.accuracy.as_millis()
+                    );
+                    let current_time = to_epoch_micros(time.now);
+                    let mut clock = Self::new(current_time, time_zone, kind, time.accuracy);
+                    if kind == Source::BuildTime {
+                        clock.mark_uncertain();
+                    }
+                    return Ok(clock);
+                }
+                Err(error) => warn!("Cannot get time from {kind:?}: {error:?}"),
+            }
+        }
 
-        let current_time = now.unix_timestamp();
+        Err(Error::NoTimeSource)
+    }
 
-        #[allow(clippy::cast_sign_loss)]
-        let current_time = current_time as u64;
+    /// Set the clock to a time entered manually
+    ///
+    /// The drift estimate is kept, but not calibrated since a manual time is
+    /// not accurate enough.
+    pub fn set_manually(&mut self, now: OffsetDateTime) {
+        *self = Self::with_drift(
+            to_epoch_micros(now),
+            self.time_zone,
+            self.drift,
+            Source::Manual,
+            MANUAL_ACCURACY,
+            false,
+        );
+    }
 
-        let offset = now.offset();
+    /// Carry over the history of the clock this one replaces
+    ///
+    /// A clock set from a network source is used to calibrate the drift
+    /// estimate.
+    /// Otherwise the previous drift estimate is kept for the next
+    /// calibration, together with the source of the previous synchronization
+    /// it refers to, and the clock is marked as uncertain since the
+    /// synchronization failed.
+    pub fn continue_from(&mut self, previous: &Self) {
+        if self.source.is_network() {
+            self.calibrate(previous);
+        } else {
+            self.drift = previous.drift;
+            self.source = previous.source;
+            self.mark_uncertain();
+        }
+    }
 
-        Ok(Self::new(current_time, offset))
+    /// Set an external real-time clock to the current time
+    pub async fn write_to_external_rtc(&self, rtc: &mut impl Rtc) -> Result<(), Error> {
+        let now = from_epoch_micros(self.now_as_epoch_micros())?;
+        rtc.write_time(now).await?;
+        Ok(())
     }
 
-    /// Initialize clock from RTC Fast memory
-    pub fn from_rtc_memory() -> Option<Self> {
-        // SAFETY:
-        // There is only one thread
-        let (now, offset_in_seconds) = unsafe { BOOT_TIME };
-        let offset = UtcOffset::from_whole_seconds(offset_in_seconds).ok();
+    /// Initialize clock from retained state in RTC Fast memory
+    pub fn from_rtc_memory(saved: Option<&SavedClock>, time_zone: TimeZone) -> Option<Self> {
+        let saved = saved?;
+        Some(Self::with_drift(
+            saved.wakeup_time,
+            time_zone,
+            saved.drift,
+            saved.source,
+            Duration::from_micros(saved.accuracy),
+            saved.uncertain,
+        ))
+    }
 
-        if now == 0 {
-            None
-        } else {
-            offset.map(|offset| Self::new(now, offset))
-        }
+    /// Store clock into retained state in RTC Fast memory
+    ///
+    /// The sleep duration is corrected by the estimated drift rate.
+    pub fn save_to_rtc_memory(
+        &self,
+        saved: &mut Option<SavedClock>,
+        expected_sleep_duration: Duration,
+    ) {
+        let now = self.now_as_epoch_micros();
+        let sleep_duration = self.drift.corrected(expected_sleep_duration.as_micros());
+        *saved = Some(SavedClock {
+            wakeup_time: now + sleep_duration,
+            sleep_duration: expected_sleep_duration.as_micros(),
+            drift: self.drift,
+            source: self.source,
+            accuracy: self.accuracy.as_micros(),
+            uncertain: self.uncertain,
+        });
     }
 
-    /// Store clock into RTC Fast memory
-    pub fn save_to_rtc_memory(&self, expected_sleep_duration: Duration) {
-        let now = self.now_as_epoch();
-        let then = now + expected_sleep_duration.as_secs();
-        let offset_in_seconds = self.offset.whole_seconds();
-        // SAFETY:
-        // There is only one thread
-        unsafe {
-            BOOT_TIME = (then, offset_in_seconds);
-        }
+    /// Update the drift estimate of a freshly synchronized clock from the
+    /// clock it replaces
+    ///
+    /// The difference between the two clocks is the offset accumulated since
+    /// the previous synchronization.
+    fn calibrate(&mut self, previous: &Self) {
+        let now = self.now_as_epoch_micros();
+        #[allow(clippy::cast_possible_wrap)]
+        let offset = now as i64 - previous.now_as_epoch_micros() as i64;
+
+        let mut drift = previous.drift;
+        drift.record_synchronization(now, offset);
+        self.drift = drift;
+    }
+
+    /// Return the estimated drift
+    pub fn drift(&self) -> &Drift {
+        &self.drift
+    }
+
+    /// Return the source of the last synchronization
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    /// Return the accuracy of the source at the last synchronization
+    pub fn accuracy(&self) -> Duration {
+        self.accuracy
+    }
+
+    /// Return the time elapsed since the last synchronization
+    pub fn since_last_synchronization(&self) -> Duration {
+        let now = self.now_as_epoch_micros();
+        Duration::from_micros(now.saturating_sub(self.drift.last_synchronization))
+    }
+
+    /// Mark the clock as uncertain after a failed synchronization attempt
+    pub fn mark_uncertain(&mut self) {
+        self.uncertain = true;
+    }
+
+    /// Return the synchronization status
+    pub fn status(&self) -> Result<SynchronizationStatus, Error> {
+        let last_synchronization = from_epoch_micros(self.drift.last_synchronization)?;
+        Ok(SynchronizationStatus {
+            last_synchronization: self.time_zone.to_local(last_synchronization)?,
+            source: self.source,
+            uncertain: self.uncertain,
+        })
+    }
+
+    /// Estimate the current clock error
+    ///
+    /// This is the accumulated drift plus the accuracy of the source.
+    pub fn estimated_error(&self) -> Duration {
+        let drift = self.drift.estimated_error(self.now_as_epoch_micros());
+        Duration::from_micros(drift.saturating_add(self.accuracy.as_micros()))
+    }
+
+    /// Check whether the clock must be synchronized because its estimated
+    /// error exceeds a threshold
+    pub fn needs_synchronization(&self, maximum_error: Duration) -> bool {
+        self.estimated_error() > maximum_error
     }
 
     /// Compute the next wakeup rounded down to a period
@@ -105,13 +272,13 @@ pub fn save_to_rtc_memory(&self, expected_sleep_duration: Duration) {
     /// * At 09:46:12 with period 5 minutes, next rounded wakeup is 09:50:00.
     /// * At 09:46:12 with period 1 hour, next rounded wakeup is 10:00:00.
     pub fn duration_to_next_rounded_wakeup(&self, period: Duration) -> Duration {
-        let epoch = Duration::from_secs(self.now_as_epoch());
+        let epoch = Duration::from_micros(self.now_as_epoch_micros());
         duration_to_next_rounded_wakeup(epoch, period)
     }
 
-    /// Return current time as a Unix epoch
-    pub fn now_as_epoch(&self) -> u64 {
-        let from_boot = Instant::now().as_secs();
+    /// Return current time in microseconds since Unix epoch
+    pub fn now_as_epoch_micros(&self) -> u64 {
+        let from_boot = Instant::now().as_micros();
         self.boot_time + from_boot
     }
 }
@@ -123,7 +290,7 @@ pub fn now_as_epoch(&self) -> u64 {
 /// * At 09:46:12 with period 1 hour, next rounded wakeup is 10:00:00.
 fn next_rounded_wakeup(now: Duration, period: Duration) -> Duration {
     let then = now + period;
-    Duration::from_secs((then.as_secs() / period.as_secs()) * period.as_secs())
+    Duration::from_micros((then.as_micros() / period.as_micros()) * period.as_micros())
 }
 
 /// Compute the duration to next wakeup rounded down to a period
@@ -138,11 +305,14 @@ pub enum Error {
     /// A time component is out of range
     TimeComponentRange(#[allow(unused)] TimeComponentRange),
 
-    /// The time is invalid in the current time offset
-    InvalidInOffset,
+    /// Error computing the local time
+    TimeZone(#[allow(unused)] TimeZoneError),
+
+    /// No time source reported the time
+    NoTimeSource,
 
-    /// Error synchronizing time from World Time API
-    Synchronization(#[allow(unused)] WorldTimeApiError),
+    /// Error reading or writing the external real-time clock
+    ExternalRtc(#[allow(unused)] ExternalRtcError),
 }
 
 impl From<TimeComponentRange> for Error {
@@ -151,8 +321,217 @@ fn from(error: TimeComponentRange) -> Self {
     }
 }
 
-impl From<WorldTimeApiError> for Error {
-    fn from(error: WorldTimeApiError) -> Self {
-        Self::Synchronization(error)
+impl From<TimeZoneError> for Error {
+    fn from(error: TimeZoneError) -> Self {
+        Self::TimeZone(error)
+    }
+}
+
+impl From<ExternalRtcError> for Error {
+    fn from(error: ExternalRtcError) -> Self {
+        Self::ExternalRtc(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embassy_futures::block_on;
+
+    use time::macros::datetime;
+
+    use crate::time_source::Error as TimeSourceError;
+    use crate::time_source::SourcedTime;
+    use crate::time_source::UNKNOWN_ACCURACY;
+
+    /// One hour in microseconds
+    const HOUR: u64 = 60 * 60 * 1_000_000;
+
+    /// A source that reports a fixed time or fails
+    struct FakeSource {
+        /// Kind of source
+        kind: Source,
+
+        /// Time to report, or `None` to fail
+        time: Option<SourcedTime>,
+
+        /// Whether the time was fetched
+        fetched: bool,
+    }
+
+    impl FakeSource {
+        /// Create a source that fails
+        fn failing(kind: Source) -> Self {
+            Self {
+                kind,
+                time: None,
+                fetched: false,
+            }
+        }
+
+        /// Create a source that reports a time
+        fn reporting(kind: Source, now: OffsetDateTime, accuracy: Duration) -> Self {
+            Self {
+                kind,
+                time: Some(SourcedTime { now, accuracy }),
+                fetched: false,
+            }
+        }
+    }
+
+    impl TimeSource for FakeSource {
+        fn source(&self) -> Source {
+            self.kind
+        }
+
+        async fn fetch(&mut self) -> Result<SourcedTime, TimeSourceError> {
+            self.fetched = true;
+            self.time.ok_or(TimeSourceError::Unavailable)
+        }
+    }
+
+    /// Assert that a clock shows a time, allowing for the test run time
+    fn assert_shows(clock: &Clock, expected: OffsetDateTime) {
+        let expected = to_epoch_micros(expected);
+        let actual = clock.now_as_epoch_micros();
+        assert!(
+            actual.abs_diff(expected) < 1_000_000,
+            "clock shows {actual}, expected {expected}"
+        );
+    }
+
+    #[test]
+    fn sources_are_tried_in_order() {
+        let mut sources = [
+            FakeSource::failing(Source::Sntp),
+            FakeSource::reporting(
+                Source::WorldTimeApi,
+                datetime!(2024-06-01 12:00 UTC),
+                Duration::from_secs(1),
+            ),
+            FakeSource::reporting(
+                Source::ExternalRtc,
+                datetime!(2020-01-01 00:00 UTC),
+                Duration::from_secs(1),
+            ),
+        ];
+
+        let clock = block_on(Clock::from_sources(&mut sources, TimeZone::UTC)).unwrap();
+
+        assert_eq!(clock.source(), Source::WorldTimeApi);
+        assert_eq!(clock.accuracy(), Duration::from_secs(1));
+        assert!(!clock.status().unwrap().uncertain);
+        assert_shows(&clock, datetime!(2024-06-01 12:00 UTC));
+        let fetched = sources.map(|source| source.fetched);
+        assert_eq!(fetched, [true, true, false]);
+    }
+
+    #[test]
+    fn offline_source_is_used_when_network_sources_fail() {
+        let mut sources = [
+            FakeSource::failing(Source::Sntp),
+            FakeSource::failing(Source::WorldTimeApi),
+            FakeSource::reporting(
+                Source::RtcMemory,
+                datetime!(2024-06-01 12:00 UTC),
+                Duration::from_millis(200),
+            ),
+        ];
+        let previous = Clock::new(
+            to_epoch_micros(datetime!(2024-06-01 12:00 UTC)),
+            TimeZone::UTC,
+            Source::Sntp,
+            Duration::from_millis(10),
+        );
+
+        let mut clock = block_on(Clock::from_sources(&mut sources, TimeZone::UTC)).unwrap();
+        assert_eq!(clock.source(), Source::RtcMemory);
+
+        clock.continue_from(&previous);
+
+        let status = clock.status().unwrap();
+        assert!(status.uncertain);
+        assert_eq!(status.source, Source::Sntp);
+        assert_eq!(clock.drift(), previous.drift());
+        assert_shows(&clock, datetime!(2024-06-01 12:00 UTC));
+    }
+
+    #[test]
+    fn build_time_marks_uncertain() {
+        let mut sources = [
+            FakeSource::failing(Source::Sntp),
+            FakeSource::failing(Source::RtcMemory),
+            FakeSource::reporting(
+                Source::BuildTime,
+                datetime!(2024-01-01 00:00 UTC),
+                UNKNOWN_ACCURACY,
+            ),
+        ];
+
+        let clock = block_on(Clock::from_sources(&mut sources, TimeZone::UTC)).unwrap();
+
+        assert_eq!(clock.source(), Source::BuildTime);
+        assert!(clock.status().unwrap().uncertain);
+    }
+
+    #[test]
+    fn no_source_is_an_error() {
+        let mut sources = [
+            FakeSource::failing(Source::Sntp),
+            FakeSource::failing(Source::RtcMemory),
+        ];
+
+        let result = block_on(Clock::from_sources(&mut sources, TimeZone::UTC));
+
+        assert!(matches!(result, Err(Error::NoTimeSource)));
+        assert!(sources.iter().all(|source| source.fetched));
+    }
+
+    #[test]
+    fn network_synchronization_calibrates_drift() {
+        let wakeup_time = to_epoch_micros(datetime!(2024-06-01 12:00 UTC));
+        let saved = SavedClock {
+            wakeup_time,
+            sleep_duration: HOUR,
+            drift: Drift::new(wakeup_time - HOUR),
+            source: Source::Sntp,
+            accuracy: 10_000,
+            uncertain: true,
+        };
+        let previous = Clock::from_rtc_memory(Some(&saved), TimeZone::UTC).unwrap();
+        let mut sources = [FakeSource::reporting(
+            Source::Sntp,
+            datetime!(2024-06-01 12:00:00.036 UTC),
+            Duration::from_millis(10),
+        )];
+
+        let mut clock = block_on(Clock::from_sources(&mut sources, TimeZone::UTC)).unwrap();
+        clock.continue_from(&previous);
+
+        assert_eq!(clock.drift().measurements, 1);
+        assert!(clock.drift().rate_ppm > 9.0 && clock.drift().rate_ppm < 11.0);
+        assert!(!clock.status().unwrap().uncertain);
+    }
+
+    #[test]
+    fn saved_clock_keeps_source_and_uncertainty() {
+        let mut clock = Clock::new(
+            to_epoch_micros(datetime!(2024-06-01 12:00 UTC)),
+            TimeZone::UTC,
+            Source::WorldTimeApi,
+            Duration::from_secs(1),
+        );
+        clock.mark_uncertain();
+
+        let mut saved = None;
+        clock.save_to_rtc_memory(&mut saved, Duration::from_secs(60));
+        let restored = Clock::from_rtc_memory(saved.as_ref(), TimeZone::UTC).unwrap();
+
+        let status = restored.status().unwrap();
+        assert!(status.uncertain);
+        assert_eq!(status.source, Source::WorldTimeApi);
+        assert_eq!(restored.accuracy(), Duration::from_secs(1));
+        assert_shows(&restored, datetime!(2024-06-01 12:01 UTC));
     }
 }
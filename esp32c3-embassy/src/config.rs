@@ -0,0 +1,55 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Configuration stored in flash
+//!
+//! The configuration is shared with the other firmware, see
+//! [`crussant_common::config`], with the defaults of this board.
+
+use core::ops::Range;
+
+use embassy_time::Duration;
+
+use crussant_common::config::Board as ConfigBoard;
+use crussant_common::config::Config as SharedConfig;
+
+pub use crussant_common::config::Error;
+pub use crussant_common::config::Key;
+
+/// Flash range of the `config` partition, see `partitions.csv`
+pub const FLASH_RANGE: Range<u32> = 0xb000..0xf000;
+
+/// Key of the gas sensor baseline, stored beside the configuration
+///
+/// Gas sensors such as the SGP30 or CCS811 take hours to calibrate, so their
+/// baseline is saved to speed up calibration after a restart.
+pub const BASELINE_KEY: u16 = 0x0100;
+
+/// Firmware configuration
+pub type Config = SharedConfig<Board>;
+
+/// Defaults of this board
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Board;
+
+impl ConfigBoard for Board {
+    /// Stay awake after a cold boot for a few minutes, to allow using the
+    /// shell
+    const AWAKE_PERIOD: Duration = Duration::from_secs(300);
+
+    const TIME_ZONE: &'static str = "CET-1CEST,M3.5.0,M10.5.0/3";
+
+    const SDA_PIN: u8 = 1;
+
+    const SCL_PIN: u8 = 2;
+
+    const I2C_FREQUENCY_KHZ: u32 = 25;
+
+    /// GPIO0 is reserved for the [battery voltage][crate::battery::PIN].
+    /// Configurations stored by older firmware that assigned it to I²C are
+    /// moved to free pins on load.
+    const I2C_PINS: &'static [u8] = &[1, 2, 4, 5];
+}
@@ -0,0 +1,234 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Line-oriented serial console
+//!
+//! Characters are echoed back as they are typed, backspace deletes the last
+//! character, and a line ends with carriage return or line feed.
+//! Lines are split in arguments on whitespace, and arguments containing
+//! whitespace can be enclosed in double quotes.
+//!
+//! The [`Console`] works on any transport implementing `embedded-io-async`,
+//! such as a UART.
+
+use core::fmt::Arguments;
+use core::fmt::Write as _;
+
+use embedded_io_async::Read;
+use embedded_io_async::Write;
+
+use heapless::String;
+use heapless::Vec;
+
+/// Maximal length of a line
+pub const LINE_SIZE: usize = 160;
+
+/// Maximal number of arguments in a line
+pub const MAXIMAL_ARGUMENTS: usize = 8;
+
+/// Size of formatted output
+const OUTPUT_SIZE: usize = 256;
+
+/// Prompt shown before each line
+const PROMPT: &str = "> ";
+
+/// An editor of a single line
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    /// Characters typed so far
+    line: String<LINE_SIZE>,
+
+    /// Whether the last character was a carriage return
+    after_carriage_return: bool,
+}
+
+/// Effect of a character on a [`LineEditor`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// Nothing to echo
+    None,
+
+    /// Echo a character
+    Echo(char),
+
+    /// Erase the last character
+    Erase,
+
+    /// The line is complete
+    Complete,
+}
+
+impl LineEditor {
+    /// Create an empty line editor
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            after_carriage_return: false,
+        }
+    }
+
+    /// Process a received byte
+    ///
+    /// Non-printable and non-ASCII bytes are ignored, as are characters
+    /// beyond [`LINE_SIZE`].
+    pub fn push(&mut self, byte: u8) -> Edit {
+        let after_carriage_return = self.after_carriage_return;
+        self.after_carriage_return = byte == b'\r';
+        match byte {
+            b'\n' if after_carriage_return => Edit::None,
+            b'\r' | b'\n' => Edit::Complete,
+            0x08 | 0x7f => match self.line.pop() {
+                Some(_) => Edit::Erase,
+                None => Edit::None,
+            },
+            b' '..=b'~' => {
+                let c = char::from(byte);
+                match self.line.push(c) {
+                    Ok(()) => Edit::Echo(c),
+                    Err(()) => Edit::None,
+                }
+            }
+            _ => Edit::None,
+        }
+    }
+
+    /// Return the line
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Clear the line
+    pub fn clear(&mut self) {
+        self.line.clear();
+    }
+}
+
+/// Split a line in arguments
+///
+/// Arguments are separated by whitespace, unless enclosed in double quotes.
+/// Quotes cannot be escaped.
+pub fn split_arguments(line: &str) -> Result<Vec<&str, MAXIMAL_ARGUMENTS>, Error> {
+    let mut arguments = Vec::new();
+    let mut remaining = line.trim_start();
+    while !remaining.is_empty() {
+        let (argument, rest) = if let Some(quoted) = remaining.strip_prefix('"') {
+            let end = quoted.find('"').ok_or(Error::UnterminatedQuote)?;
+            let rest = quoted.get(end + 1..).unwrap_or_default();
+            if rest.starts_with(|c: char| !c.is_whitespace()) {
+                return Err(Error::UnterminatedQuote);
+            }
+            (quoted.get(..end).unwrap_or_default(), rest)
+        } else {
+            let end = remaining
+                .find(char::is_whitespace)
+                .unwrap_or(remaining.len());
+            remaining.split_at(end)
+        };
+        arguments
+            .push(argument)
+            .map_err(|_| Error::TooManyArguments)?;
+        remaining = rest.trim_start();
+    }
+    Ok(arguments)
+}
+
+/// A serial console
+pub struct Console<R, W> {
+    /// Receiving half of the transport
+    rx: R,
+
+    /// Transmitting half of the transport
+    tx: W,
+
+    /// Line being edited
+    editor: LineEditor,
+
+    /// Whether the line was completed by the last call to `read_line()`
+    complete: bool,
+}
+
+impl<R, W> Console<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Create a console on a transport
+    pub fn new(rx: R, tx: W) -> Self {
+        Self {
+            rx,
+            tx,
+            editor: LineEditor::new(),
+            complete: true,
+        }
+    }
+
+    /// Read a line, showing a prompt first
+    ///
+    /// Cancelling this future does not lose characters already received.
+    pub async fn read_line(&mut self) -> Result<&str, Error> {
+        if self.complete {
+            self.editor.clear();
+            self.complete = false;
+            self.write_str(PROMPT).await?;
+        }
+
+        let mut buffer = [0_u8; 16];
+        loop {
+            let count = self.rx.read(&mut buffer).await.map_err(|_| Error::Io)?;
+            for byte in buffer.get(..count).unwrap_or_default() {
+                match self.editor.push(*byte) {
+                    Edit::None => {}
+                    Edit::Echo(c) => {
+                        let mut echo = [0_u8; 4];
+                        self.write_str(c.encode_utf8(&mut echo)).await?;
+                    }
+                    Edit::Erase => self.write_str("\x08 \x08").await?,
+                    Edit::Complete => {
+                        // Bytes after the end of line in the same read are
+                        // dropped, which only happens when pasting
+                        self.complete = true;
+                        self.write_str("\r\n").await?;
+                        return Ok(self.editor.line());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write a string
+    pub async fn write_str(&mut self, text: &str) -> Result<(), Error> {
+        self.tx
+            .write_all(text.as_bytes())
+            .await
+            .map_err(|_| Error::Io)
+    }
+
+    /// Write formatted text followed by an end of line
+    pub async fn write_line(&mut self, arguments: Arguments<'_>) -> Result<(), Error> {
+        let mut output = String::<OUTPUT_SIZE>::new();
+        output
+            .write_fmt(arguments)
+            .map_err(|_| Error::LineTooLong)?;
+        self.write_str(&output).await?;
+        self.write_str("\r\n").await
+    }
+}
+
+/// An error within a console
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A quoted argument is not terminated
+    UnterminatedQuote,
+
+    /// A line contains more than [`MAXIMAL_ARGUMENTS`] arguments
+    TooManyArguments,
+
+    /// Formatted output does not fit in its buffer
+    LineTooLong,
+
+    /// Error from the transport
+    Io,
+}
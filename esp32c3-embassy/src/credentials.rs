@@ -0,0 +1,505 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! WiFi credentials stored in flash
+//!
+//! Up to [`MAXIMAL_NETWORKS`] networks are stored in a single flash sector,
+//! most recently added first.
+//! The record is a magic number, a format version, the number of networks,
+//! each network as a length-prefixed SSID and a length-prefixed password, and
+//! a CRC of all previous bytes.
+//! Integers are little endian.
+
+use core::cmp::Reverse;
+
+use log::debug;
+use log::info;
+
+use crc::Crc;
+use crc::CRC_32_ISO_HDLC;
+
+use embedded_storage::nor_flash::NorFlash;
+use embedded_storage::nor_flash::NorFlashError as _;
+use embedded_storage::nor_flash::NorFlashErrorKind;
+use embedded_storage::nor_flash::ReadNorFlash;
+
+use heapless::String;
+use heapless::Vec;
+
+/// Maximal number of stored networks
+pub const MAXIMAL_NETWORKS: usize = 4;
+
+/// Offset of the flash sector holding the credentials
+///
+/// This is the first sector of the `nvs` partition in the default partition
+/// table, which is not otherwise used by this firmware.
+pub const FLASH_OFFSET: u32 = 0x9000;
+
+/// Size of a flash sector
+const SECTOR_SIZE: u32 = 4096;
+
+/// Magic number marking stored credentials, `WIFI` in ASCII
+const MAGIC: u32 = 0x5749_4649;
+
+/// Version of the record format
+const FORMAT_VERSION: u8 = 1;
+
+/// Size of the record buffer, large enough for all networks
+const RECORD_SIZE: usize = 4 + 1 + 1 + MAXIMAL_NETWORKS * (1 + 32 + 1 + 64) + 4;
+
+/// Checksum algorithm
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// A WiFi network
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Network {
+    /// SSID
+    pub ssid: String<32>,
+
+    /// Password, empty for open networks
+    pub password: String<64>,
+}
+
+impl Network {
+    /// Create a network from an SSID and a password
+    pub fn new(ssid: &str, password: &str) -> Result<Self, Error> {
+        if ssid.is_empty() {
+            return Err(Error::InvalidSsid);
+        }
+        Ok(Self {
+            ssid: String::try_from(ssid).map_err(|()| Error::InvalidSsid)?,
+            password: String::try_from(password).map_err(|()| Error::InvalidPassword)?,
+        })
+    }
+}
+
+/// Stored WiFi networks
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Credentials {
+    /// Networks, most recently added first
+    networks: Vec<Network, MAXIMAL_NETWORKS>,
+}
+
+impl Credentials {
+    /// Create empty credentials
+    pub const fn new() -> Self {
+        Self {
+            networks: Vec::new(),
+        }
+    }
+
+    /// Check whether no network is stored
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// Return the stored networks, most recently added first
+    pub fn networks(&self) -> &[Network] {
+        &self.networks
+    }
+
+    /// Add a network, replacing one with the same SSID
+    ///
+    /// The oldest network is dropped if there is no room left.
+    pub fn add(&mut self, network: Network) {
+        self.remove(&network.ssid);
+        if self.networks.is_full() {
+            self.networks.pop();
+        }
+        // There is room for at least one network after the pop
+        let _ = self.networks.insert(0, network);
+    }
+
+    /// Remove a network, returning whether it was stored
+    pub fn remove(&mut self, ssid: &str) -> bool {
+        let length = self.networks.len();
+        self.networks.retain(|network| network.ssid != ssid);
+        self.networks.len() != length
+    }
+
+    /// Remove all networks
+    pub fn clear(&mut self) {
+        self.networks.clear();
+    }
+
+    /// Order networks for connection attempts
+    ///
+    /// Networks found by a scan come first, strongest signal first, and are
+    /// followed by the others in stored order, since hidden networks do not
+    /// show up in scans.
+    pub fn by_signal_strength(&self, visible: &[(&str, i8)]) -> Vec<&Network, MAXIMAL_NETWORKS> {
+        let mut ordered: Vec<(Option<i8>, usize, &Network), MAXIMAL_NETWORKS> = self
+            .networks
+            .iter()
+            .enumerate()
+            .map(|(index, network)| {
+                let rssi = visible
+                    .iter()
+                    .filter(|(ssid, _)| *ssid == network.ssid.as_str())
+                    .map(|(_, rssi)| *rssi)
+                    .max();
+                (rssi, index, network)
+            })
+            .collect();
+        ordered.sort_unstable_by_key(|(rssi, index, _)| {
+            (Reverse(rssi.map_or(i16::MIN, i16::from)), *index)
+        });
+        ordered.into_iter().map(|(_, _, network)| network).collect()
+    }
+
+    /// Encode credentials to a record
+    ///
+    /// Return the number of bytes written.
+    pub fn encode(&self, output: &mut [u8]) -> Result<usize, Error> {
+        let mut record = Vec::<u8, RECORD_SIZE>::new();
+        let count = u8::try_from(self.networks.len()).map_err(|_| Error::TooLarge)?;
+        push(&mut record, &MAGIC.to_le_bytes())?;
+        push(&mut record, &[FORMAT_VERSION, count])?;
+        for network in &self.networks {
+            for field in [network.ssid.as_bytes(), network.password.as_bytes()] {
+                let length = u8::try_from(field.len()).map_err(|_| Error::TooLarge)?;
+                push(&mut record, &[length])?;
+                push(&mut record, field)?;
+            }
+        }
+        let checksum = CRC.checksum(&record);
+        push(&mut record, &checksum.to_le_bytes())?;
+
+        output
+            .get_mut(..record.len())
+            .ok_or(Error::TooLarge)?
+            .copy_from_slice(&record);
+        Ok(record.len())
+    }
+
+    /// Decode credentials from a record
+    ///
+    /// Trailing bytes after the record are ignored.
+    pub fn decode(input: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader { input, position: 0 };
+
+        if reader.take(4)? != MAGIC.to_le_bytes() {
+            return Err(Error::Empty);
+        }
+        let [version, count] = reader.take(2)? else {
+            return Err(Error::Corrupted);
+        };
+        if *version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(*version));
+        }
+
+        let mut networks = Vec::new();
+        for _ in 0..*count {
+            let ssid = reader.take_field()?;
+            let password = reader.take_field()?;
+            let network = Network::new(ssid, password).map_err(|_| Error::Corrupted)?;
+            networks.push(network).map_err(|_| Error::Corrupted)?;
+        }
+
+        let end = reader.position;
+        let checksum = reader.take(4)?;
+        let expected = CRC.checksum(input.get(..end).ok_or(Error::Corrupted)?);
+        if checksum != expected.to_le_bytes() {
+            return Err(Error::Corrupted);
+        }
+
+        Ok(Self { networks })
+    }
+
+    /// Load credentials from flash
+    ///
+    /// An erased sector yields [`Error::Empty`].
+    pub fn load(flash: &mut impl ReadNorFlash) -> Result<Self, Error> {
+        let mut buffer = [0_u8; RECORD_SIZE.next_multiple_of(4)];
+        flash
+            .read(FLASH_OFFSET, &mut buffer)
+            .map_err(|error| Error::Flash(error.kind()))?;
+        let credentials = Self::decode(&buffer)?;
+        debug!("Loaded {} networks", credentials.networks.len());
+        Ok(credentials)
+    }
+
+    /// Save credentials to flash
+    pub fn save(&self, flash: &mut impl NorFlash) -> Result<(), Error> {
+        let mut buffer = [0xff_u8; RECORD_SIZE.next_multiple_of(4)];
+        let length = self.encode(&mut buffer)?;
+        let length = length.next_multiple_of(4);
+
+        flash
+            .erase(FLASH_OFFSET, FLASH_OFFSET + SECTOR_SIZE)
+            .map_err(|error| Error::Flash(error.kind()))?;
+        flash
+            .write(FLASH_OFFSET, buffer.get(..length).unwrap_or_default())
+            .map_err(|error| Error::Flash(error.kind()))?;
+        info!("Saved {} networks", self.networks.len());
+        Ok(())
+    }
+}
+
+/// Append bytes to a record
+fn push(record: &mut Vec<u8, RECORD_SIZE>, bytes: &[u8]) -> Result<(), Error> {
+    record
+        .extend_from_slice(bytes)
+        .map_err(|()| Error::TooLarge)
+}
+
+/// A reader over a record
+struct Reader<'a> {
+    /// Record
+    input: &'a [u8],
+
+    /// Position of the next byte
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Take a number of bytes
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .input
+            .get(self.position..self.position + count)
+            .ok_or(Error::Corrupted)?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    /// Take a length-prefixed UTF-8 field
+    fn take_field(&mut self) -> Result<&'a str, Error> {
+        let [length] = self.take(1)? else {
+            return Err(Error::Corrupted);
+        };
+        let bytes = self.take(usize::from(*length))?;
+        core::str::from_utf8(bytes).map_err(|_| Error::Corrupted)
+    }
+}
+
+/// An error within credentials operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No credentials are stored
+    Empty,
+
+    /// Stored credentials are corrupted
+    Corrupted,
+
+    /// Stored credentials have an unsupported format version
+    UnsupportedVersion(#[allow(unused)] u8),
+
+    /// SSID is empty or longer than 32 bytes
+    InvalidSsid,
+
+    /// Password is longer than 64 bytes
+    InvalidPassword,
+
+    /// Credentials do not fit in a record
+    TooLarge,
+
+    /// Error from flash
+    Flash(#[allow(unused)] NorFlashErrorKind),
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    /// Credentials with a network of each kind
+    fn sample_credentials() -> Credentials {
+        let mut credentials = Credentials::new();
+        credentials.add(Network::new("Open café", "").unwrap());
+        credentials.add(Network::new("Home", "correct horse battery staple").unwrap());
+        credentials
+    }
+
+    /// Encode credentials to a record
+    fn encoded(credentials: &Credentials) -> Vec<u8, RECORD_SIZE> {
+        let mut buffer = [0_u8; RECORD_SIZE];
+        let length = credentials.encode(&mut buffer).unwrap();
+        Vec::from_slice(buffer.get(..length).unwrap()).unwrap()
+    }
+
+    /// Replace the CRC at the end of a record with the CRC of its content
+    fn reseal(record: &mut [u8]) {
+        let end = record.len() - 4;
+        let checksum = CRC.checksum(record.get(..end).unwrap());
+        record
+            .get_mut(end..)
+            .unwrap()
+            .copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn network_length_limits() {
+        let ssid = "s".repeat(32);
+        let password = "p".repeat(64);
+        assert!(Network::new(&ssid, &password).is_ok());
+        assert_eq!(Network::new("", "password"), Err(Error::InvalidSsid));
+        assert_eq!(
+            Network::new(&"s".repeat(33), "password"),
+            Err(Error::InvalidSsid)
+        );
+        assert_eq!(
+            Network::new("Home", &"p".repeat(65)),
+            Err(Error::InvalidPassword)
+        );
+
+        // Limits are in bytes, not characters
+        assert!(Network::new(&"é".repeat(16), "").is_ok());
+        assert_eq!(Network::new(&"é".repeat(17), ""), Err(Error::InvalidSsid));
+    }
+
+    #[test]
+    fn add_replaces_and_drops_the_oldest_network() {
+        let mut credentials = Credentials::new();
+        for ssid in ["a", "b", "c", "d"] {
+            credentials.add(Network::new(ssid, "old").unwrap());
+        }
+        credentials.add(Network::new("b", "new").unwrap());
+        let ssids: std::vec::Vec<&str> = credentials
+            .networks()
+            .iter()
+            .map(|network| network.ssid.as_str())
+            .collect();
+        assert_eq!(ssids, ["b", "d", "c", "a"]);
+        assert_eq!(credentials.networks().first().unwrap().password, "new");
+
+        credentials.add(Network::new("e", "").unwrap());
+        let ssids: std::vec::Vec<&str> = credentials
+            .networks()
+            .iter()
+            .map(|network| network.ssid.as_str())
+            .collect();
+        assert_eq!(ssids, ["e", "b", "d", "c"]);
+
+        assert!(credentials.remove("d"));
+        assert!(!credentials.remove("d"));
+        assert_eq!(credentials.networks().len(), 3);
+    }
+
+    #[test]
+    fn visible_networks_come_first_by_signal_strength() {
+        let mut credentials = Credentials::new();
+        for ssid in ["hidden", "weak", "strong"] {
+            credentials.add(Network::new(ssid, "").unwrap());
+        }
+        let visible = [
+            ("weak", -80),
+            ("other", -30),
+            ("strong", -50),
+            ("weak", -70),
+        ];
+        let ordered: std::vec::Vec<&str> = credentials
+            .by_signal_strength(&visible)
+            .iter()
+            .map(|network| network.ssid.as_str())
+            .collect();
+        assert_eq!(ordered, ["strong", "weak", "hidden"]);
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let credentials = sample_credentials();
+        let record = encoded(&credentials);
+        assert_eq!(record.get(..4), Some(&b"IFIW"[..]));
+        assert_eq!(Credentials::decode(&record), Ok(credentials.clone()));
+
+        // Flash is read in whole words, so erased bytes may follow the record
+        let mut padded = record.clone();
+        padded.extend_from_slice(&[0xff; 3]).unwrap();
+        assert_eq!(Credentials::decode(&padded), Ok(credentials));
+
+        let empty = encoded(&Credentials::new());
+        assert_eq!(Credentials::decode(&empty), Ok(Credentials::new()));
+    }
+
+    #[test]
+    fn encode_longest_networks() {
+        let mut credentials = Credentials::new();
+        for index in 0..MAXIMAL_NETWORKS {
+            let ssid = std::format!("{index:0>32}");
+            credentials.add(Network::new(&ssid, &"p".repeat(64)).unwrap());
+        }
+        let record = encoded(&credentials);
+        assert_eq!(record.len(), RECORD_SIZE);
+        assert_eq!(Credentials::decode(&record), Ok(credentials.clone()));
+
+        let mut buffer = [0_u8; RECORD_SIZE - 1];
+        assert_eq!(credentials.encode(&mut buffer), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn decode_erased_flash() {
+        assert_eq!(Credentials::decode(&[0xff; 64]), Err(Error::Empty));
+        assert_eq!(Credentials::decode(&[0; 64]), Err(Error::Empty));
+    }
+
+    #[test]
+    fn decode_truncated_records() {
+        let record = encoded(&sample_credentials());
+        for length in 0..record.len() {
+            assert_eq!(
+                Credentials::decode(record.get(..length).unwrap()),
+                Err(Error::Corrupted),
+                "record truncated to {length} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_corrupted_records() {
+        let record = encoded(&sample_credentials());
+        for index in 5..record.len() {
+            let mut corrupted = record.clone();
+            *corrupted.get_mut(index).unwrap() ^= 0x01;
+            assert_eq!(
+                Credentials::decode(&corrupted),
+                Err(Error::Corrupted),
+                "byte {index} flipped"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_unsupported_version() {
+        let mut record = encoded(&sample_credentials());
+        *record.get_mut(4).unwrap() = FORMAT_VERSION + 1;
+        reseal(&mut record);
+        assert_eq!(
+            Credentials::decode(&record),
+            Err(Error::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn decode_rejects_invalid_fields_with_a_valid_crc() {
+        // Header of a record with one network
+        let header = [0x49, 0x46, 0x49, 0x57, FORMAT_VERSION, 1];
+        let long_ssid = [&[33][..], &[b's'; 33], &[0]].concat();
+        let long_password = [&[1, b's'][..], &[65], &[b'p'; 65]].concat();
+        let empty_ssid = [0_u8, 0].to_vec();
+        let invalid_utf8 = [1_u8, 0xff, 0].to_vec();
+        for fields in [long_ssid, long_password, empty_ssid, invalid_utf8] {
+            let mut record = [&header[..], &fields, &[0; 4]].concat();
+            reseal(&mut record);
+            assert_eq!(
+                Credentials::decode(&record),
+                Err(Error::Corrupted),
+                "{fields:?}"
+            );
+        }
+
+        // More networks than can be stored
+        let mut record = std::vec::Vec::from(header);
+        *record.last_mut().unwrap() = 5;
+        for _ in 0..5 {
+            record.extend_from_slice(&[1, b's', 0]);
+        }
+        record.extend_from_slice(&[0; 4]);
+        reseal(&mut record);
+        assert_eq!(Credentials::decode(&record), Err(Error::Corrupted));
+    }
+}
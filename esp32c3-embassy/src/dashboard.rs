@@ -10,10 +10,15 @@
 use core::fmt::Error as FmtError;
 use core::fmt::Write as _;
 
+use embedded_graphics::geometry::AnchorPoint;
 use embedded_graphics::mono_font::iso_8859_1::FONT_10X20 as FONT;
 use embedded_graphics::mono_font::MonoTextStyle;
 use embedded_graphics::mono_font::MonoTextStyleBuilder;
 use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Circle;
+use embedded_graphics::primitives::Line;
+use embedded_graphics::primitives::PrimitiveStyle;
+use embedded_graphics::primitives::Rectangle;
 use embedded_graphics::text::Text;
 
 use embedded_layout::align::Align;
@@ -23,6 +28,9 @@
 use embedded_layout::prelude::Chain;
 use embedded_layout::View;
 
+use libm::roundf;
+
+use uom::si::electric_potential::volt;
 use uom::si::f32::Pressure;
 use uom::si::f32::Ratio as Humidity;
 use uom::si::f32::ThermodynamicTemperature as Temperature;
@@ -36,6 +44,10 @@
 
 use waveshare_154bv2_rs::Color as TriColor;
 
+use crate::battery::Battery;
+use crate::forecast::Forecast;
+use crate::forecast::Icon;
+use crate::synchronization::Status as SynchronizationStatus;
 use crate::Sample;
 
 /// Style for black text
@@ -52,11 +64,34 @@
     .background_color(TriColor::White)
     .build();
 
+/// Style for black filled shapes
+const BLACK_FILL: PrimitiveStyle<TriColor> = PrimitiveStyle::with_fill(TriColor::Black);
+
+/// Style for chromatic filled shapes
+const CHROMATIC_FILL: PrimitiveStyle<TriColor> = PrimitiveStyle::with_fill(TriColor::Chromatic);
+
+/// Style for chromatic lines
+const CHROMATIC_STROKE: PrimitiveStyle<TriColor> =
+    PrimitiveStyle::with_stroke(TriColor::Chromatic, 2);
+
+/// Style for black outlines
+const BLACK_STROKE: PrimitiveStyle<TriColor> = PrimitiveStyle::with_stroke(TriColor::Black, 1);
+
+/// Style for thick chromatic outlines
+const THICK_CHROMATIC_STROKE: PrimitiveStyle<TriColor> =
+    PrimitiveStyle::with_stroke(TriColor::Chromatic, 3);
+
+/// State of charge in percent below which the battery icon is chromatic
+const LOW_CHARGE_PERCENT: f32 = 20.0;
+
 /// Draw a dashboard
 pub fn draw<DISPLAY>(
     display: &mut DISPLAY,
     now: &OffsetDateTime,
     sample: &Sample,
+    forecast: Option<&Forecast>,
+    synchronization: &SynchronizationStatus,
+    battery: Option<&Battery>,
 ) -> Result<(), Error>
 where
     DISPLAY: DrawTarget<Color = TriColor, Error = Infallible>,
@@ -72,7 +107,7 @@ pub fn draw<DISPLAY>(
     let pressure_layout = lay_out_measurement("Pressure: ", &pressure, " hPa");
     let time_layout = lay_out_update_time(&time);
 
-    LinearLayout::vertical(
+    let layout = LinearLayout::vertical(
         Chain::new(temperature_layout)
             .append(humidity_layout)
             .append(pressure_layout)
@@ -80,8 +115,66 @@ pub fn draw<DISPLAY>(
     )
     .with_alignment(horizontal::Left)
     .arrange()
-    .align_to(&display_area, horizontal::Left, vertical::Top)
-    .draw(display)?;
+    .align_to(&display_area, horizontal::Left, vertical::Top);
+    layout.draw(display)?;
+
+    if let Some(forecast) = forecast {
+        let forecast_layout = lay_out_forecast(forecast.text).align_to(
+            &layout,
+            horizontal::Left,
+            vertical::TopToBottom,
+        );
+        forecast_layout.draw(display)?;
+        draw_icon(display, forecast.icon, forecast_layout.bounds().top_left)?;
+    }
+
+    let synchronization_time = format_time(&synchronization.last_synchronization)?;
+    if synchronization.uncertain {
+        Text::new("Time uncertain", Point::zero(), CHROMATIC_STYLE)
+            .align_to(&display_area, horizontal::Left, vertical::Bottom)
+            .draw(display)?;
+    } else {
+        lay_out_synchronization(&synchronization_time, synchronization.source.label())
+            .align_to(&display_area, horizontal::Left, vertical::Bottom)
+            .draw(display)?;
+    }
+
+    if let Some(battery) = battery {
+        // On the right of the update time row, which is the shortest
+        let right = display_area.anchor_point(AnchorPoint::BottomRight).x;
+        let bottom = layout.bounds().anchor_point(AnchorPoint::BottomRight).y;
+        draw_battery(display, battery, Point::new(right - 24, bottom - 15))?;
+    }
+
+    Ok(())
+}
+
+/// Draw a screen asking to replace the battery
+pub fn draw_replace_battery<DISPLAY>(display: &mut DISPLAY, battery: &Battery) -> Result<(), Error>
+where
+    DISPLAY: DrawTarget<Color = TriColor, Error = Infallible>,
+{
+    let display_area = display.bounding_box();
+    let center = display_area.center();
+
+    Rectangle::with_center(center - Point::new(4, 40), Size::new(90, 44))
+        .into_styled(THICK_CHROMATIC_STROKE)
+        .draw(display)?;
+    Rectangle::with_center(center + Point::new(45, -40), Size::new(8, 18))
+        .into_styled(CHROMATIC_FILL)
+        .draw(display)?;
+
+    let text = Text::new("Replace battery", Point::zero(), CHROMATIC_STYLE).align_to(
+        &display_area,
+        horizontal::Center,
+        vertical::Center,
+    );
+    text.draw(display)?;
+
+    let voltage = format_voltage(battery)?;
+    Text::new(&voltage, Point::zero(), BLACK_STYLE)
+        .align_to(&text, horizontal::Center, vertical::TopToBottom)
+        .draw(display)?;
 
     Ok(())
 }
@@ -115,6 +208,134 @@ fn lay_out_update_time<'text>(now: &'text str) -> impl Drawable<Color = TriColor
     .arrange()
 }
 
+/// Lay out the clock synchronization row
+fn lay_out_synchronization<'text>(
+    time: &'text str,
+    source: &'text str,
+) -> impl Drawable<Color = TriColor> + View + 'text {
+    LinearLayout::horizontal(
+        Chain::new(Text::new("Synced ", Point::zero(), BLACK_STYLE))
+            .append(Text::new(time, Point::zero(), BLACK_STYLE))
+            .append(Text::new(" ", Point::zero(), BLACK_STYLE))
+            .append(Text::new(source, Point::zero(), BLACK_STYLE)),
+    )
+    .with_alignment(vertical::Center)
+    .arrange()
+}
+
+/// Lay out the forecast row, leaving room for the icon on the left
+#[allow(clippy::needless_lifetimes)]
+fn lay_out_forecast<'text>(text: &'text str) -> impl Drawable<Color = TriColor> + View + 'text {
+    LinearLayout::horizontal(
+        Chain::new(Text::new("  ", Point::zero(), BLACK_STYLE)).append(Text::new(
+            text,
+            Point::zero(),
+            CHROMATIC_STYLE,
+        )),
+    )
+    .with_alignment(vertical::Center)
+    .arrange()
+}
+
+/// Draw a 20x20 forecast icon with its top left corner at `origin`
+fn draw_icon<DISPLAY>(display: &mut DISPLAY, icon: Icon, origin: Point) -> Result<(), Error>
+where
+    DISPLAY: DrawTarget<Color = TriColor, Error = Infallible>,
+{
+    match icon {
+        Icon::Sunny => {
+            Circle::new(origin + Point::new(4, 4), 12)
+                .into_styled(CHROMATIC_FILL)
+                .draw(display)?;
+        }
+        Icon::PartlyCloudy => {
+            Circle::new(origin + Point::new(1, 1), 10)
+                .into_styled(CHROMATIC_FILL)
+                .draw(display)?;
+            draw_cloud(display, origin + Point::new(0, 4))?;
+        }
+        Icon::Cloudy => {
+            draw_cloud(display, origin + Point::new(0, 2))?;
+        }
+        Icon::Rain => {
+            draw_cloud(display, origin)?;
+            for x in [5, 10, 15] {
+                Line::new(origin + Point::new(x, 14), origin + Point::new(x - 2, 19))
+                    .into_styled(CHROMATIC_STROKE)
+                    .draw(display)?;
+            }
+        }
+        Icon::Storm => {
+            draw_cloud(display, origin)?;
+            Line::new(origin + Point::new(11, 13), origin + Point::new(7, 17))
+                .into_styled(CHROMATIC_STROKE)
+                .draw(display)?;
+            Line::new(origin + Point::new(7, 17), origin + Point::new(12, 17))
+                .into_styled(CHROMATIC_STROKE)
+                .draw(display)?;
+            Line::new(origin + Point::new(12, 17), origin + Point::new(8, 19))
+                .into_styled(CHROMATIC_STROKE)
+                .draw(display)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Draw a 24x12 battery icon with its top left corner at `origin`
+///
+/// The icon is filled according to the state of charge, and it is chromatic
+/// when the charge is low.
+fn draw_battery<DISPLAY>(
+    display: &mut DISPLAY,
+    battery: &Battery,
+    origin: Point,
+) -> Result<(), Error>
+where
+    DISPLAY: DrawTarget<Color = TriColor, Error = Infallible>,
+{
+    let charge = battery.charge.get::<percent>();
+    let fill = if charge < LOW_CHARGE_PERCENT {
+        CHROMATIC_FILL
+    } else {
+        BLACK_FILL
+    };
+
+    Rectangle::new(origin, Size::new(22, 12))
+        .into_styled(BLACK_STROKE)
+        .draw(display)?;
+    Rectangle::new(origin + Point::new(22, 3), Size::new(2, 6))
+        .into_styled(BLACK_FILL)
+        .draw(display)?;
+
+    // The charge is clamped to the width of the icon
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let width = roundf(charge.clamp(0.0, 100.0) * 18.0 / 100.0) as u32;
+    Rectangle::new(origin + Point::new(2, 2), Size::new(width, 8))
+        .into_styled(fill)
+        .draw(display)?;
+
+    Ok(())
+}
+
+/// Draw a 20x14 cloud with its top left corner at `origin`
+fn draw_cloud<DISPLAY>(display: &mut DISPLAY, origin: Point) -> Result<(), Error>
+where
+    DISPLAY: DrawTarget<Color = TriColor, Error = Infallible>,
+{
+    Circle::new(origin + Point::new(1, 4), 9)
+        .into_styled(BLACK_FILL)
+        .draw(display)?;
+    Circle::new(origin + Point::new(6, 0), 11)
+        .into_styled(BLACK_FILL)
+        .draw(display)?;
+    Rectangle::new(origin + Point::new(5, 7), Size::new(14, 6))
+        .into_styled(BLACK_FILL)
+        .draw(display)?;
+
+    Ok(())
+}
+
 /// Format a time as `HOUR:MINUTE`
 fn format_time(now: &OffsetDateTime) -> Result<String<5>, Error> {
     let mut string: String<5> = String::new();
@@ -144,6 +365,13 @@ fn format_pressure(pressure: Pressure) -> Result<String<10>, FmtError> {
     Ok(string)
 }
 
+/// Format a battery voltage
+fn format_voltage(battery: &Battery) -> Result<String<10>, FmtError> {
+    let mut string: String<10> = String::new();
+    write!(&mut string, "{:.2} V", battery.voltage.get::<volt>())?;
+    Ok(string)
+}
+
 /// An error
 #[derive(Debug)]
 pub enum Error {
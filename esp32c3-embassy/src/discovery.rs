@@ -0,0 +1,255 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Home Assistant MQTT discovery
+//!
+//! Every sensor channel is announced with a retained config message on
+//! `<prefix>/sensor/<device id>/<channel>/config`, so that the station shows
+//! up in Home Assistant as a device with one entity per channel.
+//! Entities read their value from the JSON readings on the state topic and
+//! become unavailable when the status topic is `offline`.
+//!
+//! See [MQTT Discovery](https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery).
+
+use core::fmt::Error as FmtError;
+use core::fmt::Write;
+
+/// Default discovery prefix of Home Assistant
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// A device announced to Home Assistant
+#[derive(Clone, Copy, Debug)]
+pub struct Device<'a> {
+    /// Unique identifier, also used in topics
+    pub id: &'a str,
+
+    /// Human readable name
+    pub name: &'a str,
+
+    /// Hardware model
+    pub model: &'a str,
+
+    /// Firmware version
+    pub firmware_version: &'a str,
+
+    /// Number of boots since the retained state was last reset
+    pub boot_count: u32,
+}
+
+/// A sensor channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Channel {
+    /// Key of the value in JSON readings, also used in topics
+    pub key: &'static str,
+
+    /// Human readable name
+    pub name: &'static str,
+
+    /// Home Assistant device class
+    pub device_class: &'static str,
+
+    /// Unit of measurement
+    pub unit: &'static str,
+
+    /// Whether the channel is diagnostic rather than a measurement
+    pub diagnostic: bool,
+}
+
+/// All sensor channels
+pub const CHANNELS: [Channel; 7] = [
+    Channel {
+        key: "temperature",
+        name: "Temperature",
+        device_class: "temperature",
+        unit: "°C",
+        diagnostic: false,
+    },
+    Channel {
+        key: "humidity",
+        name: "Humidity",
+        device_class: "humidity",
+        unit: "%",
+        diagnostic: false,
+    },
+    Channel {
+        key: "pressure",
+        name: "Pressure",
+        device_class: "atmospheric_pressure",
+        unit: "hPa",
+        diagnostic: false,
+    },
+    Channel {
+        key: "co2eq",
+        name: "CO₂eq",
+        device_class: "carbon_dioxide",
+        unit: "ppm",
+        diagnostic: false,
+    },
+    Channel {
+        key: "tvoc",
+        name: "TVOC",
+        device_class: "volatile_organic_compounds_parts",
+        unit: "ppb",
+        diagnostic: false,
+    },
+    Channel {
+        key: "battery",
+        name: "Battery",
+        device_class: "battery",
+        unit: "%",
+        diagnostic: true,
+    },
+    Channel {
+        key: "rssi",
+        name: "RSSI",
+        device_class: "signal_strength",
+        unit: "dBm",
+        diagnostic: true,
+    },
+];
+
+/// Write the config topic of a channel
+pub fn write_topic(
+    output: &mut impl Write,
+    prefix: &str,
+    device: &Device<'_>,
+    channel: &Channel,
+) -> Result<(), FmtError> {
+    write!(
+        output,
+        "{prefix}/sensor/{}/{}/config",
+        device.id, channel.key
+    )
+}
+
+/// Write the config payload of a channel
+///
+/// The boot count is reported together with the firmware version, since
+/// Home Assistant has no dedicated device field for it.
+pub fn write_config(
+    output: &mut impl Write,
+    device: &Device<'_>,
+    channel: &Channel,
+    state_topic: &str,
+    availability_topic: &str,
+) -> Result<(), FmtError> {
+    write!(
+        output,
+        concat!(
+            "{{",
+            "\"name\":\"{name}\",",
+            "\"unique_id\":\"{id}_{key}\",",
+            "\"object_id\":\"{id}_{key}\",",
+            "\"state_topic\":\"{state_topic}\",",
+            "\"value_template\":\"{{{{ value_json.{key} }}}}\",",
+            "\"device_class\":\"{device_class}\",",
+            "\"unit_of_measurement\":\"{unit}\",",
+            "\"state_class\":\"measurement\",",
+        ),
+        name = channel.name,
+        id = device.id,
+        key = channel.key,
+        state_topic = state_topic,
+        device_class = channel.device_class,
+        unit = channel.unit,
+    )?;
+    if channel.diagnostic {
+        output.write_str("\"entity_category\":\"diagnostic\",")?;
+    }
+    write!(
+        output,
+        concat!(
+            "\"availability_topic\":\"{availability_topic}\",",
+            "\"device\":{{",
+            "\"identifiers\":[\"{id}\"],",
+            "\"name\":\"{name}\",",
+            "\"model\":\"{model}\",",
+            "\"sw_version\":\"{version} (boot {boot_count})\"",
+            "}}}}",
+        ),
+        availability_topic = availability_topic,
+        id = device.id,
+        name = device.name,
+        model = device.model,
+        version = device.firmware_version,
+        boot_count = device.boot_count,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heapless::String;
+
+    /// Device of the snapshots
+    const DEVICE: Device<'static> = Device {
+        id: "crussant_a1b2c3",
+        name: "Crussant",
+        model: "ESP32-C3",
+        firmware_version: "0.1.0",
+        boot_count: 42,
+    };
+
+    /// Write the config payload of a channel with the topics of the snapshots
+    fn config(channel: &Channel) -> String<1024> {
+        let mut output = String::new();
+        write_config(
+            &mut output,
+            &DEVICE,
+            channel,
+            "crussant/readings",
+            "crussant/status",
+        )
+        .unwrap();
+        output
+    }
+
+    /// Find a channel by key
+    fn channel(key: &str) -> &'static Channel {
+        CHANNELS.iter().find(|channel| channel.key == key).unwrap()
+    }
+
+    #[test]
+    fn topic() {
+        let mut output = String::<128>::new();
+        write_topic(
+            &mut output,
+            DISCOVERY_PREFIX,
+            &DEVICE,
+            channel("temperature"),
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "homeassistant/sensor/crussant_a1b2c3/temperature/config"
+        );
+    }
+
+    #[test]
+    fn measurement_config() {
+        assert_eq!(
+            config(channel("temperature")),
+            include_str!("../test-data/discovery/temperature.json").trim_end()
+        );
+    }
+
+    #[test]
+    fn diagnostic_config() {
+        assert_eq!(
+            config(channel("battery")),
+            include_str!("../test-data/discovery/battery.json").trim_end()
+        );
+    }
+
+    #[test]
+    fn unicode_name_config() {
+        assert_eq!(
+            config(channel("co2eq")),
+            include_str!("../test-data/discovery/co2eq.json").trim_end()
+        );
+    }
+}
@@ -8,11 +8,17 @@
 
 use log::error;
 use log::info;
+use log::warn;
 
 use embassy_time::Delay;
 
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Receiver;
+use embassy_sync::signal::Signal;
+
+use embassy_futures::select::select;
+use embassy_futures::select::Either;
 
 use embedded_hal_bus::spi::ExclusiveDevice;
 
@@ -36,8 +42,6 @@
 use esp_hal::spi::FullDuplexMode;
 use esp_hal::Async;
 
-use heapless::HistoryBuffer;
-
 use uom::si::pressure::hectopascal;
 use uom::si::ratio::percent;
 use uom::si::thermodynamic_temperature::degree_celsius;
@@ -46,51 +50,146 @@
 use waveshare_154bv2_rs::Buffer;
 use waveshare_154bv2_rs::Error as DisplayError;
 
+use crate::battery::Battery;
 use crate::dashboard::draw as draw_dashboard;
+use crate::dashboard::draw_replace_battery;
 use crate::dashboard::Error as DashboardError;
 use crate::domain::Reading;
 use crate::domain::Sample;
+use crate::energy::Policy as EnergyPolicy;
+use crate::forecast::forecast_from_history;
+use crate::forecast::Location;
+use crate::history::History;
+use crate::synchronization::Status as SynchronizationStatus;
+use crate::SharedEnergy;
+use crate::SharedHistory;
+
+/// Signal to redraw the display without waiting for a new reading
+pub static REFRESH_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signal that a reading was handled, whether the display was refreshed or
+/// not
+pub static READING_HANDLED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// SPI device of the display
+pub type DisplaySpiDevice = ExclusiveDevice<
+    SpiDma<'static, SPI2, Channel0, FullDuplexMode, Async>,
+    Output<'static, Gpio8>,
+    Delay,
+>;
 
 /// Task for displaying samples
+///
+/// The display is refreshed only when the energy policy decides so, or when
+/// a refresh is requested, and it is initialized before the first refresh.
 #[embassy_executor::task]
 pub async fn update_task(
-    spi_device: ExclusiveDevice<
-        SpiDma<'static, SPI2, Channel0, FullDuplexMode, Async>,
-        Output<'static, Gpio8>,
-        Delay,
-    >,
+    spi_device: DisplaySpiDevice,
     busy: Input<'static, Gpio9>,
     rst: Output<'static, Gpio10>,
     dc: Output<'static, Gpio19>,
     receiver: Receiver<'static, NoopRawMutex, Reading, 3>,
-    history: &'static mut HistoryBuffer<(OffsetDateTime, Sample), 96>,
+    history: &'static SharedHistory,
+    location: Location,
+    synchronization: SynchronizationStatus,
+    battery: Option<Battery>,
+    energy: &'static SharedEnergy,
+    energy_policy: EnergyPolicy,
 ) {
     info!("Create display");
     let mut display = AsyncDisplay::new_with_individual_writes(spi_device, busy, rst, dc, Delay);
-
-    info!("Initialize display");
-    if let Err(error) = display.initialize().await {
-        error!(" Cannot initialize display: {error:?}");
-        return;
-    }
+    let mut initialized = false;
 
     loop {
         info!("Wait for message from sensor");
-        let reading = receiver.receive().await;
-        let now = reading.0;
+        let (history, reading) = match select(receiver.receive(), REFRESH_REQUESTED.wait()).await {
+            Either::First(reading) => {
+                let mut history = history.lock().await;
+                history.write(&reading);
+                (history, Some(reading))
+            }
+            Either::Second(()) => {
+                info!("Refresh requested");
+                (history.lock().await, None)
+            }
+        };
+        let Some(recent) = history.recent() else {
+            warn!("No reading to display");
+            continue;
+        };
+
+        let refresh = match reading {
+            Some(ref reading) => {
+                let decision = energy.lock().await.observe(&energy_policy, reading);
+                info!(
+                    "Refresh display: {}, next deep sleep {}s",
+                    decision.refresh,
+                    decision.sleep_duration.as_secs()
+                );
+                decision.refresh
+            }
+            None => true,
+        };
 
-        history.write(reading);
+        if refresh {
+            if !initialized {
+                info!("Initialize display");
+                match display.initialize().await {
+                    Ok(()) => initialized = true,
+                    Err(error) => error!("Cannot initialize display: {error:?}"),
+                }
+            }
+            if initialized {
+                match report(
+                    &recent.0,
+                    &history,
+                    &location,
+                    &synchronization,
+                    battery.as_ref(),
+                    &mut display,
+                )
+                .await
+                {
+                    Ok(()) => energy.lock().await.mark_displayed(&recent),
+                    Err(error) => error!("Could not report sample: {error:?}"),
+                }
+            }
+        }
 
-        if let Err(error) = report(&now, history, &mut display).await {
-            error!("Could not report sample: {error:?}");
+        if reading.is_some() {
+            READING_HANDLED.signal(());
         }
     }
 }
 
+/// Show a screen asking to replace the battery
+pub async fn show_replace_battery(
+    spi_device: DisplaySpiDevice,
+    busy: Input<'static, Gpio9>,
+    rst: Output<'static, Gpio10>,
+    dc: Output<'static, Gpio19>,
+    battery: &Battery,
+) -> Result<(), ReportError> {
+    info!("Create display");
+    let mut display = AsyncDisplay::new_with_individual_writes(spi_device, busy, rst, dc, Delay);
+    display.initialize().await?;
+
+    let mut buffer = Buffer::new();
+    info!("Draw replace battery screen on buffer");
+    draw_replace_battery(&mut buffer, battery)?;
+
+    info!("Draw buffer on display");
+    display.draw_buffer(&buffer).await?;
+    Ok(())
+}
+
 /// Report a new sample
 async fn report<SPI, BUSY, RST, DC, DELAY>(
     now: &OffsetDateTime,
-    history: &HistoryBuffer<Reading, 96>,
+    history: &History,
+    location: &Location,
+    synchronization: &SynchronizationStatus,
+    battery: Option<&Battery>,
     display: &mut AsyncDisplay<SPI, BUSY, RST, DC, DELAY>,
 ) -> Result<(), ReportError>
 where
@@ -100,14 +199,26 @@ async fn report<SPI, BUSY, RST, DC, DELAY>(
     DC: OutputPin,
     DELAY: DelayNs,
 {
-    #[allow(clippy::pattern_type_mismatch)]
-    if let Some((_, sample)) = history.recent() {
+    if let Some((_, ref sample)) = history.recent() {
         log_sample(sample);
 
+        let forecast = forecast_from_history(history, location);
+        match forecast {
+            Some(ref forecast) => info!("Forecast: {} ({:?})", forecast.text, forecast.tendency),
+            None => info!("Not enough history for a forecast"),
+        }
+
         let mut buffer = Buffer::new();
 
         info!("Draw dashboard on buffer");
-        draw_dashboard(&mut buffer, now, sample)?;
+        draw_dashboard(
+            &mut buffer,
+            now,
+            sample,
+            forecast.as_ref(),
+            synchronization,
+            battery,
+        )?;
 
         info!("Draw buffer on display");
         display.draw_buffer(&buffer).await?;
@@ -130,7 +241,7 @@ fn log_sample(sample: &Sample) {
 
 /// An error
 #[derive(Debug)]
-enum ReportError {
+pub enum ReportError {
     /// An error occurred while updating the display
     Display(#[allow(unused)] DisplayError),
 
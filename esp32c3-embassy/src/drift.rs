@@ -0,0 +1,256 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Estimation of clock drift across deep sleep cycles
+//!
+//! The clock is carried across deep sleep by assuming that the sleep lasts
+//! exactly as requested, but the RTC slow clock timing the sleep is not
+//! accurate.
+//! Every synchronization measures the offset between the server time and the
+//! local clock, from which the drift rate since the previous synchronization
+//! is estimated.
+//! The rate is then applied as a correction to the following sleeps, and the
+//! residual uncertainty decides when the next synchronization is needed.
+//!
+//! All times are in microseconds, timestamps are since the Unix epoch.
+
+use libm::fabsf;
+
+/// Drift rate in parts per million assumed before any calibration
+const UNCALIBRATED_RATE_PPM: f32 = 5_000.0;
+
+/// Lowest drift rate uncertainty in parts per million
+const MINIMUM_UNCERTAINTY_PPM: f32 = 20.0;
+
+/// Largest drift rate in parts per million considered plausible
+///
+/// Larger measured rates are caused by a wrong server time or by a sleep
+/// interrupted by a reset, and are discarded.
+const MAXIMUM_RATE_PPM: f32 = 50_000.0;
+
+/// Shortest time between synchronizations to estimate a drift rate
+///
+/// On shorter intervals the synchronization error dominates the drift.
+const MINIMUM_CALIBRATION_INTERVAL_MICROSECONDS: u64 = 5 * 60 * 1_000_000;
+
+/// Weight of a new measurement in the drift rate estimate
+const SMOOTHING: f32 = 0.5;
+
+/// Error of a synchronization, mostly due to network delay asymmetry
+const SYNCHRONIZATION_ERROR_MICROSECONDS: u64 = 50_000;
+
+/// Estimated drift of a clock
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Drift {
+    /// Time of the last synchronization
+    pub last_synchronization: u64,
+
+    /// Offset measured at the last synchronization, i.e. server time minus
+    /// local time, or 0 if there was no previous synchronization
+    pub last_offset: i64,
+
+    /// Estimated drift rate in parts per million
+    ///
+    /// A positive rate means that the clock runs slow and must be advanced.
+    pub rate_ppm: f32,
+
+    /// Uncertainty of the drift rate in parts per million
+    pub uncertainty_ppm: f32,
+
+    /// Number of measurements contributing to the drift rate
+    pub measurements: u32,
+}
+
+impl Drift {
+    /// Create an uncalibrated drift estimate after a first synchronization
+    pub const fn new(synchronization_time: u64) -> Self {
+        Self {
+            last_synchronization: synchronization_time,
+            last_offset: 0,
+            rate_ppm: 0.0,
+            uncertainty_ppm: UNCALIBRATED_RATE_PPM,
+            measurements: 0,
+        }
+    }
+
+    /// Record a synchronization with a measured offset
+    ///
+    /// The offset is the server time minus the local time, which already
+    /// includes the correction of the current drift rate.
+    /// The residual drift is therefore added to the current rate.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn record_synchronization(&mut self, synchronization_time: u64, offset: i64) {
+        let elapsed = synchronization_time.saturating_sub(self.last_synchronization);
+        self.last_offset = offset;
+
+        if elapsed >= MINIMUM_CALIBRATION_INTERVAL_MICROSECONDS {
+            let residual_ppm = offset as f32 / elapsed as f32 * 1_000_000.0;
+
+            if fabsf(self.rate_ppm + residual_ppm) <= MAXIMUM_RATE_PPM {
+                let weight = if self.measurements == 0 {
+                    1.0
+                } else {
+                    SMOOTHING
+                };
+                self.rate_ppm += weight * residual_ppm;
+                self.uncertainty_ppm = fabsf(residual_ppm).max(MINIMUM_UNCERTAINTY_PPM);
+                self.measurements = self.measurements.saturating_add(1);
+            }
+        }
+
+        self.last_synchronization = synchronization_time;
+    }
+
+    /// Compute the correction to add to a nominal duration
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    pub fn correction(&self, duration: u64) -> i64 {
+        (duration as f32 * self.rate_ppm / 1_000_000.0) as i64
+    }
+
+    /// Apply the correction to a nominal duration
+    pub fn corrected(&self, duration: u64) -> u64 {
+        duration.saturating_add_signed(self.correction(duration))
+    }
+
+    /// Estimate the clock error at a time
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn estimated_error(&self, now: u64) -> u64 {
+        let elapsed = now.saturating_sub(self.last_synchronization);
+        let drift = (elapsed as f32 * self.uncertainty_ppm / 1_000_000.0) as u64;
+        SYNCHRONIZATION_ERROR_MICROSECONDS + drift
+    }
+
+    /// Check whether the estimated clock error exceeds a threshold
+    pub fn needs_synchronization(&self, now: u64, maximum_error: u64) -> bool {
+        self.estimated_error(now) > maximum_error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One hour in microseconds
+    const HOUR: u64 = 60 * 60 * 1_000_000;
+
+    /// Time of the first synchronization
+    const START: u64 = 1_700_000_000_000_000;
+
+    /// Assert that two rates are equal within floating-point precision
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            fabsf(actual - expected) < 1e-3,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn first_synchronization_is_uncalibrated() {
+        let drift = Drift::new(START);
+        assert_eq!(drift.last_offset, 0);
+        assert_eq!(drift.measurements, 0);
+        assert_close(drift.rate_ppm, 0.0);
+        assert_eq!(drift.corrected(HOUR), HOUR);
+        assert_eq!(drift.estimated_error(START), 50_000);
+        assert_eq!(drift.estimated_error(START + HOUR), 50_000 + 18_000_000);
+    }
+
+    #[test]
+    fn first_measurement_sets_rate() {
+        let mut drift = Drift::new(START);
+        drift.record_synchronization(START + HOUR, 36_000);
+        assert_eq!(drift.last_synchronization, START + HOUR);
+        assert_eq!(drift.last_offset, 36_000);
+        assert_eq!(drift.measurements, 1);
+        assert_close(drift.rate_ppm, 10.0);
+        assert_close(drift.uncertainty_ppm, MINIMUM_UNCERTAINTY_PPM);
+    }
+
+    #[test]
+    fn negative_offset_sets_negative_rate() {
+        let mut drift = Drift::new(START);
+        drift.record_synchronization(START + HOUR, -180_000);
+        assert_close(drift.rate_ppm, -50.0);
+        assert_close(drift.uncertainty_ppm, 50.0);
+    }
+
+    #[test]
+    fn later_measurements_are_smoothed() {
+        let mut drift = Drift::new(START);
+        drift.record_synchronization(START + HOUR, 36_000);
+        drift.record_synchronization(START + 2 * HOUR, -18_000);
+        assert_eq!(drift.measurements, 2);
+        assert_close(drift.rate_ppm, 7.5);
+        assert_close(drift.uncertainty_ppm, MINIMUM_UNCERTAINTY_PPM);
+    }
+
+    #[test]
+    fn zero_offset_keeps_rate() {
+        let mut drift = Drift::new(START);
+        drift.record_synchronization(START + HOUR, 36_000);
+        drift.record_synchronization(START + 2 * HOUR, 0);
+        assert_eq!(drift.measurements, 2);
+        assert_close(drift.rate_ppm, 10.0);
+        assert_close(drift.uncertainty_ppm, MINIMUM_UNCERTAINTY_PPM);
+    }
+
+    #[test]
+    fn short_interval_is_not_measured() {
+        let mut drift = Drift::new(START);
+        drift.record_synchronization(START + 60_000_000, 30_000);
+        assert_eq!(drift.last_synchronization, START + 60_000_000);
+        assert_eq!(drift.last_offset, 30_000);
+        assert_eq!(drift.measurements, 0);
+        assert_close(drift.rate_ppm, 0.0);
+        assert_close(drift.uncertainty_ppm, UNCALIBRATED_RATE_PPM);
+    }
+
+    #[test]
+    fn implausible_rate_is_discarded() {
+        let mut drift = Drift::new(START);
+        drift.record_synchronization(START + HOUR, 360_000_000);
+        assert_eq!(drift.measurements, 0);
+        assert_close(drift.rate_ppm, 0.0);
+        assert_eq!(drift.last_synchronization, START + HOUR);
+    }
+
+    #[test]
+    fn synchronization_in_the_past_is_not_measured() {
+        let mut drift = Drift::new(START);
+        drift.record_synchronization(START - HOUR, 36_000);
+        assert_eq!(drift.measurements, 0);
+        assert_eq!(drift.last_synchronization, START - HOUR);
+    }
+
+    #[test]
+    fn corrected_applies_rate_in_both_directions() {
+        let mut drift = Drift::new(START);
+        drift.rate_ppm = 10.0;
+        assert!(drift.corrected(HOUR).abs_diff(HOUR + 36_000) <= 1);
+        drift.rate_ppm = -10.0;
+        assert!(drift.corrected(HOUR).abs_diff(HOUR - 36_000) <= 1);
+        drift.rate_ppm = 0.0;
+        assert_eq!(drift.corrected(HOUR), HOUR);
+        assert_eq!(drift.corrected(0), 0);
+    }
+
+    #[test]
+    fn estimated_error_grows_with_uncertainty() {
+        let mut drift = Drift::new(START);
+        drift.record_synchronization(START + HOUR, 36_000);
+        let now = START + HOUR;
+        assert_eq!(drift.estimated_error(now), 50_000);
+        assert_eq!(drift.estimated_error(now - HOUR), 50_000);
+        assert!(drift.estimated_error(now + HOUR).abs_diff(50_000 + 72_000) <= 1);
+        assert!(!drift.needs_synchronization(now + HOUR, 200_000));
+        assert!(drift.needs_synchronization(now + 3 * HOUR, 200_000));
+    }
+}
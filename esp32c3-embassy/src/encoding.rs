@@ -0,0 +1,539 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Compact binary encoding of readings
+//!
+//! A reading is encoded in at most [`MAXIMAL_SIZE`] bytes:
+//!
+//! | Size   | Content                                              |
+//! |--------|------------------------------------------------------|
+//! | 1      | Header: version in bits 7-5, presence in bits 4-0    |
+//! | 1 to 5 | Seconds since a base time, as unsigned LEB128        |
+//! | 2      | Temperature in centi-degrees Celsius, `i16`          |
+//! | 2      | Relative humidity in per-mille, `u16`                |
+//! | 3      | Pressure in deci-pascals, `u24`                      |
+//! | 2      | Equivalent CO₂ in parts per million, `u16`           |
+//! | 2      | Total volatile organic compounds in parts per billion, `u16` |
+//!
+//! Integers are little endian.
+//! The version is [`VERSION`].
+//! Bit 0 to 4 of the presence bitmap mark temperature, humidity, pressure,
+//! CO₂ and TVOC, in this order, and only present quantities are encoded.
+//! Quantities that are not a number, such as after a sensor failure, are
+//! absent.
+//!
+//! The base time of a reading is the time of the previous one in a sequence,
+//! or the Unix epoch for the first one.
+//! Times are truncated to seconds, and decoded in UTC.
+//! Samples are rounded to the resolution of their encoding, and saturated to
+//! its range.
+//!
+//! For instance, a reading taken one minute after the previous one at
+//! 21.5°C, 45.3% and 1013.25hPa, without gas measurements, is encoded as:
+//!
+//! ```text
+//! 27 3c 66 08 c5 01 02 76 0f
+//! ```
+
+use libm::roundf;
+
+use time::OffsetDateTime;
+
+use uom::si::f32::Pressure;
+use uom::si::f32::Ratio as Humidity;
+use uom::si::f32::ThermodynamicTemperature as Temperature;
+use uom::si::pressure::pascal;
+use uom::si::ratio::per_mille;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::domain::Reading;
+use crate::domain::Sample;
+
+/// Version of the encoding
+pub const VERSION: u8 = 1;
+
+/// Maximal size of an encoded reading
+pub const MAXIMAL_SIZE: usize = 1 + 5 + 2 + 2 + 3 + 2 + 2;
+
+/// Presence bit of temperature
+const TEMPERATURE: u8 = 1 << 0;
+
+/// Presence bit of humidity
+const HUMIDITY: u8 = 1 << 1;
+
+/// Presence bit of pressure
+const PRESSURE: u8 = 1 << 2;
+
+/// Presence bit of equivalent CO₂
+const CO2: u8 = 1 << 3;
+
+/// Presence bit of total volatile organic compounds
+const TVOC: u8 = 1 << 4;
+
+/// Maximal value of a `u24`
+const U24_MAX: u32 = (1 << 24) - 1;
+
+/// Gas concentrations measured by an air quality sensor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Gas {
+    /// Equivalent CO₂ in parts per million
+    pub co2: Option<u16>,
+
+    /// Total volatile organic compounds in parts per billion
+    pub tvoc: Option<u16>,
+}
+
+/// Encode a reading with gas concentrations
+///
+/// Return the number of bytes written.
+pub fn encode(
+    (time, sample): &Reading,
+    gas: &Gas,
+    base: OffsetDateTime,
+    output: &mut [u8],
+) -> Result<usize, Error> {
+    let delta = u32::try_from(time.unix_timestamp() - base.unix_timestamp())
+        .map_err(|_| Error::InvalidTime)?;
+
+    let temperature = finite(sample.temperature.get::<degree_celsius>());
+    let humidity = finite(sample.humidity.get::<per_mille>());
+    let pressure = finite(sample.pressure.get::<pascal>());
+
+    let mut presence = 0;
+    for (present, bit) in [
+        (temperature.is_some(), TEMPERATURE),
+        (humidity.is_some(), HUMIDITY),
+        (pressure.is_some(), PRESSURE),
+        (gas.co2.is_some(), CO2),
+        (gas.tvoc.is_some(), TVOC),
+    ] {
+        if present {
+            presence |= bit;
+        }
+    }
+
+    let mut writer = Writer {
+        output,
+        position: 0,
+    };
+    writer.put(&[VERSION << 5 | presence])?;
+    writer.put_varint(delta)?;
+    if let Some(temperature) = temperature {
+        // Float to integer casts saturate
+        #[allow(clippy::cast_possible_truncation)]
+        let temperature = roundf(temperature * 100.0) as i16;
+        writer.put(&temperature.to_le_bytes())?;
+    }
+    if let Some(humidity) = humidity {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let humidity = roundf(humidity) as u16;
+        writer.put(&humidity.to_le_bytes())?;
+    }
+    if let Some(pressure) = pressure {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let pressure = (roundf(pressure * 10.0) as u32).min(U24_MAX);
+        let [byte_0, byte_1, byte_2, _] = pressure.to_le_bytes();
+        writer.put(&[byte_0, byte_1, byte_2])?;
+    }
+    if let Some(co2) = gas.co2 {
+        writer.put(&co2.to_le_bytes())?;
+    }
+    if let Some(tvoc) = gas.tvoc {
+        writer.put(&tvoc.to_le_bytes())?;
+    }
+    Ok(writer.position)
+}
+
+/// Decode a reading with gas concentrations
+///
+/// Absent quantities of the sample are not a number.
+/// Return the reading, the gas concentrations and the number of bytes read.
+pub fn decode(input: &[u8], base: OffsetDateTime) -> Result<(Reading, Gas, usize), Error> {
+    let mut reader = Reader { input, position: 0 };
+
+    let [header] = reader.take()?;
+    if header >> 5 != VERSION {
+        return Err(Error::UnsupportedVersion(header >> 5));
+    }
+    let presence = header & 0x1f;
+
+    let delta = reader.take_varint()?;
+    let time = OffsetDateTime::from_unix_timestamp(base.unix_timestamp() + i64::from(delta))
+        .map_err(|_| Error::InvalidTime)?;
+
+    let mut sample = Sample {
+        temperature: Temperature::new::<degree_celsius>(f32::NAN),
+        humidity: Humidity::new::<per_mille>(f32::NAN),
+        pressure: Pressure::new::<pascal>(f32::NAN),
+    };
+    if presence & TEMPERATURE != 0 {
+        let temperature = i16::from_le_bytes(reader.take()?);
+        sample.temperature = Temperature::new::<degree_celsius>(f32::from(temperature) / 100.0);
+    }
+    if presence & HUMIDITY != 0 {
+        let humidity = u16::from_le_bytes(reader.take()?);
+        sample.humidity = Humidity::new::<per_mille>(f32::from(humidity));
+    }
+    if presence & PRESSURE != 0 {
+        let [byte_0, byte_1, byte_2] = reader.take()?;
+        let pressure = u32::from_le_bytes([byte_0, byte_1, byte_2, 0]);
+        // Values up to 2^24 are exact in `f32`
+        #[allow(clippy::cast_precision_loss)]
+        let pressure = pressure as f32 / 10.0;
+        sample.pressure = Pressure::new::<pascal>(pressure);
+    }
+
+    let mut gas = Gas::default();
+    if presence & CO2 != 0 {
+        gas.co2 = Some(u16::from_le_bytes(reader.take()?));
+    }
+    if presence & TVOC != 0 {
+        gas.tvoc = Some(u16::from_le_bytes(reader.take()?));
+    }
+
+    Ok(((time, sample), gas, reader.position))
+}
+
+/// Return a value if it is finite
+fn finite(value: f32) -> Option<f32> {
+    value.is_finite().then_some(value)
+}
+
+/// A writer to a byte buffer
+struct Writer<'a> {
+    /// Buffer
+    output: &'a mut [u8],
+
+    /// Number of bytes written
+    position: usize,
+}
+
+impl Writer<'_> {
+    /// Write bytes
+    fn put(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.position + bytes.len();
+        self.output
+            .get_mut(self.position..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+
+    /// Write an unsigned LEB128 integer
+    #[allow(clippy::cast_possible_truncation)]
+    fn put_varint(&mut self, mut value: u32) -> Result<(), Error> {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                return self.put(&[byte]);
+            }
+            self.put(&[byte | 0x80])?;
+        }
+    }
+}
+
+/// A reader from a byte buffer
+struct Reader<'a> {
+    /// Buffer
+    input: &'a [u8],
+
+    /// Number of bytes read
+    position: usize,
+}
+
+impl Reader<'_> {
+    /// Read a fixed number of bytes
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let end = self.position + N;
+        let bytes = self
+            .input
+            .get(self.position..end)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(Error::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    /// Read an unsigned LEB128 integer of at most 32 bits
+    fn take_varint(&mut self) -> Result<u32, Error> {
+        let mut value: u32 = 0;
+        for shift in (0..32).step_by(7) {
+            let [byte] = self.take()?;
+            let bits = u32::from(byte & 0x7f);
+            if shift == 28 && bits > 0x0f {
+                return Err(Error::InvalidTime);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidTime)
+    }
+}
+
+/// An error within encoding or decoding
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer is too small
+    BufferTooSmall,
+
+    /// The input ends in the middle of a reading
+    Truncated,
+
+    /// The reading has another version of the encoding
+    UnsupportedVersion(#[allow(unused)] u8),
+
+    /// The time is before the base time, or too far after it
+    InvalidTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::macros::datetime;
+    use time::Duration;
+
+    use uom::si::pressure::hectopascal;
+    use uom::si::ratio::percent;
+
+    /// Base time of readings
+    const BASE: OffsetDateTime = datetime!(2024-08-14 10:00:00 UTC);
+
+    /// Create a reading some seconds after the base time
+    fn reading(seconds: u32, temperature: f32, humidity: f32, pressure: f32) -> Reading {
+        let sample = Sample {
+            temperature: Temperature::new::<degree_celsius>(temperature),
+            humidity: Humidity::new::<per_mille>(humidity),
+            pressure: Pressure::new::<pascal>(pressure),
+        };
+        (BASE + Duration::seconds(i64::from(seconds)), sample)
+    }
+
+    /// Encode a reading and decode it back
+    ///
+    /// Check that the decoded reading is encoded to the same bytes.
+    fn round_trip(reading: &Reading, gas: &Gas) -> (Reading, Gas) {
+        let mut buffer = [0_u8; MAXIMAL_SIZE];
+        let size = encode(reading, gas, BASE, &mut buffer).unwrap();
+        let (decoded, decoded_gas, read) = decode(&buffer, BASE).unwrap();
+        assert_eq!(read, size);
+
+        let mut again = [0_u8; MAXIMAL_SIZE];
+        let again_size = encode(&decoded, &decoded_gas, BASE, &mut again).unwrap();
+        assert_eq!(again.get(..again_size), buffer.get(..size));
+        (decoded, decoded_gas)
+    }
+
+    #[test]
+    fn documented_example() {
+        let reading = {
+            let (time, mut sample) = reading(60, 21.5, 0.0, 0.0);
+            sample.humidity = Humidity::new::<percent>(45.3);
+            sample.pressure = Pressure::new::<hectopascal>(1013.25);
+            (time, sample)
+        };
+        let mut buffer = [0_u8; MAXIMAL_SIZE];
+        let size = encode(&reading, &Gas::default(), BASE, &mut buffer).unwrap();
+        assert_eq!(
+            buffer.get(..size).unwrap(),
+            [0x27, 0x3c, 0x66, 0x08, 0xc5, 0x01, 0x02, 0x76, 0x0f]
+        );
+    }
+
+    #[test]
+    fn round_trip_temperatures() {
+        for centi_degrees in i16::MIN..=i16::MAX {
+            let reading = reading(0, f32::from(centi_degrees) / 100.0, 500.0, 100_000.0);
+            let ((_, sample), _) = round_trip(&reading, &Gas::default());
+            let decoded = roundf(sample.temperature.get::<degree_celsius>() * 100.0);
+            assert_eq!(decoded, f32::from(centi_degrees));
+        }
+    }
+
+    #[test]
+    fn round_trip_humidities() {
+        for humidity in u16::MIN..=u16::MAX {
+            let reading = reading(0, 20.0, f32::from(humidity), 100_000.0);
+            let ((_, sample), _) = round_trip(&reading, &Gas::default());
+            let decoded = roundf(sample.humidity.get::<per_mille>());
+            assert_eq!(decoded, f32::from(humidity));
+        }
+    }
+
+    #[test]
+    fn round_trip_pressures() {
+        let deci_pascals = (0..U24_MAX).step_by(997).chain([U24_MAX]);
+        for deci_pascals in deci_pascals {
+            // Pressures above 2^20 Pa are not exact in `f32`
+            #[allow(clippy::cast_precision_loss)]
+            let pressure = deci_pascals as f32 / 10.0;
+            let reading = reading(0, 20.0, 500.0, pressure);
+            let ((_, sample), _) = round_trip(&reading, &Gas::default());
+            let error = (sample.pressure.get::<pascal>() - pressure).abs();
+            assert!(error <= 0.05 + pressure * f32::EPSILON, "{deci_pascals}");
+        }
+    }
+
+    #[test]
+    fn round_trip_gas() {
+        for value in u16::MIN..=u16::MAX {
+            let gas = Gas {
+                co2: Some(value),
+                tvoc: Some(u16::MAX - value),
+            };
+            let (_, decoded) = round_trip(&reading(0, 20.0, 500.0, 100_000.0), &gas);
+            assert_eq!(decoded, gas);
+        }
+    }
+
+    #[test]
+    fn round_trip_times() {
+        let cases = [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (16_383, 2),
+            (16_384, 3),
+            (2_097_151, 3),
+            (2_097_152, 4),
+            (268_435_455, 4),
+            (268_435_456, 5),
+            (u32::MAX, 5),
+        ];
+        for (seconds, varint_size) in cases {
+            let reading = reading(seconds, f32::NAN, f32::NAN, f32::NAN);
+            let mut buffer = [0_u8; MAXIMAL_SIZE];
+            let size = encode(&reading, &Gas::default(), BASE, &mut buffer).unwrap();
+            assert_eq!(size, 1 + varint_size, "{seconds}");
+            let ((time, _), _, _) = decode(&buffer, BASE).unwrap();
+            assert_eq!(time, reading.0, "{seconds}");
+        }
+    }
+
+    #[test]
+    fn round_trip_sequence() {
+        let readings = [
+            reading(0, 21.5, 453.0, 101_325.0),
+            reading(60, 21.25, 455.0, 101_320.0),
+            reading(3600, f32::NAN, 460.0, 101_300.0),
+        ];
+        let mut buffer = [0_u8; 3 * MAXIMAL_SIZE];
+        let mut size = 0;
+        let mut base = BASE;
+        for reading in &readings {
+            size += encode(
+                reading,
+                &Gas::default(),
+                base,
+                buffer.get_mut(size..).unwrap(),
+            )
+            .unwrap();
+            base = reading.0;
+        }
+
+        let mut position = 0;
+        let mut base = BASE;
+        for reading in &readings {
+            let ((time, sample), _, read) =
+                decode(buffer.get(position..size).unwrap(), base).unwrap();
+            assert_eq!(time, reading.0);
+            assert_eq!(sample.humidity, reading.1.humidity);
+            position += read;
+            base = time;
+        }
+        assert_eq!(position, size);
+    }
+
+    #[test]
+    fn saturate_out_of_range_values() {
+        let gas = Gas::default();
+        let ((_, sample), _) = round_trip(&reading(0, 400.0, 70_000.0, 2e6), &gas);
+        assert_eq!(sample.temperature.get::<degree_celsius>(), 327.67);
+        assert_eq!(sample.humidity.get::<per_mille>(), 65_535.0);
+        assert_eq!(sample.pressure.get::<pascal>(), 1_677_721.5);
+
+        let ((_, sample), _) = round_trip(&reading(0, -400.0, -1.0, -1.0), &gas);
+        assert_eq!(sample.temperature.get::<degree_celsius>(), -327.68);
+        assert_eq!(sample.humidity.get::<per_mille>(), 0.0);
+        assert_eq!(sample.pressure.get::<pascal>(), 0.0);
+    }
+
+    #[test]
+    fn absent_values() {
+        let reading = reading(0, f32::NAN, f32::INFINITY, 100_000.0);
+        let mut buffer = [0_u8; MAXIMAL_SIZE];
+        let size = encode(&reading, &Gas::default(), BASE, &mut buffer).unwrap();
+        assert_eq!(buffer.first(), Some(&(VERSION << 5 | PRESSURE)));
+        assert_eq!(size, 1 + 1 + 3);
+
+        let ((_, sample), gas, _) = decode(&buffer, BASE).unwrap();
+        assert!(sample.temperature.get::<degree_celsius>().is_nan());
+        assert!(sample.humidity.get::<per_mille>().is_nan());
+        assert_eq!(sample.pressure.get::<pascal>(), 100_000.0);
+        assert_eq!(gas, Gas::default());
+    }
+
+    #[test]
+    fn reject_unknown_versions() {
+        for version in (0..8).filter(|version| *version != VERSION) {
+            let input = [version << 5 | TEMPERATURE, 0, 0, 0];
+            assert_eq!(
+                decode(&input, BASE),
+                Err(Error::UnsupportedVersion(version))
+            );
+        }
+    }
+
+    #[test]
+    fn reject_truncated_input() {
+        let reading = reading(300, 21.5, 453.0, 101_325.0);
+        let gas = Gas {
+            co2: Some(400),
+            tvoc: Some(10),
+        };
+        let mut buffer = [0_u8; MAXIMAL_SIZE];
+        let size = encode(&reading, &gas, BASE, &mut buffer).unwrap();
+        for length in 0..size {
+            assert_eq!(
+                decode(buffer.get(..length).unwrap(), BASE),
+                Err(Error::Truncated),
+                "{length}"
+            );
+            let mut output = [0_u8; MAXIMAL_SIZE];
+            assert_eq!(
+                encode(&reading, &gas, BASE, output.get_mut(..length).unwrap()),
+                Err(Error::BufferTooSmall),
+                "{length}"
+            );
+        }
+    }
+
+    #[test]
+    fn reject_invalid_times() {
+        let header = VERSION << 5;
+        // More than 32 bits
+        assert_eq!(
+            decode(&[header, 0xff, 0xff, 0xff, 0xff, 0x10], BASE),
+            Err(Error::InvalidTime)
+        );
+        // More than 5 bytes
+        assert_eq!(
+            decode(&[header, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00], BASE),
+            Err(Error::InvalidTime)
+        );
+
+        let before = (BASE - Duration::SECOND, reading(0, 20.0, 500.0, 1e5).1);
+        let mut buffer = [0_u8; MAXIMAL_SIZE];
+        assert_eq!(
+            encode(&before, &Gas::default(), BASE, &mut buffer),
+            Err(Error::InvalidTime)
+        );
+    }
+}
@@ -0,0 +1,672 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Policy for refreshing the display and sleeping
+//!
+//! Refreshing the E-INK display and staying awake are the most expensive
+//! operations besides WiFi, so after a wakeup the station takes one reading
+//! and goes back to deep sleep at once.
+//! The display is only refreshed when a quantity changed by more than its
+//! delta since it was last displayed, or when the displayed update time is
+//! older than [`STALE_AFTER`].
+//!
+//! The deep sleep duration starts from the configured one, and doubles for
+//! every [`STABLE_READINGS_PER_DOUBLING`] consecutive readings without a
+//! significant change, and again at night.
+//! It never exceeds [`MAXIMAL_SLEEP_DURATION`].
+//!
+//! The [`State`] is retained in RTC memory across deep sleep, together with
+//! the [`Usage`] from which an energy [`Report`] is estimated.
+
+use crc::Digest;
+
+use embassy_time::Duration;
+
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::config::Config;
+use crate::domain::Reading;
+use crate::domain::Sample;
+use crate::history::compact_sample;
+use crate::history::expand_sample;
+use crate::history::CompactSample;
+use crate::retained::Checksum;
+
+/// Age of the displayed reading after which the display is refreshed anyway
+///
+/// This is longer than [`MAXIMAL_SLEEP_DURATION`], otherwise the display
+/// would be refreshed at every wakeup once the sleep is long.
+pub const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Number of consecutive stable readings after which the sleep doubles
+pub const STABLE_READINGS_PER_DOUBLING: u32 = 3;
+
+/// Maximal number of doublings of the sleep for stable readings
+const MAXIMAL_DOUBLINGS: u32 = 3;
+
+/// Local hour at which the night starts
+const NIGHT_START_HOUR: u8 = 22;
+
+/// Local hour at which the night ends
+const NIGHT_END_HOUR: u8 = 6;
+
+/// Factor of the sleep duration at night
+const NIGHT_FACTOR: u32 = 2;
+
+/// Longest deep sleep
+///
+/// This is shorter than the long sleep of the clock synchronization policy,
+/// so that longer sleeps do not require connecting to WiFi.
+pub const MAXIMAL_SLEEP_DURATION: Duration = Duration::from_secs(40 * 60);
+
+/// Estimated consumption of the station
+///
+/// These are rough figures for an ESP32-C3 board with a BME280 sensor and a
+/// 1.54 inches tri-color E-INK display.
+pub const CONSUMPTION: Consumption = Consumption {
+    awake_milliamps: 20.0,
+    wifi_milliamps: 60.0,
+    sleep_microamps: 50.0,
+    refresh_milliamp_seconds: 60.0,
+};
+
+/// Policy for refreshing the display and sleeping
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    /// Smallest change of temperature in degrees Celsius to refresh
+    pub temperature_delta: f32,
+
+    /// Smallest change of relative humidity in percent to refresh
+    pub humidity_delta: f32,
+
+    /// Smallest change of pressure in hectopascal to refresh
+    pub pressure_delta: f32,
+
+    /// Age of the displayed reading after which the display is refreshed
+    pub stale_after: Duration,
+
+    /// Deep sleep duration while readings change
+    pub base_sleep_duration: Duration,
+
+    /// Longest deep sleep
+    pub maximal_sleep_duration: Duration,
+
+    /// Number of consecutive stable readings after which the sleep doubles
+    pub stable_readings_per_doubling: u32,
+
+    /// Local hours at which the night starts and ends
+    pub night_hours: (u8, u8),
+
+    /// Factor of the sleep duration at night
+    pub night_factor: u32,
+}
+
+impl Policy {
+    /// Create a policy with the deltas and sleep duration of a configuration
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(config: &Config) -> Self {
+        Self {
+            temperature_delta: config.temperature_delta_thousandths as f32 / 1000.0,
+            humidity_delta: config.humidity_delta_thousandths as f32 / 1000.0,
+            pressure_delta: config.pressure_delta_thousandths as f32 / 1000.0,
+            stale_after: STALE_AFTER,
+            base_sleep_duration: config.deep_sleep_duration,
+            maximal_sleep_duration: MAXIMAL_SLEEP_DURATION,
+            stable_readings_per_doubling: STABLE_READINGS_PER_DOUBLING,
+            night_hours: (NIGHT_START_HOUR, NIGHT_END_HOUR),
+            night_factor: NIGHT_FACTOR,
+        }
+    }
+
+    /// Check whether a sample changed significantly from a displayed one
+    fn changed(&self, displayed: &Sample, sample: &Sample) -> bool {
+        [
+            (
+                displayed.temperature.get::<degree_celsius>(),
+                sample.temperature.get::<degree_celsius>(),
+                self.temperature_delta,
+            ),
+            (
+                displayed.humidity.get::<percent>(),
+                sample.humidity.get::<percent>(),
+                self.humidity_delta,
+            ),
+            (
+                displayed.pressure.get::<hectopascal>(),
+                sample.pressure.get::<hectopascal>(),
+                self.pressure_delta,
+            ),
+        ]
+        .into_iter()
+        .any(|(displayed, value, delta)| {
+            let difference = (value - displayed).abs();
+            difference.is_nan() || difference > delta
+        })
+    }
+
+    /// Check whether a local hour is at night
+    fn is_night(&self, hour: u8) -> bool {
+        let (start, end) = self.night_hours;
+        if start <= end {
+            (start..end).contains(&hour)
+        } else {
+            hour >= start || hour < end
+        }
+    }
+
+    /// Compute the sleep duration after some stable readings
+    fn sleep_duration(&self, stable_readings: u32, night: bool) -> Duration {
+        let doublings = stable_readings
+            .checked_div(self.stable_readings_per_doubling)
+            .unwrap_or(0)
+            .min(MAXIMAL_DOUBLINGS);
+        let mut duration = self.base_sleep_duration * (1 << doublings);
+        if night {
+            duration *= self.night_factor;
+        }
+        duration.min(self.maximal_sleep_duration)
+    }
+}
+
+/// Decision after a reading
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+    /// Whether to refresh the display
+    pub refresh: bool,
+
+    /// Duration of the next deep sleep
+    pub sleep_duration: Duration,
+}
+
+/// State of the policy retained across deep sleep
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct State {
+    /// Time in seconds since Unix epoch and sample last displayed
+    displayed: Option<(i64, CompactSample)>,
+
+    /// Number of consecutive readings without a significant change
+    stable_readings: u32,
+
+    /// Duration of the next deep sleep in seconds, or zero if not decided
+    sleep_seconds: u32,
+
+    /// Usage since the state was last reset
+    pub usage: Usage,
+}
+
+impl State {
+    /// Create a state with nothing displayed
+    pub const fn new() -> Self {
+        Self {
+            displayed: None,
+            stable_readings: 0,
+            sleep_seconds: 0,
+            usage: Usage::new(),
+        }
+    }
+
+    /// Decide whether to refresh the display after a reading, and how long
+    /// to sleep next
+    pub fn observe(&mut self, policy: &Policy, (time, sample): &Reading) -> Decision {
+        let (changed, stale) = match self.displayed {
+            None => (true, true),
+            Some((displayed_time, displayed)) => (
+                policy.changed(&expand_sample(displayed), sample),
+                time.unix_timestamp() - displayed_time >= seconds(policy.stale_after).into(),
+            ),
+        };
+
+        self.stable_readings = if changed {
+            0
+        } else {
+            self.stable_readings.saturating_add(1)
+        };
+
+        let sleep_duration =
+            policy.sleep_duration(self.stable_readings, policy.is_night(time.hour()));
+        self.sleep_seconds = seconds(sleep_duration);
+
+        Decision {
+            refresh: changed || stale,
+            sleep_duration,
+        }
+    }
+
+    /// Record that a reading was drawn on the display
+    pub fn mark_displayed(&mut self, (time, sample): &Reading) {
+        self.displayed = Some((time.unix_timestamp(), compact_sample(sample)));
+        self.usage.refreshes = self.usage.refreshes.saturating_add(1);
+    }
+
+    /// Return the duration of the next deep sleep
+    ///
+    /// Fall back to the base duration if no reading was observed.
+    pub fn sleep_duration(&self, policy: &Policy) -> Duration {
+        if self.sleep_seconds == 0 {
+            policy.base_sleep_duration
+        } else {
+            Duration::from_secs(u64::from(self.sleep_seconds))
+        }
+    }
+
+    /// Estimate the energy used since the state was last reset
+    #[allow(clippy::cast_precision_loss)]
+    pub fn report(&self, policy: &Policy, consumption: &Consumption) -> Report {
+        let usage = self.usage;
+        let awake_seconds = usage.awake_milliseconds as f32 / 1000.0;
+        let wifi_seconds = usage.wifi_milliseconds as f32 / 1000.0;
+        let sleep_seconds = usage.sleep_milliseconds as f32 / 1000.0;
+
+        let milliamp_seconds = awake_seconds * consumption.awake_milliamps
+            + wifi_seconds * consumption.wifi_milliamps
+            + sleep_seconds * consumption.sleep_microamps / 1000.0
+            + usage.refreshes as f32 * consumption.refresh_milliamp_seconds;
+        let total_seconds = awake_seconds + sleep_seconds;
+        let average_microamps = if total_seconds > 0.0 {
+            milliamp_seconds / total_seconds * 1000.0
+        } else {
+            0.0
+        };
+
+        Report {
+            usage,
+            charge_milliamp_hours: milliamp_seconds / 3600.0,
+            average_microamps,
+            stable_readings: self.stable_readings,
+            sleep_duration: self.sleep_duration(policy),
+        }
+    }
+}
+
+impl Checksum for State {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.displayed.feed(digest);
+        self.stable_readings.feed(digest);
+        self.sleep_seconds.feed(digest);
+        self.usage.feed(digest);
+    }
+}
+
+/// Time spent in each mode since the state was last reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Usage {
+    /// Number of wakeups
+    pub wakeups: u32,
+
+    /// Number of display refreshes
+    pub refreshes: u32,
+
+    /// Time awake in milliseconds
+    pub awake_milliseconds: u64,
+
+    /// Time with WiFi on in milliseconds, also counted as awake
+    pub wifi_milliseconds: u64,
+
+    /// Time in deep sleep in milliseconds
+    pub sleep_milliseconds: u64,
+}
+
+impl Usage {
+    /// Create an empty usage
+    pub const fn new() -> Self {
+        Self {
+            wakeups: 0,
+            refreshes: 0,
+            awake_milliseconds: 0,
+            wifi_milliseconds: 0,
+            sleep_milliseconds: 0,
+        }
+    }
+
+    /// Record a wakeup before entering deep sleep
+    pub fn record_wakeup(&mut self, awake: Duration, wifi: Duration, sleep: Duration) {
+        self.wakeups = self.wakeups.saturating_add(1);
+        self.awake_milliseconds = self.awake_milliseconds.saturating_add(awake.as_millis());
+        self.wifi_milliseconds = self.wifi_milliseconds.saturating_add(wifi.as_millis());
+        self.sleep_milliseconds = self.sleep_milliseconds.saturating_add(sleep.as_millis());
+    }
+}
+
+impl Checksum for Usage {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.wakeups.feed(digest);
+        self.refreshes.feed(digest);
+        self.awake_milliseconds.feed(digest);
+        self.wifi_milliseconds.feed(digest);
+        self.sleep_milliseconds.feed(digest);
+    }
+}
+
+/// Estimated consumption in each mode
+#[derive(Clone, Copy, Debug)]
+pub struct Consumption {
+    /// Current while awake in milliamps
+    pub awake_milliamps: f32,
+
+    /// Additional current while WiFi is on in milliamps
+    pub wifi_milliamps: f32,
+
+    /// Current in deep sleep in microamps
+    pub sleep_microamps: f32,
+
+    /// Additional charge of a display refresh in milliamp seconds
+    pub refresh_milliamp_seconds: f32,
+}
+
+/// Estimated energy used since the state was last reset
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    /// Time spent in each mode
+    pub usage: Usage,
+
+    /// Estimated charge drawn from the battery in milliamp hours
+    pub charge_milliamp_hours: f32,
+
+    /// Estimated average current in microamps
+    pub average_microamps: f32,
+
+    /// Number of consecutive readings without a significant change
+    pub stable_readings: u32,
+
+    /// Duration of the next deep sleep
+    pub sleep_duration: Duration,
+}
+
+/// Convert a duration to whole seconds, saturating
+fn seconds(duration: Duration) -> u32 {
+    u32::try_from(duration.as_secs()).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    use time::macros::datetime;
+    use time::OffsetDateTime;
+
+    use uom::si::f32::Pressure;
+    use uom::si::f32::Ratio as Humidity;
+    use uom::si::f32::ThermodynamicTemperature as Temperature;
+
+    /// Policy of the tests
+    const POLICY: Policy = Policy {
+        temperature_delta: 0.2,
+        humidity_delta: 1.0,
+        pressure_delta: 0.5,
+        stale_after: STALE_AFTER,
+        base_sleep_duration: Duration::from_secs(300),
+        maximal_sleep_duration: MAXIMAL_SLEEP_DURATION,
+        stable_readings_per_doubling: STABLE_READINGS_PER_DOUBLING,
+        night_hours: (NIGHT_START_HOUR, NIGHT_END_HOUR),
+        night_factor: NIGHT_FACTOR,
+    };
+
+    /// A morning time
+    const MORNING: OffsetDateTime = datetime!(2024-06-01 08:00 +02:00);
+
+    /// Create a reading of temperature, humidity and pressure
+    fn reading(time: OffsetDateTime, [temperature, humidity, pressure]: [f32; 3]) -> Reading {
+        let sample = Sample::from((
+            Temperature::new::<degree_celsius>(temperature),
+            Humidity::new::<percent>(humidity),
+            Pressure::new::<hectopascal>(pressure),
+        ));
+        (time, sample)
+    }
+
+    /// Observe a reading and mark it displayed if the display is refreshed
+    fn wake(state: &mut State, reading: &Reading) -> Decision {
+        let decision = state.observe(&POLICY, reading);
+        if decision.refresh {
+            state.mark_displayed(reading);
+        }
+        decision
+    }
+
+    /// Observe unchanged readings every minute and return the sleep durations
+    /// in seconds
+    fn stable_sleeps(start: OffsetDateTime, count: usize) -> std::vec::Vec<u64> {
+        let mut state = State::new();
+        let mut time = start;
+        (0..count)
+            .map(|_| {
+                let decision = wake(&mut state, &reading(time, [20.0, 50.0, 1000.0]));
+                time += time::Duration::minutes(1);
+                decision.sleep_duration.as_secs()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn refresh_first_reading() {
+        let mut state = State::new();
+        assert_eq!(state.sleep_duration(&POLICY), POLICY.base_sleep_duration);
+
+        let decision = wake(&mut state, &reading(MORNING, [20.0, 50.0, 1000.0]));
+        assert_eq!(
+            decision,
+            Decision {
+                refresh: true,
+                sleep_duration: POLICY.base_sleep_duration,
+            }
+        );
+        assert_eq!(state.usage.refreshes, 1);
+    }
+
+    #[test]
+    fn refresh_beyond_deltas() {
+        let displayed = [20.0, 50.0, 1000.0];
+        for (sample, refresh) in [
+            ([20.0, 50.0, 1000.0], false),
+            ([20.15, 50.0, 1000.0], false),
+            ([19.85, 50.0, 1000.0], false),
+            ([20.25, 50.0, 1000.0], true),
+            ([19.75, 50.0, 1000.0], true),
+            ([20.0, 50.9, 1000.0], false),
+            ([20.0, 51.1, 1000.0], true),
+            ([20.0, 48.9, 1000.0], true),
+            ([20.0, 50.0, 1000.4], false),
+            ([20.0, 50.0, 1000.6], true),
+            ([20.0, 50.0, 999.4], true),
+            ([f32::NAN, 50.0, 1000.0], true),
+        ] {
+            let mut state = State::new();
+            wake(&mut state, &reading(MORNING, displayed));
+            let decision = state.observe(&POLICY, &reading(MORNING, sample));
+            assert_eq!(decision.refresh, refresh, "{sample:?}");
+        }
+    }
+
+    #[test]
+    fn compare_with_displayed_reading() {
+        let mut state = State::new();
+        let mut time = MORNING;
+        let mut refreshes = std::vec::Vec::new();
+        // A slow drift is refreshed once it adds up beyond the delta
+        for temperature in [20.0, 20.08, 20.16, 20.24, 20.32, 20.4, 20.48] {
+            refreshes.push(wake(&mut state, &reading(time, [temperature, 50.0, 1000.0])).refresh);
+            time += time::Duration::minutes(5);
+        }
+        assert_eq!(refreshes, [true, false, false, true, false, false, true]);
+        assert_eq!(state.usage.refreshes, 3);
+    }
+
+    #[test]
+    fn unrefreshed_display_is_still_compared() {
+        let mut state = State::new();
+        wake(&mut state, &reading(MORNING, [20.0, 50.0, 1000.0]));
+
+        // The display was not drawn, so the change is still pending
+        let changed = reading(MORNING + time::Duration::minutes(5), [21.0, 50.0, 1000.0]);
+        assert!(state.observe(&POLICY, &changed).refresh);
+        assert!(state.observe(&POLICY, &changed).refresh);
+        state.mark_displayed(&changed);
+        assert!(!state.observe(&POLICY, &changed).refresh);
+    }
+
+    #[test]
+    fn refresh_stale_display() {
+        let mut state = State::new();
+        let unchanged = [20.0, 50.0, 1000.0];
+        wake(&mut state, &reading(MORNING, unchanged));
+
+        let almost_stale = MORNING + time::Duration::minutes(59);
+        assert!(!wake(&mut state, &reading(almost_stale, unchanged)).refresh);
+        let stale = MORNING + time::Duration::minutes(60);
+        assert!(wake(&mut state, &reading(stale, unchanged)).refresh);
+        let fresh = stale + time::Duration::minutes(1);
+        assert!(!wake(&mut state, &reading(fresh, unchanged)).refresh);
+    }
+
+    #[test]
+    fn stale_refresh_keeps_stable_readings() {
+        let mut state = State::new();
+        let unchanged = [20.0, 50.0, 1000.0];
+        let mut time = MORNING;
+        let mut sleeps = std::vec::Vec::new();
+        for _ in 0..5 {
+            sleeps.push(
+                wake(&mut state, &reading(time, unchanged))
+                    .sleep_duration
+                    .as_secs(),
+            );
+            time += time::Duration::minutes(30);
+        }
+        // The refresh after an hour is not a change
+        assert_eq!(sleeps, [300, 300, 300, 600, 600]);
+        assert_eq!(state.usage.refreshes, 3);
+    }
+
+    #[test]
+    fn sleep_doubles_while_stable() {
+        assert_eq!(
+            stable_sleeps(MORNING, 12),
+            [300, 300, 300, 600, 600, 600, 1200, 1200, 1200, 2400, 2400, 2400]
+        );
+    }
+
+    #[test]
+    fn sleep_resets_on_change() {
+        let mut state = State::new();
+        let mut time = MORNING;
+        for _ in 0..7 {
+            wake(&mut state, &reading(time, [20.0, 50.0, 1000.0]));
+            time += time::Duration::minutes(1);
+        }
+        assert_eq!(state.sleep_duration(&POLICY), Duration::from_secs(1200));
+
+        let decision = wake(&mut state, &reading(time, [22.0, 50.0, 1000.0]));
+        assert_eq!(decision.sleep_duration, POLICY.base_sleep_duration);
+        assert_eq!(state.sleep_duration(&POLICY), POLICY.base_sleep_duration);
+    }
+
+    #[test]
+    fn sleep_longer_at_night() {
+        assert_eq!(
+            stable_sleeps(datetime!(2024-06-01 23:00 +02:00), 10),
+            [600, 600, 600, 1200, 1200, 1200, 2400, 2400, 2400, 2400]
+        );
+    }
+
+    #[test]
+    fn night_in_local_time() {
+        for (time, night) in [
+            (datetime!(2024-06-01 21:59 +02:00), false),
+            (datetime!(2024-06-01 22:00 +02:00), true),
+            (datetime!(2024-06-02 00:00 +02:00), true),
+            (datetime!(2024-06-02 05:59 +02:00), true),
+            (datetime!(2024-06-02 06:00 +02:00), false),
+        ] {
+            let mut state = State::new();
+            let decision = state.observe(&POLICY, &reading(time, [20.0, 50.0, 1000.0]));
+            let expected = if night { 600 } else { 300 };
+            assert_eq!(decision.sleep_duration.as_secs(), expected, "{time}");
+        }
+    }
+
+    #[test]
+    fn night_within_a_day() {
+        let policy = Policy {
+            night_hours: (1, 5),
+            ..POLICY
+        };
+        assert!(!policy.is_night(0));
+        assert!(policy.is_night(1));
+        assert!(policy.is_night(4));
+        assert!(!policy.is_night(5));
+        assert!(!policy.is_night(23));
+    }
+
+    #[test]
+    fn sleep_without_doubling() {
+        let policy = Policy {
+            stable_readings_per_doubling: 0,
+            ..POLICY
+        };
+        assert_eq!(
+            policy.sleep_duration(100, false),
+            policy.base_sleep_duration
+        );
+        assert_eq!(
+            policy.sleep_duration(100, true),
+            policy.base_sleep_duration * NIGHT_FACTOR
+        );
+    }
+
+    #[test]
+    fn policy_from_config() {
+        let policy = Policy::new(&Config::default());
+        assert!((policy.temperature_delta - 0.2).abs() < 1e-6);
+        assert!((policy.humidity_delta - 1.0).abs() < 1e-6);
+        assert!((policy.pressure_delta - 0.5).abs() < 1e-6);
+        assert_eq!(policy.base_sleep_duration, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn checksum_changes_with_any_field() {
+        let original = State {
+            displayed: Some((1_700_000_000, [2000, 4000, 10000])),
+            stable_readings: 3,
+            sleep_seconds: 600,
+            usage: Usage {
+                wakeups: 10,
+                refreshes: 2,
+                awake_milliseconds: 5_000,
+                wifi_milliseconds: 1_000,
+                sleep_milliseconds: 3_000_000,
+            },
+        };
+        let changes: [fn(&mut State); 11] = [
+            |state| state.displayed = None,
+            |state| state.displayed = Some((1_700_000_001, [2000, 4000, 10000])),
+            |state| state.displayed = Some((1_700_000_000, [2001, 4000, 10000])),
+            |state| state.displayed = Some((1_700_000_000, [2000, 4001, 10000])),
+            |state| state.displayed = Some((1_700_000_000, [2000, 4000, 10001])),
+            |state| state.stable_readings += 1,
+            |state| state.sleep_seconds += 1,
+            |state| state.usage.wakeups += 1,
+            |state| state.usage.refreshes += 1,
+            |state| state.usage.awake_milliseconds += 1,
+            |state| state.usage.wifi_milliseconds += 1,
+        ];
+
+        for (index, change) in changes.iter().enumerate() {
+            let mut changed = original;
+            change(&mut changed);
+            assert_ne!(changed.checksum(), original.checksum(), "change {index}");
+        }
+
+        let mut changed = original;
+        changed.usage.sleep_milliseconds += 1;
+        assert_ne!(changed.checksum(), original.checksum());
+    }
+}
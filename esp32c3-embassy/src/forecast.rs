@@ -0,0 +1,427 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Weather forecast from pressure tendency using the Zambretti algorithm
+//!
+//! As in the original Zambretti forecaster, the pressure is adjusted for the
+//! season: rising pressure in summer and falling pressure in winter shift the
+//! forecast by [`SEASONAL_ADJUSTMENT_HPA`].
+
+use libm::powf;
+
+use time::Duration;
+use time::Month;
+
+use uom::si::f32::Length;
+use uom::si::f32::Pressure;
+use uom::si::f32::ThermodynamicTemperature as Temperature;
+use uom::si::length::meter;
+use uom::si::pressure::hectopascal;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::domain::Reading;
+use crate::history::History;
+
+/// Interval over which the pressure tendency is defined
+const TENDENCY_INTERVAL: Duration = Duration::hours(3);
+
+/// Shortest history from which a tendency is extrapolated
+const MINIMUM_TENDENCY_SPAN: Duration = Duration::hours(1);
+
+/// Pressure change in hPa over [`TENDENCY_INTERVAL`] above which pressure is
+/// considered rising or falling
+const TENDENCY_THRESHOLD_HPA: f32 = 1.6;
+
+/// Lowest sea level pressure in hPa handled by the algorithm
+const MINIMUM_PRESSURE_HPA: f32 = 950.0;
+
+/// Highest sea level pressure in hPa handled by the algorithm
+const MAXIMUM_PRESSURE_HPA: f32 = 1050.0;
+
+/// Pressure adjustment in hPa for rising pressure in summer and falling
+/// pressure in winter, 7 % of the handled pressure range
+const SEASONAL_ADJUSTMENT_HPA: f32 = 7.0;
+
+/// Forecast letters for falling pressure, indexed by Zambretti number 1 to 9
+const FALLING: [u8; 9] = *b"ABDHORUXZ";
+
+/// Forecast letters for steady pressure, indexed by Zambretti number 10 to 19
+const STEADY: [u8; 10] = *b"ABEKNPSWXZ";
+
+/// Forecast letters for rising pressure, indexed by Zambretti number 20 to 32
+const RISING: [u8; 13] = *b"ABCFGIJLMQTYY";
+
+/// Forecast texts and icons, indexed by letter `A` to `Z`
+const FORECASTS: [(&str, Icon); 26] = [
+    ("Settled fine", Icon::Sunny),
+    ("Fine weather", Icon::Sunny),
+    ("Becoming fine", Icon::Sunny),
+    ("Fine, unsettling", Icon::PartlyCloudy),
+    ("Fine, some showers", Icon::PartlyCloudy),
+    ("Fine, improving", Icon::PartlyCloudy),
+    ("Showers, then fair", Icon::PartlyCloudy),
+    ("Fair, showers late", Icon::PartlyCloudy),
+    ("Showery, improving", Icon::Cloudy),
+    ("Changeable, better", Icon::Cloudy),
+    ("Showers likely", Icon::PartlyCloudy),
+    ("Clearing later", Icon::Cloudy),
+    ("Unsettled, better", Icon::Cloudy),
+    ("Showery, bright", Icon::Cloudy),
+    ("Showery, worsening", Icon::Cloudy),
+    ("Changeable, rain", Icon::Rain),
+    ("Unsettled, fine", Icon::Cloudy),
+    ("Rain later", Icon::Rain),
+    ("Unsettled, rain", Icon::Rain),
+    ("Very unsettled", Icon::Rain),
+    ("Rain, worsening", Icon::Rain),
+    ("Rain at times", Icon::Rain),
+    ("Frequent rain", Icon::Rain),
+    ("Rain, unsettled", Icon::Rain),
+    ("Stormy, improving", Icon::Storm),
+    ("Stormy, much rain", Icon::Storm),
+];
+
+/// Pressure tendency over the last three hours
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tendency {
+    /// Pressure is rising
+    Rising,
+
+    /// Pressure is steady
+    Steady,
+
+    /// Pressure is falling
+    Falling,
+}
+
+impl Tendency {
+    /// Classify a pressure change over three hours
+    pub fn from_change(change: Pressure) -> Self {
+        let change = change.get::<hectopascal>();
+        if change >= TENDENCY_THRESHOLD_HPA {
+            Self::Rising
+        } else if change <= -TENDENCY_THRESHOLD_HPA {
+            Self::Falling
+        } else {
+            Self::Steady
+        }
+    }
+}
+
+/// Hemisphere of the station, which decides the season of a month
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hemisphere {
+    /// Northern hemisphere
+    Northern,
+
+    /// Southern hemisphere
+    Southern,
+}
+
+/// Season, as used by the Zambretti algorithm
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Season {
+    /// From April to September in the northern hemisphere
+    Summer,
+
+    /// From October to March in the northern hemisphere
+    Winter,
+}
+
+impl Season {
+    /// Return the season of a month in a hemisphere
+    pub fn of(month: Month, hemisphere: Hemisphere) -> Self {
+        let northern_summer = matches!(
+            month,
+            Month::April
+                | Month::May
+                | Month::June
+                | Month::July
+                | Month::August
+                | Month::September
+        );
+        if northern_summer == (hemisphere == Hemisphere::Northern) {
+            Self::Summer
+        } else {
+            Self::Winter
+        }
+    }
+}
+
+/// Location of the station
+#[derive(Clone, Copy, Debug)]
+pub struct Location {
+    /// Altitude above sea level
+    pub altitude: Length,
+
+    /// Hemisphere
+    pub hemisphere: Hemisphere,
+}
+
+/// Icon summarizing a forecast
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Icon {
+    /// Clear sky
+    Sunny,
+
+    /// Sun and clouds
+    PartlyCloudy,
+
+    /// Overcast
+    Cloudy,
+
+    /// Rain
+    Rain,
+
+    /// Storm
+    Storm,
+}
+
+/// A weather forecast
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Forecast {
+    /// Pressure tendency the forecast is based on
+    pub tendency: Tendency,
+
+    /// Forecast letter, from `A` (settled fine) to `Z` (stormy, much rain)
+    pub letter: char,
+
+    /// Short forecast text, fitting a dashboard row
+    pub text: &'static str,
+
+    /// Forecast icon
+    pub icon: Icon,
+}
+
+/// Forecast the weather from the readings history
+///
+/// Return `None` if the history does not span enough time to compute a
+/// pressure tendency.
+pub fn forecast_from_history(history: &History, location: &Location) -> Option<Forecast> {
+    let (change, (time, latest)) = pressure_change(history)?;
+    let pressure = sea_level_pressure(latest.pressure, latest.temperature, location.altitude);
+    let season = Season::of(time.month(), location.hemisphere);
+    Some(forecast(pressure, Tendency::from_change(change), season))
+}
+
+/// Compute the pressure change over the last three hours
+///
+/// If the history spans less than three hours but more than one, the change
+/// is extrapolated linearly to three hours.
+/// Return the change together with the latest reading.
+pub fn pressure_change(history: &History) -> Option<(Pressure, Reading)> {
+    let latest = history.recent()?;
+    let earliest = history.first_since(latest.0 - TENDENCY_INTERVAL)?;
+
+    let span = latest.0 - earliest.0;
+    if span < MINIMUM_TENDENCY_SPAN {
+        return None;
+    }
+
+    let change = latest.1.pressure - earliest.1.pressure;
+    let scale = TENDENCY_INTERVAL.as_seconds_f32() / span.as_seconds_f32();
+    Some((change * scale, latest))
+}
+
+/// Reduce a station pressure to sea level using the hypsometric formula
+pub fn sea_level_pressure(
+    pressure: Pressure,
+    temperature: Temperature,
+    altitude: Length,
+) -> Pressure {
+    let pressure = pressure.get::<hectopascal>();
+    let temperature = temperature.get::<degree_celsius>();
+    let altitude = altitude.get::<meter>();
+
+    let lapse = 0.0065 * altitude;
+    let reduced = pressure * powf(1.0 - lapse / (temperature + lapse + 273.15), -5.257);
+
+    Pressure::new::<hectopascal>(reduced)
+}
+
+/// Forecast the weather using the Zambretti algorithm
+///
+/// The pressure must be reduced to sea level.
+/// The season adjusts rising pressure in summer and falling pressure in
+/// winter.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::indexing_slicing
+)]
+pub fn forecast(sea_level_pressure: Pressure, tendency: Tendency, season: Season) -> Forecast {
+    let adjustment = match (tendency, season) {
+        (Tendency::Rising, Season::Summer) => SEASONAL_ADJUSTMENT_HPA,
+        (Tendency::Falling, Season::Winter) => -SEASONAL_ADJUSTMENT_HPA,
+        _ => 0.0,
+    };
+    let pressure = (sea_level_pressure.get::<hectopascal>() + adjustment)
+        .clamp(MINIMUM_PRESSURE_HPA, MAXIMUM_PRESSURE_HPA);
+
+    let (number, first, table): (f32, usize, &[u8]) = match tendency {
+        Tendency::Falling => (127.0 - 0.12 * pressure, 1, &FALLING),
+        Tendency::Steady => (144.0 - 0.13 * pressure, 10, &STEADY),
+        Tendency::Rising => (185.0 - 0.16 * pressure, 20, &RISING),
+    };
+
+    // Round to the nearest Zambretti number and keep it inside the table
+    let index = ((number + 0.5) as usize)
+        .saturating_sub(first)
+        .min(table.len() - 1);
+    let letter = table[index];
+    let (text, icon) = FORECASTS[usize::from(letter - b'A')];
+
+    Forecast {
+        tendency,
+        letter: char::from(letter),
+        text,
+        icon,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hectopascals(value: f32) -> Pressure {
+        Pressure::new::<hectopascal>(value)
+    }
+
+    #[test]
+    fn tendency_is_classified_by_threshold() {
+        // Change in hPa over three hours, expected tendency
+        let table = [
+            (3.0, Tendency::Rising),
+            (1.6, Tendency::Rising),
+            (1.5, Tendency::Steady),
+            (0.0, Tendency::Steady),
+            (-1.5, Tendency::Steady),
+            (-1.6, Tendency::Falling),
+            (-3.0, Tendency::Falling),
+        ];
+        for (change, expected) in table {
+            assert_eq!(
+                Tendency::from_change(hectopascals(change)),
+                expected,
+                "{change}"
+            );
+        }
+    }
+
+    #[test]
+    fn falling_pressure_matches_zambretti_table() {
+        // Sea level pressure in hPa, expected letter
+        let table = [
+            (1060.0, 'A'),
+            (1050.0, 'A'),
+            (1020.0, 'O'),
+            (1000.0, 'U'),
+            (950.0, 'Z'),
+            (900.0, 'Z'),
+        ];
+        for (pressure, expected) in table {
+            let forecast = forecast(hectopascals(pressure), Tendency::Falling, Season::Summer);
+            assert_eq!(forecast.letter, expected, "{pressure}");
+        }
+    }
+
+    #[test]
+    fn steady_pressure_matches_zambretti_table() {
+        // Sea level pressure in hPa, expected letter
+        let table = [
+            (1050.0, 'A'),
+            (1020.0, 'B'),
+            (1000.0, 'N'),
+            (970.0, 'X'),
+            (950.0, 'Z'),
+        ];
+        for (pressure, expected) in table {
+            let forecast = forecast(hectopascals(pressure), Tendency::Steady, Season::Summer);
+            assert_eq!(forecast.letter, expected, "{pressure}");
+        }
+    }
+
+    #[test]
+    fn rising_pressure_matches_zambretti_table() {
+        // Sea level pressure in hPa, expected letter
+        let table = [
+            (1050.0, 'A'),
+            (1030.0, 'A'),
+            (1010.0, 'F'),
+            (990.0, 'L'),
+            (960.0, 'Y'),
+            (950.0, 'Y'),
+        ];
+        for (pressure, expected) in table {
+            let forecast = forecast(hectopascals(pressure), Tendency::Rising, Season::Winter);
+            assert_eq!(forecast.letter, expected, "{pressure}");
+        }
+    }
+
+    #[test]
+    fn season_adjusts_rising_pressure_in_summer_and_falling_in_winter() {
+        // Sea level pressure in hPa, tendency, letters in summer and winter
+        let table = [
+            (1000.0, Tendency::Rising, 'G', 'I'),
+            (1010.0, Tendency::Falling, 'R', 'U'),
+            (1000.0, Tendency::Steady, 'N', 'N'),
+        ];
+        for (pressure, tendency, summer, winter) in table {
+            let pressure = hectopascals(pressure);
+            assert_eq!(forecast(pressure, tendency, Season::Summer).letter, summer);
+            assert_eq!(forecast(pressure, tendency, Season::Winter).letter, winter);
+        }
+    }
+
+    #[test]
+    fn season_depends_on_hemisphere() {
+        // Month, season in the northern and southern hemispheres
+        let table = [
+            (Month::January, Season::Winter, Season::Summer),
+            (Month::March, Season::Winter, Season::Summer),
+            (Month::April, Season::Summer, Season::Winter),
+            (Month::July, Season::Summer, Season::Winter),
+            (Month::September, Season::Summer, Season::Winter),
+            (Month::October, Season::Winter, Season::Summer),
+        ];
+        for (month, northern, southern) in table {
+            assert_eq!(Season::of(month, Hemisphere::Northern), northern, "{month}");
+            assert_eq!(Season::of(month, Hemisphere::Southern), southern, "{month}");
+        }
+    }
+
+    #[test]
+    fn forecast_text_and_icon_match_letter() {
+        let settled = forecast(hectopascals(1050.0), Tendency::Rising, Season::Summer);
+        assert_eq!(settled.text, "Settled fine");
+        assert_eq!(settled.icon, Icon::Sunny);
+
+        let stormy = forecast(hectopascals(950.0), Tendency::Falling, Season::Winter);
+        assert_eq!(stormy.text, "Stormy, much rain");
+        assert_eq!(stormy.icon, Icon::Storm);
+    }
+
+    #[test]
+    fn sea_level_pressure_is_unchanged_at_sea_level() {
+        let pressure = sea_level_pressure(
+            hectopascals(1000.0),
+            Temperature::new::<degree_celsius>(15.0),
+            Length::new::<meter>(0.0),
+        );
+        assert!((pressure.get::<hectopascal>() - 1000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn sea_level_pressure_increases_with_altitude() {
+        // About 12 hPa per 100 m near sea level
+        let pressure = sea_level_pressure(
+            hectopascals(1000.0),
+            Temperature::new::<degree_celsius>(15.0),
+            Length::new::<meter>(100.0),
+        );
+        assert!((pressure.get::<hectopascal>() - 1011.9).abs() < 0.5);
+    }
+}
@@ -0,0 +1,950 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Multi-resolution history of readings
+//!
+//! Readings are kept at three resolutions:
+//!
+//! * every reading for the last hour,
+//! * minimum, maximum and mean per 15 minutes for the last 24 hours,
+//! * minimum, maximum and mean per hour for the last week.
+//!
+//! Samples are stored in fixed-point to fit the whole history in
+//! [`MEMORY_BUDGET`] bytes, so that it can be placed in RTC Fast memory.
+
+use core::mem::size_of;
+
+use log::trace;
+
+use crc::Digest;
+
+use heapless::HistoryBuffer;
+
+use libm::roundf;
+
+use time::Duration;
+use time::OffsetDateTime;
+use time::UtcOffset;
+
+use uom::si::f32::Pressure;
+use uom::si::f32::Ratio as Humidity;
+use uom::si::f32::ThermodynamicTemperature as Temperature;
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::domain::Reading;
+use crate::domain::Sample;
+use crate::retained::Checksum;
+
+/// Maximal size of [`History`] in bytes, leaving 0.5 KiB of the 8 KiB RTC Fast
+/// memory for other retained state
+pub const MEMORY_BUDGET: usize = 7680;
+
+/// Number of readings kept at full resolution
+pub const MINUTES: usize = 60;
+
+/// Number of 15 minutes aggregates
+const QUARTERS: usize = 24 * 4;
+
+/// Number of hourly aggregates
+const HOURS: usize = 7 * 24;
+
+/// Period of a quarter aggregate in seconds
+const QUARTER_SECONDS: u32 = 15 * 60;
+
+/// Period of an hour aggregate in seconds
+const HOUR_SECONDS: u32 = 60 * 60;
+
+const _: () = assert!(size_of::<History>() <= MEMORY_BUDGET);
+
+/// Resolution of a history query
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Every reading, for the last hour
+    Full,
+
+    /// Aggregates per 15 minutes, for the last 24 hours
+    Quarter,
+
+    /// Aggregates per hour, for the last week
+    Hour,
+}
+
+impl Resolution {
+    /// Length of an aggregation period
+    pub fn period(self) -> Duration {
+        match self {
+            Self::Full => Duration::ZERO,
+            Self::Quarter => Duration::seconds(i64::from(QUARTER_SECONDS)),
+            Self::Hour => Duration::seconds(i64::from(HOUR_SECONDS)),
+        }
+    }
+}
+
+/// Statistics of readings over a period
+#[derive(Clone, Debug)]
+pub struct Statistics {
+    /// Start of the period
+    pub start: OffsetDateTime,
+
+    /// Length of the period
+    pub period: Duration,
+
+    /// Number of readings in the period
+    pub count: u32,
+
+    /// Minimum of each quantity
+    pub minimum: Sample,
+
+    /// Maximum of each quantity
+    pub maximum: Sample,
+
+    /// Mean of each quantity
+    pub mean: Sample,
+}
+
+/// Feed the readings of a buffer, leaving out unused slots
+#[allow(clippy::cast_possible_truncation)]
+fn feed_buffer<T: Checksum, const N: usize>(
+    buffer: &HistoryBuffer<T, N>,
+    digest: &mut Digest<'_, u32>,
+) {
+    (buffer.len() as u32).feed(digest);
+    for item in buffer.oldest_ordered() {
+        item.feed(digest);
+    }
+}
+
+/// Multi-resolution history of readings
+pub struct History {
+    /// UTC offset of the most recent reading, applied to all returned times
+    offset_in_seconds: i32,
+
+    /// Readings for the last hour
+    minutes: HistoryBuffer<CompactReading, MINUTES>,
+
+    /// Completed 15 minutes aggregates
+    quarters: HistoryBuffer<Aggregate, QUARTERS>,
+
+    /// Completed hourly aggregates
+    hours: HistoryBuffer<Aggregate, HOURS>,
+
+    /// The 15 minutes aggregate being filled
+    current_quarter: Accumulator,
+
+    /// The hourly aggregate being filled
+    current_hour: Accumulator,
+}
+
+impl History {
+    /// Create an empty history
+    pub const fn new() -> Self {
+        Self {
+            offset_in_seconds: 0,
+            minutes: HistoryBuffer::new(),
+            quarters: HistoryBuffer::new(),
+            hours: HistoryBuffer::new(),
+            current_quarter: Accumulator::EMPTY,
+            current_hour: Accumulator::EMPTY,
+        }
+    }
+
+    /// Add a reading
+    ///
+    /// Readings must be added in chronological order.
+    pub fn write(&mut self, reading: &Reading) {
+        let (time, sample) = reading;
+        self.offset_in_seconds = time.offset().whole_seconds();
+
+        let compact = CompactReading::from(reading);
+        self.minutes.write(compact);
+
+        if let Some(aggregate) = self.current_quarter.add(&compact, QUARTER_SECONDS) {
+            self.quarters.write(aggregate);
+        }
+        if let Some(aggregate) = self.current_hour.add(&compact, HOUR_SECONDS) {
+            self.hours.write(aggregate);
+        }
+
+        trace!("Added reading at {time} to history: {sample:?}");
+    }
+
+    /// Number of readings at full resolution
+    pub fn len(&self) -> usize {
+        self.minutes.len()
+    }
+
+    /// Return the most recent reading
+    pub fn recent(&self) -> Option<Reading> {
+        self.minutes
+            .recent()
+            .map(|compact| compact.to_reading(self.offset()))
+    }
+
+    /// Iterate over readings at full resolution, from oldest to newest
+    pub fn readings(&self) -> impl Iterator<Item = Reading> + '_ {
+        let offset = self.offset();
+        self.minutes
+            .oldest_ordered()
+            .map(move |compact| compact.to_reading(offset))
+    }
+
+    /// Iterate over aggregates at a resolution, from oldest to newest
+    ///
+    /// The aggregate currently being filled is included last.
+    /// At [`Resolution::Full`] every reading is an aggregate of one.
+    pub fn aggregates(&self, resolution: Resolution) -> Aggregates<'_> {
+        Aggregates {
+            history: self,
+            resolution,
+            index: 0,
+        }
+    }
+
+    /// Iterate over aggregates at a resolution that start within a range
+    pub fn range(
+        &self,
+        resolution: Resolution,
+        from: OffsetDateTime,
+        to: OffsetDateTime,
+    ) -> impl Iterator<Item = Statistics> + '_ {
+        self.aggregates(resolution)
+            .skip_while(move |statistics| statistics.start < from)
+            .take_while(move |statistics| statistics.start <= to)
+    }
+
+    /// Compute statistics over a range, using the finest resolution covering it
+    pub fn statistics(&self, from: OffsetDateTime, to: OffsetDateTime) -> Option<Statistics> {
+        let resolution = self.finest_resolution_since(from);
+        let mut statistics = self.range(resolution, from, to);
+        let first = statistics.next()?;
+        let merged = statistics.fold(first, merge);
+        Some(Statistics {
+            start: from,
+            period: to - from,
+            ..merged
+        })
+    }
+
+    /// Return the oldest reading at or after a time
+    ///
+    /// If the time is older than the full resolution history, the mean of the
+    /// oldest aggregate at or after it is returned instead.
+    pub fn first_since(&self, since: OffsetDateTime) -> Option<Reading> {
+        let resolution = self.finest_resolution_since(since);
+        self.aggregates(resolution)
+            .find(|statistics| statistics.start >= since)
+            .map(|statistics| (statistics.start, statistics.mean))
+    }
+
+    /// Return the finest resolution whose history reaches back to a time
+    fn finest_resolution_since(&self, since: OffsetDateTime) -> Resolution {
+        let reaches = |resolution| {
+            self.aggregates(resolution)
+                .next()
+                .is_some_and(|statistics| statistics.start <= since)
+        };
+
+        if reaches(Resolution::Full) {
+            Resolution::Full
+        } else if reaches(Resolution::Quarter) {
+            Resolution::Quarter
+        } else {
+            Resolution::Hour
+        }
+    }
+
+    /// Offset applied to returned times
+    fn offset(&self) -> UtcOffset {
+        UtcOffset::from_whole_seconds(self.offset_in_seconds).unwrap_or(UtcOffset::UTC)
+    }
+}
+
+impl Checksum for History {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.offset_in_seconds.feed(digest);
+        feed_buffer(&self.minutes, digest);
+        feed_buffer(&self.quarters, digest);
+        feed_buffer(&self.hours, digest);
+        self.current_quarter.feed(digest);
+        self.current_hour.feed(digest);
+    }
+}
+
+/// Iterator over aggregates of a [`History`]
+pub struct Aggregates<'history> {
+    /// The history
+    history: &'history History,
+
+    /// The resolution
+    resolution: Resolution,
+
+    /// Index of the next aggregate
+    index: usize,
+}
+
+impl Iterator for Aggregates<'_> {
+    type Item = Statistics;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let history = self.history;
+        let offset = history.offset();
+        let period = self.resolution.period();
+
+        let statistics = match self.resolution {
+            Resolution::Full => {
+                let (start, sample) = history
+                    .minutes
+                    .oldest_ordered()
+                    .nth(self.index)?
+                    .to_reading(offset);
+                Statistics {
+                    start,
+                    period,
+                    count: 1,
+                    minimum: sample.clone(),
+                    maximum: sample.clone(),
+                    mean: sample,
+                }
+            }
+            Resolution::Quarter => {
+                aggregate_at(&history.quarters, &history.current_quarter, self.index)?
+                    .to_statistics(period, offset)
+            }
+            Resolution::Hour => aggregate_at(&history.hours, &history.current_hour, self.index)?
+                .to_statistics(period, offset),
+        };
+        self.index += 1;
+
+        Some(statistics)
+    }
+}
+
+/// Return the aggregate at an index, counting the current one after the completed ones
+fn aggregate_at<const N: usize>(
+    completed: &HistoryBuffer<Aggregate, N>,
+    current: &Accumulator,
+    index: usize,
+) -> Option<Aggregate> {
+    if index < completed.len() {
+        completed.oldest_ordered().nth(index).copied()
+    } else if index == completed.len() {
+        current.to_aggregate()
+    } else {
+        None
+    }
+}
+
+/// Merge two statistics into statistics over both
+fn merge(first: Statistics, second: Statistics) -> Statistics {
+    #[allow(clippy::cast_precision_loss)]
+    let (first_weight, second_weight) = (first.count as f32, second.count as f32);
+    let total = first_weight + second_weight;
+
+    let mean = |first: f32, second: f32| (first * first_weight + second * second_weight) / total;
+
+    Statistics {
+        start: first.start,
+        period: second.start + second.period - first.start,
+        count: first.count + second.count,
+        minimum: Sample {
+            temperature: first.minimum.temperature.min(second.minimum.temperature),
+            humidity: first.minimum.humidity.min(second.minimum.humidity),
+            pressure: first.minimum.pressure.min(second.minimum.pressure),
+        },
+        maximum: Sample {
+            temperature: first.maximum.temperature.max(second.maximum.temperature),
+            humidity: first.maximum.humidity.max(second.maximum.humidity),
+            pressure: first.maximum.pressure.max(second.maximum.pressure),
+        },
+        mean: Sample {
+            temperature: Temperature::new::<degree_celsius>(mean(
+                first.mean.temperature.get::<degree_celsius>(),
+                second.mean.temperature.get::<degree_celsius>(),
+            )),
+            humidity: Humidity::new::<percent>(mean(
+                first.mean.humidity.get::<percent>(),
+                second.mean.humidity.get::<percent>(),
+            )),
+            pressure: Pressure::new::<hectopascal>(mean(
+                first.mean.pressure.get::<hectopascal>(),
+                second.mean.pressure.get::<hectopascal>(),
+            )),
+        },
+    }
+}
+
+/// A sample in fixed-point, as (centi-degrees Celsius, centi-percent, deci-hectopascal)
+pub type CompactSample = [i16; 3];
+
+/// A reading with time as Unix epoch and sample in fixed-point
+#[derive(Clone, Copy, Debug)]
+struct CompactReading {
+    /// Time as Unix epoch
+    time: u32,
+
+    /// Sample
+    sample: CompactSample,
+}
+
+impl From<&Reading> for CompactReading {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn from((time, sample): &Reading) -> Self {
+        Self {
+            time: time.unix_timestamp() as u32,
+            sample: compact_sample(sample),
+        }
+    }
+}
+
+impl CompactReading {
+    /// Convert to a reading
+    fn to_reading(self, offset: UtcOffset) -> Reading {
+        (to_time(self.time, offset), expand_sample(self.sample))
+    }
+}
+
+impl Checksum for CompactReading {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.time.feed(digest);
+        self.sample.feed(digest);
+    }
+}
+
+/// Minimum, maximum and mean of readings in fixed-point over a period
+#[derive(Clone, Copy, Debug)]
+struct Aggregate {
+    /// Start of the period as Unix epoch
+    start: u32,
+
+    /// Number of readings, saturating
+    count: u16,
+
+    /// Minimum
+    minimum: CompactSample,
+
+    /// Maximum
+    maximum: CompactSample,
+
+    /// Mean
+    mean: CompactSample,
+}
+
+impl Aggregate {
+    /// Convert to statistics
+    fn to_statistics(self, period: Duration, offset: UtcOffset) -> Statistics {
+        Statistics {
+            start: to_time(self.start, offset),
+            period,
+            count: u32::from(self.count),
+            minimum: expand_sample(self.minimum),
+            maximum: expand_sample(self.maximum),
+            mean: expand_sample(self.mean),
+        }
+    }
+}
+
+impl Checksum for Aggregate {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.start.feed(digest);
+        self.count.feed(digest);
+        self.minimum.feed(digest);
+        self.maximum.feed(digest);
+        self.mean.feed(digest);
+    }
+}
+
+/// An aggregate being filled
+#[derive(Clone, Copy, Debug)]
+struct Accumulator {
+    /// Start of the period as Unix epoch
+    start: u32,
+
+    /// Number of readings
+    count: u32,
+
+    /// Minimum
+    minimum: CompactSample,
+
+    /// Maximum
+    maximum: CompactSample,
+
+    /// Sum
+    sum: [i64; 3],
+}
+
+impl Accumulator {
+    /// An empty accumulator
+    const EMPTY: Self = Self {
+        start: 0,
+        count: 0,
+        minimum: [i16::MAX; 3],
+        maximum: [i16::MIN; 3],
+        sum: [0; 3],
+    };
+
+    /// Add a reading to the period of the given length it falls in
+    ///
+    /// If the reading starts a new period, the previous one is returned as a
+    /// completed aggregate.
+    fn add(&mut self, reading: &CompactReading, period: u32) -> Option<Aggregate> {
+        let start = reading.time - reading.time % period;
+
+        let completed = if self.count > 0 && self.start != start {
+            let completed = self.to_aggregate();
+            *self = Self::EMPTY;
+            completed
+        } else {
+            None
+        };
+
+        self.start = start;
+        self.count += 1;
+        for (index, value) in reading.sample.iter().enumerate() {
+            self.minimum[index] = self.minimum[index].min(*value);
+            self.maximum[index] = self.maximum[index].max(*value);
+            self.sum[index] += i64::from(*value);
+        }
+
+        completed
+    }
+
+    /// Convert to an aggregate, if not empty
+    #[allow(clippy::cast_possible_truncation)]
+    fn to_aggregate(self) -> Option<Aggregate> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let count = i64::from(self.count);
+        Some(Aggregate {
+            start: self.start,
+            count: u16::try_from(self.count).unwrap_or(u16::MAX),
+            minimum: self.minimum,
+            maximum: self.maximum,
+            mean: self.sum.map(|sum| (sum / count) as i16),
+        })
+    }
+}
+
+impl Checksum for Accumulator {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.start.feed(digest);
+        self.count.feed(digest);
+        self.minimum.feed(digest);
+        self.maximum.feed(digest);
+        self.sum.feed(digest);
+    }
+}
+
+/// Convert a sample to fixed-point
+#[allow(clippy::cast_possible_truncation)]
+pub fn compact_sample(sample: &Sample) -> CompactSample {
+    [
+        roundf(sample.temperature.get::<degree_celsius>() * 100.0) as i16,
+        roundf(sample.humidity.get::<percent>() * 100.0) as i16,
+        roundf(sample.pressure.get::<hectopascal>() * 10.0) as i16,
+    ]
+}
+
+/// Convert a sample from fixed-point
+pub fn expand_sample([temperature, humidity, pressure]: CompactSample) -> Sample {
+    Sample {
+        temperature: Temperature::new::<degree_celsius>(f32::from(temperature) / 100.0),
+        humidity: Humidity::new::<percent>(f32::from(humidity) / 100.0),
+        pressure: Pressure::new::<hectopascal>(f32::from(pressure) / 10.0),
+    }
+}
+
+/// Convert a Unix epoch to a time in an offset
+fn to_time(epoch: u32, offset: UtcOffset) -> OffsetDateTime {
+    // A `u32` Unix epoch is always in the valid range
+    OffsetDateTime::from_unix_timestamp(i64::from(epoch))
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+        .to_offset(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heapless::Vec;
+
+    use time::macros::datetime;
+    use time::macros::offset;
+
+    /// Start of the synthetic series, at the start of an hour
+    const START: OffsetDateTime = datetime!(2024-01-01 00:00 UTC);
+
+    /// Compact sample of the synthetic series a number of minutes after [`START`]
+    ///
+    /// Temperature rises by 0.01 C per minute, humidity ramps from 40 % to 54 %
+    /// within every 15 minutes and pressure rises by 0.1 hPa per hour.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    fn synthetic_sample(minute: u32) -> CompactSample {
+        [
+            2000 + minute as i16,
+            4000 + 100 * (minute % 15) as i16,
+            10000 + (minute / 60) as i16,
+        ]
+    }
+
+    /// Create a history with one reading per minute of the synthetic series
+    fn synthetic_history(minutes: core::ops::Range<u32>) -> History {
+        let mut history = History::new();
+        for minute in minutes {
+            let time = START + Duration::minutes(i64::from(minute));
+            history.write(&(time, expand_sample(synthetic_sample(minute))));
+        }
+        history
+    }
+
+    /// Return the time a number of minutes after [`START`]
+    fn at(minute: i64) -> OffsetDateTime {
+        START + Duration::minutes(minute)
+    }
+
+    #[test]
+    fn write_keeps_the_last_hour_at_full_resolution() {
+        let history = synthetic_history(0..180);
+
+        assert_eq!(history.len(), MINUTES);
+        let (time, sample) = history.recent().unwrap();
+        assert_eq!(time, at(179));
+        assert_eq!(compact_sample(&sample), synthetic_sample(179));
+
+        let mut readings = history.readings();
+        let (oldest, sample) = readings.next().unwrap();
+        assert_eq!(oldest, at(120));
+        assert_eq!(compact_sample(&sample), synthetic_sample(120));
+        assert_eq!(readings.count(), MINUTES - 1);
+    }
+
+    #[test]
+    fn quarter_aggregates_follow_bucket_boundaries() {
+        let history = synthetic_history(0..180);
+
+        let quarters: Vec<Statistics, QUARTERS> = history.aggregates(Resolution::Quarter).collect();
+        assert_eq!(quarters.len(), 12);
+        for (index, quarter) in (0_i64..).zip(quarters.iter()) {
+            let first = u32::try_from(index * 15).unwrap();
+            assert_eq!(quarter.start, at(index * 15));
+            assert_eq!(quarter.period, Resolution::Quarter.period());
+            assert_eq!(quarter.count, 15);
+            assert_eq!(compact_sample(&quarter.minimum), {
+                let [temperature, _, pressure] = synthetic_sample(first);
+                [temperature, 4000, pressure]
+            });
+            assert_eq!(compact_sample(&quarter.maximum), {
+                let [temperature, _, pressure] = synthetic_sample(first + 14);
+                [temperature, 5400, pressure]
+            });
+            assert_eq!(compact_sample(&quarter.mean), {
+                let [temperature, _, pressure] = synthetic_sample(first + 7);
+                [temperature, 4700, pressure]
+            });
+        }
+    }
+
+    #[test]
+    fn hour_aggregates_follow_bucket_boundaries() {
+        let history = synthetic_history(0..180);
+
+        let hours: Vec<Statistics, HOURS> = history.aggregates(Resolution::Hour).collect();
+        assert_eq!(hours.len(), 3);
+        for (index, hour) in (0_i64..).zip(hours.iter()) {
+            let first = u32::try_from(index * 60).unwrap();
+            assert_eq!(hour.start, at(index * 60));
+            assert_eq!(hour.count, 60);
+            assert_eq!(
+                compact_sample(&hour.minimum),
+                [synthetic_sample(first)[0], 4000, synthetic_sample(first)[2]]
+            );
+            assert_eq!(
+                compact_sample(&hour.maximum),
+                [
+                    synthetic_sample(first + 59)[0],
+                    5400,
+                    synthetic_sample(first)[2]
+                ]
+            );
+            // The mean of 0 to 59 is 29.5, truncated in fixed-point
+            assert_eq!(
+                compact_sample(&hour.mean),
+                [
+                    synthetic_sample(first + 29)[0],
+                    4700,
+                    synthetic_sample(first)[2]
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn partial_buckets_count_only_their_readings() {
+        let history = synthetic_history(7..20);
+
+        let mut quarters = history.aggregates(Resolution::Quarter);
+        let first = quarters.next().unwrap();
+        assert_eq!(first.start, at(0));
+        assert_eq!(first.count, 8);
+        assert_eq!(compact_sample(&first.minimum)[0], synthetic_sample(7)[0]);
+        assert_eq!(compact_sample(&first.maximum)[0], synthetic_sample(14)[0]);
+        let second = quarters.next().unwrap();
+        assert_eq!(second.start, at(15));
+        assert_eq!(second.count, 5);
+        assert!(quarters.next().is_none());
+    }
+
+    #[test]
+    fn aggregates_are_downsampled_beyond_their_capacity() {
+        let history = synthetic_history(0..8 * 24 * 60);
+
+        let mut hours = history.aggregates(Resolution::Hour);
+        assert_eq!(hours.next().unwrap().start, at(24 * 60 - 60));
+        // The aggregate being filled comes after the completed ones
+        assert_eq!(hours.count(), HOURS);
+
+        let mut quarters = history.aggregates(Resolution::Quarter);
+        assert_eq!(quarters.next().unwrap().start, at(7 * 24 * 60 - 15));
+        assert_eq!(quarters.count(), QUARTERS);
+    }
+
+    #[test]
+    fn range_includes_both_bounds() {
+        let history = synthetic_history(0..180);
+
+        let mut range = history.range(Resolution::Quarter, at(30), at(60));
+        assert_eq!(range.next().unwrap().start, at(30));
+        assert_eq!(range.next().unwrap().start, at(45));
+        assert_eq!(range.next().unwrap().start, at(60));
+        assert!(range.next().is_none());
+
+        assert_eq!(history.range(Resolution::Hour, at(1), at(59)).count(), 0);
+    }
+
+    #[test]
+    fn statistics_merge_quarters_before_the_last_hour() {
+        let history = synthetic_history(0..180);
+
+        let statistics = history.statistics(at(60), at(119)).unwrap();
+        assert_eq!(statistics.start, at(60));
+        assert_eq!(statistics.period, Duration::minutes(59));
+        assert_eq!(statistics.count, 60);
+        assert_eq!(
+            compact_sample(&statistics.minimum),
+            [synthetic_sample(60)[0], 4000, synthetic_sample(60)[2]]
+        );
+        assert_eq!(
+            compact_sample(&statistics.maximum),
+            [synthetic_sample(119)[0], 5400, synthetic_sample(60)[2]]
+        );
+        // Mean of the quarter means 67, 82, 97 and 112
+        assert_eq!(
+            compact_sample(&statistics.mean),
+            [2000 + 89, 4700, synthetic_sample(60)[2]]
+        );
+    }
+
+    #[test]
+    fn statistics_use_full_resolution_within_the_last_hour() {
+        let history = synthetic_history(0..180);
+
+        let statistics = history.statistics(at(130), at(140)).unwrap();
+        assert_eq!(statistics.count, 11);
+        assert_eq!(
+            compact_sample(&statistics.minimum),
+            [synthetic_sample(130)[0], 4000, synthetic_sample(130)[2]]
+        );
+        assert_eq!(
+            compact_sample(&statistics.maximum),
+            [synthetic_sample(140)[0], 5400, synthetic_sample(130)[2]]
+        );
+        assert_eq!(
+            compact_sample(&statistics.mean)[0],
+            synthetic_sample(135)[0]
+        );
+    }
+
+    #[test]
+    fn statistics_of_an_empty_range_are_none() {
+        let history = synthetic_history(0..180);
+
+        assert!(history.statistics(at(200), at(300)).is_none());
+        assert!(History::new().statistics(at(0), at(60)).is_none());
+    }
+
+    #[test]
+    fn first_since_falls_back_to_coarser_resolutions() {
+        let history = synthetic_history(0..180);
+
+        let (time, sample) = history.first_since(at(150)).unwrap();
+        assert_eq!(time, at(150));
+        assert_eq!(compact_sample(&sample), synthetic_sample(150));
+
+        let (time, sample) = history.first_since(at(20)).unwrap();
+        assert_eq!(time, at(30));
+        assert_eq!(compact_sample(&sample)[0], synthetic_sample(37)[0]);
+
+        let (time, sample) = history.first_since(at(-60)).unwrap();
+        assert_eq!(time, at(0));
+        assert_eq!(compact_sample(&sample)[0], synthetic_sample(29)[0]);
+
+        assert!(history.first_since(at(180)).is_none());
+    }
+
+    #[test]
+    fn finest_resolution_reaching_back() {
+        let history = synthetic_history(0..180);
+
+        assert_eq!(history.finest_resolution_since(at(179)), Resolution::Full);
+        assert_eq!(history.finest_resolution_since(at(120)), Resolution::Full);
+        assert_eq!(
+            history.finest_resolution_since(at(119)),
+            Resolution::Quarter
+        );
+        assert_eq!(history.finest_resolution_since(at(0)), Resolution::Quarter);
+        assert_eq!(history.finest_resolution_since(at(-1)), Resolution::Hour);
+    }
+
+    #[test]
+    fn times_are_returned_in_the_offset_of_the_latest_reading() {
+        let mut history = synthetic_history(0..2);
+        let offset = offset!(+2);
+        let time = at(2).to_offset(offset);
+        history.write(&(time, expand_sample(synthetic_sample(2))));
+
+        let (oldest, _) = history.readings().next().unwrap();
+        assert_eq!(oldest, at(0));
+        assert_eq!(oldest.offset(), offset);
+        assert_eq!(
+            history
+                .aggregates(Resolution::Hour)
+                .next()
+                .unwrap()
+                .start
+                .offset(),
+            offset
+        );
+    }
+
+    #[test]
+    fn accumulator_mean_is_exact_after_many_readings() {
+        let mut accumulator = Accumulator::EMPTY;
+        for index in 0..1000_u32 {
+            let reading = CompactReading {
+                time: index,
+                sample: if index % 2 == 0 {
+                    [2000, 4000, 10000]
+                } else {
+                    [2100, 5000, 10100]
+                },
+            };
+            assert!(accumulator.add(&reading, HOUR_SECONDS).is_none());
+        }
+
+        let aggregate = accumulator.to_aggregate().unwrap();
+        assert_eq!(aggregate.count, 1000);
+        assert_eq!(aggregate.minimum, [2000, 4000, 10000]);
+        assert_eq!(aggregate.maximum, [2100, 5000, 10100]);
+        assert_eq!(aggregate.mean, [2050, 4500, 10050]);
+    }
+
+    #[test]
+    fn accumulator_completes_period_on_new_start() {
+        let mut accumulator = Accumulator::EMPTY;
+        let first = CompactReading {
+            time: 0,
+            sample: [2000, 4000, 10000],
+        };
+        let second = CompactReading {
+            time: QUARTER_SECONDS,
+            sample: [2100, 5000, 10100],
+        };
+
+        assert!(accumulator.add(&first, QUARTER_SECONDS).is_none());
+        let completed = accumulator.add(&second, QUARTER_SECONDS).unwrap();
+        assert_eq!(completed.start, 0);
+        assert_eq!(completed.count, 1);
+        assert_eq!(completed.mean, [2000, 4000, 10000]);
+        assert_eq!(accumulator.start, QUARTER_SECONDS);
+        assert_eq!(accumulator.count, 1);
+    }
+
+    #[test]
+    fn checksum_changes_with_any_field() {
+        let original = synthetic_history(0..20).checksum();
+        let changes: [fn(&mut History); 14] = [
+            |history| history.offset_in_seconds += 3600,
+            |history| {
+                history
+                    .minutes
+                    .write(CompactReading::from(&(at(20), expand_sample([0; 3]))))
+            },
+            |history| {
+                history
+                    .quarters
+                    .write(aggregate_with(|aggregate| aggregate.start += 1))
+            },
+            |history| {
+                history
+                    .quarters
+                    .write(aggregate_with(|aggregate| aggregate.count += 1))
+            },
+            |history| {
+                history
+                    .hours
+                    .write(aggregate_with(|aggregate| aggregate.minimum[0] += 1))
+            },
+            |history| {
+                history
+                    .hours
+                    .write(aggregate_with(|aggregate| aggregate.maximum[1] += 1))
+            },
+            |history| {
+                history
+                    .hours
+                    .write(aggregate_with(|aggregate| aggregate.mean[2] += 1))
+            },
+            |history| history.current_quarter.start += 1,
+            |history| history.current_quarter.count += 1,
+            |history| history.current_quarter.minimum[0] -= 1,
+            |history| history.current_quarter.maximum[1] += 1,
+            |history| history.current_quarter.sum[2] += 1,
+            |history| history.current_hour.start += 1,
+            |history| history.current_hour.sum[0] += 1,
+        ];
+
+        for (index, change) in changes.iter().enumerate() {
+            let mut changed = synthetic_history(0..20);
+            change(&mut changed);
+            assert_ne!(changed.checksum(), original, "change {index}");
+        }
+
+        // Two different aggregates fed to the same buffer differ too
+        let mut first = History::new();
+        first.quarters.write(aggregate_with(|_| {}));
+        let mut second = History::new();
+        second
+            .quarters
+            .write(aggregate_with(|aggregate| aggregate.mean[0] += 1));
+        assert_ne!(first.checksum(), second.checksum());
+    }
+
+    /// Create an empty aggregate with a change applied
+    fn aggregate_with(change: impl FnOnce(&mut Aggregate)) -> Aggregate {
+        let mut aggregate = Aggregate {
+            start: 0,
+            count: 1,
+            minimum: [0; 3],
+            maximum: [0; 3],
+            mean: [0; 3],
+        };
+        change(&mut aggregate);
+        aggregate
+    }
+}
@@ -21,7 +21,10 @@
 use reqwless::client::HttpClient;
 use reqwless::client::TlsConfig;
 use reqwless::client::TlsVerify;
+use reqwless::headers::ContentType;
 use reqwless::request::Method;
+use reqwless::request::RequestBuilder as _;
+use reqwless::response::Status;
 use reqwless::Error as ReqlessError;
 
 use heapless::Vec;
@@ -40,6 +43,18 @@
 pub trait ClientTrait {
     /// Send an HTTP request
     async fn send_request(&mut self, url: &str) -> Result<Vec<u8, RESPONSE_SIZE>, Error>;
+
+    /// Send an HTTP `POST` request with a plain text body and additional
+    /// headers
+    ///
+    /// Unlike [`send_request()`][Self::send_request], a response with a status
+    /// other than 2xx is an error.
+    async fn send_post_request(
+        &mut self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Vec<u8, RESPONSE_SIZE>, Error>;
 }
 
 /// HTTP client
@@ -80,6 +95,34 @@ pub fn new(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>, rng: RngWr
 
 impl ClientTrait for Client {
     async fn send_request(&mut self, url: &str) -> Result<Vec<u8, RESPONSE_SIZE>, Error> {
+        let (_status, body) = self.send(Method::GET, url, &[], None).await?;
+        Ok(body)
+    }
+
+    async fn send_post_request(
+        &mut self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Vec<u8, RESPONSE_SIZE>, Error> {
+        let (status, body) = self.send(Method::POST, url, headers, Some(body)).await?;
+        if status.is_successful() {
+            Ok(body)
+        } else {
+            Err(Error::Status(status))
+        }
+    }
+}
+
+impl Client {
+    /// Send an HTTP request and return the response status and body
+    async fn send(
+        &mut self,
+        method: Method,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<(Status, Vec<u8, RESPONSE_SIZE>), Error> {
         debug!("Send HTTPs request to {url}");
 
         debug!("Create DNS socket");
@@ -101,12 +144,22 @@ async fn send_request(&mut self, url: &str) -> Result<Vec<u8, RESPONSE_SIZE>, Er
 
         debug!("Create HTTP request");
         let mut buffer = [0_u8; 4096];
-        let mut request = client.request(Method::GET, url).await?;
+        let request = client.request(method, url).await?.headers(headers);
 
         debug!("Send HTTP request");
-        let response = request.send(&mut buffer).await?;
-
-        debug!("Response status: {:?}", response.status);
+        let response = match body {
+            Some(body) => {
+                let mut request = request.body(body).content_type(ContentType::TextPlain);
+                request.send(&mut buffer).await?
+            }
+            None => {
+                let mut request = request;
+                request.send(&mut buffer).await?
+            }
+        };
+
+        let status = response.status;
+        debug!("Response status: {status:?}");
 
         let buffer = response.body().read_to_end().await?;
 
@@ -115,7 +168,7 @@ async fn send_request(&mut self, url: &str) -> Result<Vec<u8, RESPONSE_SIZE>, Er
         let output =
             Vec::<u8, RESPONSE_SIZE>::from_slice(buffer).map_err(|()| Error::ResponseTooLarge)?;
 
-        Ok(output)
+        Ok((status, output))
     }
 }
 
@@ -125,6 +178,9 @@ pub enum Error {
     /// Response was too large
     ResponseTooLarge,
 
+    /// Server responded with a status other than 2xx
+    Status(#[allow(unused)] Status),
+
     /// Error within TCP streams
     Tcp(#[allow(unused)] TcpError),
 
@@ -0,0 +1,779 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Minimal HTTP/1.1 server exposing readings
+//!
+//! The server handles one connection at a time and closes it after each
+//! response.
+//! Only `GET` and `HEAD` requests are supported, on these paths:
+//!
+//! * `/`: a small HTML status page,
+//! * `/metrics`: the most recent reading in Prometheus exposition format,
+//! * `/api/current`: the most recent reading as JSON,
+//! * `/api/history`: the readings of the last hour as a JSON array.
+//!
+//! Readings are serialized as described in [`crate::json`].
+
+use core::fmt::Display;
+use core::fmt::Error as FmtError;
+use core::fmt::Write;
+use core::str::from_utf8;
+
+use log::debug;
+use log::info;
+use log::warn;
+
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+
+use embassy_time::with_timeout;
+use embassy_time::Duration;
+
+use embedded_io_async::Read;
+use embedded_io_async::Write as _;
+
+use esp_wifi::wifi::WifiDevice;
+use esp_wifi::wifi::WifiStaDevice;
+
+use heapless::String;
+
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::history::History;
+use crate::json::write_number;
+use crate::json::write_reading;
+use crate::json::write_time;
+use crate::SharedHistory;
+
+/// TCP port of the server
+pub const HTTP_PORT: u16 = 80;
+
+/// Size of the request buffer
+const REQUEST_SIZE: usize = 1024;
+
+/// Size of the response body buffer
+const BODY_SIZE: usize = 8192;
+
+/// Size of the response head buffer
+const HEAD_SIZE: usize = 256;
+
+/// Time to wait for a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request method
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Return a resource
+    Get,
+
+    /// Return the head of a resource, without body
+    Head,
+}
+
+/// A request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Request<'a> {
+    /// Method
+    pub method: Method,
+
+    /// Path, without query string
+    pub path: &'a str,
+}
+
+/// A resource served by the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+    /// Status page
+    Status,
+
+    /// Prometheus metrics
+    Metrics,
+
+    /// Most recent reading
+    Current,
+
+    /// Readings of the last hour
+    History,
+}
+
+impl Route {
+    /// Find the route of a path
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/" => Some(Self::Status),
+            "/metrics" => Some(Self::Metrics),
+            "/api/current" => Some(Self::Current),
+            "/api/history" => Some(Self::History),
+            _ => None,
+        }
+    }
+
+    /// Return the content type of the resource
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Status => "text/html; charset=utf-8",
+            Self::Metrics => "text/plain; version=0.0.4; charset=utf-8",
+            Self::Current | Self::History => "application/json",
+        }
+    }
+}
+
+/// A response status
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// 200 OK
+    Ok,
+
+    /// 400 Bad Request
+    BadRequest,
+
+    /// 404 Not Found
+    NotFound,
+
+    /// 405 Method Not Allowed
+    MethodNotAllowed,
+
+    /// 431 Request Header Fields Too Large
+    RequestHeaderFieldsTooLarge,
+
+    /// 500 Internal Server Error
+    InternalServerError,
+}
+
+impl Status {
+    /// Return the status code and reason phrase
+    pub fn code_and_reason(self) -> (u16, &'static str) {
+        match self {
+            Self::Ok => (200, "OK"),
+            Self::BadRequest => (400, "Bad Request"),
+            Self::NotFound => (404, "Not Found"),
+            Self::MethodNotAllowed => (405, "Method Not Allowed"),
+            Self::RequestHeaderFieldsTooLarge => (431, "Request Header Fields Too Large"),
+            Self::InternalServerError => (500, "Internal Server Error"),
+        }
+    }
+}
+
+/// Parse the head of a request
+///
+/// Return `None` if the head is not complete yet, i.e. it does not end with
+/// an empty line.
+/// Header fields are ignored.
+pub fn parse_request(buffer: &[u8]) -> Result<Option<Request<'_>>, Error> {
+    let Some(end) = head_end(buffer) else {
+        return Ok(None);
+    };
+    let head = from_utf8(buffer.get(..end).unwrap_or_default()).map_err(|_| Error::Malformed)?;
+    let request_line = head.lines().next().ok_or(Error::Malformed)?;
+
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Error::Malformed);
+    };
+
+    if !version.starts_with("HTTP/1.") {
+        return Err(Error::Malformed);
+    }
+    if !target.starts_with('/') {
+        return Err(Error::Malformed);
+    }
+    let method = match method {
+        "GET" => Method::Get,
+        "HEAD" => Method::Head,
+        _ => return Err(Error::UnsupportedMethod),
+    };
+    let path = target.split_once('?').map_or(target, |(path, _query)| path);
+
+    Ok(Some(Request { method, path }))
+}
+
+/// Find the end of the head of a request, before its empty line
+fn head_end(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(4).position(|window| window == b"\r\n\r\n")
+}
+
+/// Read the head of a request from a connection into a buffer
+///
+/// Return the number of bytes read once the head is complete.
+async fn read_request_head(connection: &mut impl Read, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut length = 0;
+    loop {
+        let free = buffer.get_mut(length..).unwrap_or_default();
+        if free.is_empty() {
+            return Err(Error::TooLarge);
+        }
+        let read = with_timeout(REQUEST_TIMEOUT, connection.read(free))
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(|_| Error::Connection)?;
+        if read == 0 {
+            return Err(Error::Connection);
+        }
+        length += read;
+        if head_end(buffer.get(..length).unwrap_or_default()).is_some() {
+            return Ok(length);
+        }
+    }
+}
+
+/// Render the response to a parsed request into a body
+///
+/// Return the status, the content type and the method of the response.
+/// The body is only complete with [`Status::Ok`], and must be discarded
+/// otherwise.
+fn respond(
+    parsed: Result<Request<'_>, Error>,
+    history: &History,
+    body: &mut impl Write,
+) -> (Status, &'static str, Method) {
+    match parsed {
+        Ok(request) => match Route::from_path(request.path) {
+            Some(route) => {
+                debug!("Serve {:?} {}", request.method, request.path);
+                match render(route, history, body) {
+                    Ok(()) => (Status::Ok, route.content_type(), request.method),
+                    Err(FmtError) => (Status::InternalServerError, "text/plain", request.method),
+                }
+            }
+            None => (Status::NotFound, "text/plain", request.method),
+        },
+        Err(Error::UnsupportedMethod) => (Status::MethodNotAllowed, "text/plain", Method::Get),
+        Err(Error::TooLarge) => (
+            Status::RequestHeaderFieldsTooLarge,
+            "text/plain",
+            Method::Get,
+        ),
+        Err(_) => (Status::BadRequest, "text/plain", Method::Get),
+    }
+}
+
+/// Render the body of a resource
+pub fn render(route: Route, history: &History, output: &mut impl Write) -> Result<(), FmtError> {
+    match route {
+        Route::Status => write_status_page(output, history),
+        Route::Metrics => write_metrics(output, history),
+        Route::Current => match history.recent() {
+            Some(reading) => write_reading(output, &reading),
+            None => output.write_str("null"),
+        },
+        Route::History => {
+            output.write_char('[')?;
+            for (index, reading) in history.readings().enumerate() {
+                if index > 0 {
+                    output.write_char(',')?;
+                }
+                write_reading(output, &reading)?;
+            }
+            output.write_char(']')
+        }
+    }
+}
+
+/// Write the head of a response
+pub fn write_response_head(
+    output: &mut impl Write,
+    status: Status,
+    content_type: &str,
+    content_length: usize,
+) -> Result<(), FmtError> {
+    let (code, reason) = status.code_and_reason();
+    write!(
+        output,
+        concat!(
+            "HTTP/1.1 {code} {reason}\r\n",
+            "Content-Type: {content_type}\r\n",
+            "Content-Length: {content_length}\r\n",
+            "Connection: close\r\n",
+            "\r\n",
+        ),
+        code = code,
+        reason = reason,
+        content_type = content_type,
+        content_length = content_length,
+    )
+}
+
+/// Write the most recent reading in Prometheus exposition format
+fn write_metrics(output: &mut impl Write, history: &History) -> Result<(), FmtError> {
+    write_metric(
+        output,
+        "crussant_history_readings",
+        "Number of readings in the last hour",
+        history.len(),
+    )?;
+
+    if let Some((time, sample)) = history.recent() {
+        write_metric(
+            output,
+            "crussant_reading_timestamp_seconds",
+            "Time of the most recent reading",
+            time.unix_timestamp(),
+        )?;
+        write_metric(
+            output,
+            "crussant_temperature_celsius",
+            "Temperature",
+            sample.temperature.get::<degree_celsius>(),
+        )?;
+        write_metric(
+            output,
+            "crussant_humidity_percent",
+            "Relative humidity",
+            sample.humidity.get::<percent>(),
+        )?;
+        write_metric(
+            output,
+            "crussant_pressure_hectopascals",
+            "Pressure",
+            sample.pressure.get::<hectopascal>(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write a gauge in Prometheus exposition format
+fn write_metric(
+    output: &mut impl Write,
+    name: &str,
+    help: &str,
+    value: impl Display,
+) -> Result<(), FmtError> {
+    writeln!(output, "# HELP {name} {help}")?;
+    writeln!(output, "# TYPE {name} gauge")?;
+    writeln!(output, "{name} {value}")
+}
+
+/// Write the status page
+fn write_status_page(output: &mut impl Write, history: &History) -> Result<(), FmtError> {
+    output.write_str(concat!(
+        "<!DOCTYPE html>\n",
+        "<html><head><meta charset=\"utf-8\"><title>Crussant</title></head><body>\n",
+        "<h1>Crussant</h1>\n",
+    ))?;
+
+    match history.recent() {
+        Some((time, sample)) => {
+            output.write_str("<p>Last reading at ")?;
+            write_time(output, time)?;
+            output.write_str("</p>\n<ul>\n<li>Temperature: ")?;
+            write_number(output, sample.temperature.get::<degree_celsius>())?;
+            output.write_str(" °C</li>\n<li>Humidity: ")?;
+            write_number(output, sample.humidity.get::<percent>())?;
+            output.write_str(" %</li>\n<li>Pressure: ")?;
+            write_number(output, sample.pressure.get::<hectopascal>())?;
+            output.write_str(" hPa</li>\n</ul>\n")?;
+        }
+        None => output.write_str("<p>No readings yet</p>\n")?,
+    }
+
+    write!(
+        output,
+        concat!(
+            "<p>{} readings in the last hour</p>\n",
+            "<p><a href=\"/metrics\">Metrics</a> ",
+            "<a href=\"/api/current\">Current</a> ",
+            "<a href=\"/api/history\">History</a></p>\n",
+            "</body></html>\n",
+        ),
+        history.len()
+    )
+}
+
+/// Task for serving readings over HTTP
+#[embassy_executor::task]
+pub async fn serve_task(
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    history: &'static SharedHistory,
+) {
+    let mut rx_buffer = [0_u8; REQUEST_SIZE];
+    let mut tx_buffer = [0_u8; 2048];
+
+    info!("Serve readings on port {HTTP_PORT}");
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(REQUEST_TIMEOUT));
+
+        if let Err(error) = socket.accept(HTTP_PORT).await {
+            warn!("Cannot accept connection: {error:?}");
+            continue;
+        }
+        debug!("Accepted connection from {:?}", socket.remote_endpoint());
+
+        if let Err(error) = serve(&mut socket, history).await {
+            warn!("Cannot serve request: {error:?}");
+        }
+
+        socket.close();
+        if let Err(error) = socket.flush().await {
+            debug!("Cannot flush socket: {error:?}");
+        }
+    }
+}
+
+/// Serve a request on a connection
+async fn serve(socket: &mut TcpSocket<'_>, history: &SharedHistory) -> Result<(), Error> {
+    let mut request = [0_u8; REQUEST_SIZE];
+    let parsed = match read_request_head(socket, &mut request).await {
+        Ok(length) => parse_request(request.get(..length).unwrap_or_default())
+            .and_then(|request| request.ok_or(Error::Malformed)),
+        Err(Error::TooLarge) => Err(Error::TooLarge),
+        Err(error) => return Err(error),
+    };
+
+    let mut body = String::<BODY_SIZE>::new();
+    let (status, content_type, method) = respond(parsed, &history.lock().await, &mut body);
+    if status != Status::Ok {
+        body.clear();
+    }
+
+    let mut head = String::<HEAD_SIZE>::new();
+    write_response_head(&mut head, status, content_type, body.len())
+        .map_err(|_| Error::TooLarge)?;
+
+    socket
+        .write_all(head.as_bytes())
+        .await
+        .map_err(|_| Error::Connection)?;
+    if method == Method::Get {
+        socket
+            .write_all(body.as_bytes())
+            .await
+            .map_err(|_| Error::Connection)?;
+    }
+
+    Ok(())
+}
+
+/// An error within the HTTP server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The request is malformed
+    Malformed,
+
+    /// The request method is not supported
+    UnsupportedMethod,
+
+    /// The request or the response does not fit in its buffer
+    TooLarge,
+
+    /// The client did not send a request in time
+    Timeout,
+
+    /// The connection failed or was closed
+    Connection,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+
+    use embedded_io_async::ErrorType;
+
+    use time::macros::datetime;
+    use time::Duration as TimeDuration;
+
+    use crate::history::expand_sample;
+    use crate::history::CompactSample;
+    use crate::history::MINUTES;
+
+    /// An in-memory client that sends a request in chunks
+    struct FakeClient {
+        /// Chunks left to send, each returned by one read
+        chunks: &'static [&'static [u8]],
+    }
+
+    impl ErrorType for FakeClient {
+        type Error = Infallible;
+    }
+
+    impl Read for FakeClient {
+        async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+            let Some((chunk, rest)) = self.chunks.split_first() else {
+                return Ok(0);
+            };
+            let length = buffer.len().min(chunk.len());
+            buffer
+                .get_mut(..length)
+                .unwrap()
+                .copy_from_slice(chunk.get(..length).unwrap());
+            self.chunks = rest;
+            Ok(length)
+        }
+    }
+
+    /// Parse a complete request head
+    fn parse(head: &[u8]) -> Result<Request<'_>, Error> {
+        parse_request(head).map(Option::unwrap)
+    }
+
+    /// Create a history with a reading every minute, with the same sample
+    fn history_of(readings: usize, sample: CompactSample) -> History {
+        let mut history = History::new();
+        let start = datetime!(2024-06-01 12:00 +02:00);
+        for minute in 0..readings {
+            let time = start + TimeDuration::minutes(i64::try_from(minute).unwrap());
+            history.write(&(time, expand_sample(sample)));
+        }
+        history
+    }
+
+    /// Render the body of a resource
+    fn rendered(route: Route, history: &History) -> String<BODY_SIZE> {
+        let mut body = String::new();
+        render(route, history, &mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn parse_get_and_head_requests() {
+        assert_eq!(
+            parse(b"GET /metrics HTTP/1.1\r\nHost: crussant\r\nAccept: */*\r\n\r\n"),
+            Ok(Request {
+                method: Method::Get,
+                path: "/metrics",
+            })
+        );
+        assert_eq!(
+            parse(b"HEAD /api/current?pretty=1 HTTP/1.0\r\n\r\n"),
+            Ok(Request {
+                method: Method::Head,
+                path: "/api/current",
+            })
+        );
+    }
+
+    #[test]
+    fn parse_incomplete_request() {
+        assert_eq!(parse_request(b""), Ok(None));
+        assert_eq!(
+            parse_request(b"GET / HTTP/1.1\r\nHost: crussant\r\n"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn parse_malformed_request_lines() {
+        let requests: [&[u8]; 9] = [
+            b"\r\n\r\n",
+            b"GET /\r\n\r\n",
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"GET  / HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/2\r\n\r\n",
+            b"GET / http/1.1\r\n\r\n",
+            b"GET metrics HTTP/1.1\r\n\r\n",
+            b"GET http://crussant/ HTTP/1.1\r\n\r\n",
+            b"GET /\xff HTTP/1.1\r\n\r\n",
+        ];
+        for request in requests {
+            assert_eq!(parse(request), Err(Error::Malformed), "{request:?}");
+        }
+    }
+
+    #[test]
+    fn parse_unsupported_methods() {
+        for request in [
+            &b"POST / HTTP/1.1\r\n\r\n"[..],
+            b"get / HTTP/1.1\r\n\r\n",
+            b"DELETE /api/history HTTP/1.1\r\n\r\n",
+        ] {
+            assert_eq!(parse(request), Err(Error::UnsupportedMethod), "{request:?}");
+        }
+    }
+
+    #[test]
+    fn read_request_head_in_chunks() {
+        let mut client = FakeClient {
+            chunks: &[b"GET /metrics HT", b"TP/1.1\r\nHost: c\r", b"\n\r\n"],
+        };
+        let mut buffer = [0_u8; REQUEST_SIZE];
+        let length = block_on(read_request_head(&mut client, &mut buffer)).unwrap();
+        assert_eq!(
+            parse(buffer.get(..length).unwrap()),
+            Ok(Request {
+                method: Method::Get,
+                path: "/metrics",
+            })
+        );
+    }
+
+    #[test]
+    fn read_request_head_rejects_oversized_headers() {
+        static COOKIE: [u8; REQUEST_SIZE] = [b'a'; REQUEST_SIZE];
+        static CHUNKS: [&[u8]; 3] = [b"GET / HTTP/1.1\r\nCookie: ", &COOKIE, b"\r\n\r\n"];
+        let mut client = FakeClient { chunks: &CHUNKS };
+        let mut buffer = [0_u8; REQUEST_SIZE];
+        assert_eq!(
+            block_on(read_request_head(&mut client, &mut buffer)),
+            Err(Error::TooLarge)
+        );
+    }
+
+    #[test]
+    fn read_request_head_fails_when_closed() {
+        let mut client = FakeClient {
+            chunks: &[b"GET / HTTP/1.1\r\n"],
+        };
+        let mut buffer = [0_u8; REQUEST_SIZE];
+        assert_eq!(
+            block_on(read_request_head(&mut client, &mut buffer)),
+            Err(Error::Connection)
+        );
+    }
+
+    #[test]
+    fn respond_to_known_and_unknown_paths() {
+        let history = history_of(2, [2150, 4500, 10132]);
+        let request = |method, path| {
+            let mut body = String::<BODY_SIZE>::new();
+            let response = respond(Ok(Request { method, path }), &history, &mut body);
+            (response, body)
+        };
+
+        let ((status, content_type, method), body) = request(Method::Get, "/api/current");
+        assert_eq!(
+            (status, content_type, method),
+            (Status::Ok, "application/json", Method::Get)
+        );
+        assert!(body.starts_with("{\"time\":"));
+
+        let ((status, _, method), _) = request(Method::Head, "/metrics");
+        assert_eq!((status, method), (Status::Ok, Method::Head));
+
+        for path in ["/api", "/api/current/", "/index.html", "/METRICS", "//"] {
+            let ((status, content_type, _), _) = request(Method::Get, path);
+            assert_eq!(
+                (status, content_type),
+                (Status::NotFound, "text/plain"),
+                "{path}"
+            );
+        }
+        let ((status, _, method), _) = request(Method::Head, "/missing");
+        assert_eq!((status, method), (Status::NotFound, Method::Head));
+    }
+
+    #[test]
+    fn respond_to_invalid_requests_without_body() {
+        let history = History::new();
+        let cases = [
+            (Error::Malformed, Status::BadRequest),
+            (Error::UnsupportedMethod, Status::MethodNotAllowed),
+            (Error::TooLarge, Status::RequestHeaderFieldsTooLarge),
+        ];
+        for (error, expected) in cases {
+            let mut body = String::<BODY_SIZE>::new();
+            let (status, content_type, method) = respond(Err(error), &history, &mut body);
+            assert_eq!(
+                (status, content_type, method),
+                (expected, "text/plain", Method::Get)
+            );
+            assert!(body.is_empty());
+        }
+    }
+
+    #[test]
+    fn respond_with_internal_error_when_body_overflows() {
+        let history = history_of(MINUTES, [2150, 4500, 10132]);
+        let mut body = String::<64>::new();
+        let request = Request {
+            method: Method::Get,
+            path: "/api/history",
+        };
+        let (status, _, _) = respond(Ok(request), &history, &mut body);
+        assert_eq!(status, Status::InternalServerError);
+    }
+
+    #[test]
+    fn render_without_readings() {
+        let history = History::new();
+        assert_eq!(rendered(Route::Current, &history), "null");
+        assert_eq!(rendered(Route::History, &history), "[]");
+        assert!(rendered(Route::Status, &history).contains("<p>No readings yet</p>"));
+        assert_eq!(
+            rendered(Route::Metrics, &history),
+            concat!(
+                "# HELP crussant_history_readings Number of readings in the last hour\n",
+                "# TYPE crussant_history_readings gauge\n",
+                "crussant_history_readings 0\n",
+            )
+        );
+    }
+
+    #[test]
+    fn render_readings() {
+        let history = history_of(2, [2150, 4500, 10132]);
+        let reading = concat!(
+            "{\"time\":\"2024-06-01T12:01:00+02:00\",",
+            "\"temperature\":21.50,\"humidity\":45.00,\"pressure\":1013.20}",
+        );
+
+        assert_eq!(rendered(Route::Current, &history), reading);
+
+        let readings = rendered(Route::History, &history);
+        assert!(readings.starts_with("[{\"time\":\"2024-06-01T12:00:00+02:00\","));
+        assert!(readings.ends_with(&[",", reading, "]"].concat()));
+
+        let metrics = rendered(Route::Metrics, &history);
+        assert!(metrics.contains("\ncrussant_history_readings 2\n"));
+        assert!(metrics.contains("\ncrussant_reading_timestamp_seconds 1717236060\n"));
+        assert!(metrics.contains("\ncrussant_temperature_celsius 21.5\n"));
+        assert!(metrics.contains("\ncrussant_pressure_hectopascals 1013.2\n"));
+
+        let status = rendered(Route::Status, &history);
+        assert!(status.contains("<p>Last reading at 2024-06-01T12:01:00+02:00</p>"));
+        assert!(status.contains("<li>Temperature: 21.50 °C</li>"));
+        assert!(status.contains("<p>2 readings in the last hour</p>"));
+    }
+
+    #[test]
+    fn full_history_of_longest_readings_fits_in_body() {
+        let history = history_of(MINUTES + 5, [i16::MIN; 3]);
+        assert_eq!(history.len(), MINUTES);
+
+        let body = rendered(Route::History, &history);
+        assert_eq!(body.matches("\"time\"").count(), MINUTES);
+        assert!(body.contains("\"temperature\":-327.68"));
+        assert!(body.contains("\"pressure\":-3276.80"));
+    }
+
+    #[test]
+    fn response_head() {
+        let mut head = String::<HEAD_SIZE>::new();
+        write_response_head(&mut head, Status::NotFound, "text/plain", 0).unwrap();
+        assert_eq!(
+            head,
+            concat!(
+                "HTTP/1.1 404 Not Found\r\n",
+                "Content-Type: text/plain\r\n",
+                "Content-Length: 0\r\n",
+                "Connection: close\r\n",
+                "\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn longest_response_head_fits() {
+        let mut head = String::<HEAD_SIZE>::new();
+        write_response_head(
+            &mut head,
+            Status::RequestHeaderFieldsTooLarge,
+            Route::Metrics.content_type(),
+            BODY_SIZE,
+        )
+        .unwrap();
+        assert!(head.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+        assert!(head.contains("\r\nContent-Length: 8192\r\n"));
+    }
+}
@@ -0,0 +1,528 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Uploader of readings to InfluxDB
+//!
+//! Readings are encoded in line protocol, one line per reading, with the
+//! time in seconds since Unix epoch:
+//!
+//! ```text
+//! environment,station=crussant-esp32c3 temperature=21.5,humidity=45.2,pressure=1013.25 1723629600
+//! ```
+//!
+//! Values that are not finite are left out, and readings without any finite
+//! value are skipped.
+//! Batches are sent with `POST` to the `/api/v2/write` endpoint of InfluxDB
+//! 2, which is also accepted by InfluxDB 3 and by many other ingestion
+//! endpoints.
+//!
+//! See [Line protocol](https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/).
+
+use core::fmt::Error as FmtError;
+use core::fmt::Write;
+
+use log::debug;
+use log::info;
+use log::warn;
+
+use embassy_time::Duration;
+use embassy_time::Timer;
+
+use heapless::String;
+
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::domain::Reading;
+use crate::http::Client as HttpClient;
+use crate::http::ClientTrait as HttpClientTrait;
+use crate::http::Error as HttpError;
+
+/// Size of a request body
+const BODY_SIZE: usize = 2048;
+
+/// Size of a URL
+const URL_SIZE: usize = 256;
+
+/// Size of the authorization header
+const AUTHORIZATION_SIZE: usize = 128;
+
+/// Number of failed requests before giving up
+const MAXIMAL_ATTEMPTS: u8 = 4;
+
+/// Time to wait after the first failed request, doubled after each failure
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Configuration of InfluxDB uploads
+#[derive(Clone, Copy, Debug)]
+pub struct Config<'a> {
+    /// Base URL of the server, e.g. `https://influxdb.example.com:8086`
+    pub url: &'a str,
+
+    /// Organization
+    pub organization: &'a str,
+
+    /// Bucket
+    pub bucket: &'a str,
+
+    /// API token, if required
+    pub token: Option<&'a str>,
+
+    /// Measurement name
+    pub measurement: &'a str,
+
+    /// Value of the `station` tag
+    pub station: &'a str,
+}
+
+/// Extend an HTTP client for uploading readings to InfluxDB
+pub trait InfluxDbClient: HttpClientTrait {
+    /// Upload readings in batches
+    ///
+    /// Readings are sent oldest first, in batches as large as fit in a
+    /// request body.
+    /// After a failed request the batch is halved and retried after a delay,
+    /// until a few requests failed.
+    /// The callback is called with the last reading of every batch accepted
+    /// by the server, so that the remaining readings can be uploaded later.
+    ///
+    /// Return the number of uploaded readings.
+    async fn upload_readings(
+        &mut self,
+        config: &Config<'_>,
+        readings: &[Reading],
+        mut uploaded: impl FnMut(&Reading),
+    ) -> Result<usize, Error> {
+        let url = build_url(config)?;
+        let mut authorization = String::<AUTHORIZATION_SIZE>::new();
+        if let Some(token) = config.token {
+            write!(authorization, "Token {token}").map_err(|_| Error::TooLarge)?;
+        }
+        let authorization_header = [("Authorization", authorization.as_str())];
+        let headers: &[(&str, &str)] = if config.token.is_some() {
+            &authorization_header
+        } else {
+            &[]
+        };
+
+        let mut remaining = readings;
+        let mut batch_size = readings.len();
+        let mut failures = 0;
+        let mut delay = RETRY_DELAY;
+
+        while !remaining.is_empty() {
+            let mut body = String::<BODY_SIZE>::new();
+            let count = write_lines(
+                &mut body,
+                config,
+                remaining.get(..batch_size).unwrap_or(remaining),
+            );
+            if count == 0 {
+                return Err(Error::TooLarge);
+            }
+            let (batch, rest) = remaining.split_at(count);
+            if body.is_empty() {
+                debug!("Skip {count} readings without values");
+                if let Some(last) = batch.last() {
+                    uploaded(last);
+                }
+                remaining = rest;
+                continue;
+            }
+
+            debug!("Upload {count} readings in {} bytes", body.len());
+            match self.send_post_request(&url, headers, body.as_bytes()).await {
+                Ok(_) => {
+                    if let Some(last) = batch.last() {
+                        uploaded(last);
+                    }
+                    remaining = rest;
+                }
+                Err(error) => {
+                    failures += 1;
+                    warn!("Cannot upload {count} readings (attempt {failures}): {error:?}");
+                    if failures >= MAXIMAL_ATTEMPTS {
+                        return Err(error.into());
+                    }
+                    batch_size = (count / 2).max(1);
+                    Timer::after(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+
+        let count = readings.len() - remaining.len();
+        info!("Uploaded {count} readings to {}", config.url);
+        Ok(count)
+    }
+}
+
+impl InfluxDbClient for HttpClient {}
+
+/// Build the URL of the write endpoint
+fn build_url(config: &Config<'_>) -> Result<String<URL_SIZE>, Error> {
+    let mut url = String::new();
+    write!(
+        url,
+        "{}/api/v2/write?org={}&bucket={}&precision=s",
+        config.url.trim_end_matches('/'),
+        config.organization,
+        config.bucket,
+    )
+    .map_err(|_| Error::TooLarge)?;
+    Ok(url)
+}
+
+/// Write as many readings as fit in a buffer, one line each
+///
+/// Return the number of readings consumed, including skipped ones.
+/// Lines are never truncated.
+pub fn write_lines<const N: usize>(
+    output: &mut String<N>,
+    config: &Config<'_>,
+    readings: &[Reading],
+) -> usize {
+    for (index, reading) in readings.iter().enumerate() {
+        let length = output.len();
+        if write_line(output, config, reading).is_err() {
+            output.truncate(length);
+            return index;
+        }
+    }
+    readings.len()
+}
+
+/// Write a reading as a line, unless it has no finite value
+pub fn write_line(
+    output: &mut impl Write,
+    config: &Config<'_>,
+    (time, sample): &Reading,
+) -> Result<(), FmtError> {
+    let fields = [
+        ("temperature", sample.temperature.get::<degree_celsius>()),
+        ("humidity", sample.humidity.get::<percent>()),
+        ("pressure", sample.pressure.get::<hectopascal>()),
+    ];
+    if !fields.iter().any(|(_, value)| value.is_finite()) {
+        return Ok(());
+    }
+
+    write_escaped(output, config.measurement, &[',', ' '])?;
+    output.write_str(",station=")?;
+    write_escaped(output, config.station, &[',', '=', ' '])?;
+
+    let mut separator = ' ';
+    for (key, value) in fields {
+        if value.is_finite() {
+            write!(output, "{separator}{key}={value}")?;
+            separator = ',';
+        }
+    }
+
+    writeln!(output, " {}", time.unix_timestamp())
+}
+
+/// Write a string escaping some characters with a backslash
+fn write_escaped(output: &mut impl Write, text: &str, special: &[char]) -> Result<(), FmtError> {
+    for c in text.chars() {
+        if c == '\\' || special.contains(&c) {
+            output.write_char('\\')?;
+        }
+        output.write_char(c)?;
+    }
+    Ok(())
+}
+
+/// An error within an upload to InfluxDB
+#[derive(Debug)]
+pub enum Error {
+    /// URL, header or a single line does not fit in its buffer
+    TooLarge,
+
+    /// Error from HTTP client
+    Http(#[allow(unused)] HttpError),
+}
+
+impl From<HttpError> for Error {
+    fn from(error: HttpError) -> Self {
+        Self::Http(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::cell::RefCell;
+
+    use embassy_futures::block_on;
+
+    use heapless::Vec;
+
+    use time::macros::datetime;
+    use time::OffsetDateTime;
+
+    use uom::si::f32::Pressure;
+    use uom::si::f32::Ratio;
+    use uom::si::f32::ThermodynamicTemperature;
+
+    use crate::domain::Sample;
+    use crate::http::RESPONSE_SIZE;
+
+    /// Configuration without special characters
+    const CONFIG: Config<'static> = Config {
+        url: "https://influxdb.example.com:8086/",
+        organization: "home",
+        bucket: "weather",
+        token: Some("secret"),
+        measurement: "environment",
+        station: "crussant-esp32c3",
+    };
+
+    /// A request received by a [`FakeServer`]
+    struct Request {
+        /// URL
+        url: String<URL_SIZE>,
+
+        /// Value of the authorization header, if any
+        authorization: Option<String<AUTHORIZATION_SIZE>>,
+
+        /// Body
+        body: String<BODY_SIZE>,
+    }
+
+    /// An in-memory stand-in for an InfluxDB server
+    struct FakeServer {
+        /// Number of requests to fail before accepting any
+        failures: usize,
+
+        /// Received requests, including failed ones
+        requests: Vec<Request, 8>,
+    }
+
+    impl FakeServer {
+        /// Create a server that fails a number of requests first
+        fn new(failures: usize) -> Self {
+            Self {
+                failures,
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    impl HttpClientTrait for FakeServer {
+        async fn send_request(&mut self, _url: &str) -> Result<Vec<u8, RESPONSE_SIZE>, HttpError> {
+            unreachable!("Only POST requests are sent to InfluxDB")
+        }
+
+        async fn send_post_request(
+            &mut self,
+            url: &str,
+            headers: &[(&str, &str)],
+            body: &[u8],
+        ) -> Result<Vec<u8, RESPONSE_SIZE>, HttpError> {
+            let authorization = headers
+                .iter()
+                .find(|(name, _)| *name == "Authorization")
+                .map(|(_, value)| String::try_from(*value).unwrap());
+            let request = Request {
+                url: String::try_from(url).unwrap(),
+                authorization,
+                body: String::try_from(core::str::from_utf8(body).unwrap()).unwrap(),
+            };
+            self.requests.push(request).ok().unwrap();
+
+            if self.failures > 0 {
+                self.failures -= 1;
+                Err(HttpError::ResponseTooLarge)
+            } else {
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    impl InfluxDbClient for FakeServer {}
+
+    /// Create a reading
+    fn reading(time: OffsetDateTime, temperature: f32, humidity: f32, pressure: f32) -> Reading {
+        let sample = Sample {
+            temperature: ThermodynamicTemperature::new::<degree_celsius>(temperature),
+            humidity: Ratio::new::<percent>(humidity),
+            pressure: Pressure::new::<hectopascal>(pressure),
+        };
+        (time, sample)
+    }
+
+    /// Create readings one minute apart
+    fn readings<const N: usize>() -> [Reading; N] {
+        let start = datetime!(2024-08-14 10:00:00 UTC);
+        core::array::from_fn(|index| {
+            let time = start + time::Duration::minutes(i64::try_from(index).unwrap());
+            reading(time, 21.5, 45.25, 1013.25)
+        })
+    }
+
+    /// Write a single line
+    fn line(config: &Config<'_>, reading: &Reading) -> String<256> {
+        let mut output = String::new();
+        write_line(&mut output, config, reading).unwrap();
+        output
+    }
+
+    #[test]
+    fn line_protocol() {
+        let reading = reading(datetime!(2024-08-14 10:00:00 UTC), 21.5, 45.25, 1013.25);
+        assert_eq!(
+            line(&CONFIG, &reading),
+            "environment,station=crussant-esp32c3 \
+             temperature=21.5,humidity=45.25,pressure=1013.25 1723629600\n"
+        );
+    }
+
+    #[test]
+    fn escape_tag_value() {
+        let config = Config {
+            station: r"living room,north=1\a",
+            ..CONFIG
+        };
+        let reading = reading(datetime!(2024-08-14 10:00:00 UTC), 21.5, 45.25, 1013.25);
+        assert_eq!(
+            line(&config, &reading),
+            r"environment,station=living\ room\,north\=1\\a temperature=21.5,humidity=45.25,pressure=1013.25 1723629600
+"
+        );
+    }
+
+    #[test]
+    fn escape_measurement() {
+        let config = Config {
+            measurement: "weather station,indoor=yes",
+            ..CONFIG
+        };
+        let reading = reading(datetime!(2024-08-14 10:00:00 UTC), 21.5, 45.25, 1013.25);
+        assert!(line(&config, &reading)
+            .starts_with(r"weather\ station\,indoor=yes,station=crussant-esp32c3 "));
+    }
+
+    #[test]
+    fn timestamp_in_seconds() {
+        let url = build_url(&CONFIG).unwrap();
+        assert!(url.ends_with("&precision=s"));
+
+        // Subsecond part is dropped and the offset does not matter
+        let reading = reading(
+            datetime!(2024-08-14 12:00:00.999_999_999 +02:00),
+            21.5,
+            45.25,
+            1013.25,
+        );
+        assert!(line(&CONFIG, &reading).ends_with(" 1723629600\n"));
+    }
+
+    #[test]
+    fn url() {
+        assert_eq!(
+            build_url(&CONFIG).unwrap(),
+            "https://influxdb.example.com:8086/api/v2/write?org=home&bucket=weather&precision=s"
+        );
+    }
+
+    #[test]
+    fn skip_values_not_finite() {
+        let time = datetime!(2024-08-14 10:00:00 UTC);
+        assert_eq!(
+            line(&CONFIG, &reading(time, f32::NAN, 45.25, f32::INFINITY)),
+            "environment,station=crussant-esp32c3 humidity=45.25 1723629600\n"
+        );
+        assert_eq!(
+            line(&CONFIG, &reading(time, f32::NAN, f32::NAN, f32::NAN)),
+            ""
+        );
+    }
+
+    #[test]
+    fn write_whole_lines_only() {
+        let readings = readings::<3>();
+        let length = line(&CONFIG, readings.first().unwrap()).len();
+
+        let mut output = String::<200>::new();
+        assert!(length * 2 <= 200 && length * 3 > 200);
+        assert_eq!(write_lines(&mut output, &CONFIG, &readings), 2);
+        assert_eq!(output.len(), length * 2);
+        assert!(output.ends_with('\n'));
+    }
+
+    #[test]
+    fn upload_in_batches() {
+        let readings = readings::<40>();
+        let uploaded = RefCell::new(Vec::<OffsetDateTime, 8>::new());
+        let mut server = FakeServer::new(0);
+
+        let count = block_on(server.upload_readings(&CONFIG, &readings, |(time, _)| {
+            uploaded.borrow_mut().push(*time).unwrap();
+        }))
+        .unwrap();
+
+        assert_eq!(count, 40);
+        assert!(server.requests.len() > 1);
+        let lines: usize = server
+            .requests
+            .iter()
+            .map(|request| request.body.lines().count())
+            .sum();
+        assert_eq!(lines, 40);
+        for request in &server.requests {
+            assert_eq!(request.url, build_url(&CONFIG).unwrap());
+            assert_eq!(request.authorization.as_deref(), Some("Token secret"));
+        }
+        let uploaded = uploaded.into_inner();
+        assert_eq!(uploaded.len(), server.requests.len());
+        assert_eq!(uploaded.last(), readings.last().map(|(time, _)| time));
+    }
+
+    #[test]
+    fn upload_without_token() {
+        let config = Config {
+            token: None,
+            ..CONFIG
+        };
+        let mut server = FakeServer::new(0);
+        block_on(server.upload_readings(&config, &readings::<1>(), |_| {})).unwrap();
+        assert_eq!(server.requests.len(), 1);
+        assert!(server.requests.first().unwrap().authorization.is_none());
+    }
+
+    #[test]
+    fn upload_skips_readings_without_values() {
+        let time = datetime!(2024-08-14 10:00:00 UTC);
+        let readings = [reading(time, f32::NAN, f32::NAN, f32::NAN)];
+        let mut uploaded = 0;
+        let mut server = FakeServer::new(0);
+
+        let count =
+            block_on(server.upload_readings(&CONFIG, &readings, |_| uploaded += 1)).unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(uploaded, 1);
+        assert!(server.requests.is_empty());
+    }
+
+    #[test]
+    fn retry_with_half_batch() {
+        let readings = readings::<4>();
+        let mut server = FakeServer::new(1);
+
+        let count = block_on(server.upload_readings(&CONFIG, &readings, |_| {})).unwrap();
+
+        assert_eq!(count, 4);
+        let lines = server
+            .requests
+            .iter()
+            .map(|request| request.body.lines().count());
+        assert!(lines.eq([4, 2, 2]));
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Serialization of readings to JSON
+//!
+//! A reading is an object with the time in ISO 8601 format, the temperature
+//! in degrees Celsius, the relative humidity in percent and the pressure in
+//! hectopascals:
+//!
+//! ```json
+//! {"time":"2024-08-14T12:00:00+02:00","temperature":21.50,"humidity":45.20,"pressure":1013.25}
+//! ```
+//!
+//! Values that are not finite are serialized as `null`.
+
+use core::fmt::Error as FmtError;
+use core::fmt::Write;
+
+use time::OffsetDateTime;
+
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::domain::Reading;
+
+/// Write a reading as a JSON object
+pub fn write_reading(output: &mut impl Write, (time, sample): &Reading) -> Result<(), FmtError> {
+    output.write_str("{\"time\":\"")?;
+    write_time(output, *time)?;
+    output.write_str("\",\"temperature\":")?;
+    write_number(output, sample.temperature.get::<degree_celsius>())?;
+    output.write_str(",\"humidity\":")?;
+    write_number(output, sample.humidity.get::<percent>())?;
+    output.write_str(",\"pressure\":")?;
+    write_number(output, sample.pressure.get::<hectopascal>())?;
+    output.write_char('}')
+}
+
+/// Write a time in ISO 8601 format, e.g. `2024-08-14T12:00:00+02:00`
+pub fn write_time(output: &mut impl Write, time: OffsetDateTime) -> Result<(), FmtError> {
+    let (hours, minutes, _) = time.offset().as_hms();
+    let sign = if hours < 0 || minutes < 0 { '-' } else { '+' };
+    write!(
+        output,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{sign}{:02}:{:02}",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        hours.unsigned_abs(),
+        minutes.unsigned_abs(),
+    )
+}
+
+/// Write a number with two decimals, or `null` if it is not finite
+pub fn write_number(output: &mut impl Write, value: f32) -> Result<(), FmtError> {
+    if value.is_finite() {
+        write!(output, "{value:.2}")
+    } else {
+        output.write_str("null")
+    }
+}
@@ -13,21 +13,29 @@
 
 use log::error;
 use log::info;
+use log::warn;
 
 use embassy_executor::Spawner;
 
+use embassy_time::with_timeout;
 use embassy_time::Delay;
 use embassy_time::Duration;
+use embassy_time::Instant;
 use embassy_time::Timer;
 
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 
 use esp_hal::clock::ClockControl;
+use esp_hal::clock::Clocks;
 use esp_hal::dma::Channel0;
 use esp_hal::dma::Dma;
 use esp_hal::dma::DmaDescriptor;
 use esp_hal::dma::DmaPriority;
+use esp_hal::gpio::ErasedPin;
 use esp_hal::gpio::Input;
 use esp_hal::gpio::Io;
 use esp_hal::gpio::Level;
@@ -35,11 +43,12 @@
 use esp_hal::gpio::Pull;
 use esp_hal::i2c::I2C;
 use esp_hal::peripherals::Peripherals;
+use esp_hal::peripherals::I2C0;
 use esp_hal::peripherals::SPI2;
 use esp_hal::prelude::_fugit_RateExtU32;
 use esp_hal::prelude::entry;
 use esp_hal::prelude::main;
-use esp_hal::prelude::ram;
+use esp_hal::reset::software_reset;
 use esp_hal::rng::Rng;
 use esp_hal::spi::master::dma::SpiDma;
 use esp_hal::spi::master::dma::WithDmaSpi2;
@@ -50,14 +59,20 @@
 use esp_hal::timer::timg::TimerGroup;
 use esp_hal::timer::ErasedTimer;
 use esp_hal::timer::OneShotTimer;
+use esp_hal::uart::Error as UartError;
+use esp_hal::uart::Uart;
 use esp_hal::Async;
 
 use esp_hal_embassy::init as initialize_embassy;
 
-use time::OffsetDateTime;
+use esp_storage::FlashStorage;
+
+use uom::si::electric_potential::volt;
+use uom::si::f32::Length;
+use uom::si::length::meter;
+use uom::si::ratio::percent;
 
-use heapless::HistoryBuffer;
-use heapless::String;
+use heapless::Vec;
 
 use embedded_hal_bus::spi::ExclusiveDevice;
 
@@ -65,61 +80,290 @@
 
 use static_cell::StaticCell;
 
+use crussant_common::external_rtc;
+use crussant_common::kv_store;
+use crussant_common::tz;
+
 mod logging;
 use self::logging::setup as setup_logging;
 
+mod battery;
+use self::battery::measure as measure_battery;
+use self::battery::Policy as BatteryPolicy;
+
 mod sensor;
 use self::sensor::sample_task as sample_sensor_task;
 
 mod dashboard;
 
 mod display;
+use self::display::show_replace_battery;
 use self::display::update_task as update_display_task;
+use self::display::READING_HANDLED;
 
-mod cell;
-use self::cell::SyncUnsafeCell;
+mod captive_portal;
 
 mod clock;
 use self::clock::Clock;
 use self::clock::Error as ClockError;
 
+mod config;
+use self::config::Config;
+use self::config::Error as ConfigError;
+use self::config::FLASH_RANGE as CONFIG_FLASH_RANGE;
+
 mod http;
 use self::http::Client as HttpClient;
 
+mod console;
+use self::console::Console;
+
+mod credentials;
+use self::credentials::Credentials;
+use self::credentials::Error as CredentialsError;
+use self::credentials::Network;
+
+mod discovery;
+use self::discovery::Device as DiscoveryDevice;
+use self::discovery::DISCOVERY_PREFIX;
+
 mod domain;
 use self::domain::Reading;
 use self::domain::Sample;
 
+mod drift;
+
+mod encoding;
+
+mod energy;
+use self::energy::Policy as EnergyPolicy;
+use self::energy::State as EnergyState;
+use self::energy::CONSUMPTION;
+
+use self::external_rtc::ExternalRtc;
+
+mod forecast;
+use self::forecast::Hemisphere;
+use self::forecast::Location;
+
+mod history;
+use self::history::History;
+use self::history::MINUTES as HISTORY_MINUTES;
+
+mod http_server;
+use self::http_server::serve_task as serve_http_task;
+
+mod influxdb;
+use self::influxdb::Config as InfluxDbConfig;
+use self::influxdb::Error as InfluxDbError;
+use self::influxdb::InfluxDbClient as _;
+
+mod json;
+
+use self::kv_store::Error as KvStoreError;
+use self::kv_store::Store as KvStore;
+
+mod mqtt;
+use self::mqtt::publish_readings as publish_to_mqtt;
+use self::mqtt::Config as MqttConfig;
+use self::mqtt::Error as MqttError;
+use self::mqtt::MQTT_PORT;
+
+mod provisioning;
+use self::provisioning::run as run_provisioning;
+use self::provisioning::Error as ProvisioningError;
+
+mod reading_log;
+use self::reading_log::Error as ReadingLogError;
+use self::reading_log::ReadingLog;
+use self::reading_log::FLASH_RANGE as READING_LOG_FLASH_RANGE;
+
+mod retained;
+use self::retained::load as load_retained_state;
+use self::retained::seal as seal_retained_state;
+use self::retained::RetainedState;
+use self::retained::SavedClock;
+
 mod random;
 use self::random::RngWrapper;
 
+mod shell;
+
 mod sleep;
 use self::sleep::enter_deep as enter_deep_sleep;
 
+mod station;
+use self::station::shell_task;
+use self::station::RunningStation;
+
 mod wifi;
-use self::wifi::connect as connect_to_wifi;
 use self::wifi::Error as WifiError;
-use self::wifi::STOP_WIFI_SIGNAL;
+use self::wifi::Wifi;
+
+mod sntp;
+use self::sntp::Bans;
+use self::sntp::MAXIMAL_SERVERS;
+
+mod synchronization;
+use self::synchronization::Policy as SynchronizationPolicy;
+use self::synchronization::Source;
+
+mod time_source;
+use self::time_source::AnySource;
+use self::time_source::BuildTimeSource;
+use self::time_source::ExternalRtcSource;
+use self::time_source::RtcMemorySource;
+use self::time_source::SntpSource;
+use self::time_source::WorldTimeApiSource;
+
+use self::tz::Error as TimeZoneError;
+use self::tz::TimeZone;
 
 mod worldtimeapi;
+use self::worldtimeapi::Format as WorldTimeApiFormat;
 
 /// Timers
 static TIMERS: StaticCell<[OneShotTimer<ErasedTimer>; 1]> = StaticCell::new();
 
-/// Period to wait between readings
-const SAMPLING_PERIOD: Duration = Duration::from_secs(60);
+/// Whether to stay awake and serve readings over HTTP instead of entering
+/// deep sleep
+///
+/// The station must be powered externally in this mode.
+/// Readings are published to MQTT every awake period, if configured.
+const ALWAYS_ON: bool = false;
+
+/// Altitude of the station above sea level in meters, used to reduce
+/// pressure to sea level for the weather forecast
+const ALTITUDE_METERS: f32 = 10.0;
 
-/// Duration of deep sleep
-const DEEP_SLEEP_DURATION: Duration = Duration::from_secs(300);
+/// Hemisphere of the station, which decides the season for the weather
+/// forecast
+const HEMISPHERE: Hemisphere = Hemisphere::Northern;
 
-/// Period to wait before going to deep sleep
-const AWAKE_PERIOD: Duration = Duration::from_secs(300);
+/// SNTP servers to synchronize the clock with, in order of preference
+const SNTP_SERVERS: &[&str] = &["pool.ntp.org", "time.cloudflare.com", "time.google.com"];
+
+const _: () = assert!(SNTP_SERVERS.len() <= MAXIMAL_SERVERS);
+
+/// Sources of the current time, in order of preference
+///
+/// Network sources are skipped when WiFi is not available.
+const TIME_SOURCES: &[Source] = &[
+    Source::Sntp,
+    Source::WorldTimeApi,
+    Source::ExternalRtc,
+    Source::RtcMemory,
+    Source::BuildTime,
+];
+
+/// Format of World Time API responses
+const WORLD_TIME_API_FORMAT: WorldTimeApiFormat = WorldTimeApiFormat::Json;
+
+/// Policy for synchronizing the clock
+const SYNCHRONIZATION_POLICY: SynchronizationPolicy = SynchronizationPolicy {
+    maximum_interval: Duration::from_secs(12 * 60 * 60),
+    long_sleep: Duration::from_secs(60 * 60),
+    maximum_error: Duration::from_secs(2),
+};
+
+/// Policy for a low battery
+///
+/// A critical battery is typically replaced rather than recharged in place,
+/// so the station only wakes up every few hours to check it.
+const BATTERY_POLICY: BatteryPolicy = BatteryPolicy {
+    critical_millivolts: 3_500,
+    recovery_millivolts: 3_700,
+    critical_sleep_duration: Duration::from_secs(6 * 60 * 60),
+};
+
+/// SSID of a WiFi network to use when none is stored in flash
+const WIFI_SSID: Option<&str> = option_env!("WIFI_SSID");
+
+/// Password of the WiFi network to use when none is stored in flash
+const WIFI_PASSWORD: &str = match option_env!("WIFI_PASSWORD") {
+    Some(password) => password,
+    None => "",
+};
+
+/// Time to wait for a WiFi connection before publishing readings
+const WIFI_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time to wait for the first reading after a wakeup from deep sleep
+///
+/// This includes a refresh of the display, which takes about 15s.
+const READING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// MQTT broker to publish readings to, if any
+const MQTT_BROKER: Option<&str> = option_env!("MQTT_BROKER");
+
+/// Configuration of MQTT publishing
+///
+/// Readings are published before entering deep sleep.
+/// While the broker is not reachable, readings are kept in the history and
+/// published later, up to [`HISTORY_MINUTES`] of them.
+const MQTT_CONFIG: Option<MqttConfig> = match MQTT_BROKER {
+    Some(broker) => Some(MqttConfig {
+        broker,
+        port: MQTT_PORT,
+        client_id: "crussant-esp32c3",
+        username: option_env!("MQTT_USERNAME"),
+        password: option_env!("MQTT_PASSWORD"),
+        topic: "crussant/readings",
+        status_topic: "crussant/status",
+        discovery_prefix: Some(DISCOVERY_PREFIX),
+    }),
+    None => None,
+};
+
+/// InfluxDB server to upload readings to, if any
+const INFLUXDB_URL: Option<&str> = option_env!("INFLUXDB_URL");
+
+/// Configuration of InfluxDB uploads
+///
+/// Like with MQTT, readings are uploaded before entering deep sleep and kept
+/// in the history while the server is not reachable.
+const INFLUXDB_CONFIG: Option<InfluxDbConfig> = match INFLUXDB_URL {
+    Some(url) => Some(InfluxDbConfig {
+        url,
+        organization: "crussant",
+        bucket: "crussant",
+        token: option_env!("INFLUXDB_TOKEN"),
+        measurement: "environment",
+        station: "crussant-esp32c3",
+    }),
+    None => None,
+};
 
-/// SSID for WiFi network
-const WIFI_SSID: &str = env!("WIFI_SSID");
+/// A device on the shared I²C bus
+pub type SharedI2cDevice = I2cDevice<'static, NoopRawMutex, I2C<'static, I2C0, Async>>;
 
-/// Password for WiFi network
-const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
+/// Shared I²C bus
+static I2C_BUS: StaticCell<Mutex<NoopRawMutex, I2C<'static, I2C0, Async>>> = StaticCell::new();
+
+/// History of readings shared between display updater and MQTT publisher
+pub type SharedHistory = Mutex<NoopRawMutex, &'static mut History>;
+
+/// Shared history of readings
+static HISTORY: StaticCell<SharedHistory> = StaticCell::new();
+
+/// Clock shared between sensor sampler and shell
+pub type SharedClock = Mutex<NoopRawMutex, Clock>;
+
+/// Shared clock
+static CLOCK: StaticCell<SharedClock> = StaticCell::new();
+
+/// Log of readings in flash shared between main task and shell
+pub type SharedReadingLog = Mutex<NoopRawMutex, ReadingLog<FlashStorage>>;
+
+/// Shared log of readings
+static READING_LOG: StaticCell<SharedReadingLog> = StaticCell::new();
+
+/// State of the energy policy shared between display updater, main task and
+/// shell
+pub type SharedEnergy = Mutex<NoopRawMutex, &'static mut EnergyState>;
+
+/// Shared state of the energy policy
+static ENERGY: StaticCell<SharedEnergy> = StaticCell::new();
 
 /// A channel between sensor sampler and display updater
 static CHANNEL: StaticCell<Channel<NoopRawMutex, Reading, 3>> = StaticCell::new();
@@ -133,43 +377,38 @@
 /// RX descriptors for SPI DMA
 static RX_DESCRIPTORS: StaticCell<[DmaDescriptor; DESCRIPTORS_SIZE]> = StaticCell::new();
 
-/// Stored boot count between deep sleep cycles
-///
-/// This is a statically allocated variable and it is placed in the RTC Fast
-/// memory, which survives deep sleep.
-#[ram(rtc_fast)]
-static BOOT_COUNT: SyncUnsafeCell<u32> = SyncUnsafeCell::new(0);
-
-/// Stored history between deep sleep cycles
-///
-/// This is a statically allocated variable and it is placed in the RTC Fast
-/// memory, which survives deep sleep.
-#[ram(rtc_fast)]
-static HISTORY: SyncUnsafeCell<HistoryBuffer<(OffsetDateTime, Sample), 96>> =
-    SyncUnsafeCell::new(HistoryBuffer::new());
-
 /// Main task
 #[main]
 async fn main(spawner: Spawner) {
     setup_logging();
 
-    // SAFETY:
-    // This is the only place where a mutable reference is taken
-    let boot_count: Option<&'static mut _> = unsafe { BOOT_COUNT.get().as_mut() };
-    // SAFETY:
-    // This is pointing to a valid value
-    let boot_count: &'static mut _ = unsafe { boot_count.unwrap_unchecked() };
+    let RetainedState {
+        boot_count,
+        clock: saved_clock,
+        history,
+        published_until,
+        uploaded_until,
+        energy,
+        battery_critical,
+        banned_servers,
+    } = load_retained_state();
     info!("Current boot count = {boot_count}");
     *boot_count += 1;
-
-    // SAFETY:
-    // This is the only place where a mutable reference is taken
-    let history: Option<&'static mut _> = unsafe { HISTORY.get().as_mut() };
-    // SAFETY:
-    // This is pointing to a valid value
-    let history: &'static mut _ = unsafe { history.unwrap_unchecked() };
-
-    if let Err(error) = main_fallible(&spawner, history).await {
+    banned_servers.expire();
+
+    if let Err(error) = main_fallible(
+        &spawner,
+        *boot_count,
+        saved_clock,
+        history,
+        published_until,
+        uploaded_until,
+        energy,
+        battery_critical,
+        banned_servers,
+    )
+    .await
+    {
         error!("Error while running firmware: {error:?}");
     }
 }
@@ -177,7 +416,14 @@ async fn main(spawner: Spawner) {
 /// Main task that can return an error
 async fn main_fallible(
     spawner: &Spawner,
-    history: &'static mut HistoryBuffer<(OffsetDateTime, Sample), 96>,
+    boot_count: u32,
+    saved_clock: &'static mut Option<SavedClock>,
+    history: &'static mut History,
+    published_until: &'static mut i64,
+    uploaded_until: &'static mut i64,
+    energy: &'static mut EnergyState,
+    battery_critical: &'static mut bool,
+    banned_servers: &'static mut Bans,
 ) -> Result<(), Error> {
     let peripherals = Peripherals::take();
     let system = SystemControl::new(peripherals.SYSTEM);
@@ -191,49 +437,91 @@ async fn main_fallible(
 
     let rng = Rng::new(peripherals.RNG);
 
-    let clock = if let Some(clock) = Clock::from_rtc_memory() {
-        info!("Clock loaded from RTC memory");
-        clock
-    } else {
-        let ssid = String::<32>::try_from(WIFI_SSID).map_err(|()| Error::ParseCredentials)?;
-        let password =
-            String::<64>::try_from(WIFI_PASSWORD).map_err(|()| Error::ParseCredentials)?;
+    let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
 
-        info!("Connect to WiFi");
-        let stack = connect_to_wifi(
+    let mut flash = FlashStorage::new();
+    let mut credentials = load_credentials(&mut flash)?;
+
+    let mut config_store = KvStore::mount(FlashStorage::new(), CONFIG_FLASH_RANGE)?;
+    let config = load_config(&mut config_store);
+
+    let provisioning_button = Input::new(io.pins.gpio3, Pull::Up);
+    let provisioning_requested = provisioning_button.is_low();
+
+    let mut wifi = Wifi::new(
+        peripherals.TIMG0,
+        rng,
+        peripherals.WIFI,
+        peripherals.RADIO_CLK,
+        credentials.clone(),
+    );
+
+    if provisioning_requested || credentials.is_empty() {
+        info!("Enter provisioning (button held: {provisioning_requested})");
+        let uart = Uart::new_async(peripherals.UART0, &clocks, io.pins.gpio21, io.pins.gpio20)?;
+        let (tx, rx) = uart.split();
+        let mut console = Console::new(rx, tx);
+        run_provisioning(
             spawner,
-            peripherals.TIMG0,
-            rng,
-            peripherals.WIFI,
-            peripherals.RADIO_CLK,
+            &mut wifi,
             &clocks,
-            (ssid, password),
+            &mut console,
+            &mut flash,
+            &mut credentials,
         )
         .await?;
 
-        info!("Synchronize clock from server");
-        let mut http_client = HttpClient::new(stack, RngWrapper::from(rng));
-        let clock = Clock::from_server(&mut http_client).await?;
-
-        info!("Request to disconnect wifi");
-        STOP_WIFI_SIGNAL.signal(());
+        info!("Restart after provisioning");
+        software_reset();
+        return Ok(());
+    }
 
-        clock
+    let reading_log = match ReadingLog::mount(FlashStorage::new(), READING_LOG_FLASH_RANGE) {
+        Ok(reading_log) => Some(&*READING_LOG.init(Mutex::new(reading_log))),
+        Err(error) => {
+            warn!("Cannot mount reading log, readings are not logged to flash: {error:?}");
+            None
+        }
     };
 
-    info!("Now is {}", clock.now()?);
-
-    let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
-
     info!("Turn off cold LED");
     let mut cold_led = io.pins.gpio18;
     cold_led.set_low();
 
-    info!("Create I²C bus");
-    let sda = io.pins.gpio1;
-    let scl = io.pins.gpio2;
+    info!(
+        "Create I²C bus on GPIO{} and GPIO{} at {}kHz",
+        config.sda_pin, config.scl_pin, config.i2c_frequency_khz
+    );
+    let mut i2c_pins = [
+        (1, Some(io.pins.gpio1.degrade())),
+        (2, Some(io.pins.gpio2.degrade())),
+        (4, Some(io.pins.gpio4.degrade())),
+        (5, Some(io.pins.gpio5.degrade())),
+    ];
+    let sda = take_pin(&mut i2c_pins, config.sda_pin)?;
+    let scl = take_pin(&mut i2c_pins, config.scl_pin)?;
+
+    let i2c = I2C::new_async(
+        peripherals.I2C0,
+        sda,
+        scl,
+        config.i2c_frequency_khz.kHz(),
+        &clocks,
+    );
+    let i2c_bus: &'static _ = I2C_BUS.init(Mutex::new(i2c));
+
+    let mut external_rtc = config
+        .external_rtc
+        .map(|model| ExternalRtc::new(model, I2cDevice::new(i2c_bus)));
+
+    let time_zone = TimeZone::parse(&config.time_zone)?;
 
-    let i2c = I2C::new_async(peripherals.I2C0, sda, scl, 25_u32.kHz(), &clocks);
+    let previous_clock = Clock::from_rtc_memory(saved_clock.as_ref(), time_zone);
+    let slept = saved_clock
+        .as_ref()
+        .map_or(Duration::from_ticks(0), |saved| {
+            Duration::from_micros(saved.sleep_duration)
+        });
 
     info!("Create SPI bus");
     let spi_bus = Spi::new(peripherals.SPI2, 25_u32.kHz(), SpiMode::Mode0, &clocks)
@@ -265,30 +553,529 @@ async fn main_fallible(
     info!("Create SPI device");
     let spi_device = ExclusiveDevice::new(spi_dma, Output::new(cs, Level::Low), Delay);
 
+    let battery = if config.battery_divider_thousandths == 0 {
+        None
+    } else {
+        match measure_battery(
+            peripherals.ADC1,
+            io.pins.gpio0,
+            config.battery_divider_thousandths,
+        )
+        .await
+        {
+            Ok(battery) => {
+                info!(
+                    "Battery voltage is {:.2}V ({:.0}%)",
+                    battery.voltage.get::<volt>(),
+                    battery.charge.get::<percent>()
+                );
+                Some(battery)
+            }
+            Err(error) => {
+                warn!("Cannot measure battery voltage: {error:?}");
+                None
+            }
+        }
+    };
+
+    if let Some(battery) =
+        battery.filter(|battery| BATTERY_POLICY.is_critical(battery.voltage, *battery_critical))
+    {
+        warn!("Battery is critical, skip sampling and WiFi");
+        if !*battery_critical {
+            if let Err(error) = show_replace_battery(spi_device, busy, rst, dc, &battery).await {
+                warn!("Cannot show replace battery screen: {error:?}");
+            }
+        }
+        *battery_critical = true;
+
+        if let Some(clock) = previous_clock.as_ref() {
+            clock.save_to_rtc_memory(saved_clock, BATTERY_POLICY.critical_sleep_duration);
+        }
+        energy.usage.record_wakeup(
+            Duration::from_ticks(Instant::now().as_ticks()),
+            Duration::from_ticks(0),
+            BATTERY_POLICY.critical_sleep_duration,
+        );
+        seal_retained_state();
+        enter_deep_sleep(
+            peripherals.LPWR,
+            BATTERY_POLICY.critical_sleep_duration.into(),
+        );
+    }
+    *battery_critical = false;
+
+    let energy_policy = EnergyPolicy::new(&config);
+    let mut wifi_time = Duration::from_ticks(0);
+
+    let reason = SYNCHRONIZATION_POLICY.reason(previous_clock.as_ref(), slept);
+
+    let clock = match (reason, previous_clock) {
+        (None, Some(clock)) => {
+            info!(
+                "Use saved clock, estimated error is {}ms",
+                clock.estimated_error().as_millis()
+            );
+            clock
+        }
+        (reason, previous_clock) => {
+            info!("Synchronize clock ({reason:?})");
+            let wifi_start = Instant::now();
+            let result = synchronize_clock(
+                spawner,
+                rng,
+                &mut wifi,
+                &clocks,
+                time_zone,
+                &config.world_time_zone,
+                *saved_clock,
+                external_rtc.as_mut(),
+                banned_servers,
+            )
+            .await;
+
+            wifi.disconnect().await;
+            wifi_time += wifi_start.elapsed();
+
+            match (result, previous_clock) {
+                (Ok(mut clock), previous_clock) => {
+                    let synchronized = clock.source().is_network();
+                    if let Some(previous_clock) = previous_clock.as_ref() {
+                        clock.continue_from(previous_clock);
+                        if synchronized {
+                            let drift = clock.drift();
+                            info!(
+                                "Clock was off by {}ms, estimated drift is {}ppm",
+                                drift.last_offset / 1_000,
+                                drift.rate_ppm
+                            );
+                        }
+                    }
+                    if let (true, Some(external_rtc)) = (synchronized, external_rtc.as_mut()) {
+                        info!("Set external RTC");
+                        if let Err(error) = clock.write_to_external_rtc(external_rtc).await {
+                            warn!("Cannot set external RTC: {error:?}");
+                        }
+                    }
+                    clock
+                }
+                (Err(error), Some(mut clock)) => {
+                    warn!("Cannot synchronize clock, time is uncertain: {error:?}");
+                    clock.mark_uncertain();
+                    clock
+                }
+                (Err(error), None) => return Err(error),
+            }
+        }
+    };
+
+    info!("Now is {}", clock.now()?);
+
     info!("Create channel");
     let channel: &'static mut _ = CHANNEL.init(Channel::new());
     let receiver = channel.receiver();
     let sender = channel.sender();
 
     info!("History contains {} elements", history.len());
+    let history: &'static _ = HISTORY.init(Mutex::new(history));
+
+    let clock_status = clock.status()?;
+    let clock: &'static _ = CLOCK.init(Mutex::new(clock));
+
+    let energy: &'static _ = ENERGY.init(Mutex::new(energy));
+
+    info!("Create UART for the shell");
+    let uart = Uart::new_async(peripherals.UART0, &clocks, io.pins.gpio21, io.pins.gpio20)?;
 
     info!("Spawn tasks");
     spawner.must_spawn(sample_sensor_task(
-        i2c,
+        I2cDevice::new(i2c_bus),
+        external_rtc,
         rng,
         sender,
-        clock.clone(),
-        SAMPLING_PERIOD,
+        clock,
+        config.sampling_period,
     ));
     spawner.must_spawn(update_display_task(
-        spi_device, busy, rst, dc, receiver, history,
+        spi_device,
+        busy,
+        rst,
+        dc,
+        receiver,
+        history,
+        Location {
+            altitude: Length::new::<meter>(ALTITUDE_METERS),
+            hemisphere: HEMISPHERE,
+        },
+        clock_status,
+        battery,
+        energy,
+        energy_policy,
+    ));
+    spawner.must_spawn(shell_task(
+        uart,
+        RunningStation::new(
+            boot_count,
+            config.clone(),
+            config_store,
+            clock,
+            history,
+            reading_log,
+            energy,
+            energy_policy,
+        ),
     ));
 
-    info!("Stay awake for {}s", AWAKE_PERIOD.as_secs());
-    Timer::after(AWAKE_PERIOD).await;
+    let device = DiscoveryDevice {
+        id: MQTT_CONFIG.map_or("crussant-esp32c3", |config| config.client_id),
+        name: "Crussant",
+        model: "ESP32-C3",
+        firmware_version: env!("CARGO_PKG_VERSION"),
+        boot_count,
+    };
+
+    if ALWAYS_ON {
+        info!("Connect to WiFi");
+        let stack = with_timeout(WIFI_TIMEOUT, wifi.connect(spawner, &clocks))
+            .await
+            .map_err(|_| Error::WifiTimeout)??;
+        spawner.must_spawn(serve_http_task(stack, history));
+
+        loop {
+            Timer::after(config.awake_period).await;
+            push_readings(
+                spawner,
+                &mut wifi,
+                &clocks,
+                rng,
+                &device,
+                history,
+                published_until,
+                uploaded_until,
+            )
+            .await;
+            if let Some(reading_log) = reading_log {
+                if let Err(error) = log_readings(reading_log, history).await {
+                    warn!("Cannot log readings: {error:?}");
+                }
+            }
+        }
+    }
+
+    if boot_count == 1 {
+        info!(
+            "Stay awake for {}s after a cold boot",
+            config.awake_period.as_secs()
+        );
+        Timer::after(config.awake_period).await;
+    } else {
+        info!("Wait for the first reading");
+        if with_timeout(READING_TIMEOUT, READING_HANDLED.wait())
+            .await
+            .is_err()
+        {
+            warn!("No reading was handled in time");
+        }
+    }
+
+    if MQTT_CONFIG.is_some() || INFLUXDB_CONFIG.is_some() {
+        let wifi_start = Instant::now();
+        push_readings(
+            spawner,
+            &mut wifi,
+            &clocks,
+            rng,
+            &device,
+            history,
+            published_until,
+            uploaded_until,
+        )
+        .await;
+        wifi.disconnect().await;
+        wifi_time += wifi_start.elapsed();
+    }
+
+    if let Some(reading_log) = reading_log {
+        if let Err(error) = log_readings(reading_log, history).await {
+            warn!("Cannot log readings: {error:?}");
+        }
+    }
 
-    clock.save_to_rtc_memory(DEEP_SLEEP_DURATION);
-    enter_deep_sleep(peripherals.LPWR, DEEP_SLEEP_DURATION.into());
+    let sleep_duration = {
+        let mut energy = energy.lock().await;
+        let sleep_duration = energy.sleep_duration(&energy_policy);
+        energy.usage.record_wakeup(
+            Duration::from_ticks(Instant::now().as_ticks()),
+            wifi_time,
+            sleep_duration,
+        );
+        let report = energy.report(&energy_policy, &CONSUMPTION);
+        info!(
+            "Estimated {:.1}mAh used over {} wakeups, average {:.0}µA",
+            report.charge_milliamp_hours, report.usage.wakeups, report.average_microamps
+        );
+        sleep_duration
+    };
+
+    clock
+        .lock()
+        .await
+        .save_to_rtc_memory(saved_clock, sleep_duration);
+    seal_retained_state();
+    enter_deep_sleep(peripherals.LPWR, sleep_duration.into());
+}
+
+/// Connect to WiFi and set a clock from the first available time source
+///
+/// Sources are tried in the order of [`TIME_SOURCES`].
+/// If WiFi cannot be connected, only the offline sources are tried.
+#[allow(clippy::too_many_arguments)]
+async fn synchronize_clock(
+    spawner: &Spawner,
+    rng: Rng,
+    wifi: &mut Wifi,
+    clocks: &Clocks<'_>,
+    time_zone: TimeZone,
+    world_time_api_time_zone: &str,
+    saved_clock: Option<SavedClock>,
+    mut external_rtc: Option<&mut ExternalRtc<SharedI2cDevice>>,
+    banned_servers: &mut Bans,
+) -> Result<Clock, Error> {
+    info!("Connect to WiFi");
+    let stack = match with_timeout(WIFI_TIMEOUT, wifi.connect(spawner, clocks)).await {
+        Ok(Ok(stack)) => Some(stack),
+        Ok(Err(error)) => {
+            warn!("Cannot connect to WiFi, skip network time sources: {error:?}");
+            None
+        }
+        Err(_) => {
+            warn!("WiFi did not connect in time, skip network time sources");
+            None
+        }
+    };
+
+    let mut http_client = stack.map(|stack| HttpClient::new(stack, RngWrapper::from(rng)));
+    let mut http_client = http_client.as_mut();
+    let mut banned_servers = Some(banned_servers);
+
+    let mut sources: Vec<
+        AnySource<'_, HttpClient, ExternalRtc<SharedI2cDevice>>,
+        { TIME_SOURCES.len() },
+    > = Vec::new();
+    for &kind in TIME_SOURCES {
+        let source = match kind {
+            Source::Sntp => stack.zip(banned_servers.take()).map(|(stack, banned)| {
+                AnySource::Sntp(SntpSource::new(stack, SNTP_SERVERS, banned))
+            }),
+            Source::WorldTimeApi => http_client.take().map(|client| {
+                AnySource::WorldTimeApi(WorldTimeApiSource::new(
+                    client,
+                    world_time_api_time_zone,
+                    WORLD_TIME_API_FORMAT,
+                ))
+            }),
+            Source::ExternalRtc => external_rtc
+                .take()
+                .map(|rtc| AnySource::ExternalRtc(ExternalRtcSource::new(rtc))),
+            Source::RtcMemory => Some(AnySource::RtcMemory(RtcMemorySource::new(saved_clock))),
+            Source::BuildTime => Some(AnySource::BuildTime(BuildTimeSource)),
+            // Manual time is only set from the console
+            Source::Manual => None,
+        };
+        if let Some(source) = source {
+            if sources.push(source).is_err() {
+                warn!("Too many time sources, skip {kind:?}");
+            }
+        }
+    }
+
+    let clock = Clock::from_sources(&mut sources, time_zone).await?;
+    Ok(clock)
+}
+
+/// Append the readings not logged yet to the reading log in flash
+async fn log_readings(
+    reading_log: &SharedReadingLog,
+    history: &SharedHistory,
+) -> Result<(), Error> {
+    // Wait for an export on the serial console to complete
+    let mut reading_log = reading_log.lock().await;
+    let latest = reading_log.latest();
+    let history = history.lock().await;
+    let mut count = 0;
+    for reading in history
+        .readings()
+        .filter(|(time, _)| latest.is_none_or(|latest| *time > latest))
+    {
+        reading_log.append(&reading)?;
+        count += 1;
+    }
+    info!("Logged {count} readings to flash");
+    Ok(())
+}
+
+/// Connect to WiFi and publish the readings not yet published to MQTT
+///
+/// The time of the last published reading is updated after each reading is
+/// acknowledged, so that the remaining ones are published on next wakeup
+/// after a failure.
+async fn publish_readings(
+    spawner: &Spawner,
+    wifi: &mut Wifi,
+    clocks: &Clocks<'_>,
+    config: &MqttConfig<'_>,
+    device: &DiscoveryDevice<'_>,
+    history: &SharedHistory,
+    published_until: &mut i64,
+) -> Result<(), Error> {
+    let readings: Vec<Reading, HISTORY_MINUTES> = history
+        .lock()
+        .await
+        .readings()
+        .filter(|(time, _)| time.unix_timestamp() > *published_until)
+        .collect();
+    if readings.is_empty() {
+        info!("No readings to publish");
+        return Ok(());
+    }
+
+    info!("Connect to WiFi");
+    let stack = with_timeout(WIFI_TIMEOUT, wifi.connect(spawner, clocks))
+        .await
+        .map_err(|_| Error::WifiTimeout)??;
+
+    publish_to_mqtt(stack, config, device, &readings, |(time, _)| {
+        *published_until = time.unix_timestamp();
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Publish readings to MQTT and upload them to InfluxDB, if configured
+///
+/// Errors are logged, since readings are kept in the history and sent again
+/// on next attempt.
+#[allow(clippy::too_many_arguments)]
+async fn push_readings(
+    spawner: &Spawner,
+    wifi: &mut Wifi,
+    clocks: &Clocks<'_>,
+    rng: Rng,
+    device: &DiscoveryDevice<'_>,
+    history: &SharedHistory,
+    published_until: &mut i64,
+    uploaded_until: &mut i64,
+) {
+    if let Some(config) = MQTT_CONFIG {
+        if let Err(error) = publish_readings(
+            spawner,
+            wifi,
+            clocks,
+            &config,
+            device,
+            history,
+            published_until,
+        )
+        .await
+        {
+            warn!("Cannot publish readings: {error:?}");
+        }
+    }
+
+    if let Some(config) = INFLUXDB_CONFIG {
+        if let Err(error) =
+            upload_readings(spawner, wifi, clocks, rng, &config, history, uploaded_until).await
+        {
+            warn!("Cannot upload readings: {error:?}");
+        }
+    }
+}
+
+/// Upload the readings not uploaded yet to InfluxDB
+///
+/// The time of the last uploaded reading is updated after each accepted
+/// batch, so that the remaining ones are uploaded on next attempt after a
+/// failure.
+async fn upload_readings(
+    spawner: &Spawner,
+    wifi: &mut Wifi,
+    clocks: &Clocks<'_>,
+    rng: Rng,
+    config: &InfluxDbConfig<'_>,
+    history: &SharedHistory,
+    uploaded_until: &mut i64,
+) -> Result<(), Error> {
+    let readings: Vec<Reading, HISTORY_MINUTES> = history
+        .lock()
+        .await
+        .readings()
+        .filter(|(time, _)| time.unix_timestamp() > *uploaded_until)
+        .collect();
+    if readings.is_empty() {
+        info!("No readings to upload");
+        return Ok(());
+    }
+
+    info!("Connect to WiFi");
+    let stack = with_timeout(WIFI_TIMEOUT, wifi.connect(spawner, clocks))
+        .await
+        .map_err(|_| Error::WifiTimeout)??;
+
+    let mut client = HttpClient::new(stack, RngWrapper::from(rng));
+    client
+        .upload_readings(config, &readings, |(time, _)| {
+            *uploaded_until = time.unix_timestamp();
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Load WiFi credentials from flash
+///
+/// If none are stored, the network set at build time is used, if any.
+fn load_credentials(flash: &mut FlashStorage) -> Result<Credentials, Error> {
+    let mut credentials = match Credentials::load(flash) {
+        Ok(credentials) => credentials,
+        Err(CredentialsError::Empty) => Credentials::new(),
+        Err(error) => {
+            warn!("Cannot load WiFi credentials: {error:?}");
+            Credentials::new()
+        }
+    };
+    if let (true, Some(ssid)) = (credentials.is_empty(), WIFI_SSID) {
+        info!("Use WiFi network {ssid} set at build time");
+        credentials.add(Network::new(ssid, WIFI_PASSWORD)?);
+    }
+    Ok(credentials)
+}
+
+/// Load the configuration from flash
+///
+/// If it cannot be loaded, the defaults are used.
+fn load_config(store: &mut KvStore<FlashStorage>) -> Config {
+    match Config::load(store) {
+        Ok(config) => {
+            info!("Loaded configuration {config:?}");
+            if config.always_on {
+                warn!("always_on is only supported by the Crussant firmware, ignore it");
+            }
+            config
+        }
+        Err(error) => {
+            warn!("Cannot load configuration, use defaults: {error:?}");
+            Config::default()
+        }
+    }
+}
+
+/// Take a GPIO from those that can be assigned by the configuration
+fn take_pin(pins: &mut [(u8, Option<ErasedPin>)], number: u8) -> Result<ErasedPin, Error> {
+    pins.iter_mut()
+        .find(|(candidate, _)| *candidate == number)
+        .and_then(|(_, pin)| pin.take())
+        .ok_or(Error::UnavailablePin(number))
 }
 
 /// An error
@@ -297,16 +1084,56 @@ enum Error {
     /// An impossible error existing only to satisfy the type system
     Impossible(Infallible),
 
-    /// Error while parsing SSID or password
-    ParseCredentials,
+    /// An error within WiFi credentials
+    #[allow(unused)]
+    Credentials(CredentialsError),
+
+    /// An error within provisioning
+    #[allow(unused)]
+    Provisioning(ProvisioningError),
+
+    /// An error within configuration
+    #[allow(unused)]
+    Config(ConfigError),
+
+    /// An error within the configuration store
+    #[allow(unused)]
+    KvStore(KvStoreError),
+
+    /// An error within the reading log
+    #[allow(unused)]
+    ReadingLog(ReadingLogError),
+
+    /// A GPIO cannot be assigned or is assigned twice
+    #[allow(unused)]
+    UnavailablePin(u8),
+
+    /// An error within UART
+    #[allow(unused)]
+    Uart(UartError),
 
     /// An error within WiFi operations
     #[allow(unused)]
     Wifi(WifiError),
 
+    /// WiFi did not connect in time
+    WifiTimeout,
+
+    /// An error within MQTT publishing
+    #[allow(unused)]
+    Mqtt(MqttError),
+
+    /// An error within InfluxDB uploads
+    #[allow(unused)]
+    InfluxDb(InfluxDbError),
+
     /// An error within clock operations
     #[allow(unused)]
     Clock(ClockError),
+
+    /// An error within time zone operations
+    #[allow(unused)]
+    TimeZone(TimeZoneError),
 }
 
 impl From<Infallible> for Error {
@@ -315,14 +1142,68 @@ fn from(error: Infallible) -> Self {
     }
 }
 
+impl From<CredentialsError> for Error {
+    fn from(error: CredentialsError) -> Self {
+        Self::Credentials(error)
+    }
+}
+
+impl From<ProvisioningError> for Error {
+    fn from(error: ProvisioningError) -> Self {
+        Self::Provisioning(error)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(error: ConfigError) -> Self {
+        Self::Config(error)
+    }
+}
+
+impl From<KvStoreError> for Error {
+    fn from(error: KvStoreError) -> Self {
+        Self::KvStore(error)
+    }
+}
+
+impl From<ReadingLogError> for Error {
+    fn from(error: ReadingLogError) -> Self {
+        Self::ReadingLog(error)
+    }
+}
+
+impl From<UartError> for Error {
+    fn from(error: UartError) -> Self {
+        Self::Uart(error)
+    }
+}
+
 impl From<WifiError> for Error {
     fn from(error: WifiError) -> Self {
         Self::Wifi(error)
     }
 }
 
+impl From<MqttError> for Error {
+    fn from(error: MqttError) -> Self {
+        Self::Mqtt(error)
+    }
+}
+
+impl From<InfluxDbError> for Error {
+    fn from(error: InfluxDbError) -> Self {
+        Self::InfluxDb(error)
+    }
+}
+
 impl From<ClockError> for Error {
     fn from(error: ClockError) -> Self {
         Self::Clock(error)
     }
 }
+
+impl From<TimeZoneError> for Error {
+    fn from(error: TimeZoneError) -> Self {
+        Self::TimeZone(error)
+    }
+}
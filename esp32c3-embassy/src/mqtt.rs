@@ -0,0 +1,936 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! MQTT client for publishing readings
+//!
+//! Only the subset of MQTT 3.1.1 needed for publishing is implemented:
+//! CONNECT with authentication and a last will, PUBLISH at QoS 0 or 1, and
+//! DISCONNECT.
+//! The [`Client`] works on any transport implementing `embedded-io-async`,
+//! so it can be run against a broker on a host as well as on a TCP socket.
+//!
+//! Readings can also be announced to Home Assistant, see [`crate::discovery`].
+//!
+//! See [MQTT 3.1.1](https://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html).
+
+use log::debug;
+use log::info;
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::dns::Error as DnsError;
+use embassy_net::tcp::ConnectError as TcpConnectError;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::IpEndpoint;
+use embassy_net::Stack;
+
+use embassy_time::Duration;
+
+use embedded_io_async::Error as _;
+use embedded_io_async::ErrorKind as IoErrorKind;
+use embedded_io_async::Read;
+use embedded_io_async::ReadExactError;
+use embedded_io_async::Write;
+
+use esp_wifi::wifi::WifiDevice;
+use esp_wifi::wifi::WifiStaDevice;
+
+use heapless::String;
+
+use crate::discovery::write_config as write_discovery_config;
+use crate::discovery::write_topic as write_discovery_topic;
+use crate::discovery::Device;
+use crate::discovery::CHANNELS;
+use crate::domain::Reading;
+use crate::json::write_reading;
+
+/// Default TCP port of MQTT brokers
+pub const MQTT_PORT: u16 = 1883;
+
+/// Protocol level of MQTT 3.1.1
+const PROTOCOL_LEVEL: u8 = 4;
+
+/// Type of CONNECT packets
+const CONNECT: u8 = 0x10;
+
+/// Type of CONNACK packets
+const CONNACK: u8 = 0x20;
+
+/// Type of PUBLISH packets
+const PUBLISH: u8 = 0x30;
+
+/// Type of PUBACK packets
+const PUBACK: u8 = 0x40;
+
+/// Type of DISCONNECT packets
+const DISCONNECT: u8 = 0xe0;
+
+/// Largest remaining length representable in a fixed header
+const MAXIMUM_REMAINING_LENGTH: usize = 268_435_455;
+
+/// Size of the packet buffer
+const PACKET_SIZE: usize = 1024;
+
+/// Size of a reading serialized as JSON
+const PAYLOAD_SIZE: usize = 128;
+
+/// Size of a discovery topic
+const DISCOVERY_TOPIC_SIZE: usize = 128;
+
+/// Size of a discovery config payload
+const DISCOVERY_PAYLOAD_SIZE: usize = 640;
+
+/// Keep alive interval in seconds
+///
+/// The connection only lasts for a few publishes, so no pings are sent.
+const KEEP_ALIVE_SECONDS: u16 = 60;
+
+/// Timeout of socket operations
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Status published while connected
+const ONLINE: &[u8] = b"online";
+
+/// Status published before disconnecting, and as last will
+const OFFLINE: &[u8] = b"offline";
+
+/// Quality of service of a message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum QoS {
+    /// Fire and forget
+    AtMostOnce = 0,
+
+    /// Acknowledged by the broker
+    AtLeastOnce = 1,
+}
+
+/// A message published by the broker when the client disconnects unexpectedly
+#[derive(Clone, Copy, Debug)]
+pub struct Will<'a> {
+    /// Topic
+    pub topic: &'a str,
+
+    /// Payload
+    pub payload: &'a [u8],
+
+    /// Quality of service
+    pub qos: QoS,
+
+    /// Whether the broker retains the message
+    pub retain: bool,
+}
+
+/// Options for connecting to a broker
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectOptions<'a> {
+    /// Client identifier
+    pub client_id: &'a str,
+
+    /// Keep alive interval in seconds
+    pub keep_alive: u16,
+
+    /// User name
+    pub username: Option<&'a str>,
+
+    /// Password
+    pub password: Option<&'a str>,
+
+    /// Last will
+    pub will: Option<Will<'a>>,
+}
+
+/// Configuration of MQTT publishing
+#[derive(Clone, Copy, Debug)]
+pub struct Config<'a> {
+    /// Host name of the broker
+    pub broker: &'a str,
+
+    /// TCP port of the broker
+    pub port: u16,
+
+    /// Client identifier
+    pub client_id: &'a str,
+
+    /// User name
+    pub username: Option<&'a str>,
+
+    /// Password
+    pub password: Option<&'a str>,
+
+    /// Topic of readings
+    pub topic: &'a str,
+
+    /// Topic of the retained `online` or `offline` status
+    pub status_topic: &'a str,
+
+    /// Prefix of Home Assistant discovery topics, or `None` to disable
+    /// discovery
+    pub discovery_prefix: Option<&'a str>,
+}
+
+/// An MQTT client
+pub struct Client<T> {
+    /// Transport
+    transport: T,
+
+    /// Buffer for incoming and outgoing packets
+    buffer: [u8; PACKET_SIZE],
+
+    /// Identifier of the last packet sent with QoS 1
+    packet_id: u16,
+}
+
+impl<T> Client<T>
+where
+    T: Read + Write,
+{
+    /// Connect to a broker over a transport
+    pub async fn connect(transport: T, options: &ConnectOptions<'_>) -> Result<Self, Error> {
+        let mut client = Self {
+            transport,
+            buffer: [0; PACKET_SIZE],
+            packet_id: 0,
+        };
+
+        let length = encode_connect(&mut client.buffer, options)?;
+        client.send(length).await?;
+
+        let (header, body) = client.receive().await?;
+        match (header, body) {
+            (CONNACK, [_flags, 0]) => Ok(client),
+            (CONNACK, [_flags, code]) => Err(Error::ConnectionRefused(*code)),
+            (header, _) => Err(Error::UnexpectedPacket(header)),
+        }
+    }
+
+    /// Publish a message
+    ///
+    /// With [`QoS::AtLeastOnce`] this waits for the broker acknowledgement.
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), Error> {
+        let packet_id = match qos {
+            QoS::AtMostOnce => None,
+            QoS::AtLeastOnce => {
+                // Packet identifiers must be non-zero
+                self.packet_id = self.packet_id.checked_add(1).unwrap_or(1);
+                Some(self.packet_id)
+            }
+        };
+
+        let length = encode_publish(&mut self.buffer, topic, payload, retain, packet_id)?;
+        self.send(length).await?;
+
+        if let Some(packet_id) = packet_id {
+            loop {
+                let (header, body) = self.receive().await?;
+                match (header, body) {
+                    (PUBACK, [high, low]) if u16::from_be_bytes([*high, *low]) == packet_id => {
+                        break;
+                    }
+                    (header, _) => debug!("Ignore packet 0x{header:02x}"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Disconnect gracefully, discarding the last will
+    pub async fn disconnect(mut self) -> Result<(), Error> {
+        let length = encode_disconnect(&mut self.buffer)?;
+        self.send(length).await
+    }
+
+    /// Send the first bytes of the buffer
+    async fn send(&mut self, length: usize) -> Result<(), Error> {
+        let packet = self.buffer.get(..length).ok_or(Error::BufferTooSmall)?;
+        self.transport
+            .write_all(packet)
+            .await
+            .map_err(|error| Error::Transport(error.kind()))?;
+        self.transport
+            .flush()
+            .await
+            .map_err(|error| Error::Transport(error.kind()))
+    }
+
+    /// Receive a packet in the buffer
+    ///
+    /// Return the first byte of the fixed header and the packet body.
+    async fn receive(&mut self) -> Result<(u8, &[u8]), Error> {
+        let mut header = [0_u8; 1];
+        read_exact(&mut self.transport, &mut header).await?;
+        let [header] = header;
+
+        let mut length = 0_usize;
+        let mut multiplier = 1_usize;
+        loop {
+            let mut byte = [0_u8; 1];
+            read_exact(&mut self.transport, &mut byte).await?;
+            let [byte] = byte;
+            length += usize::from(byte & 0x7f) * multiplier;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            multiplier *= 128;
+            if multiplier > 128 * 128 * 128 {
+                return Err(Error::MalformedLength);
+            }
+        }
+
+        let body = self.buffer.get_mut(..length).ok_or(Error::PacketTooLarge)?;
+        read_exact(&mut self.transport, body).await?;
+
+        Ok((header, body))
+    }
+}
+
+/// Read exactly enough bytes to fill a buffer
+async fn read_exact(transport: &mut impl Read, buffer: &mut [u8]) -> Result<(), Error> {
+    transport
+        .read_exact(buffer)
+        .await
+        .map_err(|error| match error {
+            ReadExactError::UnexpectedEof => Error::UnexpectedEof,
+            ReadExactError::Other(error) => Error::Transport(error.kind()),
+        })
+}
+
+/// Publish readings to a broker
+///
+/// The status topic is set to `online` while connected, and to `offline`
+/// before disconnecting or by the last will if the connection is lost.
+/// If enabled, the discovery configs are published next, so that they follow
+/// the firmware version and boot count of the device.
+/// Readings are published in order with QoS 1, and `published` is called
+/// after each of them is acknowledged.
+pub async fn publish_readings(
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    config: &Config<'_>,
+    device: &Device<'_>,
+    readings: &[Reading],
+    mut published: impl FnMut(&Reading),
+) -> Result<(), Error> {
+    debug!("Resolve {}", config.broker);
+    let addresses = stack.dns_query(config.broker, DnsQueryType::A).await?;
+    let address = *addresses.first().ok_or(Error::NoAddress)?;
+
+    let mut rx_buffer = [0_u8; PACKET_SIZE];
+    let mut tx_buffer = [0_u8; PACKET_SIZE];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(SOCKET_TIMEOUT));
+
+    debug!("Connect to MQTT broker at {address}:{}", config.port);
+    socket
+        .connect(IpEndpoint::new(address, config.port))
+        .await?;
+
+    let options = ConnectOptions {
+        client_id: config.client_id,
+        keep_alive: KEEP_ALIVE_SECONDS,
+        username: config.username,
+        password: config.password,
+        will: Some(Will {
+            topic: config.status_topic,
+            payload: OFFLINE,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        }),
+    };
+    let mut client = Client::connect(&mut socket, &options).await?;
+    client
+        .publish(config.status_topic, ONLINE, QoS::AtLeastOnce, true)
+        .await?;
+
+    if let Some(prefix) = config.discovery_prefix {
+        for channel in &CHANNELS {
+            let mut topic = String::<DISCOVERY_TOPIC_SIZE>::new();
+            write_discovery_topic(&mut topic, prefix, device, channel)
+                .map_err(|_| Error::PayloadTooLarge)?;
+            let mut payload = String::<DISCOVERY_PAYLOAD_SIZE>::new();
+            write_discovery_config(
+                &mut payload,
+                device,
+                channel,
+                config.topic,
+                config.status_topic,
+            )
+            .map_err(|_| Error::PayloadTooLarge)?;
+            client
+                .publish(&topic, payload.as_bytes(), QoS::AtLeastOnce, true)
+                .await?;
+        }
+        debug!("Published discovery configs");
+    }
+
+    for reading in readings {
+        let mut payload = String::<PAYLOAD_SIZE>::new();
+        write_reading(&mut payload, reading).map_err(|_| Error::PayloadTooLarge)?;
+        client
+            .publish(config.topic, payload.as_bytes(), QoS::AtLeastOnce, false)
+            .await?;
+        published(reading);
+    }
+    info!("Published {} readings to {}", readings.len(), config.topic);
+
+    client
+        .publish(config.status_topic, OFFLINE, QoS::AtLeastOnce, true)
+        .await?;
+    client.disconnect().await?;
+    socket.close();
+
+    Ok(())
+}
+
+/// Encode a CONNECT packet
+///
+/// Return the length of the packet.
+pub fn encode_connect(buffer: &mut [u8], options: &ConnectOptions<'_>) -> Result<usize, Error> {
+    let mut flags = 0b0000_0010; // Clean session
+    let mut remaining_length = 10 + 2 + options.client_id.len();
+    if let Some(will) = options.will {
+        flags |= 0b0000_0100 | ((will.qos as u8) << 3);
+        if will.retain {
+            flags |= 0b0010_0000;
+        }
+        remaining_length += 2 + will.topic.len() + 2 + will.payload.len();
+    }
+    if let Some(username) = options.username {
+        flags |= 0b1000_0000;
+        remaining_length += 2 + username.len();
+    }
+    if let Some(password) = options.password {
+        flags |= 0b0100_0000;
+        remaining_length += 2 + password.len();
+    }
+
+    let mut writer = Writer::new(buffer);
+    writer.fixed_header(CONNECT, remaining_length)?;
+    writer.bytes_with_length(b"MQTT")?;
+    writer.bytes(&[PROTOCOL_LEVEL, flags])?;
+    writer.bytes(&options.keep_alive.to_be_bytes())?;
+    writer.bytes_with_length(options.client_id.as_bytes())?;
+    if let Some(will) = options.will {
+        writer.bytes_with_length(will.topic.as_bytes())?;
+        writer.bytes_with_length(will.payload)?;
+    }
+    if let Some(username) = options.username {
+        writer.bytes_with_length(username.as_bytes())?;
+    }
+    if let Some(password) = options.password {
+        writer.bytes_with_length(password.as_bytes())?;
+    }
+    Ok(writer.position)
+}
+
+/// Encode a PUBLISH packet
+///
+/// The packet identifier must be given for QoS 1, and omitted for QoS 0.
+/// Return the length of the packet.
+pub fn encode_publish(
+    buffer: &mut [u8],
+    topic: &str,
+    payload: &[u8],
+    retain: bool,
+    packet_id: Option<u16>,
+) -> Result<usize, Error> {
+    let mut header = PUBLISH;
+    if packet_id.is_some() {
+        header |= (QoS::AtLeastOnce as u8) << 1;
+    }
+    if retain {
+        header |= 0b0000_0001;
+    }
+    let remaining_length = 2 + topic.len() + packet_id.map_or(0, |_| 2) + payload.len();
+
+    let mut writer = Writer::new(buffer);
+    writer.fixed_header(header, remaining_length)?;
+    writer.bytes_with_length(topic.as_bytes())?;
+    if let Some(packet_id) = packet_id {
+        writer.bytes(&packet_id.to_be_bytes())?;
+    }
+    writer.bytes(payload)?;
+    Ok(writer.position)
+}
+
+/// Encode a DISCONNECT packet
+///
+/// Return the length of the packet.
+pub fn encode_disconnect(buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut writer = Writer::new(buffer);
+    writer.fixed_header(DISCONNECT, 0)?;
+    Ok(writer.position)
+}
+
+/// A writer of packets into a buffer
+struct Writer<'a> {
+    /// Buffer
+    buffer: &'a mut [u8],
+
+    /// Position of the next byte
+    position: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Create a writer at the start of a buffer
+    fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            position: 0,
+        }
+    }
+
+    /// Write a fixed header with the packet type and flags and the remaining
+    /// length
+    fn fixed_header(&mut self, header: u8, remaining_length: usize) -> Result<(), Error> {
+        if remaining_length > MAXIMUM_REMAINING_LENGTH {
+            return Err(Error::PacketTooLarge);
+        }
+        self.bytes(&[header])?;
+
+        let mut remaining_length = remaining_length;
+        loop {
+            #[allow(clippy::cast_possible_truncation)]
+            let mut byte = (remaining_length % 128) as u8;
+            remaining_length /= 128;
+            if remaining_length > 0 {
+                byte |= 0x80;
+            }
+            self.bytes(&[byte])?;
+            if remaining_length == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Write bytes prefixed by their length
+    fn bytes_with_length(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let length = u16::try_from(bytes.len()).map_err(|_| Error::PacketTooLarge)?;
+        self.bytes(&length.to_be_bytes())?;
+        self.bytes(bytes)
+    }
+
+    /// Write bytes
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.position + bytes.len();
+        self.buffer
+            .get_mut(self.position..end)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.position = end;
+        Ok(())
+    }
+}
+
+/// An error within MQTT publishing
+#[derive(Debug)]
+pub enum Error {
+    /// A packet does not fit in the buffer
+    BufferTooSmall,
+
+    /// A packet is too large
+    PacketTooLarge,
+
+    /// A reading does not fit in a payload
+    PayloadTooLarge,
+
+    /// The remaining length of a received packet is malformed
+    MalformedLength,
+
+    /// The broker sent an unexpected packet
+    UnexpectedPacket(#[allow(unused)] u8),
+
+    /// The broker refused the connection with a return code
+    ConnectionRefused(#[allow(unused)] u8),
+
+    /// The connection was closed
+    UnexpectedEof,
+
+    /// The broker name did not resolve to any address
+    NoAddress,
+
+    /// Error from the transport
+    Transport(#[allow(unused)] IoErrorKind),
+
+    /// Error within TCP connection
+    TcpConnect(#[allow(unused)] TcpConnectError),
+
+    /// Error within DNS system
+    Dns(#[allow(unused)] DnsError),
+}
+
+impl From<TcpConnectError> for Error {
+    fn from(error: TcpConnectError) -> Self {
+        Self::TcpConnect(error)
+    }
+}
+
+impl From<DnsError> for Error {
+    fn from(error: DnsError) -> Self {
+        Self::Dns(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+
+    use embedded_io_async::ErrorType;
+
+    use heapless::Vec;
+
+    /// An in-memory broker that replays scripted packets and records the
+    /// packets sent by the client
+    struct FakeBroker {
+        /// Packets left to send to the client
+        responses: &'static [u8],
+
+        /// Packets received from the client
+        received: Vec<u8, 256>,
+    }
+
+    impl FakeBroker {
+        /// Create a broker that sends scripted packets
+        fn new(responses: &'static [u8]) -> Self {
+            Self {
+                responses,
+                received: Vec::new(),
+            }
+        }
+    }
+
+    impl ErrorType for FakeBroker {
+        type Error = Infallible;
+    }
+
+    impl Read for FakeBroker {
+        async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+            let length = buffer.len().min(self.responses.len());
+            let (head, tail) = self.responses.split_at(length);
+            buffer.get_mut(..length).unwrap().copy_from_slice(head);
+            self.responses = tail;
+            Ok(length)
+        }
+    }
+
+    impl Write for FakeBroker {
+        async fn write(&mut self, bytes: &[u8]) -> Result<usize, Infallible> {
+            self.received.extend_from_slice(bytes).unwrap();
+            Ok(bytes.len())
+        }
+    }
+
+    /// Options with a client identifier only
+    const MINIMAL_OPTIONS: ConnectOptions<'static> = ConnectOptions {
+        client_id: "c",
+        keep_alive: 60,
+        username: None,
+        password: None,
+        will: None,
+    };
+
+    /// Encode a remaining length in a fixed header
+    fn remaining_length(length: usize) -> Result<Vec<u8, 5>, Error> {
+        let mut buffer = [0_u8; 5];
+        let mut writer = Writer::new(&mut buffer);
+        writer.fixed_header(0, length)?;
+        let end = writer.position;
+        Ok(Vec::from_slice(buffer.get(1..end).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn remaining_length_boundaries() {
+        let cases: [(usize, &[u8]); 8] = [
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xff, 0x7f]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (2_097_151, &[0xff, 0xff, 0x7f]),
+            (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+            (MAXIMUM_REMAINING_LENGTH, &[0xff, 0xff, 0xff, 0x7f]),
+        ];
+        for (length, expected) in cases {
+            assert_eq!(remaining_length(length).unwrap(), expected, "{length}");
+        }
+        assert!(matches!(
+            remaining_length(MAXIMUM_REMAINING_LENGTH + 1),
+            Err(Error::PacketTooLarge)
+        ));
+    }
+
+    #[test]
+    fn publish_at_remaining_length_boundaries() {
+        let mut buffer = [0_u8; 16_400];
+        let payload = [b'x'; 16_381];
+        // The topic "t" takes 3 bytes of the remaining length
+        let cases: [(usize, &[u8]); 4] = [
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xff, 0x7f]),
+            (16_384, &[0x80, 0x80, 0x01]),
+        ];
+        for (remaining_length, encoded_length) in cases {
+            let payload = payload.get(..remaining_length - 3).unwrap();
+            let length = encode_publish(&mut buffer, "t", payload, false, None).unwrap();
+            let header_length = 1 + encoded_length.len();
+            assert_eq!(length, header_length + remaining_length);
+
+            let (header, body) = buffer.get(..length).unwrap().split_at(header_length);
+            assert_eq!(header.first(), Some(&PUBLISH));
+            assert_eq!(header.get(1..).unwrap(), encoded_length);
+            assert_eq!(body.get(..3).unwrap(), [0, 1, b't']);
+            assert_eq!(body.get(3..).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn connect_minimal() {
+        let mut buffer = [0_u8; 64];
+        let length = encode_connect(&mut buffer, &MINIMAL_OPTIONS).unwrap();
+        assert_eq!(
+            buffer.get(..length).unwrap(),
+            [CONNECT, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 60, 0, 1, b'c']
+        );
+    }
+
+    #[test]
+    fn connect_with_credentials_and_will() {
+        let options = ConnectOptions {
+            client_id: "station",
+            keep_alive: 60,
+            username: Some("user"),
+            password: Some("pass"),
+            will: Some(Will {
+                topic: "s",
+                payload: b"offline",
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+        };
+        let mut buffer = [0_u8; 64];
+        let length = encode_connect(&mut buffer, &options).unwrap();
+        let expected: &[u8] = &[
+            CONNECT, 43, // Fixed header
+            0, 4, b'M', b'Q', b'T', b'T', // Protocol name
+            4,    // Protocol level
+            0xee, // User name, password, will retain, will QoS 1, will, clean session
+            0, 60, // Keep alive
+            0, 7, b's', b't', b'a', b't', b'i', b'o', b'n', // Client identifier
+            0, 1, b's', // Will topic
+            0, 7, b'o', b'f', b'f', b'l', b'i', b'n', b'e', // Will payload
+            0, 4, b'u', b's', b'e', b'r', // User name
+            0, 4, b'p', b'a', b's', b's', // Password
+        ];
+        assert_eq!(buffer.get(..length).unwrap(), expected);
+    }
+
+    #[test]
+    fn connect_buffer_too_small() {
+        let mut buffer = [0_u8; 8];
+        assert!(matches!(
+            encode_connect(&mut buffer, &MINIMAL_OPTIONS),
+            Err(Error::BufferTooSmall)
+        ));
+    }
+
+    #[test]
+    fn publish_qos_and_retain() {
+        let mut buffer = [0_u8; 32];
+        let length = encode_publish(&mut buffer, "a/b", b"hi", true, Some(0x1234)).unwrap();
+        assert_eq!(
+            buffer.get(..length).unwrap(),
+            [0x33, 9, 0, 3, b'a', b'/', b'b', 0x12, 0x34, b'h', b'i']
+        );
+
+        let length = encode_publish(&mut buffer, "a/b", b"hi", false, None).unwrap();
+        assert_eq!(
+            buffer.get(..length).unwrap(),
+            [0x30, 7, 0, 3, b'a', b'/', b'b', b'h', b'i']
+        );
+    }
+
+    #[test]
+    fn disconnect() {
+        let mut buffer = [0_u8; 2];
+        let length = encode_disconnect(&mut buffer).unwrap();
+        assert_eq!(buffer.get(..length).unwrap(), [DISCONNECT, 0]);
+    }
+
+    #[test]
+    fn client_connects_publishes_and_disconnects() {
+        const RESPONSES: &[u8] = &[
+            CONNACK, 2, 0, 0, // Accepted
+            PUBACK, 2, 0, 1, // Acknowledge packet 1
+        ];
+        let mut broker = FakeBroker::new(RESPONSES);
+        block_on(async {
+            let mut client = Client::connect(&mut broker, &MINIMAL_OPTIONS)
+                .await
+                .unwrap();
+            client
+                .publish("t", b"1", QoS::AtMostOnce, false)
+                .await
+                .unwrap();
+            client
+                .publish("t", b"2", QoS::AtLeastOnce, true)
+                .await
+                .unwrap();
+            client.disconnect().await.unwrap();
+        });
+
+        let mut expected = [0_u8; 64];
+        let mut length = encode_connect(&mut expected, &MINIMAL_OPTIONS).unwrap();
+        length +=
+            encode_publish(expected.get_mut(length..).unwrap(), "t", b"1", false, None).unwrap();
+        length += encode_publish(
+            expected.get_mut(length..).unwrap(),
+            "t",
+            b"2",
+            true,
+            Some(1),
+        )
+        .unwrap();
+        length += encode_disconnect(expected.get_mut(length..).unwrap()).unwrap();
+        assert_eq!(broker.received, expected.get(..length).unwrap());
+        assert!(broker.responses.is_empty());
+    }
+
+    #[test]
+    fn client_connection_refused() {
+        // Not authorized
+        const RESPONSES: &[u8] = &[CONNACK, 2, 0, 5];
+        let mut broker = FakeBroker::new(RESPONSES);
+        let result = block_on(Client::connect(&mut broker, &MINIMAL_OPTIONS));
+        assert!(matches!(result, Err(Error::ConnectionRefused(5))));
+    }
+
+    #[test]
+    fn client_unexpected_packet_instead_of_connack() {
+        const RESPONSES: &[u8] = &[PUBACK, 2, 0, 1];
+        let mut broker = FakeBroker::new(RESPONSES);
+        let result = block_on(Client::connect(&mut broker, &MINIMAL_OPTIONS));
+        assert!(matches!(result, Err(Error::UnexpectedPacket(PUBACK))));
+    }
+
+    #[test]
+    fn client_connection_closed_before_connack() {
+        // Fixed header without body
+        const RESPONSES: &[u8] = &[CONNACK, 2];
+        let mut broker = FakeBroker::new(RESPONSES);
+        let result = block_on(Client::connect(&mut broker, &MINIMAL_OPTIONS));
+        assert!(matches!(result, Err(Error::UnexpectedEof)));
+    }
+
+    #[test]
+    fn client_ignores_puback_with_other_id() {
+        const RESPONSES: &[u8] = &[
+            CONNACK, 2, 0, 0, // Accepted
+            PUBACK, 2, 0, 7, // Stale acknowledgement
+            PUBACK, 2, 0, 1, // Acknowledge packet 1
+        ];
+        let mut broker = FakeBroker::new(RESPONSES);
+        block_on(async {
+            let mut client = Client::connect(&mut broker, &MINIMAL_OPTIONS)
+                .await
+                .unwrap();
+            client
+                .publish("t", b"1", QoS::AtLeastOnce, false)
+                .await
+                .unwrap();
+        });
+        assert!(broker.responses.is_empty());
+    }
+
+    #[test]
+    fn client_fails_without_matching_puback() {
+        const RESPONSES: &[u8] = &[
+            CONNACK, 2, 0, 0, // Accepted
+            PUBACK, 2, 0, 7, // Stale acknowledgement
+        ];
+        let mut broker = FakeBroker::new(RESPONSES);
+        let result = block_on(async {
+            let mut client = Client::connect(&mut broker, &MINIMAL_OPTIONS)
+                .await
+                .unwrap();
+            client.publish("t", b"1", QoS::AtLeastOnce, false).await
+        });
+        assert!(matches!(result, Err(Error::UnexpectedEof)));
+    }
+
+    #[test]
+    fn client_packet_id_skips_zero() {
+        const RESPONSES: &[u8] = &[
+            CONNACK, 2, 0, 0, // Accepted
+            PUBACK, 2, 0, 1, // Acknowledge packet 1
+        ];
+        let mut broker = FakeBroker::new(RESPONSES);
+        block_on(async {
+            let mut client = Client::connect(&mut broker, &MINIMAL_OPTIONS)
+                .await
+                .unwrap();
+            client.packet_id = u16::MAX;
+            client
+                .publish("t", b"1", QoS::AtLeastOnce, false)
+                .await
+                .unwrap();
+        });
+
+        let mut expected = [0_u8; 16];
+        let length = encode_publish(&mut expected, "t", b"1", false, Some(1)).unwrap();
+        assert!(broker.received.ends_with(expected.get(..length).unwrap()));
+    }
+
+    #[test]
+    fn client_malformed_length() {
+        const RESPONSES: &[u8] = &[CONNACK, 0x80, 0x80, 0x80, 0x80, 0x01];
+        let mut broker = FakeBroker::new(RESPONSES);
+        let result = block_on(Client::connect(&mut broker, &MINIMAL_OPTIONS));
+        assert!(matches!(result, Err(Error::MalformedLength)));
+    }
+
+    #[test]
+    fn client_packet_too_large() {
+        // Remaining length 16383
+        const RESPONSES: &[u8] = &[CONNACK, 0xff, 0x7f];
+        let mut broker = FakeBroker::new(RESPONSES);
+        let result = block_on(Client::connect(&mut broker, &MINIMAL_OPTIONS));
+        assert!(matches!(result, Err(Error::PacketTooLarge)));
+    }
+
+    #[test]
+    fn discovery_fits_in_buffers() {
+        let device = Device {
+            id: "crussant_a1b2c3",
+            name: "Crussant",
+            model: "ESP32-C3",
+            firmware_version: "0.1.0-dirty",
+            boot_count: u32::MAX,
+        };
+        for channel in &CHANNELS {
+            let mut topic = String::<DISCOVERY_TOPIC_SIZE>::new();
+            write_discovery_topic(&mut topic, "homeassistant", &device, channel).unwrap();
+            let mut payload = String::<DISCOVERY_PAYLOAD_SIZE>::new();
+            write_discovery_config(
+                &mut payload,
+                &device,
+                channel,
+                "crussant/readings",
+                "crussant/status",
+            )
+            .unwrap();
+        }
+    }
+}
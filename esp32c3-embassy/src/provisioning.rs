@@ -0,0 +1,412 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Provisioning of WiFi credentials
+//!
+//! Networks can be entered either through the [captive
+//! portal][crate::captive_portal] or with commands on the serial console:
+//!
+//! ```text
+//! wifi list
+//! wifi add <ssid> [<password>]
+//! wifi remove <ssid>
+//! wifi clear
+//! reboot
+//! ```
+//!
+//! SSIDs and passwords containing spaces must be enclosed in double quotes.
+//! Changes are saved to flash immediately.
+//! Provisioning ends when a network is submitted through the portal, or with
+//! the `reboot` command.
+
+use log::info;
+use log::warn;
+
+use embassy_executor::Spawner;
+
+use embassy_futures::select::select;
+use embassy_futures::select::Either;
+
+use embedded_io_async::Read;
+use embedded_io_async::Write;
+
+use embedded_storage::nor_flash::NorFlash;
+
+use esp_hal::clock::Clocks;
+
+use heapless::String;
+
+use crate::captive_portal::dhcp_task;
+use crate::captive_portal::dns_task;
+use crate::captive_portal::http_task;
+use crate::captive_portal::ACCESS_POINT_SSID;
+use crate::captive_portal::SUBMITTED;
+use crate::console::split_arguments;
+use crate::console::Console;
+use crate::console::Error as ConsoleError;
+use crate::console::LINE_SIZE;
+use crate::credentials::Credentials;
+use crate::credentials::Error as CredentialsError;
+use crate::credentials::Network;
+use crate::wifi::Wifi;
+
+/// Help text of the commands
+const HELP: &str = concat!(
+    "wifi list                       List stored networks\r\n",
+    "wifi add <ssid> [<password>]    Store a network\r\n",
+    "wifi remove <ssid>              Remove a stored network\r\n",
+    "wifi clear                      Remove all stored networks\r\n",
+    "reboot                          Restart the station",
+);
+
+/// A provisioning command
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// Show the help text
+    Help,
+
+    /// List stored networks
+    List,
+
+    /// Store a network
+    Add(Network),
+
+    /// Remove a stored network
+    Remove(&'a str),
+
+    /// Remove all stored networks
+    Clear,
+
+    /// Restart the station
+    Reboot,
+}
+
+/// Parse a command from its arguments
+///
+/// Return `None` for an empty line.
+pub fn parse_command<'a>(arguments: &[&'a str]) -> Result<Option<Command<'a>>, Error> {
+    let command = match arguments {
+        [] => return Ok(None),
+        ["help"] => Command::Help,
+        ["wifi", "list"] => Command::List,
+        ["wifi", "add", ssid] => Command::Add(Network::new(ssid, "")?),
+        ["wifi", "add", ssid, password] => Command::Add(Network::new(ssid, password)?),
+        ["wifi", "remove", ssid] => Command::Remove(ssid),
+        ["wifi", "clear"] => Command::Clear,
+        ["reboot"] => Command::Reboot,
+        ["wifi", ..] => return Err(Error::Usage),
+        _ => return Err(Error::UnknownCommand),
+    };
+    Ok(Some(command))
+}
+
+/// Run provisioning until the station should restart
+///
+/// The captive portal is started on an access point, and commands are read
+/// from the console at the same time.
+/// If the access point cannot be started, only the console is available.
+pub async fn run<R, W>(
+    spawner: &Spawner,
+    wifi: &mut Wifi,
+    clocks: &Clocks<'_>,
+    console: &mut Console<R, W>,
+    flash: &mut impl NorFlash,
+    credentials: &mut Credentials,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    match wifi
+        .start_access_point(spawner, clocks, ACCESS_POINT_SSID)
+        .await
+    {
+        Ok(stack) => {
+            spawner.must_spawn(dhcp_task(stack));
+            spawner.must_spawn(dns_task(stack));
+            spawner.must_spawn(http_task(stack));
+            console
+                .write_line(format_args!(
+                    "Join WiFi network {ACCESS_POINT_SSID} to enter credentials, or type help"
+                ))
+                .await?;
+        }
+        Err(error) => {
+            warn!("Cannot start access point: {error:?}");
+            console
+                .write_line(format_args!("Type help to list commands"))
+                .await?;
+        }
+    }
+
+    loop {
+        let line = match select(SUBMITTED.wait(), console.read_line()).await {
+            Either::First(network) => {
+                credentials.add(network);
+                credentials.save(flash)?;
+                return Ok(());
+            }
+            Either::Second(line) => String::<LINE_SIZE>::try_from(line?)
+                .map_err(|()| Error::Console(ConsoleError::LineTooLong))?,
+        };
+
+        match handle_line(&line, console, flash, credentials).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(Error::Console(ConsoleError::Io)) => return Err(ConsoleError::Io.into()),
+            Err(error) => {
+                console.write_str(error.message()).await?;
+                console.write_str("\r\n").await?;
+            }
+        }
+    }
+}
+
+/// Parse and execute a line, returning whether the station should restart
+async fn handle_line<R, W>(
+    line: &str,
+    console: &mut Console<R, W>,
+    flash: &mut impl NorFlash,
+    credentials: &mut Credentials,
+) -> Result<bool, Error>
+where
+    R: Read,
+    W: Write,
+{
+    let arguments = split_arguments(line)?;
+    match parse_command(&arguments)? {
+        Some(command) => execute(&command, console, flash, credentials).await,
+        None => Ok(false),
+    }
+}
+
+/// Execute a command, returning whether the station should restart
+async fn execute<R, W>(
+    command: &Command<'_>,
+    console: &mut Console<R, W>,
+    flash: &mut impl NorFlash,
+    credentials: &mut Credentials,
+) -> Result<bool, Error>
+where
+    R: Read,
+    W: Write,
+{
+    match command {
+        Command::Help => {
+            console.write_str(HELP).await?;
+            console.write_str("\r\n").await?;
+        }
+        Command::List => {
+            if credentials.is_empty() {
+                console
+                    .write_line(format_args!("No stored networks"))
+                    .await?;
+            }
+            for network in credentials.networks() {
+                console.write_line(format_args!("{}", network.ssid)).await?;
+            }
+        }
+        Command::Add(network) => {
+            credentials.add(network.clone());
+            credentials.save(flash)?;
+            console
+                .write_line(format_args!("Stored network {}", network.ssid))
+                .await?;
+        }
+        Command::Remove(ssid) => {
+            if credentials.remove(ssid) {
+                credentials.save(flash)?;
+                console
+                    .write_line(format_args!("Removed network {ssid}"))
+                    .await?;
+            } else {
+                console
+                    .write_line(format_args!("Network {ssid} is not stored"))
+                    .await?;
+            }
+        }
+        Command::Clear => {
+            credentials.clear();
+            credentials.save(flash)?;
+            console
+                .write_line(format_args!("Removed all networks"))
+                .await?;
+        }
+        Command::Reboot => {
+            info!("Reboot requested from console");
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// An error within provisioning
+#[derive(Debug)]
+pub enum Error {
+    /// The command is not known
+    UnknownCommand,
+
+    /// The command has wrong arguments
+    Usage,
+
+    /// Error from the console
+    Console(#[allow(unused)] ConsoleError),
+
+    /// Error from credentials storage
+    Credentials(#[allow(unused)] CredentialsError),
+}
+
+impl Error {
+    /// Return a message for the console
+    fn message(&self) -> &'static str {
+        match self {
+            Self::UnknownCommand => "Unknown command, type help to list commands",
+            Self::Usage => HELP,
+            Self::Console(ConsoleError::UnterminatedQuote) => "Unterminated quote",
+            Self::Console(ConsoleError::TooManyArguments) => "Too many arguments",
+            Self::Console(_) => "Console error",
+            Self::Credentials(CredentialsError::InvalidSsid) => "SSID must be 1 to 32 bytes",
+            Self::Credentials(CredentialsError::InvalidPassword) => {
+                "Password must be at most 64 bytes"
+            }
+            Self::Credentials(_) => "Cannot store credentials",
+        }
+    }
+}
+
+impl From<ConsoleError> for Error {
+    fn from(error: ConsoleError) -> Self {
+        Self::Console(error)
+    }
+}
+
+impl From<CredentialsError> for Error {
+    fn from(error: CredentialsError) -> Self {
+        Self::Credentials(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    /// Split and parse a line
+    fn parse(line: &str) -> Result<Option<Command<'_>>, Error> {
+        parse_command(&split_arguments(line)?)
+    }
+
+    /// Create a network
+    fn network(ssid: &str, password: &str) -> Network {
+        Network::new(ssid, password).unwrap()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert!(matches!(parse(""), Ok(None)));
+        assert!(matches!(parse("  \t "), Ok(None)));
+        assert!(matches!(parse("help"), Ok(Some(Command::Help))));
+        assert!(matches!(parse(" wifi   list "), Ok(Some(Command::List))));
+        assert!(matches!(parse("wifi clear"), Ok(Some(Command::Clear))));
+        assert!(matches!(parse("reboot"), Ok(Some(Command::Reboot))));
+        assert!(matches!(
+            parse("wifi remove Home"),
+            Ok(Some(Command::Remove("Home")))
+        ));
+    }
+
+    #[test]
+    fn parse_add_with_and_without_password() {
+        assert_eq!(
+            parse("wifi add Home secret").unwrap(),
+            Some(Command::Add(network("Home", "secret")))
+        );
+        assert_eq!(
+            parse("wifi add Café").unwrap(),
+            Some(Command::Add(network("Café", "")))
+        );
+        assert_eq!(
+            parse(r#"wifi add "My Home" "correct horse battery staple""#).unwrap(),
+            Some(Command::Add(network(
+                "My Home",
+                "correct horse battery staple"
+            )))
+        );
+        assert_eq!(
+            parse(r#"wifi add "Open Net" """#).unwrap(),
+            Some(Command::Add(network("Open Net", "")))
+        );
+        assert_eq!(
+            parse(r#"wifi remove "My Home""#).unwrap(),
+            Some(Command::Remove("My Home"))
+        );
+    }
+
+    #[test]
+    fn parse_add_length_limits() {
+        let ssid = "s".repeat(32);
+        let password = "p".repeat(64);
+        assert_eq!(
+            parse(&std::format!("wifi add {ssid} {password}")).unwrap(),
+            Some(Command::Add(network(&ssid, &password)))
+        );
+
+        assert!(matches!(
+            parse(&std::format!("wifi add {ssid}s {password}")),
+            Err(Error::Credentials(CredentialsError::InvalidSsid))
+        ));
+        assert!(matches!(
+            parse(&std::format!("wifi add {} x", "é".repeat(17))),
+            Err(Error::Credentials(CredentialsError::InvalidSsid))
+        ));
+        assert!(matches!(
+            parse(&std::format!("wifi add {ssid} {password}p")),
+            Err(Error::Credentials(CredentialsError::InvalidPassword))
+        ));
+        assert!(matches!(
+            parse(r#"wifi add "" secret"#),
+            Err(Error::Credentials(CredentialsError::InvalidSsid))
+        ));
+    }
+
+    #[test]
+    fn parse_invalid_lines() {
+        for line in [
+            "wifi",
+            "wifi add",
+            "wifi add a b c",
+            "wifi remove",
+            "wifi list all",
+        ] {
+            assert!(matches!(parse(line), Err(Error::Usage)), "{line}");
+        }
+        for line in ["reboot now", "WIFI list", "list", "status"] {
+            assert!(matches!(parse(line), Err(Error::UnknownCommand)), "{line}");
+        }
+        assert!(matches!(
+            parse(r#"wifi add "My Home secret"#),
+            Err(Error::Console(ConsoleError::UnterminatedQuote))
+        ));
+        assert!(matches!(
+            parse(r#"wifi add "My"Home secret"#),
+            Err(Error::Console(ConsoleError::UnterminatedQuote))
+        ));
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(
+            Error::Credentials(CredentialsError::InvalidSsid).message(),
+            "SSID must be 1 to 32 bytes"
+        );
+        assert_eq!(
+            Error::Credentials(CredentialsError::InvalidPassword).message(),
+            "Password must be at most 64 bytes"
+        );
+        assert_eq!(Error::Usage.message(), HELP);
+    }
+}
@@ -0,0 +1,797 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Append-only log of readings in flash
+//!
+//! The log spans a range of flash pages used as a ring.
+//! Readings are appended in chronological order to the active page, and when
+//! it is full the next page is erased and opened, dropping its readings,
+//! which are the oldest ones.
+//!
+//! A page starts with a header made of a magic number, a sequence number
+//! incremented on every page opening and wrapping around, the format version
+//! and a CRC of the three.
+//! It is followed by records of [`RECORD_SIZE`] bytes:
+//!
+//! | Offset | Size | Content                                 |
+//! |--------|------|-----------------------------------------|
+//! | 0      | 4    | Time as Unix epoch                      |
+//! | 4      | 2    | Temperature in centi-degrees Celsius    |
+//! | 6      | 2    | Relative humidity in centi-percent      |
+//! | 8      | 2    | Pressure in deci-hectopascal            |
+//! | 10     | 2    | Reserved, zero                          |
+//! | 12     | 4    | CRC of the previous bytes               |
+//!
+//! Integers are little endian, and the CRC is CRC-32/ISO-HDLC.
+//!
+//! A record interrupted by power loss has a wrong CRC, and is skipped when
+//! reading and when appending.
+//! A page whose header was interrupted has a wrong CRC, and is erased again
+//! before being opened.
+
+use core::ops::ControlFlow;
+use core::ops::Range;
+
+use log::debug;
+use log::info;
+use log::warn;
+
+use crc::Crc;
+use crc::CRC_32_ISO_HDLC;
+
+use embedded_storage::nor_flash::NorFlash;
+use embedded_storage::nor_flash::NorFlashError as _;
+use embedded_storage::nor_flash::NorFlashErrorKind;
+
+use time::OffsetDateTime;
+
+use crate::domain::Reading;
+use crate::history::compact_sample;
+use crate::history::expand_sample;
+use crate::history::CompactSample;
+
+/// Range of the `history` partition in flash
+pub const FLASH_RANGE: Range<u32> = 0x3c_0000..0x40_0000;
+
+/// Size of a page, which must be a multiple of the flash erase size
+pub const PAGE_SIZE: u32 = 4096;
+
+/// Size of a record, and of a page header
+pub const RECORD_SIZE: u32 = 16;
+
+/// Number of records in a page
+pub const RECORDS_PER_PAGE: u32 = PAGE_SIZE / RECORD_SIZE - 1;
+
+/// Version of the record format
+const FORMAT: u32 = 1;
+
+/// Magic number marking a page, `RLOG` in ASCII
+const MAGIC: u32 = 0x524c_4f47;
+
+/// Number of records read from flash at once
+const BATCH_SIZE: usize = 16;
+
+/// Checksum algorithm
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// A record in flash
+type RecordBytes = [u8; RECORD_SIZE as usize];
+
+/// A reading with time as Unix epoch and sample in fixed-point
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Time as Unix epoch
+    pub time: u32,
+
+    /// Sample
+    pub sample: CompactSample,
+}
+
+impl Record {
+    /// Convert a reading to a record
+    ///
+    /// Return `None` if the time does not fit a `u32` Unix epoch.
+    pub fn from_reading((time, sample): &Reading) -> Option<Self> {
+        Some(Self {
+            time: u32::try_from(time.unix_timestamp()).ok()?,
+            sample: compact_sample(sample),
+        })
+    }
+
+    /// Convert to a reading in UTC
+    pub fn to_reading(self) -> Reading {
+        // A `u32` Unix epoch is always in the valid range
+        let time = OffsetDateTime::from_unix_timestamp(i64::from(self.time))
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        (time, expand_sample(self.sample))
+    }
+
+    /// Encode to bytes
+    pub fn encode(&self) -> RecordBytes {
+        let [time_0, time_1, time_2, time_3] = self.time.to_le_bytes();
+        let [temperature, humidity, pressure] = self.sample;
+        let [temperature_0, temperature_1] = temperature.to_le_bytes();
+        let [humidity_0, humidity_1] = humidity.to_le_bytes();
+        let [pressure_0, pressure_1] = pressure.to_le_bytes();
+        let payload = [
+            time_0,
+            time_1,
+            time_2,
+            time_3,
+            temperature_0,
+            temperature_1,
+            humidity_0,
+            humidity_1,
+            pressure_0,
+            pressure_1,
+            0,
+            0,
+        ];
+        let [checksum_0, checksum_1, checksum_2, checksum_3] = CRC.checksum(&payload).to_le_bytes();
+
+        let mut bytes = [0_u8; RECORD_SIZE as usize];
+        let (head, tail) = bytes.split_at_mut(payload.len());
+        head.copy_from_slice(&payload);
+        tail.copy_from_slice(&[checksum_0, checksum_1, checksum_2, checksum_3]);
+        bytes
+    }
+
+    /// Decode from bytes
+    ///
+    /// Return `None` if the CRC is wrong.
+    pub fn decode(bytes: &RecordBytes) -> Option<Self> {
+        let [time_0, time_1, time_2, time_3, temperature_0, temperature_1, humidity_0, humidity_1, pressure_0, pressure_1, _, _, checksum_0, checksum_1, checksum_2, checksum_3] =
+            *bytes;
+        let checksum = u32::from_le_bytes([checksum_0, checksum_1, checksum_2, checksum_3]);
+        if checksum != CRC.checksum(bytes.get(..12)?) {
+            return None;
+        }
+
+        Some(Self {
+            time: u32::from_le_bytes([time_0, time_1, time_2, time_3]),
+            sample: [
+                i16::from_le_bytes([temperature_0, temperature_1]),
+                i16::from_le_bytes([humidity_0, humidity_1]),
+                i16::from_le_bytes([pressure_0, pressure_1]),
+            ],
+        })
+    }
+}
+
+/// A log of readings in flash
+pub struct ReadingLog<F> {
+    /// Flash
+    flash: F,
+
+    /// Offset of the first page
+    start: u32,
+
+    /// Number of pages
+    page_count: u32,
+
+    /// Index of the active page
+    active: u32,
+
+    /// Sequence number of the active page
+    sequence: u32,
+
+    /// Slot of the next record within the active page, starting from 1
+    slot: u32,
+
+    /// Time of the most recent record
+    latest: Option<u32>,
+}
+
+impl<F> ReadingLog<F>
+where
+    F: NorFlash,
+{
+    /// Mount a log on a range of flash
+    ///
+    /// The range must be aligned to [`PAGE_SIZE`] and contain at least two
+    /// pages.
+    /// An empty range is formatted.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn mount(flash: F, range: Range<u32>) -> Result<Self, Error> {
+        let length = range.end.saturating_sub(range.start);
+        let page_count = length / PAGE_SIZE;
+        if !range.start.is_multiple_of(PAGE_SIZE)
+            || !length.is_multiple_of(PAGE_SIZE)
+            || !PAGE_SIZE.is_multiple_of(F::ERASE_SIZE as u32)
+            || !RECORD_SIZE.is_multiple_of(F::WRITE_SIZE as u32)
+            || page_count < 2
+        {
+            return Err(Error::InvalidRange);
+        }
+
+        let mut log = Self {
+            flash,
+            start: range.start,
+            page_count,
+            active: 0,
+            sequence: 0,
+            slot: RECORDS_PER_PAGE + 1,
+            latest: None,
+        };
+
+        let mut newest = None;
+        for page in 0..page_count {
+            if let Some(sequence) = log.page_sequence(page)? {
+                if newest.is_none_or(|(other, _)| is_newer(sequence, other)) {
+                    newest = Some((sequence, page));
+                }
+            }
+        }
+        let Some((sequence, active)) = newest else {
+            info!("Format reading log");
+            log.open_page(0, 0)?;
+            return Ok(log);
+        };
+
+        log.active = active;
+        log.sequence = sequence;
+        log.slot = log.scan(active, |_| ControlFlow::Continue(()))?;
+
+        // The active page may have no valid record right after a rotation
+        let mut latest = None;
+        for page in log.pages_newest_first() {
+            log.scan(page, |record| {
+                latest = Some(record.time);
+                ControlFlow::Continue(())
+            })?;
+            if latest.is_some() {
+                break;
+            }
+        }
+        log.latest = latest;
+
+        debug!(
+            "Mounted reading log on page {active} (sequence {sequence}), {} records free",
+            RECORDS_PER_PAGE + 1 - log.slot
+        );
+        Ok(log)
+    }
+
+    /// Return the time of the most recent reading
+    pub fn latest(&self) -> Option<OffsetDateTime> {
+        self.latest
+            .and_then(|time| OffsetDateTime::from_unix_timestamp(i64::from(time)).ok())
+    }
+
+    /// Append a reading
+    ///
+    /// Readings must be appended in chronological order.
+    pub fn append(&mut self, reading: &Reading) -> Result<(), Error> {
+        let record = Record::from_reading(reading).ok_or(Error::InvalidTime)?;
+        if self.latest.is_some_and(|latest| record.time <= latest) {
+            return Err(Error::OutOfOrder);
+        }
+
+        if self.slot > RECORDS_PER_PAGE {
+            let next = self.next_page(self.active);
+            debug!("Rotate reading log to page {next}");
+            self.open_page(next, self.sequence.wrapping_add(1))?;
+        }
+
+        let result = self.write(self.active, self.slot * RECORD_SIZE, &record.encode());
+        // Skip the slot even if the write failed midway
+        self.slot += 1;
+        result?;
+
+        self.latest = Some(record.time);
+        Ok(())
+    }
+
+    /// Visit readings between two times, in chronological order
+    ///
+    /// Readings are in UTC.
+    /// Visiting stops early if the visitor returns [`ControlFlow::Break`].
+    pub fn range(
+        &mut self,
+        from: OffsetDateTime,
+        to: OffsetDateTime,
+        mut visit: impl FnMut(Reading) -> ControlFlow<()>,
+    ) -> Result<(), Error> {
+        let from = from.unix_timestamp();
+        let to = to.unix_timestamp();
+
+        let mut pages = self.pages_oldest_first().peekable();
+        while let Some(page) = pages.next() {
+            // Skip pages entirely before the range, knowing from the first
+            // record of the following page
+            if let Some(&next) = pages.peek() {
+                if self
+                    .first_time(next)?
+                    .is_some_and(|time| i64::from(time) <= from)
+                {
+                    continue;
+                }
+            }
+
+            let mut flow = ControlFlow::Continue(());
+            self.scan(page, |record| {
+                let time = i64::from(record.time);
+                if time > to {
+                    flow = ControlFlow::Break(());
+                } else if time >= from {
+                    flow = visit(record.to_reading());
+                }
+                flow
+            })?;
+            if flow.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return indices of pages in ring order, ending with the active one
+    fn pages_oldest_first(&self) -> impl Iterator<Item = u32> {
+        let (active, page_count) = (self.active, self.page_count);
+        (1..=page_count).map(move |step| (active + step) % page_count)
+    }
+
+    /// Return indices of pages in reverse ring order, starting with the
+    /// active one
+    fn pages_newest_first(&self) -> impl Iterator<Item = u32> {
+        let (active, page_count) = (self.active, self.page_count);
+        (0..page_count).map(move |step| (active + page_count - step) % page_count)
+    }
+
+    /// Return the time of the first valid record of a page
+    fn first_time(&mut self, page: u32) -> Result<Option<u32>, Error> {
+        let mut first = None;
+        self.scan(page, |record| {
+            first = Some(record.time);
+            ControlFlow::Break(())
+        })?;
+        Ok(first)
+    }
+
+    /// Visit the valid records of a page in order
+    ///
+    /// Pages that are not part of the log are empty.
+    /// Return the slot after the last written record, unless visiting
+    /// stopped early.
+    fn scan(
+        &mut self,
+        page: u32,
+        mut visit: impl FnMut(Record) -> ControlFlow<()>,
+    ) -> Result<u32, Error> {
+        if self.page_sequence(page)?.is_none() {
+            return Ok(1);
+        }
+
+        let mut slot = 1;
+        let mut batch = [[0_u8; RECORD_SIZE as usize]; BATCH_SIZE];
+        while slot <= RECORDS_PER_PAGE {
+            let count = BATCH_SIZE.min((RECORDS_PER_PAGE + 1 - slot) as usize);
+            let batch = batch.get_mut(..count).unwrap_or_default();
+            self.read(page, slot * RECORD_SIZE, batch.as_flattened_mut())?;
+
+            for bytes in batch.iter() {
+                if bytes.iter().all(|byte| *byte == 0xff) {
+                    return Ok(slot);
+                }
+                match Record::decode(bytes) {
+                    Some(record) => {
+                        if visit(record).is_break() {
+                            return Ok(slot);
+                        }
+                    }
+                    None => warn!("Skip corrupted record at page {page} slot {slot}"),
+                }
+                slot += 1;
+            }
+        }
+        Ok(slot)
+    }
+
+    /// Erase a page if needed and write its header
+    fn open_page(&mut self, page: u32, sequence: u32) -> Result<(), Error> {
+        if !self.is_erased(page)? {
+            self.erase_page(page)?;
+        }
+
+        let [magic_0, magic_1, magic_2, magic_3] = MAGIC.to_le_bytes();
+        let [sequence_0, sequence_1, sequence_2, sequence_3] = sequence.to_le_bytes();
+        let [format_0, format_1, format_2, format_3] = FORMAT.to_le_bytes();
+        let [checksum_0, checksum_1, checksum_2, checksum_3] =
+            page_checksum(sequence).to_le_bytes();
+        let header = [
+            magic_0, magic_1, magic_2, magic_3, sequence_0, sequence_1, sequence_2, sequence_3,
+            format_0, format_1, format_2, format_3, checksum_0, checksum_1, checksum_2, checksum_3,
+        ];
+        self.write(page, 0, &header)?;
+
+        self.active = page;
+        self.sequence = sequence;
+        self.slot = 1;
+        Ok(())
+    }
+
+    /// Read the sequence number of a page from its header
+    ///
+    /// Return `None` if the page is erased, corrupted or in another format.
+    fn page_sequence(&mut self, page: u32) -> Result<Option<u32>, Error> {
+        let mut header = [0_u8; RECORD_SIZE as usize];
+        self.read(page, 0, &mut header)?;
+        let [magic_0, magic_1, magic_2, magic_3, sequence_0, sequence_1, sequence_2, sequence_3, format_0, format_1, format_2, format_3, checksum_0, checksum_1, checksum_2, checksum_3] =
+            header;
+        let magic = u32::from_le_bytes([magic_0, magic_1, magic_2, magic_3]);
+        let sequence = u32::from_le_bytes([sequence_0, sequence_1, sequence_2, sequence_3]);
+        let format = u32::from_le_bytes([format_0, format_1, format_2, format_3]);
+        let checksum = u32::from_le_bytes([checksum_0, checksum_1, checksum_2, checksum_3]);
+
+        let valid = magic == MAGIC && format == FORMAT && checksum == page_checksum(sequence);
+        Ok(valid.then_some(sequence))
+    }
+
+    /// Check whether a whole page is erased
+    fn is_erased(&mut self, page: u32) -> Result<bool, Error> {
+        let mut buffer = [0_u8; 256];
+        for offset in (0..PAGE_SIZE).step_by(buffer.len()) {
+            self.read(page, offset, &mut buffer)?;
+            if buffer.iter().any(|byte| *byte != 0xff) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Return the page following another in the ring
+    fn next_page(&self, page: u32) -> u32 {
+        (page + 1) % self.page_count
+    }
+
+    /// Read bytes from a page
+    fn read(&mut self, page: u32, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.flash
+            .read(self.start + page * PAGE_SIZE + offset, bytes)
+            .map_err(|error| Error::Flash(error.kind()))
+    }
+
+    /// Write bytes to a page
+    fn write(&mut self, page: u32, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        self.flash
+            .write(self.start + page * PAGE_SIZE + offset, bytes)
+            .map_err(|error| Error::Flash(error.kind()))
+    }
+
+    /// Erase a page
+    fn erase_page(&mut self, page: u32) -> Result<(), Error> {
+        let start = self.start + page * PAGE_SIZE;
+        self.flash
+            .erase(start, start + PAGE_SIZE)
+            .map_err(|error| Error::Flash(error.kind()))
+    }
+}
+
+/// Check whether a sequence number is newer than another
+///
+/// Sequence numbers wrap around, and those of the pages in the ring are
+/// always less than half the range apart.
+#[allow(clippy::cast_possible_wrap)]
+fn is_newer(sequence: u32, other: u32) -> bool {
+    sequence.wrapping_sub(other) as i32 > 0
+}
+
+/// Compute the checksum of a page header
+fn page_checksum(sequence: u32) -> u32 {
+    let mut digest = CRC.digest();
+    digest.update(&MAGIC.to_le_bytes());
+    digest.update(&sequence.to_le_bytes());
+    digest.update(&FORMAT.to_le_bytes());
+    digest.finalize()
+}
+
+/// An error within the reading log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The flash range is not aligned to pages or has too few pages
+    InvalidRange,
+
+    /// The time of a reading is before 1970 or after 2106
+    InvalidTime,
+
+    /// A reading is not newer than the most recent one
+    OutOfOrder,
+
+    /// Error from flash
+    Flash(#[allow(unused)] NorFlashErrorKind),
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    use std::vec::Vec;
+
+    use embedded_storage::nor_flash::ErrorType;
+    use embedded_storage::nor_flash::NorFlashError;
+    use embedded_storage::nor_flash::ReadNorFlash;
+
+    use uom::si::f32::Pressure;
+    use uom::si::f32::Ratio;
+    use uom::si::f32::ThermodynamicTemperature;
+    use uom::si::pressure::hectopascal;
+    use uom::si::ratio::percent;
+    use uom::si::thermodynamic_temperature::degree_celsius;
+
+    use crate::domain::Sample;
+
+    /// Time of the first reading as Unix epoch
+    const START: u32 = 1_723_629_600;
+
+    /// Error of a flash operation after power loss
+    #[derive(Debug)]
+    struct PowerLost;
+
+    impl NorFlashError for PowerLost {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::Other
+        }
+    }
+
+    /// Flash in memory that records reads and loses power after a number of
+    /// written words
+    #[derive(Clone)]
+    struct Flash {
+        /// Contents
+        bytes: Vec<u8>,
+
+        /// Number of words that can be written before power is lost, or
+        /// `None` for no limit
+        budget: Option<usize>,
+
+        /// Pages read from
+        reads: Vec<u32>,
+    }
+
+    impl Flash {
+        /// Create an erased flash with a number of pages
+        fn new(pages: u32) -> Self {
+            Self {
+                bytes: std::vec![0xff; (pages * PAGE_SIZE) as usize],
+                budget: None,
+                reads: Vec::new(),
+            }
+        }
+    }
+
+    impl ErrorType for Flash {
+        type Error = PowerLost;
+    }
+
+    impl ReadNorFlash for Flash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), PowerLost> {
+            self.reads.push(offset / PAGE_SIZE);
+            let start = offset as usize;
+            bytes.copy_from_slice(&self.bytes[start..start + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for Flash {
+        const WRITE_SIZE: usize = 4;
+
+        const ERASE_SIZE: usize = 4096;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), PowerLost> {
+            self.bytes[from as usize..to as usize].fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), PowerLost> {
+            for (index, word) in bytes.chunks(4).enumerate() {
+                match &mut self.budget {
+                    Some(0) => return Err(PowerLost),
+                    Some(budget) => *budget -= 1,
+                    None => {}
+                }
+                let start = offset as usize + index * 4;
+                for (target, byte) in self.bytes[start..start + word.len()].iter_mut().zip(word) {
+                    // Programming only clears bits
+                    *target &= byte;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Create a reading at a number of seconds after the start
+    fn reading(seconds: u32) -> Reading {
+        let time = OffsetDateTime::from_unix_timestamp(i64::from(START + seconds)).unwrap();
+        let sample = Sample {
+            temperature: ThermodynamicTemperature::new::<degree_celsius>(21.5),
+            humidity: Ratio::new::<percent>(45.25),
+            pressure: Pressure::new::<hectopascal>(1013.2),
+        };
+        (time, sample)
+    }
+
+    /// Mount a log on all pages of a flash
+    fn mount(flash: &mut Flash) -> ReadingLog<&mut Flash> {
+        let length = u32::try_from(flash.bytes.len()).unwrap();
+        ReadingLog::mount(flash, 0..length).unwrap()
+    }
+
+    /// Return the times of readings between two times, in seconds after the
+    /// start
+    fn times(log: &mut ReadingLog<&mut Flash>, from: u32, to: u32) -> Vec<u32> {
+        let mut times = Vec::new();
+        log.range(reading(from).0, reading(to).0, |(time, _)| {
+            let time = u32::try_from(time.unix_timestamp()).unwrap();
+            times.push(time - START);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        times
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let record = Record::from_reading(&reading(0)).unwrap();
+        assert_eq!(record.sample, [2150, 4525, 10132]);
+        assert_eq!(Record::decode(&record.encode()), Some(record));
+
+        let mut bytes = record.encode();
+        bytes[4] ^= 1;
+        assert_eq!(Record::decode(&bytes), None);
+    }
+
+    #[test]
+    fn append_and_remount() {
+        let mut flash = Flash::new(2);
+        let mut log = mount(&mut flash);
+        assert_eq!(log.latest(), None);
+        for seconds in 0..10 {
+            log.append(&reading(seconds * 60)).unwrap();
+        }
+
+        let mut log = mount(&mut flash);
+        assert_eq!(log.latest(), Some(reading(9 * 60).0));
+        assert_eq!(
+            times(&mut log, 0, 3600),
+            (0..10).map(|minute| minute * 60).collect::<Vec<_>>()
+        );
+        assert_eq!(times(&mut log, 60, 180), [60, 120, 180]);
+    }
+
+    #[test]
+    fn mount_after_torn_record() {
+        let mut flash = Flash::new(2);
+        let mut log = mount(&mut flash);
+        log.append(&reading(0)).unwrap();
+        log.append(&reading(60)).unwrap();
+
+        let words = RECORD_SIZE as usize / 4;
+        for budget in 1..words {
+            let mut flash = flash.clone();
+            let mut log = mount(&mut flash);
+            log.flash.budget = Some(budget);
+            assert!(log.append(&reading(120)).is_err());
+            flash.budget = None;
+
+            let mut log = mount(&mut flash);
+            assert_eq!(log.latest(), Some(reading(60).0), "budget {budget}");
+            assert_eq!(times(&mut log, 0, 3600), [0, 60], "budget {budget}");
+
+            // The torn slot is skipped
+            log.append(&reading(180)).unwrap();
+            let mut log = mount(&mut flash);
+            assert_eq!(times(&mut log, 0, 3600), [0, 60, 180], "budget {budget}");
+        }
+    }
+
+    #[test]
+    fn rotate_over_full_ring() {
+        let mut flash = Flash::new(3);
+        let mut log = mount(&mut flash);
+        let count = 4 * RECORDS_PER_PAGE + 10;
+        for index in 0..count {
+            log.append(&reading(index)).unwrap();
+        }
+
+        // The page opened last dropped the oldest readings
+        let kept = 2 * RECORDS_PER_PAGE + 10;
+        let expected = (count - kept..count).collect::<Vec<_>>();
+        assert_eq!(times(&mut log, 0, count), expected);
+
+        let mut log = mount(&mut flash);
+        assert_eq!(log.sequence, 4);
+        assert_eq!(log.latest(), Some(reading(count - 1).0));
+        assert_eq!(times(&mut log, 0, count), expected);
+    }
+
+    #[test]
+    fn range_skips_older_pages() {
+        let mut flash = Flash::new(4);
+        let mut log = mount(&mut flash);
+        for index in 0..4 * RECORDS_PER_PAGE {
+            log.append(&reading(index)).unwrap();
+        }
+        // Pages hold readings in order, and page 3 is the active one
+        let from = 2 * RECORDS_PER_PAGE + 5;
+        log.flash.reads.clear();
+        assert_eq!(times(&mut log, from, from + 2), [from, from + 1, from + 2]);
+        assert!(!log.flash.reads.contains(&0));
+
+        // A range starting at the first reading of a page skips the page
+        // before it
+        let from = 3 * RECORDS_PER_PAGE;
+        log.flash.reads.clear();
+        assert_eq!(times(&mut log, from, from + 1), [from, from + 1]);
+        assert!(!log.flash.reads.contains(&0));
+        assert_eq!(times(&mut log, from - 1, from), [from - 1, from]);
+
+        // Visiting stops when the visitor breaks
+        let mut visited = 0;
+        log.range(reading(0).0, reading(u32::MAX - START).0, |_| {
+            visited += 1;
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        assert_eq!(visited, 1);
+    }
+
+    #[test]
+    fn reject_out_of_order() {
+        let mut flash = Flash::new(2);
+        let mut log = mount(&mut flash);
+        log.append(&reading(60)).unwrap();
+        assert_eq!(log.append(&reading(60)), Err(Error::OutOfOrder));
+        assert_eq!(log.append(&reading(0)), Err(Error::OutOfOrder));
+
+        let mut log = mount(&mut flash);
+        assert_eq!(log.append(&reading(30)), Err(Error::OutOfOrder));
+        log.append(&reading(120)).unwrap();
+        assert_eq!(times(&mut log, 0, 3600), [60, 120]);
+
+        let (_, sample) = reading(0);
+        let before_epoch = (OffsetDateTime::UNIX_EPOCH - time::Duration::SECOND, sample);
+        assert_eq!(log.append(&before_epoch), Err(Error::InvalidTime));
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let mut flash = Flash::new(3);
+        let mut log = mount(&mut flash);
+        log.open_page(1, u32::MAX - 1).unwrap();
+        // Fill pages 1, 2 and 0, and start page 1 again
+        for index in 0..=3 * RECORDS_PER_PAGE {
+            log.append(&reading(index)).unwrap();
+        }
+        assert_eq!(log.sequence, 1);
+
+        let mut log = mount(&mut flash);
+        assert_eq!(log.active, 1);
+        assert_eq!(log.sequence, 1);
+        assert_eq!(log.latest(), Some(reading(3 * RECORDS_PER_PAGE).0));
+        log.append(&reading(3 * RECORDS_PER_PAGE + 1)).unwrap();
+        let times = times(&mut log, 0, 4 * RECORDS_PER_PAGE);
+        assert_eq!(times.first(), Some(&RECORDS_PER_PAGE));
+        assert_eq!(times.last(), Some(&(3 * RECORDS_PER_PAGE + 1)));
+    }
+
+    #[test]
+    fn newer_sequences() {
+        assert!(is_newer(1, 0));
+        assert!(!is_newer(0, 1));
+        assert!(!is_newer(5, 5));
+        assert!(is_newer(0, u32::MAX));
+        assert!(is_newer(2, u32::MAX - 1));
+        assert!(!is_newer(u32::MAX, 0));
+    }
+}
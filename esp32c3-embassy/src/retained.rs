@@ -0,0 +1,295 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! State retained in RTC Fast memory between deep sleep cycles
+//!
+//! The state is stored behind a [`Header`], and it is only trusted after
+//! waking up from deep sleep with a valid header, as described in
+//! [`crussant_common::retained`].
+//! Otherwise it is reset to defaults.
+
+use core::mem::size_of;
+use core::ptr::addr_of;
+use core::ptr::addr_of_mut;
+
+use log::info;
+use log::warn;
+
+use crc::Digest;
+
+use esp_hal::macros::ram;
+use esp_hal::reset::get_reset_reason;
+use esp_hal::rtc_cntl::SocResetReason;
+
+use crussant_common::retained::Header;
+
+pub use crussant_common::retained::Checksum;
+
+use crate::drift::Drift;
+use crate::energy::State as EnergyState;
+use crate::history::History;
+use crate::sntp::Bans;
+use crate::synchronization::Source;
+
+/// Magic number marking retained state, `RTCS` in ASCII
+const MAGIC: u32 = 0x5254_4353;
+
+/// Version of the layout of [`RetainedState`]
+///
+/// This must be incremented whenever the layout changes in a way that is not
+/// caught by the size check.
+const LAYOUT_VERSION: u16 = 14;
+
+/// Header of a state of the current layout
+const LAYOUT: Header = Header::layout(MAGIC, LAYOUT_VERSION, size_of::<RetainedState>());
+
+/// Size of the RTC Fast memory in bytes
+const RTC_FAST_BUDGET: usize = 8 * 1024;
+
+const _: () = assert!(size_of::<Retained>() <= RTC_FAST_BUDGET);
+
+/// Retained state and its header
+///
+/// This is a statically allocated variable and it is placed in the RTC Fast
+/// memory, which survives deep sleep.
+/// It is not initialized on boot, and must be validated before use.
+#[ram(rtc_fast, persistent)]
+static mut RETAINED: Retained = Retained {
+    header: Header::EMPTY,
+    state: RetainedState::new(),
+};
+
+/// Retained state and its header
+#[repr(C)]
+struct Retained {
+    /// Header
+    header: Header,
+
+    /// State
+    state: RetainedState,
+}
+
+impl Checksum for Drift {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.last_synchronization.feed(digest);
+        self.last_offset.feed(digest);
+        self.rate_ppm.feed(digest);
+        self.uncertainty_ppm.feed(digest);
+        self.measurements.feed(digest);
+    }
+}
+
+impl Checksum for Source {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        (*self as u8).feed(digest);
+    }
+}
+
+/// A clock saved before deep sleep
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct SavedClock {
+    /// Expected wakeup time in microseconds since Unix epoch
+    pub wakeup_time: u64,
+
+    /// Expected sleep duration in microseconds
+    pub sleep_duration: u64,
+
+    /// Estimated drift
+    pub drift: Drift,
+
+    /// Source of the last synchronization
+    pub source: Source,
+
+    /// Accuracy of the source in microseconds
+    pub accuracy: u64,
+
+    /// Whether the last synchronization attempt failed
+    pub uncertain: bool,
+}
+
+/// State retained between deep sleep cycles
+#[repr(C)]
+pub struct RetainedState {
+    /// Number of boots since the state was last reset
+    pub boot_count: u32,
+
+    /// Clock saved before deep sleep
+    pub clock: Option<SavedClock>,
+
+    /// History of readings
+    pub history: History,
+
+    /// Time of the last reading published to MQTT in seconds since Unix epoch
+    pub published_until: i64,
+
+    /// Time of the last reading uploaded to InfluxDB in seconds since Unix
+    /// epoch
+    pub uploaded_until: i64,
+
+    /// State of the energy policy
+    pub energy: EnergyState,
+
+    /// Whether the battery was critical at the last wakeup
+    pub battery_critical: bool,
+
+    /// SNTP servers that denied access
+    pub banned_servers: Bans,
+}
+
+impl Checksum for SavedClock {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.wakeup_time.feed(digest);
+        self.sleep_duration.feed(digest);
+        self.drift.feed(digest);
+        self.source.feed(digest);
+        self.accuracy.feed(digest);
+        self.uncertain.feed(digest);
+    }
+}
+
+impl RetainedState {
+    /// Create a default state
+    pub const fn new() -> Self {
+        Self {
+            boot_count: 0,
+            clock: None,
+            history: History::new(),
+            published_until: 0,
+            uploaded_until: 0,
+            energy: EnergyState::new(),
+            battery_critical: false,
+            banned_servers: Bans::new(),
+        }
+    }
+}
+
+impl Checksum for RetainedState {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.boot_count.feed(digest);
+        self.clock.feed(digest);
+        self.history.feed(digest);
+        self.published_until.feed(digest);
+        self.uploaded_until.feed(digest);
+        self.energy.feed(digest);
+        self.battery_critical.feed(digest);
+        self.banned_servers.feed(digest);
+    }
+}
+
+/// Load the retained state, resetting it to defaults if it is not valid
+///
+/// This must be called only once, and [`seal()`] must be called before
+/// entering deep sleep.
+pub fn load() -> &'static mut RetainedState {
+    let woke_from_deep_sleep = get_reset_reason() == Some(SocResetReason::CoreDeepSleep);
+
+    // SAFETY:
+    // There is only one thread, and this is the only place where a reference
+    // to the retained state is taken.
+    // RTC Fast memory is always physically initialized, so reading the header
+    // as integers is fine even when it contains garbage.
+    let header = unsafe { addr_of!(RETAINED.header).read_volatile() };
+
+    // SAFETY:
+    // The state is only read after waking up from deep sleep with a header of
+    // the same layout, so it holds the values sealed before deep sleep
+    let checksum = || unsafe { (*addr_of!(RETAINED.state)).checksum() };
+
+    match header.verify(&LAYOUT, woke_from_deep_sleep, checksum) {
+        Ok(()) => info!("Retained state is valid"),
+        Err(reason) => {
+            warn!("Retained state is not valid ({reason:?}), reset to defaults");
+            // SAFETY:
+            // The old value is not dropped, since it might be garbage
+            unsafe { addr_of_mut!(RETAINED.state).write(RetainedState::new()) };
+        }
+    }
+
+    // SAFETY:
+    // The state is either valid or was just reset
+    unsafe { &mut *addr_of_mut!(RETAINED.state) }
+}
+
+/// Seal the retained state with a header before entering deep sleep
+pub fn seal() {
+    // SAFETY:
+    // There is only one thread, and no task can run while this function runs
+    unsafe {
+        let header = LAYOUT.seal((*addr_of!(RETAINED.state)).checksum());
+        addr_of_mut!(RETAINED.header).write_volatile(header);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::OffsetDateTime;
+
+    use crate::domain::Sample;
+
+    /// Create a saved clock with a change applied
+    fn clock_with(change: impl FnOnce(&mut SavedClock)) -> SavedClock {
+        let mut clock = SavedClock {
+            wakeup_time: 1_700_000_000_000_000,
+            sleep_duration: 300_000_000,
+            drift: Drift::new(1_700_000_000_000_000),
+            source: Source::Sntp,
+            accuracy: 10_000,
+            uncertain: false,
+        };
+        change(&mut clock);
+        clock
+    }
+
+    #[test]
+    fn clock_checksum_changes_with_any_field() {
+        let original = clock_with(|_| {}).checksum();
+        let changes: [fn(&mut SavedClock); 10] = [
+            |clock| clock.wakeup_time += 1,
+            |clock| clock.sleep_duration += 1,
+            |clock| clock.drift.last_synchronization += 1,
+            |clock| clock.drift.last_offset += 1,
+            |clock| clock.drift.rate_ppm += 1.0,
+            |clock| clock.drift.uncertainty_ppm += 1.0,
+            |clock| clock.drift.measurements += 1,
+            |clock| clock.source = Source::WorldTimeApi,
+            |clock| clock.accuracy += 1,
+            |clock| clock.uncertain = true,
+        ];
+
+        for (index, change) in changes.into_iter().enumerate() {
+            assert_ne!(clock_with(change).checksum(), original, "change {index}");
+        }
+    }
+
+    #[test]
+    fn state_checksum_changes_with_any_field() {
+        let original = RetainedState::new().checksum();
+        let changes: [fn(&mut RetainedState); 9] = [
+            |state| state.boot_count += 1,
+            |state| state.clock = Some(clock_with(|_| {})),
+            |state| {
+                state
+                    .history
+                    .write(&(OffsetDateTime::UNIX_EPOCH, Sample::default()))
+            },
+            |state| state.published_until += 1,
+            |state| state.uploaded_until += 1,
+            |state| state.energy.usage.wakeups += 1,
+            |state| state.energy.usage.sleep_milliseconds += 1,
+            |state| state.battery_critical = true,
+            |state| state.banned_servers.ban(0),
+        ];
+
+        for (index, change) in changes.into_iter().enumerate() {
+            let mut state = RetainedState::new();
+            change(&mut state);
+            assert_ne!(state.checksum(), original, "change {index}");
+        }
+    }
+}
@@ -14,14 +14,18 @@
 use embassy_time::Duration;
 use embassy_time::Timer;
 
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Sender;
+use embassy_sync::signal::Signal;
+
+use embassy_futures::select::select;
+use embassy_futures::select::Either;
+
+use embassy_embedded_hal::shared_bus::I2cDeviceError;
 
 use esp_hal::i2c::Error as I2cError;
-use esp_hal::i2c::I2C;
-use esp_hal::peripherals::I2C0;
 use esp_hal::rng::Rng;
-use esp_hal::Async;
 
 use bme280_rs::AsyncBme280;
 use bme280_rs::Configuration;
@@ -29,22 +33,32 @@
 use bme280_rs::Sample as Bme280Sample;
 use bme280_rs::SensorMode;
 
-use crate::clock::Clock;
+use uom::si::f32::ThermodynamicTemperature as Temperature;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
 use crate::clock::Error as ClockError;
 use crate::domain::Error as DomainError;
 use crate::domain::Reading;
 use crate::domain::Sample;
+use crate::external_rtc::ExternalRtc;
+use crate::external_rtc::Rtc as _;
+use crate::SharedClock;
+use crate::SharedI2cDevice;
 
 /// Interval to wait for sensor warmup
 const WARMUP_INTERVAL: Duration = Duration::from_millis(10);
 
+/// Signal to take a sample without waiting for the sampling period
+pub static SAMPLE_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
 /// Task for sampling sensor
 #[embassy_executor::task]
 pub async fn sample_task(
-    i2c: I2C<'static, I2C0, Async>,
+    i2c: SharedI2cDevice,
+    mut external_rtc: Option<ExternalRtc<SharedI2cDevice>>,
     mut rng: Rng,
     sender: Sender<'static, NoopRawMutex, Reading, 3>,
-    clock: Clock,
+    clock: &'static SharedClock,
     sampling_period: Duration,
 ) {
     info!("Create");
@@ -61,38 +75,63 @@ pub async fn sample_task(
     Timer::after(WARMUP_INTERVAL).await;
 
     loop {
-        if let Err(error) = sample_and_send(&mut sensor, &mut rng, &sender, &clock).await {
+        if let Err(error) =
+            sample_and_send(&mut sensor, external_rtc.as_mut(), &mut rng, &sender, clock).await
+        {
             error!("Could not sample sensor: {error:?}");
         }
 
-        let wait_interval = clock.duration_to_next_rounded_wakeup(sampling_period);
+        let wait_interval = clock
+            .lock()
+            .await
+            .duration_to_next_rounded_wakeup(sampling_period);
         info!("Wait {}s for next sample", wait_interval.as_secs());
-        Timer::after(wait_interval).await;
+        if let Either::Second(()) =
+            select(Timer::after(wait_interval), SAMPLE_REQUESTED.wait()).await
+        {
+            info!("Sample requested");
+        }
     }
 }
 
 /// Sample sensor and send reading to receiver
 async fn sample_and_send(
-    sensor: &mut AsyncBme280<I2C<'static, I2C0, Async>, Delay>,
+    sensor: &mut AsyncBme280<SharedI2cDevice, Delay>,
+    external_rtc: Option<&mut ExternalRtc<SharedI2cDevice>>,
     rng: &mut Rng,
     sender: &Sender<'static, NoopRawMutex, Reading, 3>,
-    clock: &Clock,
+    clock: &SharedClock,
 ) -> Result<(), SensorError> {
     info!("Read sample");
 
-    let now = clock.now()?;
+    let now = clock.lock().await.now()?;
 
     let sample_result = sensor
         .read_sample()
         .await
         .map_err(SensorError::I2c)
         .and_then(|sample: Bme280Sample| Ok(Sample::try_from(sample)?));
-    let sample = sample_result.unwrap_or_else(|error| {
-        error!("Cannot read sample: {error:?}");
-        warn!("Use a random sample");
-
-        Sample::random(rng)
-    });
+    let sample = match sample_result {
+        Ok(sample) => sample,
+        Err(error) => {
+            error!("Cannot read sample: {error:?}");
+            warn!("Use a random sample");
+            let mut sample = Sample::random(rng);
+
+            if let Some(external_rtc) = external_rtc {
+                match external_rtc.read_temperature().await {
+                    Ok(Some(temperature)) => {
+                        info!("Use temperature from external RTC");
+                        sample.temperature = Temperature::new::<degree_celsius>(temperature);
+                    }
+                    Ok(None) => {}
+                    Err(error) => warn!("Cannot read temperature from external RTC: {error:?}"),
+                }
+            }
+
+            sample
+        }
+    };
 
     let reading = (now, sample);
     sender.send(reading).await;
@@ -102,8 +141,8 @@ async fn sample_and_send(
 
 /// Initialize sensor
 async fn initialize(
-    bme280: &mut AsyncBme280<I2C<'static, I2C0, Async>, Delay>,
-) -> Result<(), I2cError> {
+    bme280: &mut AsyncBme280<SharedI2cDevice, Delay>,
+) -> Result<(), I2cDeviceError<I2cError>> {
     info!("Initialize");
     bme280.init().await?;
 
@@ -130,7 +169,7 @@ enum SensorError {
     Domain(#[allow(unused)] DomainError),
 
     /// Error from I²C bus
-    I2c(#[allow(unused)] I2cError),
+    I2c(#[allow(unused)] I2cDeviceError<I2cError>),
 }
 
 impl From<ClockError> for SensorError {
@@ -145,8 +184,8 @@ fn from(error: DomainError) -> Self {
     }
 }
 
-impl From<I2cError> for SensorError {
-    fn from(error: I2cError) -> Self {
+impl From<I2cDeviceError<I2cError>> for SensorError {
+    fn from(error: I2cDeviceError<I2cError>) -> Self {
         Self::I2c(error)
     }
 }
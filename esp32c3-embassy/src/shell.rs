@@ -0,0 +1,949 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Command shell on the serial console
+//!
+//! ```text
+//! status
+//! history [dump]
+//! export
+//! energy
+//! config [get [<key>]]
+//! config set <key> <value>
+//! sample
+//! refresh
+//! time [set <time>]
+//! baseline [erase]
+//! reboot
+//! ```
+//!
+//! Commands are parsed and executed independently of the transport, and act
+//! on the firmware through the [`Station`] trait.
+//! Configuration changes are saved to flash immediately, and take effect
+//! after a restart.
+//!
+//! The `export` command streams all readings logged in flash as CSV, between
+//! a line `# begin export` and a line `# end export, <count> records`.
+//! The header row is followed by one record per line, whose last column is
+//! the CRC-32/ISO-HDLC of the line before the last comma, in hexadecimal.
+//! Log messages may be interleaved with records on the same serial port.
+//!
+//! The `energy` command shows the time spent awake, with WiFi on and in deep
+//! sleep since the last cold boot, and the charge estimated from it.
+
+use core::fmt::Write as _;
+
+use log::info;
+
+use crc::Crc;
+use crc::CRC_32_ISO_HDLC;
+
+use embassy_time::Duration;
+
+use embedded_io_async::Read;
+use embedded_io_async::Write;
+
+use heapless::String;
+use heapless::Vec;
+
+use time::Date;
+use time::Month;
+use time::OffsetDateTime;
+use time::PrimitiveDateTime;
+use time::Time;
+use time::UtcOffset;
+
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::clock::Error as ClockError;
+use crate::config::Config;
+use crate::config::Error as ConfigError;
+use crate::config::Key;
+use crate::console::split_arguments;
+use crate::console::Console;
+use crate::console::Error as ConsoleError;
+use crate::console::LINE_SIZE;
+use crate::domain::Reading;
+use crate::encoding::encode;
+use crate::encoding::Error as EncodingError;
+use crate::encoding::Gas;
+use crate::encoding::MAXIMAL_SIZE as MAXIMAL_ENCODED_SIZE;
+use crate::encoding::VERSION as ENCODING_VERSION;
+use crate::energy::Report as EnergyReport;
+use crate::history::MINUTES as HISTORY_MINUTES;
+use crate::json::write_time;
+use crate::kv_store::MAXIMAL_VALUE_SIZE;
+use crate::reading_log::Error as ReadingLogError;
+use crate::synchronization::Source;
+
+/// Help text of the commands
+const HELP: &str = concat!(
+    "status                      Show the station status\r\n",
+    "history [dump]              Show the readings of the last hour, or dump\r\n",
+    "                            them in compact binary encoding\r\n",
+    "export                      Stream all logged readings as CSV\r\n",
+    "energy                      Show the estimated energy used\r\n",
+    "config [get [<key>]]        Show configuration values\r\n",
+    "config set <key> <value>    Change a configuration value\r\n",
+    "sample                      Take a sample now\r\n",
+    "refresh                     Redraw the display\r\n",
+    "time [set <time>]           Show or set the time, as Unix timestamp\r\n",
+    "                            or as 2024-08-14T12:00:00+02:00\r\n",
+    "baseline [erase]            Show or erase the gas sensor baseline\r\n",
+    "reboot                      Restart the station",
+);
+
+/// Number of logged readings fetched at once during an export
+pub const EXPORT_BATCH_SIZE: usize = 32;
+
+/// Header row of an export
+const EXPORT_HEADER: &str = "time,temperature_celsius,humidity_percent,pressure_hectopascal,crc32";
+
+/// Checksum algorithm of exported records
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Status of the station
+#[derive(Clone, Debug)]
+pub struct Status {
+    /// Number of boots since the last cold boot
+    pub boot_count: u32,
+
+    /// Time since boot
+    pub uptime: Duration,
+
+    /// Current local time, if the clock is valid
+    pub now: Option<OffsetDateTime>,
+
+    /// Source of the last clock synchronization
+    pub clock_source: Source,
+
+    /// Estimated clock error
+    pub clock_error: Duration,
+
+    /// Number of readings in history
+    pub readings: usize,
+
+    /// Most recent reading
+    pub latest: Option<Reading>,
+}
+
+/// Firmware operations available to the shell
+pub trait Station {
+    /// Return the status of the station
+    async fn status(&mut self) -> Status;
+
+    /// Return the readings of the last hour, oldest first
+    async fn readings(&mut self) -> Vec<Reading, HISTORY_MINUTES>;
+
+    /// Return the next logged readings after a time, oldest first
+    ///
+    /// An export starts with no time, and continues with the time of the last
+    /// returned reading until fewer than [`EXPORT_BATCH_SIZE`] readings are
+    /// returned.
+    async fn logged_readings(
+        &mut self,
+        after: Option<OffsetDateTime>,
+    ) -> Result<Vec<Reading, EXPORT_BATCH_SIZE>, ReadingLogError>;
+
+    /// End an export, whether it completed or not
+    fn end_export(&mut self);
+
+    /// Return the estimated energy used since the last cold boot
+    async fn energy(&mut self) -> EnergyReport;
+
+    /// Return the configuration
+    fn config(&self) -> &Config;
+
+    /// Change a configuration value and save it
+    fn set_config(&mut self, key: Key, value: &str) -> Result<(), ConfigError>;
+
+    /// Take a sample now
+    fn request_sample(&mut self);
+
+    /// Redraw the display
+    fn request_refresh(&mut self);
+
+    /// Set the clock, returning the new local time
+    async fn set_time(&mut self, now: OffsetDateTime) -> Result<OffsetDateTime, ClockError>;
+
+    /// Read the stored gas sensor baseline
+    fn baseline<'b>(&mut self, buffer: &'b mut [u8]) -> Result<Option<&'b [u8]>, ConfigError>;
+
+    /// Erase the stored gas sensor baseline
+    fn erase_baseline(&mut self) -> Result<(), ConfigError>;
+
+    /// Restart the station
+    fn reboot(&mut self);
+}
+
+/// A shell command
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// Show the help text
+    Help,
+
+    /// Show the station status
+    Status,
+
+    /// Show the readings of the last hour
+    History,
+
+    /// Dump the readings of the last hour in compact binary encoding
+    DumpHistory,
+
+    /// Stream all logged readings as CSV
+    Export,
+
+    /// Show the estimated energy used
+    Energy,
+
+    /// Show one or all configuration values
+    GetConfig(Option<Key>),
+
+    /// Change a configuration value
+    SetConfig(Key, &'a str),
+
+    /// Take a sample now
+    Sample,
+
+    /// Redraw the display
+    Refresh,
+
+    /// Show the time
+    GetTime,
+
+    /// Set the time
+    SetTime(OffsetDateTime),
+
+    /// Show the gas sensor baseline
+    GetBaseline,
+
+    /// Erase the gas sensor baseline
+    EraseBaseline,
+
+    /// Restart the station
+    Reboot,
+}
+
+/// Parse a command from its arguments
+///
+/// Return `None` for an empty line.
+pub fn parse_command<'a>(arguments: &[&'a str]) -> Result<Option<Command<'a>>, Error> {
+    let command = match arguments {
+        [] => return Ok(None),
+        ["help"] => Command::Help,
+        ["status"] => Command::Status,
+        ["history"] => Command::History,
+        ["history", "dump"] => Command::DumpHistory,
+        ["export"] => Command::Export,
+        ["energy"] => Command::Energy,
+        ["config"] | ["config", "get"] => Command::GetConfig(None),
+        ["config", "get", key] => Command::GetConfig(Some(parse_key(key)?)),
+        ["config", "set", key, value] => Command::SetConfig(parse_key(key)?, value),
+        ["sample"] => Command::Sample,
+        ["refresh"] => Command::Refresh,
+        ["time"] => Command::GetTime,
+        ["time", "set", time] => Command::SetTime(parse_time(time)?),
+        ["baseline"] => Command::GetBaseline,
+        ["baseline", "erase"] => Command::EraseBaseline,
+        ["reboot"] => Command::Reboot,
+        ["history" | "export" | "energy" | "config" | "time" | "baseline", ..] => {
+            return Err(Error::Usage)
+        }
+        _ => return Err(Error::UnknownCommand),
+    };
+    Ok(Some(command))
+}
+
+/// Parse the name of a configuration key
+fn parse_key(name: &str) -> Result<Key, Error> {
+    Key::from_name(name).ok_or(Error::UnknownKey)
+}
+
+/// Parse a time
+///
+/// The time is either a Unix timestamp or an ISO 8601 date and time with
+/// seconds and a UTC offset, such as `2024-08-14T12:00:00Z` or
+/// `2024-08-14T12:00:00+02:00`.
+pub fn parse_time(text: &str) -> Result<OffsetDateTime, Error> {
+    if text.bytes().all(|byte| byte.is_ascii_digit()) {
+        let timestamp = text.parse().map_err(|_| Error::InvalidTime)?;
+        return OffsetDateTime::from_unix_timestamp(timestamp).map_err(|_| Error::InvalidTime);
+    }
+
+    let number = |range: core::ops::Range<usize>| -> Result<u8, Error> {
+        let digits = text.get(range).ok_or(Error::InvalidTime)?;
+        if digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            digits.parse().map_err(|_| Error::InvalidTime)
+        } else {
+            Err(Error::InvalidTime)
+        }
+    };
+    let separator = |position: usize, expected: &[u8]| -> Result<(), Error> {
+        match text.as_bytes().get(position) {
+            Some(byte) if expected.contains(byte) => Ok(()),
+            _ => Err(Error::InvalidTime),
+        }
+    };
+
+    let year = text
+        .get(0..4)
+        .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_digit()))
+        .and_then(|digits| digits.parse().ok())
+        .ok_or(Error::InvalidTime)?;
+    separator(4, b"-")?;
+    let month = Month::try_from(number(5..7)?).map_err(|_| Error::InvalidTime)?;
+    separator(7, b"-")?;
+    let day = number(8..10)?;
+    separator(10, b"Tt ")?;
+    let hour = number(11..13)?;
+    separator(13, b":")?;
+    let minute = number(14..16)?;
+    separator(16, b":")?;
+    let second = number(17..19)?;
+
+    let offset = match text.get(19..) {
+        Some("Z" | "z") => UtcOffset::UTC,
+        Some(offset) if offset.len() == 6 => {
+            let sign = match offset.as_bytes().first() {
+                Some(b'+') => 1,
+                Some(b'-') => -1,
+                _ => return Err(Error::InvalidTime),
+            };
+            separator(22, b":")?;
+            let hours = i8::try_from(number(20..22)?).map_err(|_| Error::InvalidTime)?;
+            let minutes = i8::try_from(number(23..25)?).map_err(|_| Error::InvalidTime)?;
+            UtcOffset::from_hms(sign * hours, sign * minutes, 0).map_err(|_| Error::InvalidTime)?
+        }
+        _ => return Err(Error::InvalidTime),
+    };
+
+    let date = Date::from_calendar_date(year, month, day).map_err(|_| Error::InvalidTime)?;
+    let time = Time::from_hms(hour, minute, second).map_err(|_| Error::InvalidTime)?;
+    Ok(PrimitiveDateTime::new(date, time).assume_offset(offset))
+}
+
+/// Format a time in ISO 8601 format
+fn format_time(time: OffsetDateTime) -> Result<String<32>, Error> {
+    let mut text = String::new();
+    write_time(&mut text, time).map_err(|_| ConsoleError::LineTooLong)?;
+    Ok(text)
+}
+
+/// Format bytes in hexadecimal
+fn to_hex<const N: usize>(bytes: &[u8]) -> Result<String<N>, Error> {
+    let mut text = String::new();
+    for byte in bytes {
+        write!(text, "{byte:02x}").map_err(|_| ConsoleError::LineTooLong)?;
+    }
+    Ok(text)
+}
+
+/// Run the shell until the console fails
+pub async fn run<R, W>(console: &mut Console<R, W>, station: &mut impl Station) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    console
+        .write_line(format_args!("Type help to list commands"))
+        .await?;
+
+    loop {
+        let line = String::<LINE_SIZE>::try_from(console.read_line().await?)
+            .map_err(|()| Error::Console(ConsoleError::LineTooLong))?;
+
+        match handle_line(&line, console, station).await {
+            Ok(()) => {}
+            Err(Error::Console(ConsoleError::Io)) => return Err(ConsoleError::Io.into()),
+            Err(error) => {
+                console.write_str(error.message()).await?;
+                console.write_str("\r\n").await?;
+            }
+        }
+    }
+}
+
+/// Parse and execute a line
+async fn handle_line<R, W>(
+    line: &str,
+    console: &mut Console<R, W>,
+    station: &mut impl Station,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    let arguments = split_arguments(line)?;
+    match parse_command(&arguments)? {
+        Some(command) => execute(&command, console, station).await,
+        None => Ok(()),
+    }
+}
+
+/// Execute a command
+async fn execute<R, W>(
+    command: &Command<'_>,
+    console: &mut Console<R, W>,
+    station: &mut impl Station,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    match command {
+        Command::Help => {
+            console.write_str(HELP).await?;
+            console.write_str("\r\n").await?;
+        }
+        Command::Status => write_status(console, &station.status().await).await?,
+        Command::History => {
+            let readings = station.readings().await;
+            if readings.is_empty() {
+                console.write_line(format_args!("No readings")).await?;
+            }
+            for reading in &readings {
+                write_reading(console, reading).await?;
+            }
+        }
+        Command::DumpHistory => {
+            console
+                .write_line(format_args!("Encoding version {ENCODING_VERSION}"))
+                .await?;
+            let mut base = OffsetDateTime::UNIX_EPOCH;
+            for reading in &station.readings().await {
+                let mut buffer = [0_u8; MAXIMAL_ENCODED_SIZE];
+                let size = encode(reading, &Gas::default(), base, &mut buffer)?;
+                let bytes = buffer.get(..size).unwrap_or_default();
+                console
+                    .write_line(format_args!(
+                        "{}",
+                        to_hex::<{ 2 * MAXIMAL_ENCODED_SIZE }>(bytes)?
+                    ))
+                    .await?;
+                base = reading.0;
+            }
+        }
+        Command::Export => {
+            let result = export(console, station).await;
+            station.end_export();
+            result?;
+        }
+        Command::Energy => write_energy(console, &station.energy().await).await?,
+        Command::GetConfig(None) => {
+            for key in Key::FIELDS {
+                write_config_value(console, station.config(), key).await?;
+            }
+        }
+        Command::GetConfig(Some(key)) => {
+            write_config_value(console, station.config(), *key).await?;
+        }
+        Command::SetConfig(key, value) => {
+            station.set_config(*key, value)?;
+            write_config_value(console, station.config(), *key).await?;
+            console
+                .write_line(format_args!("Saved, restart to apply"))
+                .await?;
+        }
+        Command::Sample => {
+            station.request_sample();
+            console.write_line(format_args!("Sample requested")).await?;
+        }
+        Command::Refresh => {
+            station.request_refresh();
+            console
+                .write_line(format_args!("Refresh requested"))
+                .await?;
+        }
+        Command::GetTime => match station.status().await.now {
+            Some(now) => {
+                console
+                    .write_line(format_args!("{}", format_time(now)?))
+                    .await?
+            }
+            None => console.write_line(format_args!("Clock is invalid")).await?,
+        },
+        Command::SetTime(now) => {
+            let now = station.set_time(*now).await?;
+            console
+                .write_line(format_args!("Clock set to {}", format_time(now)?))
+                .await?;
+        }
+        Command::GetBaseline => {
+            let mut buffer = [0_u8; MAXIMAL_VALUE_SIZE];
+            match station.baseline(&mut buffer)? {
+                Some(baseline) => {
+                    console
+                        .write_line(format_args!(
+                            "Baseline {}",
+                            to_hex::<{ 2 * MAXIMAL_VALUE_SIZE }>(baseline)?
+                        ))
+                        .await?;
+                }
+                None => {
+                    console
+                        .write_line(format_args!("No stored baseline"))
+                        .await?
+                }
+            }
+        }
+        Command::EraseBaseline => {
+            station.erase_baseline()?;
+            console.write_line(format_args!("Baseline erased")).await?;
+        }
+        Command::Reboot => {
+            info!("Reboot requested from console");
+            station.reboot();
+        }
+    }
+    Ok(())
+}
+
+/// Write the station status
+async fn write_status<R, W>(console: &mut Console<R, W>, status: &Status) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    console
+        .write_line(format_args!("Boot count: {}", status.boot_count))
+        .await?;
+    console
+        .write_line(format_args!("Uptime: {}s", status.uptime.as_secs()))
+        .await?;
+    match &status.now {
+        Some(now) => {
+            console
+                .write_line(format_args!(
+                    "Time: {} (from {}, error {}ms)",
+                    format_time(*now)?,
+                    status.clock_source.label(),
+                    status.clock_error.as_millis()
+                ))
+                .await?;
+        }
+        None => console.write_line(format_args!("Time: invalid")).await?,
+    }
+    console
+        .write_line(format_args!("Readings in history: {}", status.readings))
+        .await?;
+    if let Some(reading) = &status.latest {
+        console.write_str("Latest reading: ").await?;
+        write_reading(console, reading).await?;
+    }
+    Ok(())
+}
+
+/// Write the estimated energy used
+async fn write_energy<R, W>(console: &mut Console<R, W>, report: &EnergyReport) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    let usage = &report.usage;
+    console
+        .write_line(format_args!(
+            "Wakeups: {}, display refreshes: {}",
+            usage.wakeups, usage.refreshes
+        ))
+        .await?;
+    console
+        .write_line(format_args!(
+            "Awake: {}s, WiFi: {}s, deep sleep: {}s",
+            usage.awake_milliseconds / 1000,
+            usage.wifi_milliseconds / 1000,
+            usage.sleep_milliseconds / 1000
+        ))
+        .await?;
+    console
+        .write_line(format_args!(
+            "Estimated charge: {:.2}mAh, average current: {:.0}uA",
+            report.charge_milliamp_hours, report.average_microamps
+        ))
+        .await?;
+    console
+        .write_line(format_args!(
+            "Next deep sleep: {}s, after {} stable readings",
+            report.sleep_duration.as_secs(),
+            report.stable_readings
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Write a reading on a line
+async fn write_reading<R, W>(
+    console: &mut Console<R, W>,
+    (time, sample): &Reading,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    console
+        .write_line(format_args!(
+            "{} {:.2}°C {:.1}% {:.2}hPa",
+            format_time(*time)?,
+            sample.temperature.get::<degree_celsius>(),
+            sample.humidity.get::<percent>(),
+            sample.pressure.get::<hectopascal>(),
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Stream all logged readings as CSV records
+async fn export<R, W>(console: &mut Console<R, W>, station: &mut impl Station) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    console.write_line(format_args!("# begin export")).await?;
+    console.write_line(format_args!("{EXPORT_HEADER}")).await?;
+    let mut count: u32 = 0;
+    let mut after = None;
+    loop {
+        let readings = station.logged_readings(after).await?;
+        for reading in &readings {
+            write_record(console, reading).await?;
+            count += 1;
+        }
+        match readings.last() {
+            Some((time, _)) if readings.is_full() => after = Some(*time),
+            _ => break,
+        }
+    }
+    console
+        .write_line(format_args!("# end export, {count} records"))
+        .await?;
+    Ok(())
+}
+
+/// Write a reading as a CSV record with its checksum
+async fn write_record<R, W>(
+    console: &mut Console<R, W>,
+    (time, sample): &Reading,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    let mut record = String::<LINE_SIZE>::new();
+    write_time(&mut record, *time).map_err(|_| ConsoleError::LineTooLong)?;
+    write!(
+        record,
+        ",{:.2},{:.2},{:.1}",
+        sample.temperature.get::<degree_celsius>(),
+        sample.humidity.get::<percent>(),
+        sample.pressure.get::<hectopascal>(),
+    )
+    .map_err(|_| ConsoleError::LineTooLong)?;
+    let checksum = CRC.checksum(record.as_bytes());
+    console
+        .write_line(format_args!("{record},{checksum:08x}"))
+        .await?;
+    Ok(())
+}
+
+/// Write a configuration value on a line
+async fn write_config_value<R, W>(
+    console: &mut Console<R, W>,
+    config: &Config,
+    key: Key,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    let mut value = String::<{ MAXIMAL_VALUE_SIZE + 8 }>::new();
+    config
+        .write_value(key, &mut value)
+        .map_err(|_| ConsoleError::LineTooLong)?;
+    if let Some(unit) = key.unit() {
+        write!(value, " {unit}").map_err(|_| ConsoleError::LineTooLong)?;
+    }
+    console
+        .write_line(format_args!("{} = {value}", key.name()))
+        .await?;
+    Ok(())
+}
+
+/// An error within the shell
+#[derive(Debug)]
+pub enum Error {
+    /// The command is not known
+    UnknownCommand,
+
+    /// The command has wrong arguments
+    Usage,
+
+    /// The configuration key is not known
+    UnknownKey,
+
+    /// The time cannot be parsed
+    InvalidTime,
+
+    /// Error from the console
+    Console(#[allow(unused)] ConsoleError),
+
+    /// Error from configuration
+    Config(#[allow(unused)] ConfigError),
+
+    /// Error from the clock
+    Clock(#[allow(unused)] ClockError),
+
+    /// Error encoding a reading
+    Encoding(#[allow(unused)] EncodingError),
+
+    /// Error from the reading log
+    ReadingLog(#[allow(unused)] ReadingLogError),
+}
+
+impl Error {
+    /// Return a message for the console
+    fn message(&self) -> &'static str {
+        match self {
+            Self::UnknownCommand => "Unknown command, type help to list commands",
+            Self::Usage => HELP,
+            Self::UnknownKey => "Unknown key, type config to list keys",
+            Self::InvalidTime => "Time must be a Unix timestamp or like 2024-08-14T12:00:00Z",
+            Self::Console(ConsoleError::UnterminatedQuote) => "Unterminated quote",
+            Self::Console(ConsoleError::TooManyArguments) => "Too many arguments",
+            Self::Console(_) => "Console error",
+            Self::Config(ConfigError::InvalidValue(_)) => "Invalid value",
+            Self::Config(ConfigError::Store(_)) => "Cannot access flash",
+            Self::Clock(_) => "Cannot set clock",
+            Self::Encoding(_) => "Cannot encode reading",
+            Self::ReadingLog(_) => "Cannot read logged readings",
+        }
+    }
+}
+
+impl From<ConsoleError> for Error {
+    fn from(error: ConsoleError) -> Self {
+        Self::Console(error)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(error: ConfigError) -> Self {
+        Self::Config(error)
+    }
+}
+
+impl From<ClockError> for Error {
+    fn from(error: ClockError) -> Self {
+        Self::Clock(error)
+    }
+}
+
+impl From<EncodingError> for Error {
+    fn from(error: EncodingError) -> Self {
+        Self::Encoding(error)
+    }
+}
+
+impl From<ReadingLogError> for Error {
+    fn from(error: ReadingLogError) -> Self {
+        Self::ReadingLog(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use time::macros::datetime;
+
+    /// Split and parse a line
+    fn parse(line: &str) -> Result<Option<Command<'_>>, Error> {
+        parse_command(&split_arguments(line)?)
+    }
+
+    /// Set a configuration value on the defaults and return it as text
+    fn set(key: Key, value: &str) -> Result<String<MAXIMAL_VALUE_SIZE>, ConfigError> {
+        let mut config = Config::default();
+        config.set(key, value)?;
+        let mut text = String::new();
+        config.write_value(key, &mut text).unwrap();
+        Ok(text)
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse(""), Ok(None)));
+        assert!(matches!(parse("   "), Ok(None)));
+        assert!(matches!(parse("status"), Ok(Some(Command::Status))));
+        assert!(matches!(
+            parse("  history   dump "),
+            Ok(Some(Command::DumpHistory))
+        ));
+        assert!(matches!(
+            parse("config"),
+            Ok(Some(Command::GetConfig(None)))
+        ));
+        assert!(matches!(
+            parse("config get sda_pin"),
+            Ok(Some(Command::GetConfig(Some(Key::SdaPin))))
+        ));
+        assert!(matches!(
+            parse("config set world_time_zone America/New_York"),
+            Ok(Some(Command::SetConfig(
+                Key::WorldTimeZone,
+                "America/New_York"
+            )))
+        ));
+        assert!(matches!(
+            parse("baseline erase"),
+            Ok(Some(Command::EraseBaseline))
+        ));
+    }
+
+    #[test]
+    fn quoted_arguments() {
+        assert!(matches!(
+            parse(r#"config set time_zone "CET-1CEST,M3.5.0,M10.5.0/3""#),
+            Ok(Some(Command::SetConfig(
+                Key::TimeZone,
+                "CET-1CEST,M3.5.0,M10.5.0/3"
+            )))
+        ));
+        assert!(matches!(
+            parse(r#""config" set "time_zone" "<+03> -3""#),
+            Ok(Some(Command::SetConfig(Key::TimeZone, "<+03> -3")))
+        ));
+        assert!(matches!(
+            parse(r#"config set time_zone """#),
+            Ok(Some(Command::SetConfig(Key::TimeZone, "")))
+        ));
+    }
+
+    #[test]
+    fn unterminated_quotes() {
+        assert!(matches!(
+            parse(r#"config set time_zone "UTC0"#),
+            Err(Error::Console(ConsoleError::UnterminatedQuote))
+        ));
+        assert!(matches!(
+            parse(r#"config set time_zone "UTC"0"#),
+            Err(Error::Console(ConsoleError::UnterminatedQuote))
+        ));
+    }
+
+    #[test]
+    fn too_many_arguments() {
+        assert!(matches!(
+            parse("a b c d e f g h i"),
+            Err(Error::Console(ConsoleError::TooManyArguments))
+        ));
+    }
+
+    #[test]
+    fn unknown_commands() {
+        assert!(matches!(parse("statuss"), Err(Error::UnknownCommand)));
+        assert!(matches!(parse("STATUS"), Err(Error::UnknownCommand)));
+        assert!(matches!(parse("reboot now"), Err(Error::UnknownCommand)));
+        assert!(matches!(parse("\"\""), Err(Error::UnknownCommand)));
+    }
+
+    #[test]
+    fn wrong_arguments() {
+        assert!(matches!(parse("history all"), Err(Error::Usage)));
+        assert!(matches!(parse("config set sda_pin"), Err(Error::Usage)));
+        assert!(matches!(parse("config set sda_pin 1 2"), Err(Error::Usage)));
+        assert!(matches!(parse("config remove sda_pin"), Err(Error::Usage)));
+        assert!(matches!(parse("time set"), Err(Error::Usage)));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert!(matches!(parse("config get sda"), Err(Error::UnknownKey)));
+        assert!(matches!(
+            parse("config set SDA_PIN 1"),
+            Err(Error::UnknownKey)
+        ));
+    }
+
+    #[test]
+    fn times() {
+        assert!(matches!(
+            parse("time set 1723629600"),
+            Ok(Some(Command::SetTime(time))) if time == datetime!(2024-08-14 10:00:00 UTC)
+        ));
+        assert_eq!(
+            parse_time("2024-08-14T12:00:00+02:00").unwrap(),
+            datetime!(2024-08-14 10:00:00 UTC)
+        );
+        assert_eq!(
+            parse_time("2024-08-14 10:00:00z").unwrap(),
+            datetime!(2024-08-14 10:00:00 UTC)
+        );
+        for text in [
+            "",
+            "-1",
+            "2024-08-14T12:00:00",
+            "2024-08-14T12:00Z",
+            "2024-02-30T12:00:00Z",
+            "2024-08-14T24:00:00Z",
+            "2024-08-14T12:00:00+2:00",
+            "2024-08-14T12:00:00+02:00:00",
+            "+024-08-14T12:00:00Z",
+        ] {
+            assert!(
+                matches!(parse_time(text), Err(Error::InvalidTime)),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn set_and_get_config() {
+        assert_eq!(set(Key::SamplingPeriod, "30").unwrap(), "30");
+        assert_eq!(set(Key::SdaPin, "4").unwrap(), "4");
+        assert_eq!(set(Key::I2cFrequency, "400").unwrap(), "400");
+        assert_eq!(set(Key::TemperatureDelta, "0.5").unwrap(), "0.500");
+        assert_eq!(set(Key::BatteryDivider, "2").unwrap(), "2.000");
+        assert_eq!(set(Key::BatteryDivider, "0").unwrap(), "0.000");
+        assert_eq!(set(Key::TimeZone, "UTC0").unwrap(), "UTC0");
+    }
+
+    #[test]
+    fn set_invalid_config_values() {
+        let cases = [
+            (Key::SchemaVersion, "2"),
+            (Key::SamplingPeriod, "0"),
+            (Key::SamplingPeriod, "-1"),
+            (Key::SamplingPeriod, "1.5"),
+            (Key::SamplingPeriod, "4294967296"),
+            (Key::AwakePeriod, ""),
+            (Key::SdaPin, "3"),
+            (Key::SdaPin, "256"),
+            (Key::SdaPin, "2"),
+            (Key::SclPin, "1"),
+            (Key::I2cFrequency, "0"),
+            (Key::I2cFrequency, "401"),
+            (Key::TimeZone, "not a zone"),
+            (Key::BatteryDivider, "0.5"),
+            (Key::BatteryDivider, "1.0005"),
+            (Key::BatteryDivider, "."),
+            (Key::PressureDelta, "100.001"),
+            (Key::HumidityDelta, "+1"),
+        ];
+        for (key, value) in cases {
+            let mut config = Config::default();
+            let result = config.set(key, value);
+            assert_eq!(
+                result,
+                Err(ConfigError::InvalidValue(key)),
+                "{key:?} {value}"
+            );
+            assert_eq!(config, Config::default(), "{key:?} {value}");
+            assert_eq!(Error::from(result.unwrap_err()).message(), "Invalid value");
+        }
+    }
+}
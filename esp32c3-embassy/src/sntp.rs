@@ -0,0 +1,772 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Simple Network Time Protocol (SNTP) client
+//!
+//! See [RFC 4330](https://www.rfc-editor.org/rfc/rfc4330).
+
+use log::debug;
+use log::error;
+use log::warn;
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::dns::Error as DnsError;
+use embassy_net::udp::BindError;
+use embassy_net::udp::PacketMetadata;
+use embassy_net::udp::RecvError;
+use embassy_net::udp::SendError;
+use embassy_net::udp::UdpSocket;
+use embassy_net::IpEndpoint;
+use embassy_net::Stack;
+
+use embassy_time::with_timeout;
+use embassy_time::Duration;
+use embassy_time::Instant;
+
+use esp_wifi::wifi::WifiDevice;
+use esp_wifi::wifi::WifiStaDevice;
+
+use time::error::ComponentRange as TimeComponentRangeError;
+use time::OffsetDateTime;
+
+use crc::Digest;
+
+use crate::retained::Checksum;
+
+/// UDP port of NTP servers
+pub const NTP_PORT: u16 = 123;
+
+/// Size of an SNTP packet without extensions
+const PACKET_SIZE: usize = 48;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_TO_UNIX_SECONDS: i64 = 2_208_988_800;
+
+/// NTP version sent in requests
+const VERSION: u8 = 4;
+
+/// Mode of client packets
+const MODE_CLIENT: u8 = 3;
+
+/// Mode of server packets
+const MODE_SERVER: u8 = 4;
+
+/// Leap indicator of unsynchronized servers
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// Time to wait for a response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest number of servers that can be banned
+pub const MAXIMAL_SERVERS: usize = 8;
+
+/// Number of wakeups a server stays banned after a fatal kiss-of-death code
+///
+/// This is a day when waking up every 5 minutes.
+const BAN_WAKEUPS: u16 = 24 * 12;
+
+/// An NTP timestamp, i.e. 32.32 fixed-point seconds since 1900
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timestamp {
+    /// Seconds since 1900, wrapping every 136 years
+    pub seconds: u32,
+
+    /// Fraction of second in units of 2^-32 seconds
+    pub fraction: u32,
+}
+
+impl Timestamp {
+    /// Parse a timestamp from network byte order
+    pub fn from_bytes([a, b, c, d, e, f, g, h]: [u8; 8]) -> Self {
+        Self {
+            seconds: u32::from_be_bytes([a, b, c, d]),
+            fraction: u32::from_be_bytes([e, f, g, h]),
+        }
+    }
+
+    /// Serialize a timestamp to network byte order
+    pub fn to_bytes(self) -> [u8; 8] {
+        let [a, b, c, d] = self.seconds.to_be_bytes();
+        let [e, f, g, h] = self.fraction.to_be_bytes();
+        [a, b, c, d, e, f, g, h]
+    }
+
+    /// Convert to nanoseconds since the Unix epoch
+    ///
+    /// Timestamps with the most significant bit cleared are assumed to be in
+    /// NTP era 1, i.e. after February 2036.
+    pub fn to_unix_nanoseconds(self) -> i128 {
+        let era_offset = if self.seconds & 0x8000_0000 == 0 {
+            1_i64 << 32
+        } else {
+            0
+        };
+        let seconds = i64::from(self.seconds) + era_offset - NTP_TO_UNIX_SECONDS;
+        let nanoseconds = (u64::from(self.fraction) * 1_000_000_000) >> 32;
+        i128::from(seconds) * 1_000_000_000 + i128::from(nanoseconds)
+    }
+}
+
+/// A response from an SNTP server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Response {
+    /// Leap indicator
+    pub leap: u8,
+
+    /// Protocol version
+    pub version: u8,
+
+    /// Stratum, i.e. distance from the reference clock
+    pub stratum: u8,
+
+    /// Reference identifier
+    pub reference_id: [u8; 4],
+
+    /// Time the request left the client, as sent by the client
+    pub originate: Timestamp,
+
+    /// Time the request arrived at the server
+    pub receive: Timestamp,
+
+    /// Time the response left the server
+    pub transmit: Timestamp,
+}
+
+/// Encode a client request
+///
+/// The transmit timestamp does not need to be the actual time, it is echoed
+/// by the server and used to match the response to the request.
+pub fn encode_request(transmit: Timestamp) -> [u8; PACKET_SIZE] {
+    let mut packet = [0_u8; PACKET_SIZE];
+    packet[0] = (VERSION << 3) | MODE_CLIENT;
+    packet[40..48].copy_from_slice(&transmit.to_bytes());
+    packet
+}
+
+/// Decode a server response to a request with a transmit timestamp
+#[allow(clippy::indexing_slicing)]
+pub fn decode_response(packet: &[u8], sent: Timestamp) -> Result<Response, Error> {
+    let packet: &[u8; PACKET_SIZE] = packet
+        .get(..PACKET_SIZE)
+        .and_then(|packet| packet.try_into().ok())
+        .ok_or(Error::TooShort)?;
+
+    let timestamp_at = |offset: usize| {
+        let mut bytes = [0_u8; 8];
+        bytes.copy_from_slice(&packet[offset..offset + 8]);
+        Timestamp::from_bytes(bytes)
+    };
+
+    let response = Response {
+        leap: packet[0] >> 6,
+        version: (packet[0] >> 3) & 0b111,
+        stratum: packet[1],
+        reference_id: [packet[12], packet[13], packet[14], packet[15]],
+        originate: timestamp_at(24),
+        receive: timestamp_at(32),
+        transmit: timestamp_at(40),
+    };
+    let mode = packet[0] & 0b111;
+
+    // A packet that does not answer the request, e.g. a spoofed or stale
+    // kiss-of-death packet, must not be trusted for anything else
+    if response.originate != sent {
+        return Err(Error::OriginateMismatch);
+    }
+    if mode != MODE_SERVER {
+        return Err(Error::UnexpectedMode(mode));
+    }
+    if response.stratum == 0 {
+        return Err(Error::KissOfDeath(KissCode(response.reference_id)));
+    }
+    if response.leap == LEAP_UNSYNCHRONIZED {
+        return Err(Error::Unsynchronized);
+    }
+    if response.transmit == Timestamp::default() {
+        return Err(Error::MissingTransmitTime);
+    }
+
+    Ok(response)
+}
+
+/// Compute the current time from a response and the measured round-trip time
+///
+/// The network delay is the round-trip time minus the time spent by the
+/// server, and it is assumed to be symmetric.
+pub fn current_time(
+    response: &Response,
+    round_trip: Duration,
+) -> Result<OffsetDateTime, TimeComponentRangeError> {
+    let receive = response.receive.to_unix_nanoseconds();
+    let transmit = response.transmit.to_unix_nanoseconds();
+    let processing = (transmit - receive).max(0);
+
+    let round_trip = i128::from(round_trip.as_micros()) * 1_000;
+    let delay = (round_trip - processing).max(0);
+
+    OffsetDateTime::from_unix_timestamp_nanos(transmit + delay / 2)
+}
+
+/// Servers banned after a fatal kiss-of-death code, by index in the list
+///
+/// Bans are lifted after [`BAN_WAKEUPS`] wakeups, so that a server that
+/// denied access once, or a forged denial that slipped through, does not
+/// disable it for the whole life of the retained state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Bans {
+    /// Wakeups left before each ban is lifted, zero if not banned
+    remaining: [u16; MAXIMAL_SERVERS],
+}
+
+impl Bans {
+    /// Create an empty list of bans
+    pub const fn new() -> Self {
+        Self {
+            remaining: [0; MAXIMAL_SERVERS],
+        }
+    }
+
+    /// Check whether the server at an index is banned
+    pub fn is_banned(&self, index: usize) -> bool {
+        self.remaining
+            .get(index)
+            .is_some_and(|&remaining| remaining > 0)
+    }
+
+    /// Ban the server at an index
+    ///
+    /// Servers beyond [`MAXIMAL_SERVERS`] cannot be banned.
+    pub fn ban(&mut self, index: usize) {
+        if let Some(remaining) = self.remaining.get_mut(index) {
+            *remaining = BAN_WAKEUPS;
+        }
+    }
+
+    /// Count a wakeup, lifting the bans that expire
+    pub fn expire(&mut self) {
+        for remaining in &mut self.remaining {
+            *remaining = remaining.saturating_sub(1);
+        }
+    }
+}
+
+impl Checksum for Bans {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.remaining.feed(digest);
+    }
+}
+
+/// Fetch the current time from the first server that responds
+///
+/// Servers are skipped if they are banned.
+/// A server that sends a fatal kiss-of-death code is banned, so that it is
+/// not queried again until the ban expires.
+///
+/// Return the time together with the measured round-trip time.
+pub async fn fetch_current_time(
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    servers: &[&str],
+    bans: &mut Bans,
+) -> Result<(OffsetDateTime, Duration), Error> {
+    let mut last_error = Error::NoServers;
+    for (index, server) in servers.iter().enumerate() {
+        if bans.is_banned(index) {
+            debug!("Skip banned server {server}");
+            continue;
+        }
+        match query(stack, server).await {
+            Ok(response) => return Ok(response),
+            Err(Error::KissOfDeath(code)) if code.is_fatal() => {
+                error!("Server {server} denied access ({}), ban it", code.as_str());
+                bans.ban(index);
+                last_error = Error::KissOfDeath(code);
+            }
+            Err(error) => {
+                warn!("Cannot fetch time from {server}: {error:?}");
+                last_error = error;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// Query a server for the current time
+async fn query(
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    server: &str,
+) -> Result<(OffsetDateTime, Duration), Error> {
+    debug!("Resolve {server}");
+    let addresses = stack.dns_query(server, DnsQueryType::A).await?;
+    let address = *addresses.first().ok_or(Error::NoAddress)?;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0_u8; 2 * PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0_u8; 2 * PACKET_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0)?;
+
+    // Use the local uptime as a nonce to match the response
+    let ticks = Instant::now().as_ticks();
+    #[allow(clippy::cast_possible_truncation)]
+    let sent = Timestamp {
+        seconds: (ticks >> 32) as u32,
+        fraction: ticks as u32,
+    };
+
+    debug!("Send SNTP request to {address}");
+    let sent_at = Instant::now();
+    socket
+        .send_to(&encode_request(sent), IpEndpoint::new(address, NTP_PORT))
+        .await?;
+
+    // Packets that do not answer the request are skipped, so that a forged
+    // or late packet cannot hide the actual response
+    let receive = async {
+        let mut packet = [0_u8; 2 * PACKET_SIZE];
+        loop {
+            let (length, _) = socket.recv_from(&mut packet).await?;
+            match decode_response(packet.get(..length).unwrap_or_default(), sent) {
+                Err(Error::OriginateMismatch) => warn!("Skip SNTP packet not matching the request"),
+                result => break result,
+            }
+        }
+    };
+    let response = with_timeout(RESPONSE_TIMEOUT, receive)
+        .await
+        .map_err(|_| Error::Timeout)?;
+    let round_trip = Instant::now() - sent_at;
+    debug!("Received SNTP response after {}ms", round_trip.as_millis());
+
+    let response = response?;
+    Ok((current_time(&response, round_trip)?, round_trip))
+}
+
+/// A kiss-of-death code, sent by a server to tell the client to go away
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct KissCode(pub [u8; 4]);
+
+impl KissCode {
+    /// Return the code as text, e.g. `RATE` or `DENY`
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0).unwrap_or("????")
+    }
+
+    /// Check whether the client must stop querying this server
+    pub fn is_fatal(&self) -> bool {
+        matches!(&self.0, b"DENY" | b"RSTR")
+    }
+}
+
+impl core::fmt::Debug for KissCode {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(formatter, "KissCode({})", self.as_str())
+    }
+}
+
+/// An error within an SNTP request
+#[derive(Debug)]
+pub enum Error {
+    /// No server was configured, or all of them are banned
+    NoServers,
+
+    /// Server name resolved to no address
+    NoAddress,
+
+    /// Server did not respond in time
+    Timeout,
+
+    /// Response was shorter than an SNTP packet
+    TooShort,
+
+    /// Response was not sent by a server
+    UnexpectedMode(#[allow(unused)] u8),
+
+    /// Server sent a kiss-of-death packet
+    KissOfDeath(#[allow(unused)] KissCode),
+
+    /// Server clock is not synchronized
+    Unsynchronized,
+
+    /// Response does not match the request
+    OriginateMismatch,
+
+    /// Response does not contain the server time
+    MissingTransmitTime,
+
+    /// A time component is out of range
+    TimeComponentRange(#[allow(unused)] TimeComponentRangeError),
+
+    /// Error within DNS system
+    Dns(#[allow(unused)] DnsError),
+
+    /// Error binding the UDP socket
+    Bind(#[allow(unused)] BindError),
+
+    /// Error sending the request
+    Send(#[allow(unused)] SendError),
+
+    /// Error receiving the response
+    Recv(#[allow(unused)] RecvError),
+}
+
+impl From<TimeComponentRangeError> for Error {
+    fn from(error: TimeComponentRangeError) -> Self {
+        Self::TimeComponentRange(error)
+    }
+}
+
+impl From<DnsError> for Error {
+    fn from(error: DnsError) -> Self {
+        Self::Dns(error)
+    }
+}
+
+impl From<BindError> for Error {
+    fn from(error: BindError) -> Self {
+        Self::Bind(error)
+    }
+}
+
+impl From<SendError> for Error {
+    fn from(error: SendError) -> Self {
+        Self::Send(error)
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(error: RecvError) -> Self {
+        Self::Recv(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    use std::net::UdpSocket as StdUdpSocket;
+    use std::thread;
+
+    use time::macros::datetime;
+
+    /// Transmit timestamp of the request
+    const SENT: Timestamp = Timestamp {
+        seconds: 0x0000_1234,
+        fraction: 0x5678_9abc,
+    };
+
+    /// Time the request arrived at the server, 2023-11-14T22:13:20.25Z
+    const RECEIVE: Timestamp = Timestamp {
+        seconds: 0xe8fe_6f80,
+        fraction: 0x4000_0000,
+    };
+
+    /// Time the response left the server, 2023-11-14T22:13:20.5Z
+    const TRANSMIT: Timestamp = Timestamp {
+        seconds: 0xe8fe_6f80,
+        fraction: 0x8000_0000,
+    };
+
+    /// Response of a synchronized stratum 2 server to [`SENT`]
+    const RESPONSE: [u8; PACKET_SIZE] = [
+        0x24, 0x02, 0x00, 0xe9, // LI 0, VN 4, mode 4, stratum 2, poll, precision
+        0x00, 0x00, 0x00, 0x1d, // Root delay
+        0x00, 0x00, 0x00, 0x2b, // Root dispersion
+        0xc0, 0xa8, 0x00, 0x01, // Reference identifier, upstream 192.168.0.1
+        0xe8, 0xfe, 0x6f, 0x00, 0x00, 0x00, 0x00, 0x00, // Reference timestamp
+        0x00, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, // Originate timestamp
+        0xe8, 0xfe, 0x6f, 0x80, 0x40, 0x00, 0x00, 0x00, // Receive timestamp
+        0xe8, 0xfe, 0x6f, 0x80, 0x80, 0x00, 0x00, 0x00, // Transmit timestamp
+    ];
+
+    /// Kiss-of-death response denying access to [`SENT`]
+    const DENY: [u8; PACKET_SIZE] = [
+        0xe4, 0x00, 0x00, 0x00, // LI 3, VN 4, mode 4, stratum 0
+        0x00, 0x00, 0x00, 0x00, // Root delay
+        0x00, 0x00, 0x00, 0x00, // Root dispersion
+        b'D', b'E', b'N', b'Y', // Kiss code
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Reference timestamp
+        0x00, 0x00, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, // Originate timestamp
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Receive timestamp
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Transmit timestamp
+    ];
+
+    /// Return a packet with a change applied
+    fn packet_with(
+        packet: [u8; PACKET_SIZE],
+        change: impl FnOnce(&mut [u8; PACKET_SIZE]),
+    ) -> [u8; PACKET_SIZE] {
+        let mut packet = packet;
+        change(&mut packet);
+        packet
+    }
+
+    /// Answer requests like a server, with a response prepared for each
+    /// request
+    ///
+    /// Each response is sent with the originate timestamp set to the
+    /// transmit timestamp of the request, unless `echo` is false.
+    fn stand_in_server(responses: std::vec::Vec<([u8; PACKET_SIZE], bool)>) -> StdUdpSocket {
+        let server = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+            .unwrap();
+
+        thread::spawn(move || {
+            let mut request = [0_u8; 2 * PACKET_SIZE];
+            let (length, client) = server.recv_from(&mut request).unwrap();
+            assert_eq!(length, PACKET_SIZE);
+            for (mut response, echo) in responses {
+                if echo {
+                    response[24..32].copy_from_slice(&request[40..48]);
+                }
+                server.send_to(&response, client).unwrap();
+            }
+        });
+
+        client
+    }
+
+    #[test]
+    fn request_is_a_version_4_client_packet() {
+        let request = encode_request(SENT);
+        assert_eq!(request[0], 0x23);
+        assert_eq!(&request[40..48], &SENT.to_bytes());
+        assert!(request[1..40].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn timestamp_round_trips_through_bytes() {
+        let bytes = [0xe8, 0xfe, 0x6f, 0x80, 0x80, 0x00, 0x00, 0x00];
+        assert_eq!(Timestamp::from_bytes(bytes), TRANSMIT);
+        assert_eq!(TRANSMIT.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn timestamp_converts_to_unix_time() {
+        assert_eq!(TRANSMIT.to_unix_nanoseconds(), 1_700_000_000_500_000_000);
+        assert_eq!(
+            Timestamp {
+                seconds: 2_208_988_800,
+                fraction: 0,
+            }
+            .to_unix_nanoseconds(),
+            0
+        );
+        assert_eq!(
+            Timestamp {
+                seconds: 2_208_988_800,
+                fraction: 1,
+            }
+            .to_unix_nanoseconds(),
+            0
+        );
+        assert_eq!(
+            Timestamp {
+                seconds: 2_208_988_800,
+                fraction: u32::MAX,
+            }
+            .to_unix_nanoseconds(),
+            999_999_999
+        );
+    }
+
+    #[test]
+    fn timestamp_wraps_to_era_1_in_2036() {
+        let last_of_era_0 = Timestamp {
+            seconds: u32::MAX,
+            fraction: 0,
+        };
+        let first_of_era_1 = Timestamp {
+            seconds: 0,
+            fraction: 0,
+        };
+        assert_eq!(
+            last_of_era_0.to_unix_nanoseconds(),
+            2_085_978_495_000_000_000
+        );
+        assert_eq!(
+            first_of_era_1.to_unix_nanoseconds(),
+            2_085_978_496_000_000_000
+        );
+    }
+
+    #[test]
+    fn decode_response_of_synchronized_server() {
+        let response = decode_response(&RESPONSE, SENT).unwrap();
+        assert_eq!(response.leap, 0);
+        assert_eq!(response.version, 4);
+        assert_eq!(response.stratum, 2);
+        assert_eq!(response.reference_id, [192, 168, 0, 1]);
+        assert_eq!(response.originate, SENT);
+        assert_eq!(response.receive, RECEIVE);
+        assert_eq!(response.transmit, TRANSMIT);
+    }
+
+    #[test]
+    fn decode_response_ignores_extensions() {
+        let mut packet = [0_u8; PACKET_SIZE + 20];
+        packet[..PACKET_SIZE].copy_from_slice(&RESPONSE);
+        assert!(decode_response(&packet, SENT).is_ok());
+    }
+
+    #[test]
+    fn decode_rejects_short_packet() {
+        assert!(matches!(
+            decode_response(&RESPONSE[..PACKET_SIZE - 1], SENT),
+            Err(Error::TooShort)
+        ));
+        assert!(matches!(decode_response(&[], SENT), Err(Error::TooShort)));
+    }
+
+    #[test]
+    fn decode_rejects_wrong_mode() {
+        // A client request reflected back
+        let packet = packet_with(RESPONSE, |packet| packet[0] = 0x23);
+        assert!(matches!(
+            decode_response(&packet, SENT),
+            Err(Error::UnexpectedMode(3))
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_wrong_originate() {
+        let stale = Timestamp {
+            seconds: SENT.seconds,
+            fraction: SENT.fraction + 1,
+        };
+        assert!(matches!(
+            decode_response(&RESPONSE, stale),
+            Err(Error::OriginateMismatch)
+        ));
+    }
+
+    #[test]
+    fn decode_kiss_of_death() {
+        let Err(Error::KissOfDeath(code)) = decode_response(&DENY, SENT) else {
+            panic!("Kiss-of-death packet was not rejected");
+        };
+        assert_eq!(code.as_str(), "DENY");
+        assert!(code.is_fatal());
+
+        let rate = packet_with(DENY, |packet| packet[12..16].copy_from_slice(b"RATE"));
+        let Err(Error::KissOfDeath(code)) = decode_response(&rate, SENT) else {
+            panic!("Kiss-of-death packet was not rejected");
+        };
+        assert_eq!(code.as_str(), "RATE");
+        assert!(!code.is_fatal());
+    }
+
+    #[test]
+    fn decode_ignores_kiss_of_death_to_another_request() {
+        let stale = Timestamp {
+            seconds: SENT.seconds + 1,
+            fraction: SENT.fraction,
+        };
+        assert!(matches!(
+            decode_response(&DENY, stale),
+            Err(Error::OriginateMismatch)
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_unsynchronized_server() {
+        let packet = packet_with(RESPONSE, |packet| packet[0] |= 0xc0);
+        assert!(matches!(
+            decode_response(&packet, SENT),
+            Err(Error::Unsynchronized)
+        ));
+    }
+
+    #[test]
+    fn decode_rejects_missing_transmit_time() {
+        let packet = packet_with(RESPONSE, |packet| packet[40..48].fill(0));
+        assert!(matches!(
+            decode_response(&packet, SENT),
+            Err(Error::MissingTransmitTime)
+        ));
+    }
+
+    #[test]
+    fn current_time_compensates_half_of_network_delay() {
+        let response = decode_response(&RESPONSE, SENT).unwrap();
+
+        // 250 ms of processing and 100 ms of network delay
+        let now = current_time(&response, Duration::from_millis(350)).unwrap();
+        assert_eq!(now, datetime!(2023-11-14 22:13:20.55 UTC));
+
+        // A round trip shorter than the processing time is not negative
+        let now = current_time(&response, Duration::from_millis(100)).unwrap();
+        assert_eq!(now, datetime!(2023-11-14 22:13:20.5 UTC));
+    }
+
+    #[test]
+    fn bans_expire_after_wakeups() {
+        let mut bans = Bans::new();
+        bans.ban(1);
+        assert!(!bans.is_banned(0));
+        assert!(bans.is_banned(1));
+
+        for _ in 1..BAN_WAKEUPS {
+            bans.expire();
+        }
+        assert!(bans.is_banned(1));
+        bans.expire();
+        assert!(!bans.is_banned(1));
+        assert_eq!(bans, Bans::new());
+    }
+
+    #[test]
+    fn bans_beyond_maximal_servers_are_ignored() {
+        let mut bans = Bans::new();
+        bans.ban(MAXIMAL_SERVERS);
+        assert!(!bans.is_banned(MAXIMAL_SERVERS));
+        assert_eq!(bans, Bans::new());
+    }
+
+    #[test]
+    fn query_stand_in_server() {
+        let client = stand_in_server(std::vec![(RESPONSE, true)]);
+        client.send(&encode_request(SENT)).unwrap();
+
+        let mut packet = [0_u8; 2 * PACKET_SIZE];
+        let length = client.recv(&mut packet).unwrap();
+        let response = decode_response(&packet[..length], SENT).unwrap();
+        assert_eq!(response.transmit, TRANSMIT);
+    }
+
+    #[test]
+    fn spoofed_kiss_of_death_from_stand_in_server_is_ignored() {
+        let client = stand_in_server(std::vec![(DENY, false), (RESPONSE, true)]);
+        let sent = Timestamp {
+            seconds: 0x0000_4321,
+            fraction: 1,
+        };
+        client.send(&encode_request(sent)).unwrap();
+
+        let mut packet = [0_u8; 2 * PACKET_SIZE];
+        let length = client.recv(&mut packet).unwrap();
+        assert!(matches!(
+            decode_response(&packet[..length], sent),
+            Err(Error::OriginateMismatch)
+        ));
+
+        let length = client.recv(&mut packet).unwrap();
+        let response = decode_response(&packet[..length], sent).unwrap();
+        assert_eq!(response.originate, sent);
+    }
+}
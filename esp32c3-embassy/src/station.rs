@@ -0,0 +1,228 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Shell on the serial console of the running station
+//!
+//! This module connects the [shell][crate::shell] to the UART and to the
+//! tasks of the firmware.
+
+use core::ops::ControlFlow;
+
+use log::error;
+use log::info;
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::MutexGuard;
+
+use embassy_time::Duration;
+use embassy_time::Instant;
+
+use esp_hal::peripherals::UART0;
+use esp_hal::reset::software_reset;
+use esp_hal::uart::Uart;
+use esp_hal::Async;
+
+use esp_storage::FlashStorage;
+
+use heapless::Vec;
+
+use time::Date;
+use time::Duration as TimeDuration;
+use time::OffsetDateTime;
+
+use crate::clock::Error as ClockError;
+use crate::config::Config;
+use crate::config::Error as ConfigError;
+use crate::config::Key;
+use crate::config::BASELINE_KEY;
+use crate::console::Console;
+use crate::display::REFRESH_REQUESTED;
+use crate::domain::Reading;
+use crate::energy::Policy as EnergyPolicy;
+use crate::energy::Report as EnergyReport;
+use crate::energy::CONSUMPTION;
+use crate::history::MINUTES as HISTORY_MINUTES;
+use crate::kv_store::Store;
+use crate::reading_log::Error as ReadingLogError;
+use crate::reading_log::ReadingLog;
+use crate::sensor::SAMPLE_REQUESTED;
+use crate::shell::run as run_shell;
+use crate::shell::Station;
+use crate::shell::Status;
+use crate::shell::EXPORT_BATCH_SIZE;
+use crate::SharedClock;
+use crate::SharedEnergy;
+use crate::SharedHistory;
+use crate::SharedReadingLog;
+
+/// The running station, as seen from the shell
+pub struct RunningStation {
+    /// Number of boots since the last cold boot
+    boot_count: u32,
+
+    /// Configuration, including changes not applied yet
+    config: Config,
+
+    /// Store of the configuration
+    store: Store<FlashStorage>,
+
+    /// Shared clock
+    clock: &'static SharedClock,
+
+    /// Shared history of readings
+    history: &'static SharedHistory,
+
+    /// Shared log of readings in flash, if mounted
+    reading_log: Option<&'static SharedReadingLog>,
+
+    /// Lock on the reading log during an export
+    ///
+    /// Readings are logged before entering deep sleep, so holding the lock
+    /// keeps the station awake until the export completes.
+    export: Option<MutexGuard<'static, NoopRawMutex, ReadingLog<FlashStorage>>>,
+
+    /// Shared state of the energy policy
+    energy: &'static SharedEnergy,
+
+    /// Energy policy
+    energy_policy: EnergyPolicy,
+}
+
+impl RunningStation {
+    /// Create a station
+    pub fn new(
+        boot_count: u32,
+        config: Config,
+        store: Store<FlashStorage>,
+        clock: &'static SharedClock,
+        history: &'static SharedHistory,
+        reading_log: Option<&'static SharedReadingLog>,
+        energy: &'static SharedEnergy,
+        energy_policy: EnergyPolicy,
+    ) -> Self {
+        Self {
+            boot_count,
+            config,
+            store,
+            clock,
+            history,
+            reading_log,
+            export: None,
+            energy,
+            energy_policy,
+        }
+    }
+}
+
+impl Station for RunningStation {
+    async fn status(&mut self) -> Status {
+        let (now, clock_source, clock_error) = {
+            let clock = self.clock.lock().await;
+            (clock.now().ok(), clock.source(), clock.estimated_error())
+        };
+        let history = self.history.lock().await;
+        Status {
+            boot_count: self.boot_count,
+            uptime: Duration::from_ticks(Instant::now().as_ticks()),
+            now,
+            clock_source,
+            clock_error,
+            readings: history.len(),
+            latest: history.recent(),
+        }
+    }
+
+    async fn readings(&mut self) -> Vec<Reading, HISTORY_MINUTES> {
+        self.history.lock().await.readings().collect()
+    }
+
+    async fn logged_readings(
+        &mut self,
+        after: Option<OffsetDateTime>,
+    ) -> Result<Vec<Reading, EXPORT_BATCH_SIZE>, ReadingLogError> {
+        if after.is_none() {
+            if let Some(reading_log) = self.reading_log {
+                self.export = Some(reading_log.lock().await);
+            }
+        }
+        let Some(reading_log) = self.export.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let from = after.map_or(OffsetDateTime::UNIX_EPOCH, |after| {
+            after + TimeDuration::SECOND
+        });
+        let to = Date::MAX.midnight().assume_utc();
+        let mut readings = Vec::new();
+        let result = reading_log.range(from, to, |reading| {
+            // The visitor stops before the buffer overflows
+            let _ = readings.push(reading);
+            if readings.is_full() {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        result.map(|()| readings)
+    }
+
+    fn end_export(&mut self) {
+        self.export = None;
+    }
+
+    async fn energy(&mut self) -> EnergyReport {
+        self.energy
+            .lock()
+            .await
+            .report(&self.energy_policy, &CONSUMPTION)
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn set_config(&mut self, key: Key, value: &str) -> Result<(), ConfigError> {
+        self.config.set(key, value)?;
+        self.config.save(&mut self.store)
+    }
+
+    fn request_sample(&mut self) {
+        SAMPLE_REQUESTED.signal(());
+    }
+
+    fn request_refresh(&mut self) {
+        REFRESH_REQUESTED.signal(());
+    }
+
+    async fn set_time(&mut self, now: OffsetDateTime) -> Result<OffsetDateTime, ClockError> {
+        let mut clock = self.clock.lock().await;
+        clock.set_manually(now);
+        clock.now()
+    }
+
+    fn baseline<'b>(&mut self, buffer: &'b mut [u8]) -> Result<Option<&'b [u8]>, ConfigError> {
+        Ok(self.store.fetch(BASELINE_KEY, buffer)?)
+    }
+
+    fn erase_baseline(&mut self) -> Result<(), ConfigError> {
+        Ok(self.store.remove(BASELINE_KEY)?)
+    }
+
+    fn reboot(&mut self) {
+        software_reset();
+    }
+}
+
+/// Run the shell on the UART
+#[embassy_executor::task]
+pub async fn shell_task(uart: Uart<'static, UART0, Async>, mut station: RunningStation) {
+    info!("Start shell on serial console");
+    let (tx, rx) = uart.split();
+    let mut console = Console::new(rx, tx);
+    if let Err(error) = run_shell(&mut console, &mut station).await {
+        error!("Shell stopped: {error:?}");
+    }
+}
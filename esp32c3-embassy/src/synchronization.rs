@@ -0,0 +1,129 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Policy for synchronizing the clock with network time sources
+//!
+//! Connecting to WiFi is the most expensive operation of a wakeup, so the
+//! clock is only synchronized when needed:
+//!
+//! * when there is no saved clock, e.g. after a cold boot,
+//! * after a sleep longer than [`Policy::long_sleep`],
+//! * when the last synchronization is older than [`Policy::maximum_interval`],
+//! * when the estimated clock error exceeds [`Policy::maximum_error`].
+
+use embassy_time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::clock::Clock;
+
+/// A source of time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source {
+    /// SNTP server
+    Sntp,
+
+    /// World Time API
+    WorldTimeApi,
+
+    /// External real-time clock
+    ExternalRtc,
+
+    /// Clock saved in RTC Fast memory
+    RtcMemory,
+
+    /// Time of compilation
+    BuildTime,
+
+    /// Time entered on the serial console
+    Manual,
+}
+
+impl Source {
+    /// Return a short label for the dashboard
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Sntp => "SNTP",
+            Self::WorldTimeApi => "HTTP",
+            Self::ExternalRtc => "RTC",
+            Self::RtcMemory => "MEM",
+            Self::BuildTime => "BUILD",
+            Self::Manual => "USER",
+        }
+    }
+
+    /// Check whether the source is reached over the network
+    ///
+    /// Only network sources are accurate enough to calibrate the drift.
+    pub fn is_network(self) -> bool {
+        matches!(self, Self::Sntp | Self::WorldTimeApi)
+    }
+}
+
+/// Reason for synchronizing the clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// There is no saved clock
+    NoClock,
+
+    /// The device slept for a long time
+    LongSleep,
+
+    /// The last synchronization is too old
+    Interval,
+
+    /// The estimated clock error is too large
+    Error,
+}
+
+/// Policy for synchronizing the clock
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    /// Longest time between synchronizations
+    pub maximum_interval: Duration,
+
+    /// Sleep duration after which the saved clock is not trusted
+    pub long_sleep: Duration,
+
+    /// Largest estimated clock error
+    pub maximum_error: Duration,
+}
+
+impl Policy {
+    /// Decide whether the clock must be synchronized
+    ///
+    /// Return the reason for synchronizing, or `None` if the saved clock can
+    /// be used as it is.
+    pub fn reason(&self, clock: Option<&Clock>, slept: Duration) -> Option<Reason> {
+        let Some(clock) = clock else {
+            return Some(Reason::NoClock);
+        };
+
+        if slept >= self.long_sleep {
+            Some(Reason::LongSleep)
+        } else if clock.since_last_synchronization() >= self.maximum_interval {
+            Some(Reason::Interval)
+        } else if clock.needs_synchronization(self.maximum_error) {
+            Some(Reason::Error)
+        } else {
+            None
+        }
+    }
+}
+
+/// Synchronization status of a clock, for display
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Status {
+    /// Local time of the last successful synchronization
+    pub last_synchronization: OffsetDateTime,
+
+    /// Source of the last successful synchronization
+    pub source: Source,
+
+    /// Whether the last synchronization attempt failed
+    pub uncertain: bool,
+}
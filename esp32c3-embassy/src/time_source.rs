@@ -0,0 +1,440 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Sources of the current time
+//!
+//! A [`Clock`][crate::clock::Clock] is set from the first available source
+//! in a configurable priority order.
+//! Every source reports the current time in UTC together with its accuracy,
+//! so that the clock knows how much to trust it.
+
+use embassy_net::Stack;
+
+use embassy_time::Duration;
+use embassy_time::Instant;
+
+use esp_wifi::wifi::WifiDevice;
+use esp_wifi::wifi::WifiStaDevice;
+
+use time::error::ComponentRange as TimeComponentRangeError;
+use time::OffsetDateTime;
+
+use crate::external_rtc::Error as ExternalRtcError;
+use crate::external_rtc::Rtc;
+use crate::retained::SavedClock;
+use crate::sntp::fetch_current_time as fetch_sntp_time;
+use crate::sntp::Bans;
+use crate::sntp::Error as SntpError;
+use crate::synchronization::Source;
+use crate::worldtimeapi::Error as WorldTimeApiError;
+use crate::worldtimeapi::Format as WorldTimeApiFormat;
+use crate::worldtimeapi::WorldTimeApiClient;
+
+/// Accuracy of World Time API, which only reports whole seconds
+const WORLD_TIME_API_ACCURACY: Duration = Duration::from_secs(1);
+
+/// Accuracy of external RTCs, which only report whole seconds
+const EXTERNAL_RTC_ACCURACY: Duration = Duration::from_secs(1);
+
+/// Accuracy of a source that does not know how far off it is
+///
+/// This is larger than any sensible synchronization threshold.
+pub const UNKNOWN_ACCURACY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Current time reported by a source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourcedTime {
+    /// Current time
+    pub now: OffsetDateTime,
+
+    /// Largest expected error
+    pub accuracy: Duration,
+}
+
+/// A source of the current time
+pub trait TimeSource {
+    /// Return the kind of source
+    fn source(&self) -> Source;
+
+    /// Fetch the current time
+    async fn fetch(&mut self) -> Result<SourcedTime, Error>;
+}
+
+/// SNTP servers
+pub struct SntpSource<'banned> {
+    /// Network stack
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+
+    /// Server names, in order of preference
+    servers: &'static [&'static str],
+
+    /// Servers that denied access
+    banned: &'banned mut Bans,
+}
+
+impl<'banned> SntpSource<'banned> {
+    /// Create a source from a list of SNTP servers
+    pub fn new(
+        stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+        servers: &'static [&'static str],
+        banned: &'banned mut Bans,
+    ) -> Self {
+        Self {
+            stack,
+            servers,
+            banned,
+        }
+    }
+}
+
+impl TimeSource for SntpSource<'_> {
+    fn source(&self) -> Source {
+        Source::Sntp
+    }
+
+    async fn fetch(&mut self) -> Result<SourcedTime, Error> {
+        let (now, round_trip) = fetch_sntp_time(self.stack, self.servers, self.banned).await?;
+
+        // In the worst case the whole delay is on one direction
+        Ok(SourcedTime {
+            now,
+            accuracy: round_trip,
+        })
+    }
+}
+
+/// World Time API
+pub struct WorldTimeApiSource<'client, C> {
+    /// HTTP client
+    client: &'client mut C,
+
+    /// Name of the time zone to query, e.g. `Europe/Copenhagen`
+    time_zone: &'client str,
+
+    /// Format of responses
+    format: WorldTimeApiFormat,
+}
+
+impl<'client, C> WorldTimeApiSource<'client, C> {
+    /// Create a source from an HTTP client
+    pub fn new(
+        client: &'client mut C,
+        time_zone: &'client str,
+        format: WorldTimeApiFormat,
+    ) -> Self {
+        Self {
+            client,
+            time_zone,
+            format,
+        }
+    }
+}
+
+impl<C> TimeSource for WorldTimeApiSource<'_, C>
+where
+    C: WorldTimeApiClient,
+{
+    fn source(&self) -> Source {
+        Source::WorldTimeApi
+    }
+
+    async fn fetch(&mut self) -> Result<SourcedTime, Error> {
+        let now = self
+            .client
+            .fetch_current_time(self.time_zone, self.format)
+            .await?;
+        Ok(SourcedTime {
+            now,
+            accuracy: WORLD_TIME_API_ACCURACY,
+        })
+    }
+}
+
+/// External real-time clock
+pub struct ExternalRtcSource<'rtc, R> {
+    /// Real-time clock
+    rtc: &'rtc mut R,
+}
+
+impl<'rtc, R> ExternalRtcSource<'rtc, R> {
+    /// Create a source from an external real-time clock
+    pub fn new(rtc: &'rtc mut R) -> Self {
+        Self { rtc }
+    }
+}
+
+impl<R> TimeSource for ExternalRtcSource<'_, R>
+where
+    R: Rtc,
+{
+    fn source(&self) -> Source {
+        Source::ExternalRtc
+    }
+
+    async fn fetch(&mut self) -> Result<SourcedTime, Error> {
+        let now = self.rtc.read_time().await?;
+        Ok(SourcedTime {
+            now,
+            accuracy: EXTERNAL_RTC_ACCURACY,
+        })
+    }
+}
+
+/// Clock saved in RTC Fast memory before deep sleep
+pub struct RtcMemorySource {
+    /// Saved clock, if any
+    saved: Option<SavedClock>,
+}
+
+impl RtcMemorySource {
+    /// Create a source from a saved clock
+    pub fn new(saved: Option<SavedClock>) -> Self {
+        Self { saved }
+    }
+}
+
+impl TimeSource for RtcMemorySource {
+    fn source(&self) -> Source {
+        Source::RtcMemory
+    }
+
+    async fn fetch(&mut self) -> Result<SourcedTime, Error> {
+        let saved = self.saved.as_ref().ok_or(Error::Unavailable)?;
+        let now = saved.wakeup_time + Instant::now().as_micros();
+        let error = saved.drift.estimated_error(now);
+        let accuracy = Duration::from_micros(error.saturating_add(saved.accuracy));
+        Ok(SourcedTime {
+            now: from_epoch_micros(now)?,
+            accuracy,
+        })
+    }
+}
+
+/// Time of compilation
+///
+/// This is only useful as a last resort, since the device might have been
+/// flashed long ago.
+pub struct BuildTimeSource;
+
+impl TimeSource for BuildTimeSource {
+    fn source(&self) -> Source {
+        Source::BuildTime
+    }
+
+    async fn fetch(&mut self) -> Result<SourcedTime, Error> {
+        let build_time: i64 = env!("BUILD_TIME").parse().map_err(|_| Error::Unavailable)?;
+        Ok(SourcedTime {
+            now: OffsetDateTime::from_unix_timestamp(build_time)?,
+            accuracy: UNKNOWN_ACCURACY,
+        })
+    }
+}
+
+/// Any of the available sources
+///
+/// Sources cannot be used as trait objects, so they are collected in a list
+/// of this type instead.
+pub enum AnySource<'a, C, R> {
+    /// SNTP servers
+    Sntp(SntpSource<'a>),
+
+    /// World Time API
+    WorldTimeApi(WorldTimeApiSource<'a, C>),
+
+    /// External real-time clock
+    ExternalRtc(ExternalRtcSource<'a, R>),
+
+    /// Clock saved in RTC Fast memory
+    RtcMemory(RtcMemorySource),
+
+    /// Time of compilation
+    BuildTime(BuildTimeSource),
+}
+
+impl<C, R> TimeSource for AnySource<'_, C, R>
+where
+    C: WorldTimeApiClient,
+    R: Rtc,
+{
+    fn source(&self) -> Source {
+        match self {
+            Self::Sntp(source) => source.source(),
+            Self::WorldTimeApi(source) => source.source(),
+            Self::ExternalRtc(source) => source.source(),
+            Self::RtcMemory(source) => source.source(),
+            Self::BuildTime(source) => source.source(),
+        }
+    }
+
+    async fn fetch(&mut self) -> Result<SourcedTime, Error> {
+        match self {
+            Self::Sntp(source) => source.fetch().await,
+            Self::WorldTimeApi(source) => source.fetch().await,
+            Self::ExternalRtc(source) => source.fetch().await,
+            Self::RtcMemory(source) => source.fetch().await,
+            Self::BuildTime(source) => source.fetch().await,
+        }
+    }
+}
+
+/// Convert microseconds since Unix epoch to a UTC time
+pub fn from_epoch_micros(epoch: u64) -> Result<OffsetDateTime, TimeComponentRangeError> {
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from(epoch) * 1_000)
+}
+
+/// Convert a UTC time to microseconds since Unix epoch
+///
+/// Times before the Unix epoch are clamped to it.
+pub fn to_epoch_micros(now: OffsetDateTime) -> u64 {
+    u64::try_from(now.unix_timestamp_nanos() / 1_000).unwrap_or(0)
+}
+
+/// An error within a time source
+#[derive(Debug)]
+pub enum Error {
+    /// The source has no time to report
+    Unavailable,
+
+    /// Error from SNTP servers
+    Sntp(#[allow(unused)] SntpError),
+
+    /// Error from World Time API
+    WorldTimeApi(#[allow(unused)] WorldTimeApiError),
+
+    /// Error from the external real-time clock
+    ExternalRtc(#[allow(unused)] ExternalRtcError),
+
+    /// A time component is out of range
+    TimeComponentRange(#[allow(unused)] TimeComponentRangeError),
+}
+
+impl From<SntpError> for Error {
+    fn from(error: SntpError) -> Self {
+        Self::Sntp(error)
+    }
+}
+
+impl From<WorldTimeApiError> for Error {
+    fn from(error: WorldTimeApiError) -> Self {
+        Self::WorldTimeApi(error)
+    }
+}
+
+impl From<ExternalRtcError> for Error {
+    fn from(error: ExternalRtcError) -> Self {
+        Self::ExternalRtc(error)
+    }
+}
+
+impl From<TimeComponentRangeError> for Error {
+    fn from(error: TimeComponentRangeError) -> Self {
+        Self::TimeComponentRange(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embassy_futures::block_on;
+
+    use time::macros::datetime;
+
+    use crate::drift::Drift;
+
+    /// A real-time clock that reports a fixed time or fails
+    struct FakeRtc {
+        /// Time to report, or `None` if the chip lost time
+        time: Option<OffsetDateTime>,
+    }
+
+    impl Rtc for FakeRtc {
+        async fn read_time(&mut self) -> Result<OffsetDateTime, ExternalRtcError> {
+            self.time.ok_or(ExternalRtcError::TimeLost)
+        }
+
+        async fn write_time(&mut self, now: OffsetDateTime) -> Result<(), ExternalRtcError> {
+            self.time = Some(now);
+            Ok(())
+        }
+
+        async fn read_temperature(&mut self) -> Result<Option<f32>, ExternalRtcError> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn epoch_conversions() {
+        let time = datetime!(2023-11-14 22:13:20.123456 UTC);
+        assert_eq!(to_epoch_micros(time), 1_700_000_000_123_456);
+        assert_eq!(from_epoch_micros(1_700_000_000_123_456).unwrap(), time);
+        assert_eq!(to_epoch_micros(datetime!(1969-12-31 23:59 UTC)), 0);
+    }
+
+    #[test]
+    fn rtc_memory_without_saved_clock_is_unavailable() {
+        let mut source = RtcMemorySource::new(None);
+        assert_eq!(source.source(), Source::RtcMemory);
+        assert!(matches!(block_on(source.fetch()), Err(Error::Unavailable)));
+    }
+
+    #[test]
+    fn rtc_memory_adds_drift_error_to_accuracy() {
+        let wakeup_time = to_epoch_micros(datetime!(2024-06-01 12:00 UTC));
+        let saved = SavedClock {
+            wakeup_time,
+            sleep_duration: 60_000_000,
+            drift: Drift::new(wakeup_time),
+            source: Source::Sntp,
+            accuracy: 10_000,
+            uncertain: false,
+        };
+        let mut source = RtcMemorySource::new(Some(saved));
+
+        let time = block_on(source.fetch()).unwrap();
+
+        assert!(time.now >= datetime!(2024-06-01 12:00 UTC));
+        assert!(time.now < datetime!(2024-06-01 12:00:01 UTC));
+        assert!(time.accuracy >= Duration::from_millis(60));
+        assert!(time.accuracy < Duration::from_millis(70));
+    }
+
+    #[test]
+    fn build_time_has_unknown_accuracy() {
+        let build_time: i64 = env!("BUILD_TIME").parse().unwrap();
+        let mut source = BuildTimeSource;
+
+        let time = block_on(source.fetch()).unwrap();
+
+        assert_eq!(source.source(), Source::BuildTime);
+        assert_eq!(time.now.unix_timestamp(), build_time);
+        assert_eq!(time.accuracy, UNKNOWN_ACCURACY);
+    }
+
+    #[test]
+    fn external_rtc_reports_its_time() {
+        let mut rtc = FakeRtc {
+            time: Some(datetime!(2024-06-01 12:00 UTC)),
+        };
+        let mut source = ExternalRtcSource::new(&mut rtc);
+
+        let time = block_on(source.fetch()).unwrap();
+
+        assert_eq!(source.source(), Source::ExternalRtc);
+        assert_eq!(time.now, datetime!(2024-06-01 12:00 UTC));
+        assert_eq!(time.accuracy, EXTERNAL_RTC_ACCURACY);
+    }
+
+    #[test]
+    fn external_rtc_that_lost_time_fails() {
+        let mut rtc = FakeRtc { time: None };
+        let mut source = ExternalRtcSource::new(&mut rtc);
+        assert!(matches!(
+            block_on(source.fetch()),
+            Err(Error::ExternalRtc(ExternalRtcError::TimeLost))
+        ));
+    }
+}
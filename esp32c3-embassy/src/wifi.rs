@@ -5,18 +5,33 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 //! Functions and task for WiFi connection
+//!
+//! The network stack can only be created once, so WiFi is initialized on the
+//! first connection and later connections restart the same controller.
+//!
+//! Stored networks are tried in order of signal strength, and WiFi can
+//! alternatively be started as an access point for provisioning.
+
+use core::mem::replace;
 
 use log::debug;
 use log::error;
 use log::info;
+use log::warn;
 
 use embassy_executor::Spawner;
 
+use embassy_futures::select::select;
+use embassy_futures::select::Either;
+
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::signal::Signal;
 
+use esp_wifi::wifi::AccessPointConfiguration;
+use esp_wifi::wifi::AuthMethod;
 use esp_wifi::wifi::ClientConfiguration;
 use esp_wifi::wifi::Configuration;
+use esp_wifi::wifi::WifiApDevice;
 use esp_wifi::wifi::WifiController;
 use esp_wifi::wifi::WifiDevice;
 use esp_wifi::wifi::WifiError as EspWifiError;
@@ -24,12 +39,17 @@
 use esp_wifi::wifi::WifiStaDevice;
 use esp_wifi::wifi::WifiState;
 use esp_wifi::EspWifiInitFor;
+use esp_wifi::EspWifiInitialization;
 use esp_wifi::InitializationError as WifiInitializationError;
 
 use embassy_net::Config;
 use embassy_net::DhcpConfig;
+use embassy_net::Ipv4Address;
+use embassy_net::Ipv4Cidr;
 use embassy_net::Stack;
 use embassy_net::StackResources;
+use embassy_net::StaticConfigV4;
+use embassy_time::with_timeout;
 use embassy_time::Duration;
 use embassy_time::Timer;
 
@@ -43,11 +63,15 @@
 use esp_hal::timer::PeriodicTimer;
 
 use heapless::String;
+use heapless::Vec;
 
 use static_cell::StaticCell;
 
 use rand_core::RngCore as _;
 
+use crate::captive_portal::ADDRESS as ACCESS_POINT_ADDRESS;
+use crate::captive_portal::PREFIX_LENGTH as ACCESS_POINT_PREFIX_LENGTH;
+use crate::credentials::Credentials;
 use crate::RngWrapper;
 
 /// Static cell for network stack resources
@@ -56,34 +80,216 @@
 /// Static cell for network stack
 static STACK: StaticCell<Stack<WifiDevice<'static, WifiStaDevice>>> = StaticCell::new();
 
+/// Static cell for access point network stack resources
+static ACCESS_POINT_STACK_RESOURCES: StaticCell<StackResources<4>> = StaticCell::new();
+
+/// Static cell for access point network stack
+static ACCESS_POINT_STACK: StaticCell<Stack<WifiDevice<'static, WifiApDevice>>> = StaticCell::new();
+
+/// Maximal number of access points returned by a scan
+const SCAN_SIZE: usize = 16;
+
 /// Signal to request to stop WiFi
-pub static STOP_WIFI_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static STOP_WIFI_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signal to request to start WiFi again after it was stopped
+static START_WIFI_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signal that WiFi was stopped
+static WIFI_STOPPED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Time to wait for WiFi to stop
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time to wait before retrying a failed connection
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A WiFi interface
+pub struct Wifi {
+    /// State of the interface
+    state: State,
+
+    /// Networks to connect to
+    credentials: Credentials,
+}
+
+/// State of a WiFi interface
+enum State {
+    /// WiFi was never initialized
+    Uninitialized {
+        /// Timer group for WiFi
+        timg0: TIMG0,
+
+        /// Random numbers generator
+        rng: Rng,
+
+        /// WiFi peripheral
+        wifi: WIFI,
+
+        /// Radio clock control
+        radio_clock_control: RADIO_CLK,
+    },
+
+    /// WiFi was initialized
+    Initialized(&'static Stack<WifiDevice<'static, WifiStaDevice>>),
+
+    /// WiFi was initialized as an access point
+    AccessPoint,
+
+    /// WiFi initialization failed
+    Failed,
+}
+
+impl Wifi {
+    /// Create a WiFi interface, without initializing it
+    pub fn new(
+        timg0: TIMG0,
+        rng: Rng,
+        wifi: WIFI,
+        radio_clock_control: RADIO_CLK,
+        credentials: Credentials,
+    ) -> Self {
+        Self {
+            state: State::Uninitialized {
+                timg0,
+                rng,
+                wifi,
+                radio_clock_control,
+            },
+            credentials,
+        }
+    }
+
+    /// Connect to WiFi, initializing it if needed
+    ///
+    /// This waits until a network is connected, which might never happen, so
+    /// callers should wrap it in a timeout.
+    /// The interface stays usable if it is cancelled.
+    pub async fn connect(
+        &mut self,
+        spawner: &Spawner,
+        clocks: &Clocks<'_>,
+    ) -> Result<&'static Stack<WifiDevice<'static, WifiStaDevice>>, Error> {
+        match replace(&mut self.state, State::Failed) {
+            State::Uninitialized {
+                timg0,
+                rng,
+                wifi,
+                radio_clock_control,
+            } => {
+                let stack = initialize_station(
+                    spawner,
+                    timg0,
+                    rng,
+                    wifi,
+                    radio_clock_control,
+                    clocks,
+                    self.credentials.clone(),
+                )?;
+                self.state = State::Initialized(stack);
+                wait_for_connection(stack).await;
+                Ok(stack)
+            }
+            State::Initialized(stack) => {
+                self.state = State::Initialized(stack);
+                if stack.is_link_up() && stack.config_v4().is_some() {
+                    debug!("WiFi is already connected");
+                    return Ok(stack);
+                }
+                info!("Restart WiFi");
+                STOP_WIFI_SIGNAL.reset();
+                START_WIFI_SIGNAL.signal(());
+                wait_for_connection(stack).await;
+                Ok(stack)
+            }
+            State::AccessPoint => {
+                self.state = State::AccessPoint;
+                Err(Error::AccessPoint)
+            }
+            State::Failed => Err(Error::Failed),
+        }
+    }
 
-/// Connect to WiFi
-pub async fn connect(
+    /// Start an open access point, initializing WiFi
+    ///
+    /// The station cannot connect to a network afterwards, until it is
+    /// restarted.
+    pub async fn start_access_point(
+        &mut self,
+        spawner: &Spawner,
+        clocks: &Clocks<'_>,
+        ssid: &str,
+    ) -> Result<&'static Stack<WifiDevice<'static, WifiApDevice>>, Error> {
+        let State::Uninitialized {
+            timg0,
+            rng,
+            wifi,
+            radio_clock_control,
+        } = replace(&mut self.state, State::Failed)
+        else {
+            return Err(Error::Failed);
+        };
+
+        let mut rng_wrapper = RngWrapper::from(rng);
+        let seed = rng_wrapper.next_u64();
+
+        let init = initialize(timg0, rng, radio_clock_control, clocks)?;
+        let (wifi_interface, controller) =
+            esp_wifi::wifi::new_with_mode(&init, wifi, WifiApDevice)?;
+
+        let [a, b, c, d] = ACCESS_POINT_ADDRESS;
+        let address = Ipv4Address::new(a, b, c, d);
+        let config = Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(address, ACCESS_POINT_PREFIX_LENGTH),
+            gateway: Some(address),
+            dns_servers: Vec::new(),
+        });
+
+        debug!("Initialize access point network stack");
+        let stack_resources: &'static mut _ =
+            ACCESS_POINT_STACK_RESOURCES.init(StackResources::new());
+        let stack: &'static mut _ =
+            ACCESS_POINT_STACK.init(Stack::new(wifi_interface, config, stack_resources, seed));
+
+        let ssid = ssid.try_into().map_err(|()| Error::InvalidSsid)?;
+        spawner.must_spawn(access_point(controller, ssid));
+        spawner.must_spawn(access_point_net_task(stack));
+
+        self.state = State::AccessPoint;
+        Ok(stack)
+    }
+
+    /// Disconnect from WiFi and wait until it is stopped
+    pub async fn disconnect(&self) {
+        if let State::Initialized(_) = self.state {
+            info!("Request to disconnect wifi");
+            WIFI_STOPPED_SIGNAL.reset();
+            STOP_WIFI_SIGNAL.signal(());
+            if with_timeout(STOP_TIMEOUT, WIFI_STOPPED_SIGNAL.wait())
+                .await
+                .is_err()
+            {
+                warn!("WiFi did not stop in time");
+            }
+        }
+    }
+}
+
+/// Initialize WiFi and spawn the tasks connecting to a network
+fn initialize_station(
     spawner: &Spawner,
     timg0: TIMG0,
     rng: Rng,
     wifi: WIFI,
     radio_clock_control: RADIO_CLK,
     clocks: &Clocks<'_>,
-    (ssid, password): (String<32>, String<64>),
+    credentials: Credentials,
 ) -> Result<&'static Stack<WifiDevice<'static, WifiStaDevice>>, Error> {
     let mut rng_wrapper = RngWrapper::from(rng);
     let seed = rng_wrapper.next_u64();
     debug!("Use random seed 0x{seed:016x}");
 
-    let timg0 = TimerGroup::new(timg0, clocks, None);
-    let timer0: ErasedTimer = timg0.timer0.into();
-    let timer = PeriodicTimer::new(timer0);
-
-    let init = esp_wifi::initialize(
-        EspWifiInitFor::Wifi,
-        timer,
-        rng,
-        radio_clock_control,
-        clocks,
-    )?;
+    let init = initialize(timg0, rng, radio_clock_control, clocks)?;
 
     let (wifi_interface, controller) = esp_wifi::wifi::new_with_mode(&init, wifi, WifiStaDevice)?;
 
@@ -94,9 +300,35 @@ pub async fn connect(
     let stack: &'static mut _ =
         STACK.init(Stack::new(wifi_interface, config, stack_resources, seed));
 
-    spawner.must_spawn(connection(controller, ssid, password));
+    spawner.must_spawn(connection(controller, credentials));
     spawner.must_spawn(net_task(stack));
 
+    Ok(stack)
+}
+
+/// Initialize the WiFi driver
+fn initialize(
+    timg0: TIMG0,
+    rng: Rng,
+    radio_clock_control: RADIO_CLK,
+    clocks: &Clocks<'_>,
+) -> Result<EspWifiInitialization, Error> {
+    let timg0 = TimerGroup::new(timg0, clocks, None);
+    let timer0: ErasedTimer = timg0.timer0.into();
+    let timer = PeriodicTimer::new(timer0);
+
+    let init = esp_wifi::initialize(
+        EspWifiInitFor::Wifi,
+        timer,
+        rng,
+        radio_clock_control,
+        clocks,
+    )?;
+    Ok(init)
+}
+
+/// Wait until the network link is up and an IP address is assigned
+async fn wait_for_connection(stack: &Stack<WifiDevice<'static, WifiStaDevice>>) {
     debug!("Wait for network link");
     loop {
         if stack.is_link_up() {
@@ -113,8 +345,6 @@ pub async fn connect(
         }
         Timer::after(Duration::from_millis(500)).await;
     }
-
-    Ok(stack)
 }
 
 /// Task for ongoing network processing
@@ -127,8 +357,8 @@ async fn net_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
 ///
 /// This will wrap [`connection_fallible()`] and trap any error.
 #[embassy_executor::task]
-async fn connection(controller: WifiController<'static>, ssid: String<32>, password: String<64>) {
-    if let Err(error) = connection_fallible(controller, ssid, password).await {
+async fn connection(controller: WifiController<'static>, credentials: Credentials) {
+    if let Err(error) = connection_fallible(controller, credentials).await {
         error!("Cannot connect to WiFi: {error:?}");
     }
 }
@@ -136,8 +366,7 @@ async fn connection(controller: WifiController<'static>, ssid: String<32>, passw
 /// Fallible task for WiFi connection
 async fn connection_fallible(
     mut controller: WifiController<'static>,
-    ssid: String<32>,
-    password: String<64>,
+    credentials: Credentials,
 ) -> Result<(), Error> {
     debug!("Start connection");
     debug!("Device capabilities: {:?}", controller.get_capabilities());
@@ -149,43 +378,143 @@ async fn connection_fallible(
         }
 
         if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = Configuration::Client(ClientConfiguration {
-                ssid: ssid.clone(),
-                password: password.clone(),
-                ..Default::default()
-            });
+            let client_config = Configuration::Client(ClientConfiguration::default());
             controller.set_configuration(&client_config)?;
             debug!("Starting WiFi controller");
             controller.start().await?;
             debug!("WiFi controller started");
         }
 
-        debug!("Connect to WiFi network");
-
-        match controller.connect().await {
-            Ok(()) => {
+        // A request to stop wifi is also handled while connecting and while
+        // waiting to retry, otherwise a failed connection would keep the
+        // radio on until the stop times out
+        let connected = connect_to_strongest(&mut controller, &credentials);
+        let stop_requested = match select(connected, STOP_WIFI_SIGNAL.wait()).await {
+            Either::First(Ok(())) => {
                 debug!("Connected to WiFi network");
 
                 debug!("Wait for request to stop wifi");
                 STOP_WIFI_SIGNAL.wait().await;
-                info!("Received signal to stop wifi");
-                controller.stop().await?;
-                break;
+                true
             }
-            Err(error) => {
+            Either::First(Err(error)) => {
                 error!("Failed to connect to WiFi network: {error:?}");
-                Timer::after(Duration::from_millis(5000)).await;
+                let retry = Timer::after(RETRY_DELAY);
+                matches!(
+                    select(retry, STOP_WIFI_SIGNAL.wait()).await,
+                    Either::Second(())
+                )
             }
+            Either::Second(()) => true,
+        };
+
+        if stop_requested {
+            info!("Received signal to stop wifi");
+            controller.stop().await?;
+            WIFI_STOPPED_SIGNAL.signal(());
+
+            debug!("Wait for request to start wifi");
+            START_WIFI_SIGNAL.wait().await;
+            info!("Received signal to start wifi");
         }
     }
+}
 
-    info!("Leave connection task");
-    Ok(())
+/// Connect to the stored network with the strongest signal
+///
+/// Networks are tried in order until one accepts the connection.
+async fn connect_to_strongest(
+    controller: &mut WifiController<'static>,
+    credentials: &Credentials,
+) -> Result<(), Error> {
+    let access_points = match controller.scan_n::<SCAN_SIZE>().await {
+        Ok((access_points, _count)) => access_points,
+        Err(error) => {
+            warn!("Cannot scan WiFi networks: {error:?}");
+            Vec::new()
+        }
+    };
+    let visible: Vec<(&str, i8), SCAN_SIZE> = access_points
+        .iter()
+        .map(|access_point| (access_point.ssid.as_str(), access_point.signal_strength))
+        .collect();
+    debug!("Found networks {visible:?}");
+
+    let mut result = Err(Error::NoNetwork);
+    for network in credentials.by_signal_strength(&visible) {
+        debug!("Connect to WiFi network {}", network.ssid);
+        let auth_method = if network.password.is_empty() {
+            AuthMethod::None
+        } else {
+            AuthMethod::default()
+        };
+        let client_config = Configuration::Client(ClientConfiguration {
+            ssid: network.ssid.clone(),
+            password: network.password.clone(),
+            auth_method,
+            ..Default::default()
+        });
+        controller.set_configuration(&client_config)?;
+
+        match controller.connect().await {
+            Ok(()) => {
+                info!("Connected to WiFi network {}", network.ssid);
+                return Ok(());
+            }
+            Err(error) => {
+                warn!("Cannot connect to WiFi network {}: {error:?}", network.ssid);
+                result = Err(error.into());
+            }
+        }
+    }
+    result
+}
+
+/// Task for ongoing access point network processing
+#[embassy_executor::task]
+async fn access_point_net_task(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    stack.run().await;
+}
+
+/// Task for the access point
+#[embassy_executor::task]
+async fn access_point(mut controller: WifiController<'static>, ssid: String<32>) {
+    let config = Configuration::AccessPoint(AccessPointConfiguration {
+        ssid,
+        auth_method: AuthMethod::None,
+        ..Default::default()
+    });
+    if let Err(error) = controller.set_configuration(&config) {
+        error!("Cannot configure access point: {error:?}");
+        return;
+    }
+    if let Err(error) = controller.start().await {
+        error!("Cannot start access point: {error:?}");
+        return;
+    }
+    info!("Started access point");
+
+    loop {
+        controller.wait_for_event(WifiEvent::ApStaconnected).await;
+        info!("A device joined the access point");
+    }
 }
 
 /// Error within WiFi connection
 #[derive(Debug)]
 pub enum Error {
+    /// WiFi initialization failed earlier
+    Failed,
+
+    /// WiFi was started as an access point
+    AccessPoint,
+
+    /// Access point SSID is longer than 32 bytes
+    InvalidSsid,
+
+    /// No network is stored
+    NoNetwork,
+
     /// Error during WiFi initialization
     WifiInitialization(#[allow(unused)] WifiInitializationError),
 
@@ -5,13 +5,22 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 //! Client for World Time API
+//!
+//! Both the JSON and the plain text endpoints are supported.
+//! The local offset is the raw offset of the time zone plus the DST offset
+//! when DST is in effect.
 
+use core::fmt::Write as _;
 use core::num::ParseIntError;
 use core::str::from_utf8;
+use core::str::ParseBoolError;
 use core::str::Utf8Error;
 
 use log::debug;
 use log::trace;
+use log::warn;
+
+use heapless::String;
 
 use time::error::ComponentRange as TimeComponentRangeError;
 use time::OffsetDateTime;
@@ -21,53 +30,187 @@
 use crate::http::ClientTrait as HttpClientTrait;
 use crate::http::Error as HttpError;
 
+/// Base URL of time zone endpoints
+const BASE_URL: &str = "https://worldtimeapi.org/api/timezone/";
+
+/// Maximal length of a URL
+const URL_SIZE: usize = 128;
+
+/// Format of World Time API responses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// JSON object
+    Json,
+
+    /// Plain text with one `key: value` pair per line
+    Text,
+}
+
 /// Extend an HTTP client for querying World Time API
 pub trait WorldTimeApiClient: HttpClientTrait {
-    /// Fetch the current time
-    async fn fetch_current_time(&mut self) -> Result<OffsetDateTime, Error> {
-        let url = "https://worldtimeapi.org/api/timezone/Europe/Copenhagen.txt";
+    /// Fetch the current time in a time zone, e.g. `Europe/Copenhagen`
+    async fn fetch_current_time(
+        &mut self,
+        time_zone: &str,
+        format: Format,
+    ) -> Result<OffsetDateTime, Error> {
+        let url = build_url(time_zone, format)?;
 
-        let response = self.send_request(url).await?;
+        let response = self.send_request(&url).await?;
 
         let text = from_utf8(&response)?;
-        let mut timestamp: Option<u64> = None;
-        let mut offset: Option<i32> = None;
-        for line in text.lines() {
-            trace!("Line: \"{line}\"");
-            if let Some(timestamp_string) = line.strip_prefix("unixtime: ") {
-                debug!("Parse line \"{line}\"");
-                let timestamp_: u64 = timestamp_string.parse()?;
-
-                debug!("Current time is {timestamp_}");
-                timestamp = Some(timestamp_);
-            }
-            if let Some(offset_string) = line.strip_prefix("raw_offset: ") {
-                debug!("Parse line \"{line}\"");
-                let offset_: i32 = offset_string.parse()?;
+        let time = match format {
+            Format::Json => parse_json(text)?,
+            Format::Text => parse_text(text)?,
+        };
+
+        debug!("Current time is {time:?}");
+        time.local_time()
+    }
+}
+
+impl WorldTimeApiClient for HttpClient {}
+
+/// Current time in a time zone as reported by World Time API
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CurrentTime {
+    /// Seconds since Unix epoch
+    pub unixtime: i64,
+
+    /// Offset from UTC in seconds, without DST
+    pub raw_offset: i32,
+
+    /// Additional offset in seconds when DST is in effect
+    pub dst_offset: i32,
+
+    /// Whether DST is in effect
+    pub dst: bool,
+}
+
+impl CurrentTime {
+    /// Compute the local offset
+    pub fn offset(&self) -> Result<UtcOffset, Error> {
+        let dst_offset = if self.dst { self.dst_offset } else { 0 };
+        let offset = UtcOffset::from_whole_seconds(self.raw_offset + dst_offset)?;
+        Ok(offset)
+    }
+
+    /// Compute the local time
+    pub fn local_time(&self) -> Result<OffsetDateTime, Error> {
+        let utc = OffsetDateTime::from_unix_timestamp(self.unixtime)?;
+        utc.checked_to_offset(self.offset()?)
+            .ok_or(Error::InvalidInOffset)
+    }
+}
+
+/// Build the URL of a time zone endpoint
+///
+/// Only characters found in IANA time zone names are accepted.
+fn build_url(time_zone: &str, format: Format) -> Result<String<URL_SIZE>, Error> {
+    let is_valid = !time_zone.is_empty()
+        && time_zone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'));
+    if !is_valid {
+        return Err(Error::InvalidTimeZone);
+    }
 
-                debug!("Current offset is {offset_}");
-                offset = Some(offset_);
+    let extension = match format {
+        Format::Json => "",
+        Format::Text => ".txt",
+    };
+
+    let mut url = String::new();
+    write!(url, "{BASE_URL}{time_zone}{extension}").map_err(|_| Error::UrlTooLong)?;
+    Ok(url)
+}
+
+/// Parse a response from the plain text endpoint
+pub fn parse_text(text: &str) -> Result<CurrentTime, Error> {
+    let mut unixtime = None;
+    let mut raw_offset = None;
+    let mut dst_offset = None;
+    let mut dst = None;
+
+    for line in text.lines() {
+        trace!("Line: \"{line}\"");
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "unixtime" => unixtime = Some(value.parse()?),
+            "raw_offset" => raw_offset = Some(value.parse()?),
+            "dst_offset" => dst_offset = Some(value.parse()?),
+            "dst" => dst = Some(value.parse()?),
+            "error" | "Error" => {
+                warn!("World Time API returned an error: {value}");
+                return Err(Error::Server);
             }
+            _ => {}
         }
+    }
 
-        if let (Some(timestamp), Some(offset)) = (timestamp, offset) {
-            let offset = UtcOffset::from_whole_seconds(offset)?;
+    Ok(CurrentTime {
+        unixtime: unixtime.ok_or(Error::MissingField("unixtime"))?,
+        raw_offset: raw_offset.ok_or(Error::MissingField("raw_offset"))?,
+        dst_offset: dst_offset.unwrap_or(0),
+        dst: dst.unwrap_or(false),
+    })
+}
 
-            #[allow(clippy::cast_possible_wrap)]
-            let timestamp = timestamp as i64;
+/// Parse a response from the JSON endpoint
+///
+/// Only the flat fields used here are extracted, the rest of the object is
+/// ignored.
+/// A truncated object is rejected, since a number cut short still parses.
+pub fn parse_json(text: &str) -> Result<CurrentTime, Error> {
+    if !text.trim_end().ends_with('}') {
+        return Err(Error::Truncated);
+    }
 
-            let utc = OffsetDateTime::from_unix_timestamp(timestamp)?;
-            let local = utc
-                .checked_to_offset(offset)
-                .ok_or(Error::InvalidInOffset)?;
-            Ok(local)
-        } else {
-            Err(Error::Unknown)
-        }
+    if let Some(message) = json_value(text, "error") {
+        warn!("World Time API returned an error: {message}");
+        return Err(Error::Server);
     }
+
+    let field = |key: &'static str| json_value(text, key).ok_or(Error::MissingField(key));
+
+    Ok(CurrentTime {
+        unixtime: field("unixtime")?.parse()?,
+        raw_offset: field("raw_offset")?.parse()?,
+        dst_offset: json_value(text, "dst_offset").map_or(Ok(0), str::parse)?,
+        dst: json_value(text, "dst").map_or(Ok(false), str::parse)?,
+    })
 }
 
-impl WorldTimeApiClient for HttpClient {}
+/// Find the value of a key in a JSON object
+///
+/// Strings are returned without quotes and escape sequences are not
+/// decoded, other values are returned as they are.
+fn json_value<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    let mut remaining = text;
+    while let Some(position) = remaining.find(key) {
+        let before = remaining.get(..position)?;
+        let after = remaining.get(position + key.len()..)?;
+        remaining = after;
+
+        let Some(after) = after.strip_prefix('"').filter(|_| before.ends_with('"')) else {
+            continue;
+        };
+        let Some(value) = after.trim_start().strip_prefix(':') else {
+            continue;
+        };
+        let value = value.trim_start();
+
+        if let Some(string) = value.strip_prefix('"') {
+            return string.find('"').and_then(|end| string.get(..end));
+        }
+        let end = value.find([',', '}', ']']).unwrap_or(value.len());
+        return value.get(..end).map(str::trim_end);
+    }
+    None
+}
 
 /// An error within a request to World Time API
 #[derive(Debug)]
@@ -75,8 +218,20 @@ pub enum Error {
     /// Current timestamp is invalid in this offset
     InvalidInOffset,
 
-    /// Current time could not be fetched
-    Unknown,
+    /// Time zone name contains invalid characters
+    InvalidTimeZone,
+
+    /// URL does not fit in its buffer
+    UrlTooLong,
+
+    /// Server returned an error, e.g. for an unknown time zone
+    Server,
+
+    /// A required field is missing from the response
+    MissingField(#[allow(unused)] &'static str),
+
+    /// The response ends before the end of the object
+    Truncated,
 
     /// A time component is out of range
     TimeComponentRange(#[allow(unused)] TimeComponentRangeError),
@@ -87,6 +242,9 @@ pub enum Error {
     /// An integer valued returned by the server could not be parsed
     ParseInt(#[allow(unused)] ParseIntError),
 
+    /// A boolean value returned by the server could not be parsed
+    ParseBool(#[allow(unused)] ParseBoolError),
+
     /// Text returned by the server is not valid UTF-8
     Utf8(#[allow(unused)] Utf8Error),
 }
@@ -109,8 +267,227 @@ fn from(error: ParseIntError) -> Self {
     }
 }
 
+impl From<ParseBoolError> for Error {
+    fn from(error: ParseBoolError) -> Self {
+        Self::ParseBool(error)
+    }
+}
+
 impl From<Utf8Error> for Error {
     fn from(error: Utf8Error) -> Self {
         Self::Utf8(error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embassy_futures::block_on;
+
+    use heapless::Vec;
+
+    use time::macros::datetime;
+
+    use crate::http::RESPONSE_SIZE;
+
+    /// Response of the JSON endpoint for a time zone with DST in effect
+    const COPENHAGEN_JSON: &str = include_str!("../test-data/worldtimeapi/europe-copenhagen.json");
+
+    /// Response of the plain text endpoint for the same time zone
+    const COPENHAGEN_TEXT: &str = include_str!("../test-data/worldtimeapi/europe-copenhagen.txt");
+
+    /// Response of the JSON endpoint for a time zone without DST
+    const SAO_PAULO_JSON: &str = include_str!("../test-data/worldtimeapi/america-sao-paulo.json");
+
+    /// Response of the JSON endpoint for an unknown time zone
+    const UNKNOWN_LOCATION_JSON: &str =
+        include_str!("../test-data/worldtimeapi/unknown-location.json");
+
+    /// Current time in Copenhagen in the saved responses
+    const COPENHAGEN: CurrentTime = CurrentTime {
+        unixtime: 1_717_243_200,
+        raw_offset: 3600,
+        dst_offset: 3600,
+        dst: true,
+    };
+
+    /// An HTTP client that returns a saved response
+    struct FakeClient {
+        /// Body of the response
+        body: &'static str,
+
+        /// URL of the last request
+        url: Option<String<URL_SIZE>>,
+    }
+
+    impl HttpClientTrait for FakeClient {
+        async fn send_request(&mut self, url: &str) -> Result<Vec<u8, RESPONSE_SIZE>, HttpError> {
+            self.url = url.try_into().ok();
+            Vec::from_slice(self.body.as_bytes()).map_err(|()| HttpError::ResponseTooLarge)
+        }
+
+        async fn send_post_request(
+            &mut self,
+            _url: &str,
+            _headers: &[(&str, &str)],
+            _body: &[u8],
+        ) -> Result<Vec<u8, RESPONSE_SIZE>, HttpError> {
+            Err(HttpError::ResponseTooLarge)
+        }
+    }
+
+    impl WorldTimeApiClient for FakeClient {}
+
+    #[test]
+    fn parse_valid_json() {
+        assert_eq!(parse_json(COPENHAGEN_JSON).unwrap(), COPENHAGEN);
+
+        let sao_paulo = parse_json(SAO_PAULO_JSON).unwrap();
+        assert_eq!(
+            sao_paulo,
+            CurrentTime {
+                unixtime: 1_717_243_200,
+                raw_offset: -10_800,
+                dst_offset: 0,
+                dst: false,
+            }
+        );
+        assert_eq!(
+            sao_paulo.local_time().unwrap(),
+            datetime!(2024-06-01 09:00 -03:00)
+        );
+    }
+
+    #[test]
+    fn parse_valid_text() {
+        assert_eq!(parse_text(COPENHAGEN_TEXT).unwrap(), COPENHAGEN);
+    }
+
+    #[test]
+    fn local_time_includes_dst() {
+        assert_eq!(
+            COPENHAGEN.local_time().unwrap(),
+            datetime!(2024-06-01 14:00 +02:00)
+        );
+        let standard = CurrentTime {
+            dst: false,
+            ..COPENHAGEN
+        };
+        assert_eq!(
+            standard.local_time().unwrap(),
+            datetime!(2024-06-01 13:00 +01:00)
+        );
+    }
+
+    #[test]
+    fn reject_truncated_json() {
+        let body = COPENHAGEN_JSON.trim_end();
+        for length in 0..body.len() {
+            let truncated = &body[..length];
+            assert!(parse_json(truncated).is_err(), "{truncated:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn reject_truncated_text() {
+        let end = COPENHAGEN_TEXT.find("unixtime").unwrap();
+        assert!(matches!(
+            parse_text(&COPENHAGEN_TEXT[..end]),
+            Err(Error::MissingField("unixtime"))
+        ));
+    }
+
+    #[test]
+    fn reject_missing_fields() {
+        let without_raw_offset = COPENHAGEN_JSON.replace("\"raw_offset\":3600,", "");
+        assert!(matches!(
+            parse_json(&without_raw_offset),
+            Err(Error::MissingField("raw_offset"))
+        ));
+
+        let without_unixtime = COPENHAGEN_TEXT.replace("unixtime: 1717243200\n", "");
+        assert!(matches!(
+            parse_text(&without_unixtime),
+            Err(Error::MissingField("unixtime"))
+        ));
+    }
+
+    #[test]
+    fn optional_fields_default_to_standard_time() {
+        let without_dst = COPENHAGEN_JSON
+            .replace("\"dst\":true,", "")
+            .replace("\"dst_offset\":3600,", "");
+        let current = parse_json(&without_dst).unwrap();
+        assert_eq!(current.dst_offset, 0);
+        assert!(!current.dst);
+    }
+
+    #[test]
+    fn reject_invalid_values() {
+        let invalid = COPENHAGEN_JSON.replace("1717243200", "\"soon\"");
+        assert!(matches!(parse_json(&invalid), Err(Error::ParseInt(_))));
+
+        let invalid = COPENHAGEN_TEXT.replace("dst: true", "dst: yes");
+        assert!(matches!(parse_text(&invalid), Err(Error::ParseBool(_))));
+    }
+
+    #[test]
+    fn reject_server_errors() {
+        assert!(matches!(
+            parse_json(UNKNOWN_LOCATION_JSON),
+            Err(Error::Server)
+        ));
+        assert!(matches!(
+            parse_text("error: unknown location\n"),
+            Err(Error::Server)
+        ));
+    }
+
+    #[test]
+    fn build_urls() {
+        assert_eq!(
+            build_url("Europe/Copenhagen", Format::Json)
+                .unwrap()
+                .as_str(),
+            "https://worldtimeapi.org/api/timezone/Europe/Copenhagen"
+        );
+        assert_eq!(
+            build_url("America/Sao_Paulo", Format::Text)
+                .unwrap()
+                .as_str(),
+            "https://worldtimeapi.org/api/timezone/America/Sao_Paulo.txt"
+        );
+        assert!(matches!(
+            build_url("Europe/Copenhagen?x=1", Format::Json),
+            Err(Error::InvalidTimeZone)
+        ));
+        assert!(matches!(
+            build_url("", Format::Json),
+            Err(Error::InvalidTimeZone)
+        ));
+    }
+
+    #[test]
+    fn fetch_from_saved_responses() {
+        let mut client = FakeClient {
+            body: COPENHAGEN_TEXT,
+            url: None,
+        };
+        let now = block_on(client.fetch_current_time("Europe/Copenhagen", Format::Text)).unwrap();
+        assert_eq!(now, datetime!(2024-06-01 14:00 +02:00));
+        assert_eq!(
+            client.url.as_deref(),
+            Some("https://worldtimeapi.org/api/timezone/Europe/Copenhagen.txt")
+        );
+
+        let mut client = FakeClient {
+            body: "{\"unixtime\":17172",
+            url: None,
+        };
+        assert!(matches!(
+            block_on(client.fetch_current_time("Europe/Copenhagen", Format::Json)),
+            Err(Error::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,373 @@
+// Copyright Max Kivits 2026.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Capture the history exported by the station into a CSV file
+//!
+//! ```text
+//! history-capture <output> [<port>]
+//! ```
+//!
+//! When a serial port is given, the `export` command is sent to the station
+//! and its output is read from the port, which must already be configured,
+//! for instance with `stty -F /dev/ttyUSB0 115200 raw -echo`.
+//! Otherwise the output of the command is read from the standard input.
+//!
+//! Records are written to the output file without their checksum.
+//! Lines that are not records, such as log messages, are skipped.
+//! The capture fails if a record has an invalid checksum or if the number of
+//! records does not match the one announced at the end of the export.
+
+use std::env::args;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::stdin;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Error as IoError;
+use std::io::Write;
+use std::process::ExitCode;
+
+use crc::Crc;
+use crc::CRC_32_ISO_HDLC;
+
+/// First line of an export
+const BEGIN: &str = "# begin export";
+
+/// Prefix of the last line of an export
+const END: &str = "# end export, ";
+
+/// Suffix of the last line of an export
+const END_SUFFIX: &str = " records";
+
+/// Header row of an export
+const HEADER: &str = "time,temperature_celsius,humidity_percent,pressure_hectopascal,crc32";
+
+/// Checksum algorithm of exported records
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(count) => {
+            eprintln!("Captured {count} records");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("Error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parse arguments and capture an export
+fn run() -> Result<u32, Error> {
+    let arguments: Vec<String> = args().skip(1).collect();
+    let (output, port) = match arguments.as_slice() {
+        [output] => (output, None),
+        [output, port] => (output, Some(port)),
+        _ => return Err(Error::Usage),
+    };
+
+    let mut output = BufWriter::new(File::create(output)?);
+    let count = if let Some(port) = port {
+        let mut port = OpenOptions::new().read(true).write(true).open(port)?;
+        port.write_all(b"export\r\n")?;
+        capture(BufReader::new(port), &mut output)?
+    } else {
+        capture(stdin().lock(), &mut output)?
+    };
+    output.flush()?;
+    Ok(count)
+}
+
+/// Capture an export from an input to an output
+///
+/// Return the number of records.
+fn capture(input: impl BufRead, output: &mut impl Write) -> Result<u32, Error> {
+    let mut lines = input.lines();
+
+    loop {
+        let line = lines.next().ok_or(Error::MissingBegin)??;
+        if line.trim_end() == BEGIN {
+            break;
+        }
+    }
+
+    let line = lines.next().ok_or(Error::MissingHeader)??;
+    if line.trim_end() != HEADER {
+        return Err(Error::MissingHeader);
+    }
+    let (header, _) = HEADER.rsplit_once(',').ok_or(Error::MissingHeader)?;
+    writeln!(output, "{header}")?;
+
+    let mut count = 0;
+    let mut invalid = 0;
+    for line in lines {
+        let line = line?;
+        match parse_line(&line)? {
+            Line::Record(record) => {
+                writeln!(output, "{record}")?;
+                count += 1;
+            }
+            Line::InvalidRecord => {
+                eprintln!("Invalid record: {}", line.trim_end());
+                invalid += 1;
+            }
+            Line::End(announced) => {
+                if invalid > 0 {
+                    return Err(Error::InvalidRecords(invalid));
+                }
+                if announced != count {
+                    return Err(Error::MissingRecords { announced, count });
+                }
+                return Ok(count);
+            }
+            Line::Other => {}
+        }
+    }
+    Err(Error::MissingEnd)
+}
+
+/// A line of an export after its header row
+#[derive(Debug, PartialEq, Eq)]
+enum Line<'a> {
+    /// A record with a valid checksum, without its checksum
+    Record(&'a str),
+
+    /// A record with an invalid checksum
+    InvalidRecord,
+
+    /// The end of the export, with the announced number of records
+    End(u32),
+
+    /// Any other line, such as a log message
+    Other,
+}
+
+/// Parse a line of an export after its header row
+fn parse_line(line: &str) -> Result<Line<'_>, Error> {
+    let line = line.trim_end();
+    if let Some(announced) = line.strip_prefix(END) {
+        return announced
+            .strip_suffix(END_SUFFIX)
+            .and_then(|announced| announced.parse().ok())
+            .map(Line::End)
+            .ok_or(Error::MissingEnd);
+    }
+
+    // Records start with the year of their time
+    if !line.starts_with(|character: char| character.is_ascii_digit()) {
+        return Ok(Line::Other);
+    }
+    Ok(verify(line).map_or(Line::InvalidRecord, Line::Record))
+}
+
+/// Return a record without its checksum if the checksum matches
+fn verify(line: &str) -> Option<&str> {
+    let (record, checksum) = line.rsplit_once(',')?;
+    let checksum = u32::from_str_radix(checksum, 16).ok()?;
+    (CRC.checksum(record.as_bytes()) == checksum).then_some(record)
+}
+
+/// An error within a capture
+#[derive(Debug)]
+enum Error {
+    /// The arguments are invalid
+    Usage,
+
+    /// The input ends before the beginning of an export
+    MissingBegin,
+
+    /// The header row is missing or unexpected
+    MissingHeader,
+
+    /// The input ends before the end of the export
+    MissingEnd,
+
+    /// Some records have an invalid checksum
+    InvalidRecords(u32),
+
+    /// Fewer records were captured than announced
+    MissingRecords { announced: u32, count: u32 },
+
+    /// An error from reading or writing
+    Io(IoError),
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Usage => write!(formatter, "Usage: history-capture <output> [<port>]"),
+            Self::MissingBegin => write!(formatter, "No export found in input"),
+            Self::MissingHeader => write!(formatter, "Unexpected header row"),
+            Self::MissingEnd => write!(formatter, "Export ended unexpectedly"),
+            Self::InvalidRecords(count) => {
+                write!(formatter, "{count} records have an invalid checksum")
+            }
+            Self::MissingRecords { announced, count } => {
+                write!(formatter, "Captured {count} records out of {announced}")
+            }
+            Self::Io(error) => write!(formatter, "{error}"),
+        }
+    }
+}
+
+impl From<IoError> for Error {
+    fn from(error: IoError) -> Self {
+        Self::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of the `export` command captured from a station, with a log
+    /// message in the middle
+    const EXPORT: &str = concat!(
+        "INFO - Sample requested\r\n",
+        "# begin export\r\n",
+        "time,temperature_celsius,humidity_percent,pressure_hectopascal,crc32\r\n",
+        "2024-08-14T12:00:00+02:00,21.50,45.20,1013.2,6352de2f\r\n",
+        "2024-08-14T12:01:00+02:00,21.52,45.10,1013.2,d8b6ff63\r\n",
+        "INFO - Refresh display\r\n",
+        "2024-08-14T12:02:00+02:00,-3.05,100.00,987.6,ebb4a99b\r\n",
+        "# end export, 3 records\r\n",
+        "> ",
+    );
+
+    /// CSV file written from [`EXPORT`]
+    const CSV: &str = concat!(
+        "time,temperature_celsius,humidity_percent,pressure_hectopascal\n",
+        "2024-08-14T12:00:00+02:00,21.50,45.20,1013.2\n",
+        "2024-08-14T12:01:00+02:00,21.52,45.10,1013.2\n",
+        "2024-08-14T12:02:00+02:00,-3.05,100.00,987.6\n",
+    );
+
+    /// Capture an export, returning the result and the output
+    fn captured(input: &str) -> (Result<u32, Error>, String) {
+        let mut output = Vec::new();
+        let result = capture(input.as_bytes(), &mut output);
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn verify_checksums() {
+        assert_eq!(
+            verify("2024-08-14T12:00:00+02:00,21.50,45.20,1013.2,6352de2f"),
+            Some("2024-08-14T12:00:00+02:00,21.50,45.20,1013.2")
+        );
+        assert_eq!(
+            verify("2024-08-14T12:00:00+02:00,21.50,45.20,1013.2,6352DE2F"),
+            Some("2024-08-14T12:00:00+02:00,21.50,45.20,1013.2")
+        );
+        assert_eq!(
+            verify("2024-08-14T12:00:00+02:00,21.50,45.20,1013.3,6352de2f"),
+            None
+        );
+        assert_eq!(
+            verify("2024-08-14T12:00:00+02:00,21.50,45.20,1013.2,6352de"),
+            None
+        );
+        assert_eq!(
+            verify("2024-08-14T12:00:00+02:00,21.50,45.20,1013.2,"),
+            None
+        );
+        assert_eq!(verify("2024-08-14T12:00:00+02:00"), None);
+    }
+
+    #[test]
+    fn parse_lines() {
+        assert_eq!(
+            parse_line("2024-08-14T12:01:00+02:00,21.52,45.10,1013.2,d8b6ff63\r").unwrap(),
+            Line::Record("2024-08-14T12:01:00+02:00,21.52,45.10,1013.2")
+        );
+        assert_eq!(
+            parse_line("2024-08-14T12:01:00+02:00,21.52,45.10,1013.2,d8b6ff64").unwrap(),
+            Line::InvalidRecord
+        );
+        assert_eq!(
+            parse_line("# end export, 42 records").unwrap(),
+            Line::End(42)
+        );
+        assert_eq!(parse_line("INFO - Refresh display").unwrap(), Line::Other);
+        assert_eq!(parse_line("").unwrap(), Line::Other);
+        assert_eq!(parse_line("> ").unwrap(), Line::Other);
+        assert!(matches!(
+            parse_line("# end export, many records"),
+            Err(Error::MissingEnd)
+        ));
+        assert!(matches!(
+            parse_line("# end export, 42"),
+            Err(Error::MissingEnd)
+        ));
+    }
+
+    #[test]
+    fn capture_export() {
+        let (result, output) = captured(EXPORT);
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(output, CSV);
+    }
+
+    #[test]
+    fn capture_rejects_a_corrupted_record() {
+        // A bit flipped in transit changes a temperature
+        let corrupted = EXPORT.replacen("21.52,45.10", "21.53,45.10", 1);
+        let (result, _) = captured(&corrupted);
+        assert!(matches!(result, Err(Error::InvalidRecords(1))));
+
+        // Characters lost in transit truncate a record
+        let corrupted = EXPORT.replacen(",45.10,1013.2,d8b6ff63", ",45.1", 1);
+        let (result, _) = captured(&corrupted);
+        assert!(matches!(result, Err(Error::InvalidRecords(1))));
+    }
+
+    #[test]
+    fn capture_rejects_a_missing_record() {
+        let missing = EXPORT.replacen(
+            "2024-08-14T12:01:00+02:00,21.52,45.10,1013.2,d8b6ff63\r\n",
+            "",
+            1,
+        );
+        let (result, _) = captured(&missing);
+        assert!(matches!(
+            result,
+            Err(Error::MissingRecords {
+                announced: 3,
+                count: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn capture_rejects_truncated_streams() {
+        let end = EXPORT.find(" records").unwrap() + " records".len();
+        for length in 0..end {
+            let (result, _) = captured(EXPORT.get(..length).unwrap());
+            assert!(
+                matches!(
+                    result,
+                    Err(Error::MissingBegin | Error::MissingHeader | Error::MissingEnd)
+                ),
+                "stream truncated to {length} bytes: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn capture_requires_begin_and_header() {
+        let (result, _) = captured("INFO - Boot\r\n> ");
+        assert!(matches!(result, Err(Error::MissingBegin)));
+
+        let unknown_header = EXPORT.replacen("pressure_hectopascal", "pressure_pascal", 1);
+        let (result, _) = captured(&unknown_header);
+        assert!(matches!(result, Err(Error::MissingHeader)));
+    }
+}
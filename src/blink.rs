@@ -1,12 +1,12 @@
 use embassy_executor::task;
 
 use embassy_time::Duration;
-use embassy_time::Timer;
 
 use esp_hal::gpio::AnyPin;
 use esp_hal::gpio::Level;
 
 use crate::info;
+use crate::power::sleep;
 use crate::trace;
 
 use Level::*;
@@ -26,7 +26,7 @@ pub async fn blink_task(led: AnyPin) {
         info!("Blinking LED");
         for (level, duration_ms) in HEARTBEAT_PATTERN {
             led.set_level(level);
-            Timer::after(duration_ms).await;
+            sleep(duration_ms).await;
             led.toggle();
         }
     }
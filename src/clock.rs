@@ -1,45 +1,84 @@
-use embassy_time::Instant;
-use time::{OffsetDateTime, UtcOffset};
+use embassy_time::Duration;
+use time::OffsetDateTime;
 
 use time::error::ComponentRange as TimeComponentRange;
 
-const AUCKLAND_UTC_OFFSET_HOURS: i8 = 13;
+use crate::external_rtc::Error as ExternalRtcError;
+use crate::external_rtc::Rtc as ExternalRtc;
+use crate::power::uptime;
+use crate::retained;
+use crate::tz::Error as TimeZoneError;
+use crate::tz::TimeZone;
 
 #[derive(Clone, Debug)]
 pub struct Clock {
     // boot time in Unix timestamp: seconds from Unix Epoch
     boot_time: u64,
 
-    // time offset
-    offset: UtcOffset,
+    // time zone, used to compute the offset at any instant
+    time_zone: TimeZone,
 }
 
 impl Clock {
     /// Construct a new [`Clock`] with time defined during compilation
-    pub fn new() -> Self {
+    pub fn new(time_zone: TimeZone) -> Self {
         // Get current time from compilation, env defined in build.rs
         let boot_time = env!("BUILD_TIME").parse::<u64>().unwrap();
-        // Get current offset
-        let offset = UtcOffset::from_hms(AUCKLAND_UTC_OFFSET_HOURS, 0, 0).unwrap();
-        Clock { boot_time, offset }
+        Clock {
+            boot_time,
+            time_zone,
+        }
     }
 
     pub fn now(&self) -> Result<OffsetDateTime, ClockError> {
         let now = self.now_as_unix_timestamp();
         let utc = OffsetDateTime::from_unix_timestamp(now as i64)?;
-        let local = utc
-            .checked_to_offset(self.offset)
-            .ok_or(ClockError::InvalidInOffset)?;
+        let local = self.time_zone.to_local(utc)?;
         Ok(local)
     }
 
     /// Return current time as a Unix epoch
     pub fn now_as_unix_timestamp(&self) -> u64 {
-        let from_boot = Instant::now().as_secs();
+        let from_boot = uptime().as_secs();
         self.boot_time + from_boot
     }
 
-    // TODO: save clock in rtc_fast and retrieve it from there on boot
+    /// Restore a [`Clock`] saved to RTC fast memory before deep sleep
+    ///
+    /// Return `None` after a cold boot or if the saved state is corrupted.
+    pub fn from_rtc_memory(time_zone: TimeZone) -> Option<Self> {
+        let wakeup_time = retained::load_clock()?;
+
+        // The saved time is the expected wakeup, i.e. the time of this boot
+        let from_boot = uptime().as_secs();
+        let boot_time = wakeup_time.checked_sub(from_boot)?;
+        Some(Clock {
+            boot_time,
+            time_zone,
+        })
+    }
+
+    /// Set a [`Clock`] from a battery-backed external RTC
+    ///
+    /// The external RTCs cannot store times before 2000, so the read time is
+    /// never before Unix Epoch.
+    pub async fn from_external_rtc(
+        rtc: &mut impl ExternalRtc,
+        time_zone: TimeZone,
+    ) -> Result<Self, ExternalRtcError> {
+        let now = rtc.read_time().await?.unix_timestamp().unsigned_abs();
+        let from_boot = uptime().as_secs();
+        Ok(Clock {
+            boot_time: now.saturating_sub(from_boot),
+            time_zone,
+        })
+    }
+
+    /// Save the clock to RTC fast memory before entering deep sleep
+    pub fn save_to_rtc_memory(&self, expected_sleep_duration: Duration) {
+        let wakeup_time = self.now_as_unix_timestamp() + expected_sleep_duration.as_secs();
+        retained::save_clock(wakeup_time);
+    }
 }
 
 /// A clock error
@@ -48,8 +87,8 @@ pub enum ClockError {
     /// A time component is out of range
     TimeComponentRange(#[allow(unused)] TimeComponentRange),
 
-    /// The clocks utc offset is invalid
-    InvalidInOffset,
+    /// The local time cannot be computed in the clock time zone
+    TimeZone(#[allow(unused)] TimeZoneError),
 }
 
 impl From<TimeComponentRange> for ClockError {
@@ -57,3 +96,9 @@ fn from(error: TimeComponentRange) -> Self {
         Self::TimeComponentRange(error)
     }
 }
+
+impl From<TimeZoneError> for ClockError {
+    fn from(error: TimeZoneError) -> Self {
+        Self::TimeZone(error)
+    }
+}
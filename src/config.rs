@@ -0,0 +1,39 @@
+//! Configuration stored in flash
+//!
+//! The configuration is shared with the other firmware, see
+//! [`crussant_common::config`], with the defaults of this board.
+
+use core::ops::Range;
+
+use embassy_time::Duration;
+
+use crussant_common::config::Board as ConfigBoard;
+use crussant_common::config::Config as SharedConfig;
+
+/// Flash range of the `config` partition, see `partitions.csv`
+pub const FLASH_RANGE: Range<u32> = 0xb000..0xf000;
+
+/// Firmware configuration
+pub type Config = SharedConfig<Board>;
+
+/// Defaults of this board
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Board;
+
+impl ConfigBoard for Board {
+    /// Wait at most a minute for the display update before deep sleep
+    const AWAKE_PERIOD: Duration = Duration::from_secs(60);
+
+    const TIME_ZONE: &'static str = "NZST-12NZDT,M9.5.0,M4.1.0/3";
+
+    const SDA_PIN: u8 = 2;
+
+    const SCL_PIN: u8 = 4;
+
+    const I2C_FREQUENCY_KHZ: u32 = 100;
+
+    /// GPIO0 is left to the battery voltage, as on the `esp32c3-embassy`
+    /// firmware, which shares this configuration.
+    /// Configurations that assigned it to I²C are moved to free pins on load.
+    const I2C_PINS: &'static [u8] = &[1, 2, 4, 5];
+}
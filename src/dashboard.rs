@@ -18,6 +18,8 @@
 use uom::si::f32::Pressure;
 use uom::si::f32::Ratio as Humidity;
 use uom::si::f32::ThermodynamicTemperature as Temperature;
+use uom::si::length::meter;
+use uom::si::mass_density::gram_per_cubic_meter;
 use uom::si::pressure::hectopascal;
 use uom::si::ratio::percent;
 use uom::si::thermodynamic_temperature::degree_celsius;
@@ -28,6 +30,7 @@
 
 use waveshare_154bv2_rs::Color as TriColor;
 
+use crate::derived::Derived;
 use crate::sensor::SensorReading;
 
 /// Style for black text
@@ -44,10 +47,30 @@
     .background_color(TriColor::White)
     .build();
 
+/// An optional dashboard row showing a quantity derived from the sample
+#[derive(Clone, Copy, Debug)]
+pub enum DerivedRow {
+    /// Dew point
+    DewPoint,
+    /// Absolute humidity
+    AbsoluteHumidity,
+    /// Heat index
+    HeatIndex,
+    /// Humidex
+    Humidex,
+    /// Comfort classification
+    Comfort,
+    /// Pressure altitude
+    PressureAltitude,
+}
+
 /// Draw a dashboard
+///
+/// The `rows` are drawn below the measurements, in the given order.
 pub fn draw_dashboard<DISPLAY>(
     display: &mut DISPLAY,
     sensor_reading: &SensorReading,
+    rows: &[DerivedRow],
 ) -> Result<(), DashboardError>
 where
     DISPLAY: DrawTarget<Color = TriColor, Error = Infallible>,
@@ -65,7 +88,7 @@ pub fn draw_dashboard<DISPLAY>(
     let pressure_layout = lay_out_measurement("Pressure: ", &pressure, " hPa");
     let time_layout = lay_out_update_time(&time);
 
-    LinearLayout::vertical(
+    let layout = LinearLayout::vertical(
         Chain::new(temperature_layout)
             .append(humidity_layout)
             .append(pressure_layout)
@@ -73,8 +96,21 @@ pub fn draw_dashboard<DISPLAY>(
     )
     .with_alignment(horizontal::Left)
     .arrange()
-    .align_to(&display_area, horizontal::Left, vertical::Top)
-    .draw(display)?;
+    .align_to(&display_area, horizontal::Left, vertical::Top);
+    layout.draw(display)?;
+
+    let derived = Derived::from(sample);
+    let mut previous = layout.bounds();
+    for row in rows {
+        let (label, value, unit) = format_derived_row(*row, &derived)?;
+        let row_layout = lay_out_measurement(label, &value, unit).align_to(
+            &previous,
+            horizontal::Left,
+            vertical::TopToBottom,
+        );
+        row_layout.draw(display)?;
+        previous = row_layout.bounds();
+    }
 
     Ok(())
 }
@@ -137,6 +173,63 @@ fn format_pressure(pressure: Pressure) -> Result<String<10>, FmtError> {
     Ok(string)
 }
 
+/// Format a derived row as a tuple (label, value, unit)
+fn format_derived_row(
+    row: DerivedRow,
+    derived: &Derived,
+) -> Result<(&'static str, String<12>, &'static str), FmtError> {
+    let mut string: String<12> = String::new();
+    let (label, unit) = match row {
+        DerivedRow::DewPoint => {
+            write!(
+                &mut string,
+                "{:>3.1}",
+                derived.dew_point.get::<degree_celsius>()
+            )?;
+            ("Dew point: ", " C")
+        }
+        DerivedRow::AbsoluteHumidity => {
+            write!(
+                &mut string,
+                "{:>3.1}",
+                derived.absolute_humidity.get::<gram_per_cubic_meter>()
+            )?;
+            ("Abs. hum.: ", " g/m3")
+        }
+        DerivedRow::HeatIndex => {
+            write!(
+                &mut string,
+                "{:>3.1}",
+                derived.heat_index.get::<degree_celsius>()
+            )?;
+            ("Heat index: ", " C")
+        }
+        DerivedRow::Humidex => {
+            write!(
+                &mut string,
+                "{:>3.0}",
+                derived.humidex.get::<degree_celsius>()
+            )?;
+            ("Humidex: ", "")
+        }
+        DerivedRow::Comfort => {
+            string
+                .push_str(derived.comfort.label())
+                .map_err(|()| FmtError)?;
+            ("Comfort: ", "")
+        }
+        DerivedRow::PressureAltitude => {
+            write!(
+                &mut string,
+                "{:>5.0}",
+                derived.pressure_altitude.get::<meter>()
+            )?;
+            ("Altitude: ", " m")
+        }
+    };
+    Ok((label, string, unit))
+}
+
 /// An error
 #[derive(Debug)]
 pub enum DashboardError {
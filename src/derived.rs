@@ -0,0 +1,65 @@
+//! Quantities derived from a [`Sample`]
+//!
+//! The formulas are in [`crussant_common::derived`], on plain numbers, and
+//! this module converts the quantities of the sensor from and to them.
+
+use uom::si::f32::Length;
+use uom::si::f32::MassDensity;
+use uom::si::f32::Ratio as Humidity;
+use uom::si::f32::ThermodynamicTemperature as Temperature;
+use uom::si::length::meter;
+use uom::si::mass_density::gram_per_cubic_meter;
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crussant_common::derived;
+pub use crussant_common::derived::Comfort;
+
+use crate::sensor::Sample;
+
+/// Quantities derived from a [`Sample`]
+#[derive(Clone, Debug)]
+pub struct Derived {
+    /// Dew point
+    pub dew_point: Temperature,
+    /// Absolute humidity, i.e. mass of water vapour per volume of air
+    pub absolute_humidity: MassDensity,
+    /// Heat index, i.e. apparent temperature (NOAA)
+    pub heat_index: Temperature,
+    /// Humidex, i.e. apparent temperature (Environment Canada)
+    pub humidex: Temperature,
+    /// Comfort classification
+    pub comfort: Comfort,
+    /// Pressure altitude in the International Standard Atmosphere
+    pub pressure_altitude: Length,
+}
+
+impl From<&Sample> for Derived {
+    fn from(sample: &Sample) -> Self {
+        let temperature = sample.temperature.get::<degree_celsius>();
+        let humidity = sample.humidity.get::<percent>();
+        let dew_point = derived::dew_point(temperature, humidity);
+        Self {
+            dew_point: Temperature::new::<degree_celsius>(dew_point),
+            absolute_humidity: absolute_humidity(sample.temperature, sample.humidity),
+            heat_index: Temperature::new::<degree_celsius>(derived::heat_index(
+                temperature,
+                humidity,
+            )),
+            humidex: Temperature::new::<degree_celsius>(derived::humidex(temperature, dew_point)),
+            comfort: Comfort::classify(temperature, humidity),
+            pressure_altitude: Length::new::<meter>(derived::pressure_altitude(
+                sample.pressure.get::<hectopascal>(),
+            )),
+        }
+    }
+}
+
+/// Compute the absolute humidity, i.e. the mass of water vapour in a volume of air
+pub fn absolute_humidity(temperature: Temperature, humidity: Humidity) -> MassDensity {
+    MassDensity::new::<gram_per_cubic_meter>(derived::absolute_humidity(
+        temperature.get::<degree_celsius>(),
+        humidity.get::<percent>(),
+    ))
+}
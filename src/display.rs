@@ -1,14 +1,14 @@
 use embassy_executor::task;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Receiver};
 
-use embassy_time::Delay;
 use embedded_hal::digital::OutputPin;
 use embedded_hal_async::delay::DelayNs;
 use embedded_hal_async::digital::Wait;
 use embedded_hal_async::spi::SpiDevice;
 use embedded_hal_bus::spi::ExclusiveDevice;
 use esp_hal::gpio::AnyPin;
-use esp_hal::gpio::Input;
 use esp_hal::gpio::Output;
 use esp_hal::peripherals::SPI2;
 use esp_hal::spi::master::SpiDmaBus;
@@ -23,24 +23,34 @@
 
 use crate::dashboard::draw_dashboard;
 use crate::dashboard::DashboardError;
+use crate::dashboard::DerivedRow;
 use crate::error;
 use crate::info;
+use crate::power::Awake;
+use crate::power::AwakeDelay;
+use crate::power::WakeupInput;
 use crate::sensor::SensorReading;
 
+/// Signal raised every time the display has been updated
+pub static DISPLAY_UPDATED_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
 #[task]
 pub async fn display_task(
     receiver: Receiver<'static, NoopRawMutex, SensorReading, 3>,
-    spi_device: ExclusiveDevice<
-        SpiDmaBus<'static, SPI2, FullDuplexMode, Async>,
-        Output<'static, AnyPin>,
-        Delay,
+    spi_device: Awake<
+        ExclusiveDevice<
+            SpiDmaBus<'static, SPI2, FullDuplexMode, Async>,
+            Output<'static, AnyPin>,
+            AwakeDelay,
+        >,
     >,
-    busy: Input<'static, AnyPin>,
+    busy: WakeupInput<'static>,
     rst: Output<'static, AnyPin>,
     dc: Output<'static, AnyPin>,
+    rows: &'static [DerivedRow],
 ) {
     info!("Create display");
-    let mut display = Display::new_with_individual_writes(spi_device, busy, rst, dc, Delay);
+    let mut display = Display::new_with_individual_writes(spi_device, busy, rst, dc, AwakeDelay);
 
     info!("Initialize display");
     if let Err(error) = display.initialize().await {
@@ -52,15 +62,17 @@ pub async fn display_task(
         info!("Wait for message from sensor");
         let sensor_reading = receiver.receive().await;
 
-        if let Err(error) = report(&mut display, sensor_reading).await {
+        if let Err(error) = report(&mut display, sensor_reading, rows).await {
             error!("Could not report sample: {error:?}");
         }
+        DISPLAY_UPDATED_SIGNAL.signal(());
     }
 }
 
 async fn report<SPI, BUSY, RST, DC, DELAY>(
     display: &mut Display<SPI, BUSY, RST, DC, DELAY>,
     sensor_reading: SensorReading,
+    rows: &[DerivedRow],
 ) -> Result<(), ReportError>
 where
     SPI: SpiDevice,
@@ -70,13 +82,14 @@ async fn report<SPI, BUSY, RST, DC, DELAY>(
     DELAY: DelayNs,
 {
     log_sample(&sensor_reading)?;
-    update_display(display, &sensor_reading).await?;
+    update_display(display, &sensor_reading, rows).await?;
     Ok(())
 }
 
 async fn update_display<SPI, BUSY, RST, DC, DELAY>(
     display: &mut Display<SPI, BUSY, RST, DC, DELAY>,
     sensor_reading: &(time::OffsetDateTime, crate::sensor::Sample),
+    rows: &[DerivedRow],
 ) -> Result<(), ReportError>
 where
     SPI: SpiDevice,
@@ -88,7 +101,7 @@ async fn update_display<SPI, BUSY, RST, DC, DELAY>(
     let mut buffer = Buffer::new();
 
     info!("Draw dashboard on buffer");
-    draw_dashboard(&mut buffer, sensor_reading).map_err(ReportError::Dashboard)?;
+    draw_dashboard(&mut buffer, sensor_reading, rows).map_err(ReportError::Dashboard)?;
     info!("Draw buffer on display");
     display
         .draw_buffer(&buffer)
@@ -0,0 +1,69 @@
+//! Thread mode executor that lets the chip sleep when idle
+//!
+//! This replaces the executor of `esp-hal-embassy`, which only waits for an
+//! interrupt between polls.
+//! Whenever no task is ready the [idle hook][crate::power::idle] runs with
+//! interrupts disabled, so that a wakeup cannot be missed between the check
+//! and the sleep.
+//! It only enters light sleep until the earliest deadline of the
+//! [timer queue][crate::timer_queue], and otherwise waits for an interrupt.
+
+use core::marker::PhantomData;
+use core::ptr::null_mut;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use embassy_executor::raw::Executor as RawExecutor;
+use embassy_executor::Spawner;
+
+use crate::power;
+
+/// Whether a task was woken since the executor last polled
+///
+/// This is only loaded and stored, since the ESP32-C3 has no atomic
+/// read-modify-write instructions.
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+/// Thread mode executor
+pub struct Executor {
+    /// Underlying executor
+    inner: RawExecutor,
+
+    /// Marker to keep the executor on a single thread
+    not_send: PhantomData<*mut ()>,
+}
+
+impl Executor {
+    /// Create an executor
+    pub fn new() -> Self {
+        Self {
+            inner: RawExecutor::new(null_mut()),
+            not_send: PhantomData,
+        }
+    }
+
+    /// Spawn the initial tasks and run the executor forever
+    pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
+        init(self.inner.spawner());
+
+        loop {
+            // SAFETY:
+            // The executor is only polled from this loop
+            unsafe { self.inner.poll() };
+
+            critical_section::with(|_| {
+                if SIGNALED.load(Ordering::SeqCst) {
+                    SIGNALED.store(false, Ordering::SeqCst);
+                } else {
+                    power::idle();
+                }
+            });
+        }
+    }
+}
+
+/// Notify the executor that a task was woken
+#[export_name = "__pender"]
+fn __pender(_context: *mut ()) {
+    SIGNALED.store(true, Ordering::SeqCst);
+}
@@ -4,12 +4,14 @@
 
 use blink::blink_task;
 use clock::ClockError;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_executor::task;
 use embassy_executor::Spawner;
+use embassy_futures::select::select;
+use embassy_futures::select::Either;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::mutex::Mutex;
-use embassy_time::Delay;
-use embassy_time::Timer;
 use embedded_hal_bus::spi::ExclusiveDevice;
 use esp_hal::clock::CpuClock;
 use esp_hal::dma::Dma;
@@ -18,6 +20,7 @@
 use esp_hal::dma::DmaRxBuf;
 use esp_hal::dma::DmaTxBuf;
 use esp_hal::dma_buffers;
+use esp_hal::gpio::AnyPin;
 use esp_hal::gpio::Input;
 use esp_hal::gpio::Io;
 use esp_hal::gpio::Level;
@@ -28,6 +31,7 @@
 use esp_hal::peripherals::I2C0;
 use esp_hal::peripherals::SPI2;
 use esp_hal::rng::Rng;
+use esp_hal::rtc_cntl::Rtc;
 use esp_hal::spi::master::Spi;
 use esp_hal::spi::master::SpiDma;
 use esp_hal::spi::master::SpiDmaBus;
@@ -35,7 +39,9 @@
 use esp_hal::spi::SpiMode;
 use esp_hal::Async;
 
-use esp_hal_embassy::main;
+use esp_hal::entry;
+
+use esp_storage::FlashStorage;
 
 use fugit::RateExtU32 as _;
 
@@ -44,12 +50,10 @@
 use core::convert::Infallible;
 
 // use log::debug;
-// use log::warn;
 use log::error;
 use log::info;
 use log::trace;
-
-use embassy_time::Duration;
+use log::warn;
 
 use esp_hal::timer::timg::TimerGroup;
 
@@ -63,30 +67,74 @@
 mod clock;
 use clock::Clock;
 
+mod config;
+use config::Config;
+use config::FLASH_RANGE as CONFIG_FLASH_RANGE;
+
+use crussant_common::external_rtc;
+use external_rtc::ExternalRtc;
+
+mod executor;
+use executor::Executor;
+
 mod display;
 use display::display_task;
+use display::DISPLAY_UPDATED_SIGNAL;
+
+use crussant_common::kv_store;
+use kv_store::Error as KvStoreError;
+use kv_store::Store as KvStore;
 
 mod logger;
 
+mod power;
+use power::Awake;
+use power::AwakeDelay;
+use power::WakeupInput;
+
+mod retained;
+
 mod sensor;
 use sensor::sensor_task;
 use sensor::SensorReading;
 
+mod sleep;
+
+mod timer_queue;
+
 mod dashboard;
+use dashboard::DerivedRow;
+
+mod derived;
+
+use crussant_common::tz;
+use tz::Error as TimeZoneError;
+use tz::TimeZone;
 
-/// Period to wait before going to deep sleep
-const AWAKE_PERIOD: Duration = Duration::from_secs(3);
+/// Derived quantities to show on the dashboard below the measurements
+const DASHBOARD_ROWS: &[DerivedRow] = &[DerivedRow::DewPoint, DerivedRow::Comfort];
 
 /// A channel between sensor sampler and display updater
 static CHANNEL: StaticCell<Channel<NoopRawMutex, SensorReading, 3>> = StaticCell::new();
 
-static I2C_BUS: StaticCell<Mutex<NoopRawMutex, I2c<I2C0, Async>>> = StaticCell::new();
+static I2C_BUS: StaticCell<Mutex<NoopRawMutex, Awake<I2c<I2C0, Async>>>> = StaticCell::new();
+
+/// Executor running all tasks
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
 
 /// Application entry point
-/// Sets up logger and runs firmware
-#[main]
-async fn entry(spawner: Spawner) {
+/// Sets up logger and runs the executor
+#[entry]
+fn entry() -> ! {
     logger::setup();
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| spawner.must_spawn(main_task(spawner)))
+}
+
+/// Main task
+/// Runs the fallible main
+#[task]
+async fn main_task(spawner: Spawner) {
     info!("spawning main");
     match main(&spawner).await {
         Err(err) => {
@@ -115,12 +163,31 @@ async fn main(spawner: &Spawner) -> Result<!, Error> {
     // Green LED on my T8-C3 <3
     let led = io.pins.gpio3;
 
+    // This is marked as uart TxD on my T8-C3
+    info!("Create button pin");
+    let button = WakeupInput::new(Input::new(io.pins.gpio21.degrade(), Pull::Up));
+
+    info!("Initialize power management");
+    power::init(Rtc::new(peripherals.LPWR), peripherals.SYSTEM);
+
+    info!("Load configuration");
+    let mut config_store =
+        KvStore::mount(FlashStorage::new(), CONFIG_FLASH_RANGE).map_err(Error::KvStore)?;
+    let config = match Config::load(&mut config_store) {
+        Ok(config) => config,
+        Err(err) => {
+            warn!("Cannot load configuration, using defaults: {err:?}");
+            Config::default()
+        }
+    };
+    info!("Configuration: {config:?}");
+
     info!("Initialize the RNG peripheral");
     let rng = Rng::new(peripherals.RNG);
 
     info!("Create Display and SPI Chip Select pins");
     let cs = Output::new(io.pins.gpio8, Level::Low);
-    let busy = Input::new(io.pins.gpio9, Pull::Up);
+    let busy = WakeupInput::new(Input::new(io.pins.gpio9.degrade(), Pull::Up));
     let rst = Output::new(io.pins.gpio10, Level::Low);
     // This is marked as uart RxD on my T8-C3
     let dc = Output::new(io.pins.gpio20, Level::Low);
@@ -145,27 +212,58 @@ async fn main(spawner: &Spawner) -> Result<!, Error> {
 
     info!("Create SPI DMA Bus");
     let spi_dma_bus = SpiDmaBus::new(spi_dma, dma_rx_buf, dma_tx_buf);
-    let spi_device = ExclusiveDevice::new(spi_dma_bus, cs, Delay).map_err(|err| {
+    let spi_device = ExclusiveDevice::new(spi_dma_bus, cs, AwakeDelay).map_err(|err| {
         error!("Error creating SPI ExclusiveDevice {err}");
         Error::SpiBusCreation
     })?;
-
-    info!("Creating I2C pins");
-    let sda = io.pins.gpio2;
-    let scl = io.pins.gpio4;
+    let spi_device = Awake::new(spi_device);
+
+    info!(
+        "Creating I2C pins GPIO{} and GPIO{}",
+        config.sda_pin, config.scl_pin
+    );
+    let mut i2c_pins = [
+        (1, Some(io.pins.gpio1.degrade())),
+        (2, Some(io.pins.gpio2.degrade())),
+        (4, Some(io.pins.gpio4.degrade())),
+        (5, Some(io.pins.gpio5.degrade())),
+    ];
+    let sda = take_pin(&mut i2c_pins, config.sda_pin)?;
+    let scl = take_pin(&mut i2c_pins, config.scl_pin)?;
 
     info!("Creating I2C device");
-    let i2c_bus = I2C_BUS.init(Mutex::new(I2c::new_with_timeout_async(
+    let i2c_bus = I2C_BUS.init(Mutex::new(Awake::new(I2c::new_with_timeout_async(
         peripherals.I2C0,
         sda,
         scl,
-        100.kHz(),
+        config.i2c_frequency_khz.kHz(),
         Some(20),
-    )));
+    ))));
     // let i2c = I2c::new_with_timeout(peripherals.I2C0, sda, scl, 400.kHz(), Some(20));
 
+    retained::increment_boot_count();
+
     info!("Creating Clock");
-    let clock = Clock::new();
+    let time_zone = TimeZone::parse(&config.time_zone).map_err(Error::TimeZone)?;
+    let clock = match config.external_rtc {
+        Some(model) => {
+            let mut rtc = ExternalRtc::new(model, I2cDevice::new(i2c_bus));
+            match Clock::from_external_rtc(&mut rtc, time_zone).await {
+                Ok(clock) => Some(clock),
+                Err(err) => {
+                    warn!("Cannot read external RTC {}: {err:?}", model.name());
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let clock = clock
+        .or_else(|| Clock::from_rtc_memory(time_zone))
+        .unwrap_or_else(|| {
+            info!("No clock in RTC memory, using compilation time");
+            Clock::new(time_zone)
+        });
     info!("Now is {}", clock.now().map_err(Error::Clock)?);
 
     info!("Create channel");
@@ -181,23 +279,55 @@ async fn main(spawner: &Spawner) -> Result<!, Error> {
     info!("Spawning blink task");
     spawner.must_spawn(blink_task(led.degrade()));
     info!("Spawning sensor task");
-    spawner.must_spawn(sensor_task(sender, i2c_bus, rng, clock.clone()));
+    spawner.must_spawn(sensor_task(
+        sender,
+        i2c_bus,
+        button,
+        rng,
+        clock.clone(),
+        config.sampling_period,
+    ));
     info!("Spawning display task");
-    spawner.must_spawn(display_task(receiver, spi_device, busy, rst, dc));
+    spawner.must_spawn(display_task(
+        receiver,
+        spi_device,
+        busy,
+        rst,
+        dc,
+        DASHBOARD_ROWS,
+    ));
+
+    if config.always_on {
+        info!(
+            "Stay on, sampling every {}s in light sleep",
+            config.sampling_period.as_secs()
+        );
+        return core::future::pending().await;
+    }
 
-    // info!("Stay awake for {}s", AWAKE_PERIOD.as_secs());
-    // Timer::after(AWAKE_PERIOD).await
+    info!(
+        "Wait up to {}s for the display to be updated",
+        config.awake_period.as_secs()
+    );
+    if let Either::First(()) = select(
+        power::sleep(config.awake_period),
+        DISPLAY_UPDATED_SIGNAL.wait(),
+    )
+    .await
+    {
+        warn!("Display was not updated in time");
+    }
 
-    // info!("Go to sleep for {}s", DEEP_SLEEP_DURATION.as_secs());
-    //
-    // clock.save_to_rtc_memory(DEEP_SLEEP_DURATION);
-    // enter_deep_sleep(peripherals.LPWR, DEEP_SLEEP_DURATION.into());
-    //
-    // info!("Awoken");
+    clock.save_to_rtc_memory(config.deep_sleep_duration);
+    power::enter_deep_sleep(config.deep_sleep_duration.into());
+}
 
-    loop {
-        Timer::after(Duration::from_secs(1200)).await;
-    }
+/// Take a GPIO from those that can be assigned by the configuration
+fn take_pin(pins: &mut [(u8, Option<AnyPin>)], number: u8) -> Result<AnyPin, Error> {
+    pins.iter_mut()
+        .find(|(candidate, _)| *candidate == number)
+        .and_then(|(_, pin)| pin.take())
+        .ok_or(Error::UnavailablePin(number))
 }
 
 /// An error
@@ -211,6 +341,13 @@ enum Error {
     DmaBufferCreation(DmaBufError),
 
     Clock(ClockError),
+
+    TimeZone(TimeZoneError),
+
+    KvStore(KvStoreError),
+
+    /// A GPIO cannot be assigned or is assigned twice
+    UnavailablePin(u8),
 }
 
 impl From<Infallible> for Error {
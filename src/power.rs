@@ -0,0 +1,331 @@
+//! Power management while awake
+//!
+//! When no task is ready the [executor][crate::executor] calls [`idle()`],
+//! which either enters light sleep or waits for an interrupt with the CPU
+//! clock gated.
+//!
+//! Light sleep stops the embassy time driver, so it is never entered while an
+//! embassy [`Timer`] is pending, which would be late by the whole sleep.
+//! Tasks that can wait in light sleep do so in [`sleep()`] instead, whose
+//! earliest deadline in the [timer queue][crate::timer_queue] programs the
+//! RTC timer wakeup.
+//! GPIO wakeups are enabled by [`WakeupInput`] while waiting for a level.
+//! The time spent in light sleep is tracked by [`uptime()`].
+//!
+//! The choice between light sleep and waiting for an interrupt is made by
+//! [`Timers::next_wakeup()`][crussant_common::timers::Timers::next_wakeup].
+//!
+//! Light sleep is only entered when no [`AwakeLock`] is held either.
+//! [`Awake`] holds one during each I²C or SPI transaction and [`AwakeDelay`]
+//! during each delay, so a transfer or a timed sequence is never interrupted.
+//! The digital domain stays powered during light sleep, so the I²C and SPI
+//! peripherals keep their configuration.
+//!
+//! While waiting for an interrupt the CPU clock is gated, and its frequency
+//! drops from 160 MHz to 80 MHz until the interrupt arrives.
+//! The APB clock stays at 80 MHz from the PLL at either CPU frequency, so the
+//! peripheral dividers computed by esp-hal at initialization remain valid.
+//! Lower frequencies would need the PLL to be switched off, which slows down
+//! the APB clock as well.
+
+use core::arch::asm;
+use core::cell::Cell;
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use embassy_time::Duration;
+use embassy_time::Instant;
+use embassy_time::Timer;
+
+use embedded_hal::digital::ErrorType as DigitalErrorType;
+use embedded_hal::i2c::ErrorType as I2cErrorType;
+use embedded_hal::i2c::Operation as I2cOperation;
+use embedded_hal::spi::ErrorType as SpiErrorType;
+use embedded_hal::spi::Operation as SpiOperation;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::spi::SpiDevice;
+
+use esp_hal::gpio::AnyPin;
+use esp_hal::gpio::Input;
+use esp_hal::gpio::WakeEvent;
+use esp_hal::peripherals::SYSTEM;
+use esp_hal::rtc_cntl::Rtc;
+
+use crussant_common::timers::Wakeup;
+
+use crate::sleep::enter_deep;
+use crate::sleep::enter_light;
+use crate::timer_queue;
+
+/// Value of `SYSTEM_CPUPERIOD_SEL` for a CPU clock of 80 MHz from the PLL
+const CPU_PERIOD_80_MHZ: u8 = 0;
+
+/// RTC controller, shared by light and deep sleep
+static RTC: Mutex<CriticalSectionRawMutex, RefCell<Option<Rtc<'static>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// System registers, used to lower the CPU frequency while idle
+static SYSTEM_REGISTERS: Mutex<CriticalSectionRawMutex, RefCell<Option<SYSTEM>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Number of held [`AwakeLock`]s
+static AWAKE_LOCKS: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Time spent in light sleep, during which the embassy time driver stops
+static TIME_ASLEEP: Mutex<CriticalSectionRawMutex, Cell<Duration>> =
+    Mutex::new(Cell::new(Duration::from_ticks(0)));
+
+/// Initialize power management
+///
+/// Until this is called the chip never enters light sleep.
+pub fn init(rtc: Rtc<'static>, system: SYSTEM) {
+    RTC.lock(|cell| *cell.borrow_mut() = Some(rtc));
+
+    // Let the CPU clock be gated while waiting for an interrupt, which does
+    // not affect any peripheral
+    critical_section::with(|_| {
+        system
+            .cpu_per_conf()
+            .modify(|_, w| w.cpu_wait_mode_force_on().clear_bit());
+    });
+    SYSTEM_REGISTERS.lock(|cell| *cell.borrow_mut() = Some(system));
+}
+
+/// Return the time since boot, including the time spent in light sleep
+pub fn uptime() -> Duration {
+    Duration::from_ticks(Instant::now().as_ticks()) + TIME_ASLEEP.lock(Cell::get)
+}
+
+/// Wait for a duration, entering light sleep if nothing else is pending
+///
+/// Unlike [`Timer`], the duration includes the time spent in light sleep.
+pub async fn sleep(duration: Duration) {
+    let deadline = uptime() + duration;
+    poll_fn(|context| match deadline.checked_sub(uptime()) {
+        Some(remaining) if remaining > Duration::from_ticks(0) => {
+            timer_queue::schedule_sleep(Instant::now() + remaining, context.waker());
+            Poll::Pending
+        }
+        _ => Poll::Ready(()),
+    })
+    .await;
+}
+
+/// Enter deep sleep for the specified interval
+///
+/// # Panics
+///
+/// Panics if [`init()`] was not called.
+pub fn enter_deep_sleep(interval: core::time::Duration) -> ! {
+    RTC.lock(|cell| {
+        if let Some(rtc) = cell.borrow_mut().as_mut() {
+            enter_deep(rtc, interval);
+        }
+    });
+    panic!("Power management is not initialized");
+}
+
+/// Wait until the next interrupt or wakeup
+///
+/// This is called by the executor with interrupts disabled when no task is
+/// ready.
+/// Enter light sleep until the earliest deadline of [`sleep()`] if no
+/// [`AwakeLock`] is held and no [`Timer`] is pending, otherwise wait for an
+/// interrupt.
+pub fn idle() {
+    let awake = AWAKE_LOCKS.lock(Cell::get) > 0;
+
+    match timer_queue::next_wakeup(awake) {
+        Wakeup::LightSleep(remaining) => {
+            let slept = RTC.lock(|cell| {
+                cell.borrow_mut().as_mut().map(|rtc| {
+                    let before = Instant::now();
+                    let slept = enter_light(rtc, remaining.into());
+                    (slept, before.elapsed())
+                })
+            });
+            match slept {
+                Some((slept, elapsed)) => {
+                    let slept =
+                        Duration::from_micros(u64::try_from(slept.as_micros()).unwrap_or(u64::MAX));
+                    if let Some(lost) = slept.checked_sub(elapsed) {
+                        TIME_ASLEEP.lock(|time| time.set(time.get() + lost));
+                        timer_queue::skip(lost);
+                    }
+                }
+                None => wait_for_interrupt(),
+            }
+        }
+        Wakeup::Interrupt => wait_for_interrupt(),
+    }
+}
+
+/// Wait for an interrupt, with the CPU clock gated and lowered to 80 MHz
+fn wait_for_interrupt() {
+    SYSTEM_REGISTERS.lock(|cell| match cell.borrow().as_ref() {
+        Some(system) => {
+            let period = system.cpu_per_conf().read().cpuperiod_sel().bits();
+            set_cpu_period(system, CPU_PERIOD_80_MHZ);
+            wfi();
+            set_cpu_period(system, period);
+        }
+        None => wfi(),
+    });
+}
+
+/// Wait for an interrupt, with the CPU clock gated
+fn wfi() {
+    // SAFETY:
+    // Waiting for an interrupt has no side effect, and a pending interrupt
+    // ends the wait even while interrupts are disabled
+    unsafe { asm!("wfi") };
+}
+
+/// Select the divider of the CPU clock from the PLL
+///
+/// The APB clock is not affected.
+fn set_cpu_period(system: &SYSTEM, period: u8) {
+    // SAFETY:
+    // Both dividers are valid with the PLL as the CPU clock source, which
+    // esp-hal selects for 80 MHz and 160 MHz
+    system
+        .cpu_per_conf()
+        .modify(|_, w| unsafe { w.cpuperiod_sel().bits(period) });
+}
+
+/// A lock preventing light sleep until dropped
+pub struct AwakeLock;
+
+impl AwakeLock {
+    /// Acquire a lock
+    pub fn acquire() -> Self {
+        AWAKE_LOCKS.lock(|locks| locks.set(locks.get() + 1));
+        Self
+    }
+}
+
+impl Drop for AwakeLock {
+    fn drop(&mut self) {
+        AWAKE_LOCKS.lock(|locks| locks.set(locks.get() - 1));
+    }
+}
+
+/// A bus or device that prevents light sleep during transactions
+pub struct Awake<T>(T);
+
+impl<T> Awake<T> {
+    /// Wrap a bus or device
+    pub fn new(inner: T) -> Self {
+        Self(inner)
+    }
+}
+
+impl<T: I2cErrorType> I2cErrorType for Awake<T> {
+    type Error = T::Error;
+}
+
+impl<T: I2c> I2c for Awake<T> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<(), Self::Error> {
+        let _lock = AwakeLock::acquire();
+        self.0.transaction(address, operations).await
+    }
+}
+
+impl<T: SpiErrorType> SpiErrorType for Awake<T> {
+    type Error = T::Error;
+}
+
+impl<T: SpiDevice> SpiDevice for Awake<T> {
+    async fn transaction(
+        &mut self,
+        operations: &mut [SpiOperation<'_, u8>],
+    ) -> Result<(), Self::Error> {
+        let _lock = AwakeLock::acquire();
+        self.0.transaction(operations).await
+    }
+}
+
+/// A delay that prevents light sleep while waiting
+///
+/// Light sleep stops the embassy time driver, so a delay must not be
+/// interrupted by it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AwakeDelay;
+
+impl DelayNs for AwakeDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        let _lock = AwakeLock::acquire();
+        Timer::after_nanos(u64::from(ns)).await;
+    }
+}
+
+impl embedded_hal::delay::DelayNs for AwakeDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        embedded_hal::delay::DelayNs::delay_ns(&mut embassy_time::Delay, ns);
+    }
+}
+
+/// An input that wakes up the chip from light sleep while waiting for a level
+///
+/// Edges cannot wake up the chip, so light sleep is prevented while waiting
+/// for them.
+pub struct WakeupInput<'d>(Input<'d, AnyPin>);
+
+impl<'d> WakeupInput<'d> {
+    /// Wrap an input
+    pub fn new(input: Input<'d, AnyPin>) -> Self {
+        Self(input)
+    }
+
+    /// Wait for a level with the GPIO wakeup enabled
+    async fn wait_for_level(&mut self, event: WakeEvent) {
+        self.0.wakeup_enable(true, event);
+        let _ = match event {
+            WakeEvent::HighLevel => Wait::wait_for_high(&mut self.0).await,
+            _ => Wait::wait_for_low(&mut self.0).await,
+        };
+        self.0.wakeup_enable(false, event);
+    }
+}
+
+impl DigitalErrorType for WakeupInput<'_> {
+    type Error = Infallible;
+}
+
+impl Wait for WakeupInput<'_> {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(WakeEvent::HighLevel).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_for_level(WakeEvent::LowLevel).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        let _lock = AwakeLock::acquire();
+        Wait::wait_for_rising_edge(&mut self.0).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        let _lock = AwakeLock::acquire();
+        Wait::wait_for_falling_edge(&mut self.0).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        let _lock = AwakeLock::acquire();
+        Wait::wait_for_any_edge(&mut self.0).await
+    }
+}
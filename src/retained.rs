@@ -0,0 +1,145 @@
+use core::cell::Cell;
+use core::mem::size_of;
+use core::ptr::addr_of;
+use core::ptr::addr_of_mut;
+
+use crc::Digest;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use esp_hal::macros::ram;
+use esp_hal::reset::get_reset_reason;
+use esp_hal::rtc_cntl::SocResetReason;
+
+use crussant_common::retained::Checksum;
+use crussant_common::retained::Header;
+
+use crate::info;
+use crate::warn;
+
+/// Magic number marking valid retained state, `CRUS` in ASCII
+const MAGIC: u32 = 0x4352_5553;
+
+/// Version of the layout of [`RetainedState`]
+///
+/// This must be incremented whenever the layout changes in a way that is not
+/// caught by the size check.
+const LAYOUT_VERSION: u16 = 1;
+
+/// Header of a state of the current layout
+const LAYOUT: Header = Header::layout(MAGIC, LAYOUT_VERSION, size_of::<RetainedState>());
+
+/// State retained between deep sleep cycles and its header
+///
+/// This is placed in the RTC Fast memory, which survives deep sleep. It is not
+/// initialized on boot, so after a cold boot it contains random data, and
+/// after a panic, a watchdog or a software reset it contains the state of the
+/// previous boot, which must both be rejected as described in
+/// [`crussant_common::retained`].
+#[ram(rtc_fast, persistent)]
+static mut RETAINED: Retained = Retained {
+    header: Header::EMPTY,
+    state: RetainedState::EMPTY,
+};
+
+/// Whether the retained state was stored since boot
+///
+/// A state stored since boot is valid whatever the reset reason.
+static STORED_SINCE_BOOT: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Retained state and its header
+#[repr(C)]
+struct Retained {
+    /// Header
+    header: Header,
+
+    /// State
+    state: RetainedState,
+}
+
+/// State retained between deep sleep cycles
+///
+/// All fields are plain integers, so any bit pattern is a valid value.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct RetainedState {
+    /// Number of boots since the last cold boot
+    boot_count: u32,
+
+    /// Expected wakeup time in Unix timestamp, or 0 if no clock was saved
+    wakeup_time: u64,
+}
+
+impl RetainedState {
+    /// State after a cold boot
+    const EMPTY: Self = Self {
+        boot_count: 0,
+        wakeup_time: 0,
+    };
+}
+
+impl Checksum for RetainedState {
+    fn feed(&self, digest: &mut Digest<'_, u32>) {
+        self.boot_count.feed(digest);
+        self.wakeup_time.feed(digest);
+    }
+}
+
+/// Load the retained state, falling back to a cold boot state if invalid
+fn load() -> RetainedState {
+    let woke_from_deep_sleep = STORED_SINCE_BOOT.lock(Cell::get)
+        || get_reset_reason() == Some(SocResetReason::CoreDeepSleep);
+
+    // SAFETY:
+    // There is only one thread, and every bit pattern is a valid state
+    let Retained { header, state } = unsafe { addr_of!(RETAINED).read_volatile() };
+
+    match header.verify(&LAYOUT, woke_from_deep_sleep, || state.checksum()) {
+        Ok(()) => state,
+        Err(reason) => {
+            warn!("No valid state in RTC memory ({reason:?}), assuming cold boot");
+            RetainedState::EMPTY
+        }
+    }
+}
+
+/// Store the retained state, sealing it with a checksum
+fn store(state: RetainedState) {
+    let retained = Retained {
+        header: LAYOUT.seal(state.checksum()),
+        state,
+    };
+
+    // SAFETY:
+    // There is only one thread
+    unsafe { addr_of_mut!(RETAINED).write_volatile(retained) };
+    STORED_SINCE_BOOT.lock(|stored| stored.set(true));
+}
+
+/// Increment the boot count and return its new value
+pub fn increment_boot_count() -> u32 {
+    let mut state = load();
+    state.boot_count = state.boot_count.wrapping_add(1);
+    store(state);
+
+    info!("Boot count is {}", state.boot_count);
+    state.boot_count
+}
+
+/// Load the saved clock as its expected wakeup time
+pub fn load_clock() -> Option<u64> {
+    let state = load();
+    if state.wakeup_time == 0 {
+        None
+    } else {
+        Some(state.wakeup_time)
+    }
+}
+
+/// Save the clock as its expected wakeup time
+pub fn save_clock(wakeup_time: u64) {
+    let mut state = load();
+    state.wakeup_time = wakeup_time;
+    store(state);
+}
@@ -3,7 +3,6 @@
 use bme280_rs::SensorMode;
 use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use embassy_time::Delay;
 use embedded_ccs811::Ccs811Awake;
 use embedded_ccs811::SlaveAddr;
 use embedded_hal_bus::i2c::RefCellDevice;
@@ -14,13 +13,19 @@
 
 use embassy_executor::task;
 
+use embassy_futures::select::select;
+use embassy_futures::select::Either;
+
 use embassy_sync::channel::Sender;
 
 use embassy_time::Duration;
 use embassy_time::Timer;
 
+use embedded_hal_async::digital::Wait;
+
 use esp_hal::Async;
 use esp_hal::Blocking;
+use sgp30::Humidity as Sgp30Humidity;
 use sgp30::Measurement;
 use sgp30::Sgp30;
 use time::OffsetDateTime;
@@ -28,15 +33,20 @@
 use uom::si::f32::Pressure;
 use uom::si::f32::Ratio as Humidity;
 use uom::si::f32::ThermodynamicTemperature as Temperature;
+use uom::si::mass_density::gram_per_cubic_meter;
 
 use crate::error;
 use crate::info;
 
 use crate::clock::Clock;
+use crate::derived::absolute_humidity;
+use crate::power::sleep;
+use crate::power::Awake;
+use crate::power::AwakeDelay;
+use crate::power::WakeupInput;
 
 /// Interval to wait for sensor warmup
 const WARMUP_INTERVAL: Duration = Duration::from_millis(10);
-const WAIT_INTERVAL: Duration = Duration::from_secs(60);
 
 /// A sample
 #[derive(Clone, Debug, Default)]
@@ -89,10 +99,12 @@ pub async fn sensor_task(
     // i2c: I2C0,
     i2c_bus: &'static mut embassy_sync::mutex::Mutex<
         NoopRawMutex,
-        esp_hal::i2c::I2c<'static, I2C0, Async>,
+        Awake<esp_hal::i2c::I2c<'static, I2C0, Async>>,
     >,
+    mut button: WakeupInput<'static>,
     mut rng: Rng,
     clock: Clock,
+    sampling_period: Duration,
 ) {
     info!("Creating I2C devices to share I2C bus between sensors");
     let i2c_device_1 = I2cDevice::new(i2c_bus);
@@ -100,7 +112,7 @@ pub async fn sensor_task(
     let i2c_device_3 = I2cDevice::new(i2c_bus);
 
     info!("Initializing hdc1080 sensor");
-    let mut hdc1080 = Hdc1080::new(RefCellDevice::new(i2c_device_1), Delay).unwrap();
+    let mut hdc1080 = Hdc1080::new(RefCellDevice::new(i2c_device_1), AwakeDelay).unwrap();
     let device_id = hdc1080.get_device_id().unwrap();
     let manufacturing_id = hdc1080.get_man_id().unwrap();
     info!("hdc1080 device id: {device_id} - expected 0x1050");
@@ -110,7 +122,7 @@ pub async fn sensor_task(
     let mut ccs811 = Ccs811Awake::new(RefCellDevice::new(i2c_device_2), SlaveAddr::default());
 
     info!("Initializing sgp30 sensor");
-    let mut sgp30 = Sgp30::new(RefCellDevice::new(i2c_device_3), 0x58, Delay);
+    let mut sgp30 = Sgp30::new(RefCellDevice::new(i2c_device_3), 0x58, AwakeDelay);
     sgp30.init();
 
     info!(
@@ -129,21 +141,39 @@ pub async fn sensor_task(
             .unwrap();
         info!("hdc1080 reading: {hdc_reading:?}");
 
-        let measurement: Measurement = sgp30.measure().unwrap();
-        info!("CO₂eq parts per million: {}", measurement.co2eq_ppm);
-        info!("TVOC parts per billion: {}", measurement.tvoc_ppb);
-
         let sensor_reading = sample(&mut rng, &clock).await.unwrap_or_else(|err| {
             error!("sensor measurement error: {err:?}");
             (OffsetDateTime::UNIX_EPOCH, Sample::random(&mut rng))
         });
 
+        // Compensate the sgp30 gas readings for the current absolute humidity
+        let latest = &sensor_reading.1;
+        let humidity = absolute_humidity(latest.temperature, latest.humidity);
+        match Sgp30Humidity::from_f32(humidity.get::<gram_per_cubic_meter>()) {
+            Ok(humidity) => {
+                if let Err(err) = sgp30.set_humidity(Some(&humidity)) {
+                    error!("sgp30 humidity compensation error: {err:?}");
+                }
+            }
+            Err(err) => error!("Absolute humidity out of sgp30 range: {err:?}"),
+        }
+
+        let measurement: Measurement = sgp30.measure().unwrap();
+        info!("CO₂eq parts per million: {}", measurement.co2eq_ppm);
+        info!("TVOC parts per billion: {}", measurement.tvoc_ppb);
+
         if let Err(send_err) = send(sensor_reading, &sender).await {
             error!("Sending measurement error: {send_err:?}");
         }
 
-        info!("Wait {}s for next sample", WAIT_INTERVAL.as_secs());
-        Timer::after(WAIT_INTERVAL).await;
+        info!(
+            "Wait {}s or for a button press for next sample",
+            sampling_period.as_secs()
+        );
+        if let Either::Second(_) = select(sleep(sampling_period), button.wait_for_low()).await {
+            info!("Button pressed, sample now");
+            let _ = button.wait_for_high().await;
+        }
     }
 }
 
@@ -0,0 +1,34 @@
+use core::time::Duration;
+
+use esp_hal::rtc_cntl::sleep::GpioWakeupSource;
+use esp_hal::rtc_cntl::sleep::TimerWakeupSource;
+use esp_hal::rtc_cntl::Rtc;
+
+use crate::info;
+
+/// Enter deep sleep for the specified interval
+///
+/// Only RTC fast memory survives, the firmware restarts from the entry point
+/// on wakeup.
+pub fn enter_deep(rtc: &mut Rtc, interval: Duration) -> ! {
+    let wakeup_source = TimerWakeupSource::new(interval);
+
+    info!("Entering deep sleep for {interval:?}");
+    rtc.sleep_deep(&[&wakeup_source]);
+}
+
+/// Enter light sleep for at most the specified interval
+///
+/// Execution resumes when the interval expires or when an input with an
+/// enabled GPIO wakeup reaches its level.
+/// Return the time actually spent asleep.
+pub fn enter_light(rtc: &mut Rtc, interval: Duration) -> Duration {
+    let timer_wakeup_source = TimerWakeupSource::new(interval);
+    let gpio_wakeup_source = GpioWakeupSource::new();
+
+    let before = rtc.time_since_boot();
+    rtc.sleep_light(&[&timer_wakeup_source, &gpio_wakeup_source]);
+    let after = rtc.time_since_boot();
+
+    Duration::from_micros((after - before).to_micros())
+}
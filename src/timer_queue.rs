@@ -0,0 +1,142 @@
+//! Timer queue of the embassy time driver
+//!
+//! This replaces the generic queue of `embassy-time`, so that
+//! [power management][crate::power] can see the pending deadlines before
+//! entering light sleep.
+//! The pending wakeups are kept in [`Timers`], see
+//! [`crussant_common::timers`] for how light sleep affects them.
+//! A [`Kind::Sleep`] checks its deadline against the
+//! [uptime][crate::power::uptime], so after waking up its deadline is moved
+//! earlier by the time lost with [`skip()`].
+
+use core::cell::RefCell;
+use core::ptr::null_mut;
+use core::task::Waker;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use embassy_time::Duration;
+use embassy_time::Instant;
+
+use embassy_time_driver::allocate_alarm;
+use embassy_time_driver::set_alarm;
+use embassy_time_driver::set_alarm_callback;
+use embassy_time_driver::AlarmHandle;
+
+use embassy_time_queue_driver::timer_queue_impl;
+use embassy_time_queue_driver::TimerQueue;
+
+use crussant_common::timers::Kind;
+use crussant_common::timers::Timers;
+use crussant_common::timers::Wakeup;
+
+timer_queue_impl!(static QUEUE: Queue = Queue::new());
+
+/// Schedule a wakeup for a sleep
+pub fn schedule_sleep(deadline: Instant, waker: &Waker) {
+    QUEUE.schedule(deadline, waker, Kind::Sleep);
+}
+
+/// Choose how to wait when no task is ready
+///
+/// The chip stays awake if `awake` is set.
+pub fn next_wakeup(awake: bool) -> Wakeup {
+    QUEUE.inner.lock(|inner| match inner.borrow().as_ref() {
+        Some(inner) => inner.timers.next_wakeup(Instant::now(), awake),
+        None => Wakeup::Interrupt,
+    })
+}
+
+/// Move the deadlines of sleeps earlier by a duration and wake those due
+///
+/// This is called after the embassy time driver was stopped for the
+/// duration.
+pub fn skip(duration: Duration) {
+    QUEUE.inner.lock(|inner| {
+        if let Some(inner) = inner.borrow_mut().as_mut() {
+            inner.timers.skip(duration);
+            inner.dispatch();
+        }
+    });
+}
+
+/// Pending timers and the alarm programmed for the earliest one
+struct Inner {
+    /// Pending timers
+    timers: Timers,
+
+    /// Alarm of the time driver
+    alarm: AlarmHandle,
+}
+
+impl Inner {
+    /// Schedule a wakeup, keeping the earliest deadline of a task and kind
+    fn schedule(&mut self, deadline: Instant, waker: &Waker, kind: Kind) {
+        self.timers.schedule(deadline, waker, kind);
+        self.dispatch();
+    }
+
+    /// Wake the timers due and program the alarm for the next one
+    fn dispatch(&mut self) {
+        loop {
+            // The alarm is not set if the deadline has passed in the meantime
+            match self.timers.wake_due(Instant::now()) {
+                None => break,
+                Some(deadline) if set_alarm(self.alarm, deadline.as_ticks()) => break,
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+/// Queue of pending timers
+struct Queue {
+    /// Timers and alarm, allocated on the first schedule
+    inner: Mutex<CriticalSectionRawMutex, RefCell<Option<Inner>>>,
+}
+
+impl Queue {
+    /// Create an empty queue
+    const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Schedule a wakeup, allocating the alarm on the first one
+    fn schedule(&self, deadline: Instant, waker: &Waker, kind: Kind) {
+        self.inner.lock(|inner| {
+            inner
+                .borrow_mut()
+                .get_or_insert_with(|| {
+                    // SAFETY:
+                    // The alarm is allocated once, before any is set
+                    let Some(alarm) = (unsafe { allocate_alarm() }) else {
+                        panic!("No alarm available for the timer queue");
+                    };
+                    set_alarm_callback(alarm, Self::handle_alarm, null_mut());
+                    Inner {
+                        timers: Timers::new(),
+                        alarm,
+                    }
+                })
+                .schedule(deadline, waker, kind);
+        });
+    }
+
+    /// Wake the timers due when the alarm fires
+    fn handle_alarm(_context: *mut ()) {
+        QUEUE.inner.lock(|inner| {
+            if let Some(inner) = inner.borrow_mut().as_mut() {
+                inner.dispatch();
+            }
+        });
+    }
+}
+
+impl TimerQueue for Queue {
+    fn schedule_wake(&'static self, at: u64, waker: &Waker) {
+        self.schedule(Instant::from_ticks(at), waker, Kind::Timer);
+    }
+}